@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use tauri::command;
 use log::{info, error};
+use crate::device::verify::BOOT_MARKER;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CodeGenerationRequest {
@@ -54,7 +55,7 @@ pub async fn generate_microbit_code(
     
     // 这里应该实现真正的Blockly XML到MicroPython代码的转换
     // 暂时返回一个示例代码
-    let code = generate_micropython_code_from_xml(&request.blocks_xml)?;
+    let code = generate_micropython_code_from_xml(&request.blocks_xml, &request.device_type)?;
     
     let response = CodeGenerationResponse {
         code,
@@ -79,6 +80,7 @@ fn generate_arduino_code_from_xml(xml: &str, device_type: &str) -> Result<String
 void setup() {
     // 初始化串口通信
     Serial.begin(9600);
+    Serial.println("{boot_marker}");
     
     // 初始化引脚
     pinMode(LED_BUILTIN, OUTPUT);
@@ -103,6 +105,7 @@ void loop() {
 void setup() {
     // 初始化串口通信
     Serial.begin(115200);
+    Serial.println("{boot_marker}");
     
     // 初始化内置LED
     pinMode(LED_BUILTIN, OUTPUT);
@@ -126,6 +129,7 @@ void loop() {
 
 void setup() {
     Serial.begin(9600);
+    Serial.println("{boot_marker}");
     pinMode(LED_BUILTIN, OUTPUT);
 }
 
@@ -139,19 +143,47 @@ void loop() {
     };
     
     let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
-    Ok(template.replace("{}", &timestamp.to_string()))
+    Ok(template
+        .replace("{}", &timestamp.to_string())
+        .replace("{boot_marker}", BOOT_MARKER))
 }
 
-fn generate_micropython_code_from_xml(xml: &str) -> Result<String, String> {
+fn generate_micropython_code_from_xml(xml: &str, device_type: &str) -> Result<String, String> {
     // 这是一个简化的示例，实际实现需要解析Blockly XML
     // 并根据积木块生成对应的MicroPython代码
     
-    let template = r#"# MicroPython代码 - 由RustBlock自动生成
+    let template = match device_type {
+        "ESP32" | "RaspberryPiPico" => {
+            r#"# MicroPython代码 - 由RustBlock自动生成
+# 生成时间: {}
+
+from machine import Pin
+import time
+
+# 启动标记，上传后用于验证程序已运行
+print("{boot_marker}")
+
+# 板载LED
+led = Pin({led_pin}, Pin.OUT)
+
+# 主循环
+while True:
+    led.on()
+    time.sleep(1)
+    led.off()
+    time.sleep(1)
+"#
+        },
+        _ => {
+            r#"# MicroPython代码 - 由RustBlock自动生成
 # 生成时间: {}
 
 from microbit import *
 import time
 
+# 启动标记，上传后用于验证程序已运行
+print("{boot_marker}")
+
 # 显示欢迎信息
 display.scroll("Hello!")
 
@@ -171,10 +203,15 @@ while True:
     
     if button_b.was_pressed():
         display.scroll("B pressed!")
-"#;
+"#
+        }
+    };
     
     let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
-    Ok(template.replace("{}", &timestamp.to_string()))
+    Ok(template
+        .replace("{}", &timestamp.to_string())
+        .replace("{led_pin}", if device_type == "ESP32" { "2" } else { "\"LED\"" })
+        .replace("{boot_marker}", BOOT_MARKER))
 }
 
 #[command]
@@ -227,4 +264,18 @@ pub async fn get_available_blocks(device_type: String, language: String) -> Resu
     };
     
     Ok(blocks)
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_template_prints_boot_marker() {
+        for device_type in ["Arduino", "ESP32", "Unknown"] {
+            assert!(generate_arduino_code_from_xml("<xml/>", device_type).unwrap().contains(BOOT_MARKER));
+        }
+        for device_type in ["MicroBit", "ESP32", "RaspberryPiPico"] {
+            assert!(generate_micropython_code_from_xml("<xml/>", device_type).unwrap().contains(BOOT_MARKER));
+        }
+    }
+}
@@ -1,34 +1,144 @@
 use crate::device::{
     DeviceInfo, DeviceType, UploadOptions, 
     detector::{DeviceDetector, DeviceStatus},
+    hotplug::HotplugWatcher,
     driver::DriverInfo,
+    diagnostics::{self, SerialDiagnostics},
+    board_db::board_db,
     uploader::DeviceUploader,
-    serial::SerialManager,
+    interactive::InteractiveManager,
+    serial_macro::MacroRunner,
+    reset::{self, ResetMethod},
+    serial::{PortUser, SerialManager},
+    repl::probe_micropython,
+    upload_queue::{BatchUploadResult, UploadJobStatus, UploadQueue},
+    boards::{BoardConfig, DETECTED_VARIANT_KEY},
+    connection_manager::DeviceProfile,
+    probe,
+    autobaud::{self, AutoBaudResult, COMMON_BAUD_RATES, DEFAULT_LISTEN_TIME},
 };
 use anyhow::Result;
+use futures_util::future::join_all;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
-use tauri::{command, State};
+use tauri::{command, AppHandle, Emitter, Manager, State};
 use log::{info, error};
 
 // 全局设备检测器状态
 pub type DeviceDetectorState = Mutex<DeviceDetector>;
-pub type DeviceUploaderState = Mutex<DeviceUploader>;
-pub type SerialManagerState = Mutex<SerialManager>;
+pub type DeviceUploaderState = Arc<DeviceUploader>;
+pub type SerialManagerState = Arc<SerialManager>;
+pub type UploadQueueState = Arc<UploadQueue>;
+pub type InteractiveManagerState = Arc<InteractiveManager>;
+pub type MacroRunnerState = Arc<MacroRunner>;
 
 #[command]
 pub async fn scan_devices(
-    detector: State<'_, DeviceDetectorState>
+    probe: Option<bool>,
+    detector: State<'_, DeviceDetectorState>,
+    serial: State<'_, SerialManagerState>
 ) -> Result<Vec<DeviceInfo>, String> {
     info!("前端请求扫描设备");
     
-    let mut detector = detector.lock().await;
+    let candidates = {
+        let mut detector = detector.lock().await;
+        let devices = detector.scan_devices().map_err(|e| {
+            error!("扫描设备失败: {}", e);
+            format!("设备扫描失败: {}", e)
+        })?;
+        if !probe.unwrap_or(false) {
+            return Ok(devices);
+        }
+        detector.probe_candidates()
+    };
+    
+    // 通用USB转串口芯片的板子逐个主动探测，结果按序列号缓存，下次扫描直接使用
+    probe_and_record(&detector, &serial, candidates).await;
+    Ok(detector.lock().await.list_devices())
+}
+
+/// 主动探测指定设备（忽略缓存的结果），返回修正后的设备信息
+#[command]
+pub async fn probe_devices(
+    device_ids: Vec<String>,
+    detector: State<'_, DeviceDetectorState>,
+    serial: State<'_, SerialManagerState>
+) -> Result<Vec<DeviceInfo>, String> {
+    let devices = {
+        let detector = detector.lock().await;
+        device_ids.iter()
+            .map(|id| detector.get_device(id).cloned().ok_or_else(|| format!("未找到设备: {}", id)))
+            .collect::<Result<Vec<_>, String>>()?
+    };
+    Ok(probe_and_record(&detector, &serial, devices).await)
+}
+
+/// 占用各个设备的串口并行探测，保存结果
+async fn probe_and_record(detector: &DeviceDetectorState, serial: &SerialManager, devices: Vec<DeviceInfo>) -> Vec<DeviceInfo> {
+    let probes = devices.into_iter().map(|device| async move {
+        let result = match serial.acquire(&device.port, PortUser::Probe).await {
+            Ok(_lease) => {
+                let port = device.port.clone();
+                tokio::task::spawn_blocking(move || probe::probe_port(&port)).await.map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        (device, result)
+    });
     
-    detector.scan_devices().map_err(|e| {
-        error!("扫描设备失败: {}", e);
-        format!("设备扫描失败: {}", e)
+    let mut probed = Vec::new();
+    for (device, result) in join_all(probes).await {
+        match result {
+            Ok(Some(result)) => probed.extend(detector.lock().await.record_probe(&device.id, result)),
+            Ok(None) => detector.lock().await.clear_probe(&device.id),
+            Err(e) => error!("探测设备 {} 失败: {}", device.port, e),
+        }
+    }
+    probed
+}
+
+/// 启动USB插拔监视
+///
+/// 串口列表变化时重新扫描设备并发送 `device-added`、`device-removed`、`device-changed` 事件；
+/// 拔出的设备标记为未连接，自动重连任务据此开始重连。
+pub fn start_hotplug_watcher(app: AppHandle) -> HotplugWatcher {
+    HotplugWatcher::spawn(move || {
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            let detector = app.state::<DeviceDetectorState>();
+            let (changes, connections) = {
+                let mut detector = detector.lock().await;
+                match detector.rescan() {
+                    Ok(changes) => (changes, detector.connection_manager()),
+                    Err(e) => {
+                        error!("插拔后重新扫描设备失败: {}", e);
+                        return;
+                    }
+                }
+            };
+            
+            for change in changes {
+                let device = change.device();
+                info!("{}: {} ({})", change.event_name(), device.name, device.port);
+                change.update_connection(&connections).await;
+                let _ = app.emit(change.event_name(), device);
+            }
+        });
     })
 }
 
+/// 在设备列表中显示或隐藏模拟Arduino（无硬件时用于测试和演示）
+#[command]
+pub async fn set_virtual_devices_enabled(
+    enabled: bool,
+    detector: State<'_, DeviceDetectorState>
+) -> Result<(), String> {
+    detector.lock().await.set_virtual_devices(enabled);
+    Ok(())
+}
+
 #[command]
 pub async fn connect_device(
     device_id: String,
@@ -76,84 +186,383 @@ pub async fn disconnect_device(
 
 #[command]
 pub async fn upload_code(
-    options: UploadOptions,
+    mut options: UploadOptions,
     detector: State<'_, DeviceDetectorState>,
-    uploader: State<'_, DeviceUploaderState>
+    uploader: State<'_, DeviceUploaderState>,
+    queue: State<'_, UploadQueueState>,
+    serial: State<'_, SerialManagerState>
 ) -> Result<String, String> {
     info!("前端请求上传代码到设备: {}", options.device_id);
     
-    let detector = detector.lock().await;
-    let uploader = uploader.lock().await;
+    // 只在检查阶段持有检测器锁，上传过程中其他命令不受影响
+    let device = {
+        let detector = detector.lock().await;
+        let device = check_upload_target(&detector, &options.device_id, &options.language)?;
+        if options.board_variant.is_none() {
+            options.board_variant = profile_board_variant(&detector, &device.id).await;
+        }
+        device
+    };
     
-    match detector.get_device(&options.device_id) {
-        Some(device) => {
-            info!("开始上传代码到设备: {}", device.name);
-            
-            // 检查设备是否支持指定的编程语言
-            if !detector.supports_language(&options.device_id, &options.language) {
-                let recommended = detector.get_recommended_language(&options.device_id)
-                    .unwrap_or("arduino");
-                return Err(format!(
-                    "设备 {} 不支持 {} 语言，推荐使用 {}", 
-                    device.name, options.language, recommended
-                ));
-            }
-            
-            // 检查设备是否准备就绪（驱动已安装）
-            if !detector.is_device_ready(&options.device_id) {
-                return Err(format!("设备 {} 驱动未安装或未准备就绪", device.name));
-            }
-            
-            // 根据设备类型和语言选择合适的上传方式
-            match (&device.device_type, options.language.as_str()) {
-                (DeviceType::Arduino, "arduino") => {
-                    upload_arduino_code(&options, device, &uploader).await
-                },
-                (DeviceType::MicroBit, "micropython") => {
-                    upload_micropython_code(&options, device, &uploader).await
-                },
-                (DeviceType::ESP32, "arduino") => {
-                    upload_arduino_code(&options, device, &uploader).await
-                },
-                (DeviceType::ESP32, "micropython") => {
-                    upload_micropython_code(&options, device, &uploader).await
-                },
-                (DeviceType::RaspberryPiPico, "arduino") => {
-                    upload_arduino_code(&options, device, &uploader).await
-                },
-                (DeviceType::RaspberryPiPico, "micropython") => {
-                    upload_micropython_code(&options, device, &uploader).await
-                },
-                _ => {
-                    Err("不支持的设备类型和语言组合".to_string())
-                }
+    info!("开始上传代码到设备: {}", device.name);
+    let is_micropython = device.device_type != DeviceType::Arduino && options.language != "arduino";
+    let result = queue.run_upload(Arc::clone(&uploader), device.clone(), options).await;
+    
+    match result {
+        Ok(outcome) => {
+            if let Some(variant) = &outcome.fallback_variant {
+                let detector = detector.lock().await;
+                remember_board_variant(&detector, &device, variant).await;
             }
+            Ok(outcome.message)
         },
-        None => {
-            error!("未找到设备: {}", options.device_id);
-            Err(format!("未找到设备: {}", options.device_id))
+        Err(e) if is_micropython => {
+            Err(explain_micropython_failure(&serial, &device, e).await)
+        },
+        Err(e) => {
+            error!("代码上传失败: {}", e);
+            Err(format!("代码上传失败: {}", e))
         }
     }
 }
 
-async fn upload_arduino_code(options: &UploadOptions, device: &DeviceInfo, uploader: &DeviceUploader) -> Result<String, String> {
-    info!("上传Arduino代码到设备: {}", device.name);
+/// 检查设备是否存在、支持该语言并且驱动就绪
+pub(crate) fn check_upload_target(detector: &DeviceDetector, device_id: &str, language: &str) -> Result<DeviceInfo, String> {
+    let device = detector.get_device(device_id).ok_or_else(|| {
+        error!("未找到设备: {}", device_id);
+        format!("未找到设备: {}", device_id)
+    })?;
+    
+    // 检查设备是否支持指定的编程语言
+    if !detector.supports_language(device_id, language) {
+        let recommended = detector.get_recommended_language(device_id)
+            .unwrap_or("arduino");
+        return Err(format!(
+            "设备 {} 不支持 {} 语言，推荐使用 {}", 
+            device.name, language, recommended
+        ));
+    }
+    
+    // 检查设备是否准备就绪（驱动已安装）
+    if !detector.is_device_ready(device_id) {
+        return Err(format!("设备 {} 驱动未安装或未准备就绪", device.name));
+    }
     
-    uploader.upload_arduino_code(options).await.map_err(|e| {
-        error!("Arduino代码上传失败: {}", e);
-        format!("Arduino代码上传失败: {}", e)
+    Ok(device.clone())
+}
+
+/// 设备关联的配置文件中的开发板型号，用户选择的优先，其次是上传时自动识别出的
+pub(crate) async fn profile_board_variant(detector: &DeviceDetector, device_id: &str) -> Option<String> {
+    let profile = detector.connection_manager().get_device_profile(device_id).await?;
+    profile.board_variant.or_else(|| {
+        profile.custom_settings
+            .get(DETECTED_VARIANT_KEY)
+            .and_then(|v| v.as_str())
+            .map(|v| v.to_string())
     })
 }
 
-async fn upload_micropython_code(options: &UploadOptions, device: &DeviceInfo, uploader: &DeviceUploader) -> Result<String, String> {
-    info!("上传MicroPython代码到设备: {}", device.name);
+/// 设备关联的配置文件ID，设备没有配置文件时新建一个
+pub(crate) async fn ensure_device_profile(detector: &DeviceDetector, device: &DeviceInfo) -> Option<String> {
+    let manager = detector.connection_manager();
+    if let Some(profile) = manager.get_device_profile(&device.id).await {
+        return Some(profile.id);
+    }
+    
+    let profile = DeviceProfile::new(device.name.clone(), device.device_type.clone());
+    let profile_id = match manager.create_profile(profile).await {
+        Ok(id) => id,
+        Err(e) => {
+            error!("创建配置文件失败: {}", e);
+            return None;
+        }
+    };
+    if let Err(e) = manager.associate_device_profile(&device.id, &profile_id).await {
+        error!("关联配置文件失败: {}", e);
+        return None;
+    }
+    Some(profile_id)
+}
+
+/// 把自动识别出的开发板型号记到设备的配置文件中，设备没有配置文件时新建一个
+pub async fn remember_board_variant(detector: &DeviceDetector, device: &DeviceInfo, variant: &str) {
+    let Some(profile_id) = ensure_device_profile(detector, device).await else {
+        return;
+    };
+    
+    let mut updates = HashMap::new();
+    updates.insert(DETECTED_VARIANT_KEY.to_string(), serde_json::Value::String(variant.to_string()));
+    match detector.connection_manager().update_profile(&profile_id, updates).await {
+        Ok(()) => info!("设备 {} 记住开发板型号: {}", device.name, variant),
+        Err(e) => error!("保存开发板型号失败: {}", e),
+    }
+}
+
+/// MicroPython上传失败时检查板子上是否有固件，给出明确的提示
+async fn explain_micropython_failure(serial: &SerialManager, device: &DeviceInfo, e: anyhow::Error) -> String {
+    error!("MicroPython代码上传失败: {}", e);
+    
+    // 新买的板子通常还没有MicroPython固件
+    let _lease = match serial.acquire(&device.port, PortUser::Probe).await {
+        Ok(lease) => lease,
+        Err(_) => return format!("MicroPython代码上传失败: {}", e),
+    };
+    let port = device.port.clone();
+    let probe = tokio::task::spawn_blocking(move || {
+        probe_micropython(&port, Duration::from_secs(2))
+    }).await;
+    
+    if !matches!(probe, Ok(Ok(_))) {
+        return format!(
+            "设备 {} 上没有检测到MicroPython固件，请先安装MicroPython固件后再上传",
+            device.name
+        );
+    }
+    format!("MicroPython代码上传失败: {}", e)
+}
+
+/// 把同一个项目上传到所有已连接的同类型板子（老师批量准备课堂用板）
+#[command]
+pub async fn flash_project_to_boards(
+    app: AppHandle,
+    device_type: DeviceType,
+    code: String,
+    language: String,
+    board_type: String,
+    board_variant: Option<String>,
+    detector: State<'_, DeviceDetectorState>,
+    uploader: State<'_, DeviceUploaderState>,
+    queue: State<'_, UploadQueueState>
+) -> Result<Vec<BatchUploadResult>, String> {
+    info!("批量上传到所有 {:?} 设备", device_type);
+    
+    let (targets, mut results) = {
+        let mut detector = detector.lock().await;
+        detector.scan_devices().map_err(|e| format!("设备扫描失败: {}", e))?;
+        
+        let mut targets = Vec::new();
+        let mut skipped = Vec::new();
+        for device in detector.list_devices().into_iter().filter(|d| d.device_type == device_type) {
+            match check_upload_target(&detector, &device.id, &language) {
+                Ok(device) => {
+                    let variant = match &board_variant {
+                        Some(variant) => Some(variant.clone()),
+                        None => profile_board_variant(&detector, &device.id).await,
+                    };
+                    targets.push((device, variant));
+                },
+                Err(message) => skipped.push(BatchUploadResult {
+                    device_id: device.id.clone(),
+                    device_name: device.name.clone(),
+                    port: device.port.clone(),
+                    success: false,
+                    message,
+                    duration_ms: 0,
+                }),
+            }
+        }
+        (targets, skipped)
+    };
+    
+    if targets.is_empty() && results.is_empty() {
+        return Err(format!("没有找到已连接的 {:?} 设备", device_type));
+    }
+    
+    let total = targets.len();
+    let uploads = targets.into_iter().map(|(device, board_variant)| {
+        let options = UploadOptions {
+            device_id: device.id.clone(),
+            code: code.clone(),
+            language: language.clone(),
+            board_type: board_type.clone(),
+            board_variant,
+        };
+        let uploader = Arc::clone(&uploader);
+        let queue = Arc::clone(&queue);
+        let app = app.clone();
+        async move {
+            let started = Instant::now();
+            let result = queue.run_upload(uploader, device.clone(), options).await;
+            let outcome = BatchUploadResult::from_result(&device, &result, started);
+            let _ = app.emit("batch-upload-progress", &outcome);
+            let fallback_variant = result.ok().and_then(|r| r.fallback_variant);
+            (device, outcome, fallback_variant)
+        }
+    });
+    
+    let outcomes = join_all(uploads).await;
+    {
+        let detector = detector.lock().await;
+        for (device, _, fallback_variant) in &outcomes {
+            if let Some(variant) = fallback_variant {
+                remember_board_variant(&detector, device, variant).await;
+            }
+        }
+    }
+    results.extend(outcomes.into_iter().map(|(_, outcome, _)| outcome));
+    let succeeded = results.iter().filter(|r| r.success).count();
+    info!("批量上传完成: {}/{} 成功", succeeded, total);
     
-    uploader.upload_micropython_code(options).await.map_err(|e| {
-        error!("MicroPython代码上传失败: {}", e);
-        format!("MicroPython代码上传失败: {}", e)
+    Ok(results)
+}
+
+/// 列出开发板型号，可按设备类型过滤
+#[command]
+pub async fn list_boards(
+    device_type: Option<DeviceType>,
+    uploader: State<'_, DeviceUploaderState>
+) -> Result<Vec<BoardConfig>, String> {
+    let catalog = uploader.board_catalog();
+    Ok(match device_type {
+        Some(device_type) => catalog.for_device_type(&device_type),
+        None => catalog.all().to_vec(),
     })
 }
 
+/// 推断设备的开发板型号
+///
+/// 配置文件中选择的型号优先，其次按VID/PID；`probe` 为真时通过ESP引导程序读取芯片型号。
+#[command]
+pub async fn detect_board_variant(
+    device_id: String,
+    probe: Option<bool>,
+    detector: State<'_, DeviceDetectorState>,
+    uploader: State<'_, DeviceUploaderState>,
+    serial: State<'_, SerialManagerState>
+) -> Result<BoardConfig, String> {
+    let (device, variant) = {
+        let detector = detector.lock().await;
+        let device = detector.get_device(&device_id)
+            .cloned()
+            .ok_or_else(|| format!("未找到设备: {}", device_id))?;
+        let variant = profile_board_variant(&detector, &device_id).await;
+        (device, variant)
+    };
+    
+    let variant = match variant {
+        Some(variant) => Some(variant),
+        None if probe.unwrap_or(false) && device.device_type == DeviceType::ESP32 => {
+            let port = device.port.clone();
+            let _lease = serial.acquire(&port, PortUser::Probe).await.map_err(|e| e.to_string())?;
+            tokio::task::spawn_blocking(move || probe::probe_esp_chip(&port))
+                .await
+                .map_err(|e| format!("探测任务失败: {}", e))?
+                .and_then(probe::esp_chip_variant)
+                .map(|variant| variant.to_string())
+        },
+        None => None,
+    };
+    
+    uploader.board_catalog()
+        .resolve(variant.as_deref(), &device)
+        .cloned()
+        .map_err(|e| e.to_string())
+}
+
+/// 自动检测设备串口输出的波特率
+///
+/// 依次在常用波特率下监听设备输出并评分，设备需要正在打印数据。
+/// `save_to_profile` 为真时把结果保存到设备配置文件的 `baud_rate`。
+#[command]
+pub async fn detect_baud_rate(
+    device_id: String,
+    rates: Option<Vec<u32>>,
+    save_to_profile: Option<bool>,
+    detector: State<'_, DeviceDetectorState>,
+    serial: State<'_, SerialManagerState>
+) -> Result<AutoBaudResult, String> {
+    let device = detector.lock().await
+        .get_device(&device_id)
+        .cloned()
+        .ok_or_else(|| format!("未找到设备: {}", device_id))?;
+    
+    let result = {
+        let _lease = serial.acquire(&device.port, PortUser::Probe).await.map_err(|e| e.to_string())?;
+        let port = device.port.clone();
+        let rates = rates.unwrap_or_else(|| COMMON_BAUD_RATES.to_vec());
+        tokio::task::spawn_blocking(move || autobaud::detect_baud_rate(&port, &rates, DEFAULT_LISTEN_TIME))
+            .await
+            .map_err(|e| format!("检测任务失败: {}", e))?
+            .map_err(|e| {
+                error!("自动检测波特率失败: {}", e);
+                e.to_string()
+            })?
+    };
+    
+    if let Some(baud_rate) = result.baud_rate {
+        let mut detector = detector.lock().await;
+        detector.set_detected_baud_rate(&device_id, baud_rate);
+        if save_to_profile.unwrap_or(false) {
+            if let Some(profile_id) = ensure_device_profile(&detector, &device).await {
+                let mut updates = HashMap::new();
+                updates.insert("baud_rate".to_string(), serde_json::Value::from(baud_rate));
+                detector.connection_manager().update_profile(&profile_id, updates).await
+                    .map_err(|e| format!("保存波特率失败: {}", e))?;
+                info!("设备 {} 的波特率 {} 已保存到配置文件", device.name, baud_rate);
+            }
+        }
+    }
+    
+    Ok(result)
+}
+
+/// 复位开发板
+///
+/// 不指定 `method` 时按设备类型和开发板型号选择（Leonardo用1200波特率复位，ESP32用EN复位等）。
+/// 复位期间暂停串口监视器，完成后自动恢复。
+#[command]
+pub async fn reset_board(
+    device_id: String,
+    method: Option<ResetMethod>,
+    detector: State<'_, DeviceDetectorState>,
+    uploader: State<'_, DeviceUploaderState>,
+    serial: State<'_, SerialManagerState>
+) -> Result<ResetMethod, String> {
+    let (device, baud_rate, variant) = {
+        let detector = detector.lock().await;
+        let device = detector.get_device(&device_id)
+            .cloned()
+            .ok_or_else(|| format!("未找到设备: {}", device_id))?;
+        let variant = profile_board_variant(&detector, &device_id).await;
+        (device, detector.get_baud_rate(&device_id), variant)
+    };
+    let board = uploader.board_catalog().resolve(variant.as_deref(), &device).ok();
+    let method = method.unwrap_or_else(|| ResetMethod::default_for(&device.device_type, board));
+    
+    let mut lease = serial.acquire(&device.port, PortUser::Reset).await.map_err(|e| e.to_string())?;
+    if let Some(board) = board {
+        lease.set_resume_timeout(board.resume_timeout());
+    }
+    let port = device.port.clone();
+    tokio::task::spawn_blocking(move || reset::reset_board(&port, baud_rate, method))
+        .await
+        .map_err(|e| format!("复位任务失败: {}", e))?
+        .map_err(|e| {
+            error!("复位开发板失败: {}", e);
+            format!("复位开发板失败: {}", e)
+        })?;
+    Ok(method)
+}
+
+#[command]
+pub async fn get_upload_jobs(
+    queue: State<'_, UploadQueueState>
+) -> Result<Vec<UploadJobStatus>, String> {
+    Ok(queue.list_jobs().await)
+}
+
+#[command]
+pub async fn set_upload_concurrency(
+    limit: usize,
+    queue: State<'_, UploadQueueState>
+) -> Result<(), String> {
+    if limit == 0 {
+        return Err("并发数必须大于0".to_string());
+    }
+    queue.set_concurrency_limit(limit).await;
+    Ok(())
+}
+
 // 新增的设备管理命令
 
 #[command]
@@ -202,6 +611,29 @@ pub async fn get_available_drivers(
     Ok(detector.get_available_drivers())
 }
 
+#[command]
+pub async fn diagnose_serial_permissions() -> Result<SerialDiagnostics, String> {
+    info!("诊断串口权限");
+    tokio::task::spawn_blocking(diagnostics::diagnose)
+        .await
+        .map_err(|e| format!("诊断串口权限失败: {}", e))
+}
+
+#[command]
+pub async fn get_udev_rules() -> Result<String, String> {
+    Ok(diagnostics::generate_udev_rules(board_db()))
+}
+
+#[command]
+pub async fn install_udev_rules() -> Result<String, String> {
+    info!("安装udev规则: {}", diagnostics::UDEV_RULES_PATH);
+    let rules = diagnostics::generate_udev_rules(board_db());
+    diagnostics::install_udev_rules(&rules).await.map_err(|e| {
+        error!("安装udev规则失败: {}", e);
+        format!("安装udev规则失败: {}", e)
+    })
+}
+
 #[command]
 pub async fn get_installed_drivers(
     detector: State<'_, DeviceDetectorState>
@@ -217,7 +649,6 @@ pub async fn get_arduino_libraries(
 ) -> Result<Vec<String>, String> {
     info!("获取Arduino库列表");
     
-    let uploader = uploader.lock().await;
     uploader.list_arduino_libraries().await.map_err(|e| {
         error!("获取Arduino库列表失败: {}", e);
         format!("获取Arduino库列表失败: {}", e)
@@ -231,7 +662,6 @@ pub async fn install_arduino_library(
 ) -> Result<String, String> {
     info!("安装Arduino库: {}", library_name);
     
-    let uploader = uploader.lock().await;
     uploader.install_arduino_library(&library_name).await.map_err(|e| {
         error!("安装Arduino库失败: {}", e);
         format!("安装Arduino库失败: {}", e)
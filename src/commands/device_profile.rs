@@ -13,6 +13,7 @@ pub struct CreateProfileRequest {
     pub name: String,
     pub device_type: DeviceType,
     pub preferred_language: Option<String>,
+    pub board_variant: Option<String>,
     pub baud_rate: Option<u32>,
     pub auto_reconnect: Option<bool>,
     pub reconnect_interval_ms: Option<u64>,
@@ -34,6 +35,9 @@ pub async fn create_device_profile(
     if let Some(lang) = request.preferred_language {
         profile.preferred_language = lang;
     }
+    if let Some(variant) = request.board_variant {
+        profile.board_variant = Some(variant);
+    }
     if let Some(baud) = request.baud_rate {
         profile.baud_rate = baud;
     }
@@ -0,0 +1,236 @@
+use crate::commands::device::{
+    check_upload_target, profile_board_variant,
+    DeviceDetectorState, DeviceUploaderState, InteractiveManagerState, UploadQueueState,
+};
+use crate::device::firmata::{FirmataClient, FirmataEvent, FirmataEventSink, FirmwareInfo, PinMode};
+use anyhow::Result;
+use log::{info, error};
+use std::sync::Arc;
+use tauri::{command, AppHandle, Emitter, State};
+
+/// 把开发板的输入上报转发给前端
+fn event_sink(app: AppHandle) -> FirmataEventSink {
+    Arc::new(move |event: FirmataEvent| {
+        let _ = app.emit("firmata-report", &event);
+    })
+}
+
+/// 在交互模式的开发板上执行一个动作
+async fn with_client<T>(
+    interactive: &InteractiveManagerState,
+    port: &str,
+    action: impl FnOnce(&FirmataClient) -> Result<T>,
+) -> Result<T, String> {
+    let client = interactive.client(port).await.map_err(|e| e.to_string())?;
+    action(&client).map_err(|e| {
+        error!("{}", e);
+        e.to_string()
+    })
+}
+
+/// 进入交互模式
+///
+/// 开发板没有运行StandardFirmata时先自动上传，之后积木的动作立即在开发板上执行，
+/// 输入引脚的变化通过 `firmata-report` 事件推送。
+#[command]
+pub async fn start_interactive_mode(
+    app: AppHandle,
+    device_id: String,
+    board_variant: Option<String>,
+    force_upload: Option<bool>,
+    detector: State<'_, DeviceDetectorState>,
+    uploader: State<'_, DeviceUploaderState>,
+    queue: State<'_, UploadQueueState>,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<FirmwareInfo, String> {
+    info!("进入交互模式: {}", device_id);
+    
+    let (device, board_variant) = {
+        let detector = detector.lock().await;
+        let device = check_upload_target(&detector, &device_id, "arduino")?;
+        let variant = match board_variant {
+            Some(variant) => Some(variant),
+            None => profile_board_variant(&detector, &device_id).await,
+        };
+        (device, variant)
+    };
+    
+    interactive.start(
+        &device,
+        board_variant,
+        Arc::clone(&uploader),
+        &queue,
+        force_upload.unwrap_or(false),
+        event_sink(app),
+    ).await.map_err(|e| {
+        error!("进入交互模式失败: {}", e);
+        e.to_string()
+    })
+}
+
+#[command]
+pub async fn stop_interactive_mode(
+    port: String,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    interactive.stop(&port).await.map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn get_interactive_ports(
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<Vec<String>, String> {
+    Ok(interactive.active_ports().await)
+}
+
+#[command]
+pub async fn firmata_set_pin_mode(
+    port: String,
+    pin: u8,
+    mode: PinMode,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    with_client(&interactive, &port, |client| client.set_pin_mode(pin, mode)).await
+}
+
+#[command]
+pub async fn firmata_digital_write(
+    port: String,
+    pin: u8,
+    value: bool,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    with_client(&interactive, &port, |client| client.digital_write(pin, value)).await
+}
+
+/// PWM输出，`value` 为0-255（Uno等8位PWM）
+#[command]
+pub async fn firmata_analog_write(
+    port: String,
+    pin: u8,
+    value: u32,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    with_client(&interactive, &port, |client| client.analog_write(pin, value)).await
+}
+
+/// 开启数字引脚上报，引脚需要先设置为输入模式
+#[command]
+pub async fn firmata_report_digital(
+    port: String,
+    pin: u8,
+    enable: bool,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    with_client(&interactive, &port, |client| client.report_digital(pin, enable)).await
+}
+
+/// 开启模拟通道上报，`channel` 为模拟通道号（A0为0）
+#[command]
+pub async fn firmata_report_analog(
+    port: String,
+    channel: u8,
+    enable: bool,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    with_client(&interactive, &port, |client| {
+        if enable {
+            if let Some(pin) = client.analog_pin(channel) {
+                client.set_pin_mode(pin, PinMode::Analog)?;
+            }
+        }
+        client.report_analog(channel, enable)
+    }).await
+}
+
+/// 最近一次上报的数字引脚电平，没有开启上报时为空
+#[command]
+pub async fn firmata_digital_read(
+    port: String,
+    pin: u8,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<Option<bool>, String> {
+    with_client(&interactive, &port, |client| Ok(client.digital_value(pin))).await
+}
+
+/// 最近一次上报的模拟通道数值（0-1023），没有开启上报时为空
+#[command]
+pub async fn firmata_analog_read(
+    port: String,
+    channel: u8,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<Option<u16>, String> {
+    with_client(&interactive, &port, |client| Ok(client.analog_value(channel))).await
+}
+
+/// 舵机转到指定角度，可选设置脉宽范围（微秒）
+#[command]
+pub async fn firmata_servo_write(
+    port: String,
+    pin: u8,
+    angle: u16,
+    min_pulse: Option<u16>,
+    max_pulse: Option<u16>,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    with_client(&interactive, &port, |client| {
+        match (min_pulse, max_pulse) {
+            (Some(min), Some(max)) => client.servo_config(pin, min, max)?,
+            _ => client.set_pin_mode(pin, PinMode::Servo)?,
+        }
+        client.servo_write(pin, angle)
+    }).await
+}
+
+#[command]
+pub async fn firmata_i2c_config(
+    port: String,
+    delay_us: Option<u16>,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    with_client(&interactive, &port, |client| client.i2c_config(delay_us.unwrap_or(0))).await
+}
+
+#[command]
+pub async fn firmata_i2c_write(
+    port: String,
+    address: u8,
+    data: Vec<u8>,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    with_client(&interactive, &port, |client| client.i2c_write(address, &data)).await
+}
+
+/// 读取I2C设备，结果通过 `firmata-report` 事件（`i2cReply`）返回
+#[command]
+pub async fn firmata_i2c_read(
+    port: String,
+    address: u8,
+    register: Option<u16>,
+    length: u16,
+    continuous: Option<bool>,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    with_client(&interactive, &port, |client| {
+        client.i2c_read(address, register, length, continuous.unwrap_or(false))
+    }).await
+}
+
+#[command]
+pub async fn firmata_i2c_stop_reading(
+    port: String,
+    address: u8,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    with_client(&interactive, &port, |client| client.i2c_stop_reading(address)).await
+}
+
+/// 设置模拟通道和I2C连续读取的上报间隔（毫秒）
+#[command]
+pub async fn firmata_set_sampling_interval(
+    port: String,
+    interval_ms: u16,
+    interactive: State<'_, InteractiveManagerState>
+) -> Result<(), String> {
+    with_client(&interactive, &port, |client| client.set_sampling_interval(interval_ms)).await
+}
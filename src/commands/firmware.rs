@@ -0,0 +1,98 @@
+use crate::device::{
+    firmware::{default_board_for, install_firmware, FirmwareCatalog, FirmwareInstallResult, FirmwareStore},
+    repl::{probe_micropython, ReplBanner},
+    serial::PortUser,
+    uploader::UploadProgress,
+};
+use crate::commands::device::{DeviceDetectorState, SerialManagerState};
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::{command, AppHandle, Emitter, State};
+use log::{info, error};
+
+#[command]
+pub async fn list_firmware_catalog() -> Result<FirmwareCatalog, String> {
+    info!("获取固件库列表");
+
+    let store = FirmwareStore::open().map_err(|e| format!("打开固件库失败: {}", e))?;
+    store.load_catalog().map_err(|e| {
+        error!("读取固件清单失败: {}", e);
+        format!("读取固件清单失败: {}", e)
+    })
+}
+
+/// 从目录（例如老师准备好的U盘）导入固件清单和固件
+#[command]
+pub async fn import_firmware_catalog(source_dir: String) -> Result<usize, String> {
+    info!("导入固件库: {}", source_dir);
+
+    let store = FirmwareStore::open().map_err(|e| format!("打开固件库失败: {}", e))?;
+    store.import_from_dir(&PathBuf::from(source_dir)).map_err(|e| {
+        error!("导入固件失败: {}", e);
+        format!("导入固件失败: {}", e)
+    })
+}
+
+/// 为空白板子安装MicroPython固件
+///
+/// `device_id` 为空时按 `board` 烧录（例如处于BOOTSEL模式、没有串口的Pico）。
+#[command]
+pub async fn install_micropython_firmware(
+    app: AppHandle,
+    device_id: Option<String>,
+    board: Option<String>,
+    version: Option<String>,
+    detector: State<'_, DeviceDetectorState>,
+    serial: State<'_, SerialManagerState>
+) -> Result<FirmwareInstallResult, String> {
+    info!("安装MicroPython固件: 设备 {:?}, 板卡 {:?}, 版本 {:?}", device_id, board, version);
+
+    let (port, board) = {
+        let detector = detector.lock().await;
+        match &device_id {
+            Some(id) => {
+                let device = detector.get_device(id)
+                    .ok_or_else(|| format!("未找到设备: {}", id))?;
+                let board = board
+                    .or_else(|| default_board_for(&device.device_type).map(|b| b.to_string()))
+                    .ok_or_else(|| format!("设备 {} 不支持MicroPython", device.name))?;
+                (Some(device.port.clone()), board)
+            }
+            None => (None, board.ok_or_else(|| "请指定设备或板卡类型".to_string())?),
+        }
+    };
+
+    let store = FirmwareStore::open().map_err(|e| format!("打开固件库失败: {}", e))?;
+    let entry = store.find(&board, version.as_deref()).map_err(|e| e.to_string())?;
+
+    let _lease = match &port {
+        Some(port) => Some(serial.acquire(port, PortUser::Flash).await.map_err(|e| e.to_string())?),
+        None => None,
+    };
+
+    tokio::task::spawn_blocking(move || {
+        install_firmware(&store, &entry, port.as_deref(), |stage, progress| {
+            let _ = app.emit("firmware-progress", UploadProgress {
+                stage: stage.to_string(),
+                progress,
+                message: format!("{} {}", entry.board, entry.version),
+            });
+        })
+    })
+    .await
+    .map_err(|e| format!("固件安装任务失败: {}", e))?
+    .map_err(|e| {
+        error!("安装MicroPython固件失败: {}", e);
+        format!("安装MicroPython固件失败: {}", e)
+    })
+}
+
+/// 读取设备上MicroPython的版本
+#[command]
+pub async fn probe_micropython_version(port: String, serial: State<'_, SerialManagerState>) -> Result<ReplBanner, String> {
+    let _lease = serial.acquire(&port, PortUser::Repl).await.map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || probe_micropython(&port, Duration::from_secs(3)))
+        .await
+        .map_err(|e| format!("探测任务失败: {}", e))?
+        .map_err(|e| format!("没有检测到MicroPython: {}", e))
+}
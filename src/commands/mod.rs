@@ -7,6 +7,8 @@ pub mod code_gen;
 pub mod performance;
 pub mod serial;
 pub mod tools;
+pub mod firmware;
+pub mod firmata;
 
 use tauri::command;
 
@@ -1,18 +1,26 @@
 use anyhow::Result;
-use tauri::command;
+use tauri::{command, AppHandle, Emitter, State};
 use log::{info, error, debug};
-use serialport::{SerialPort, SerialPortType};
+use serialport::SerialPortType;
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use crate::commands::device::{ensure_device_profile, DeviceDetectorState, MacroRunnerState, SerialManagerState};
+use crate::device::DeviceInfo;
+use crate::device::detector::DeviceDetector;
+use crate::device::serial::SerialEventSink;
+use crate::device::reset::send_break;
+use crate::device::serial_codec::{ReadFormat, WriteOptions};
+use crate::device::serial_macro::{format_macros, parse_macros, MacroProgressSink, SerialMacro, MACROS_SETTING_KEY};
+use crate::device::serial_plot::PlotSample;
+use crate::device::serial_trigger::{TriggerRule, TriggerSet, TRIGGERS_SETTING_KEY};
+use crate::device::serial_reader::SerialReaderEvent;
+use crate::device::session::{ExportFormat, SessionInfo, SessionStore};
+use crate::device::transport::ControlLines;
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::time::Duration;
 
-// 全局串口连接管理
-lazy_static::lazy_static! {
-    static ref SERIAL_CONNECTIONS: Arc<Mutex<HashMap<String, Arc<Mutex<Box<dyn SerialPort>>>>>> = 
-        Arc::new(Mutex::new(HashMap::new()));
-}
-
 #[derive(serde::Serialize)]
 pub struct SerialPortInfo {
     pub port_name: String,
@@ -23,7 +31,6 @@ pub struct SerialPortInfo {
     pub manufacturer: Option<String>,
     pub product: Option<String>,
 }
-
 #[command]
 pub async fn list_serial_ports() -> Result<Vec<SerialPortInfo>, String> {
     info!("列出所有串口");
@@ -62,108 +69,114 @@ pub async fn list_serial_ports() -> Result<Vec<SerialPortInfo>, String> {
     Ok(port_infos)
 }
 
+/// 把串口服务的事件转发给前端
+fn event_sink(app: AppHandle) -> SerialEventSink {
+    Arc::new(move |event| {
+        let _ = match event {
+            SerialReaderEvent::Data(batch) => app.emit("serial-data", &batch),
+            SerialReaderEvent::Disconnected(disconnected) => app.emit("serial-disconnected", &disconnected),
+            SerialReaderEvent::Paused(handoff) => app.emit("serial-paused", &handoff),
+            SerialReaderEvent::Resumed(handoff) => app.emit("serial-resumed", &handoff),
+            SerialReaderEvent::Plot(plot) => app.emit("serial-plot", &plot),
+            SerialReaderEvent::Trigger(trigger) => app.emit("serial-trigger", &trigger),
+        };
+    })
+}
+
+/// 打开串口监视器并启动后台读取任务
+///
+/// 收到的数据按行批量通过 `serial-data` 事件推送，其中的数值通过 `serial-plot` 事件推送，拔掉USB线时发送 `serial-disconnected` 事件。
+/// 上传等操作占用串口时发送 `serial-paused`，结束后自动恢复并发送 `serial-resumed`。
+/// 设备配置文件中保存的触发规则随之生效。
 #[command]
-pub async fn connect_serial(port: String, baud_rate: u32) -> Result<(), String> {
+pub async fn connect_serial(
+    app: AppHandle,
+    port: String,
+    baud_rate: u32,
+    serial: State<'_, SerialManagerState>,
+    detector: State<'_, DeviceDetectorState>
+) -> Result<(), String> {
     info!("连接串口: {} @ {} baud", port, baud_rate);
     
-    // 检查是否已连接
-    {
-        let connections = SERIAL_CONNECTIONS.lock().await;
-        if connections.contains_key(&port) {
-            return Err(format!("串口 {} 已连接", port));
+    serial.open_monitor(&port, baud_rate, event_sink(app)).map_err(|e| {
+        error!("打开串口失败: {}", e);
+        e.to_string()
+    })?;
+    
+    let rules = {
+        let detector = detector.lock().await;
+        match detector.list_devices().into_iter().find(|d| d.port == port) {
+            Some(device) => profile_triggers(&detector, &device.id).await,
+            None => Vec::new(),
+        }
+    };
+    if !rules.is_empty() {
+        if let Err(e) = serial.set_triggers(&port, rules) {
+            error!("加载触发规则失败: {}", e);
         }
     }
     
-    // 创建串口连接
-    let serial_port = serialport::new(&port, baud_rate)
-        .timeout(Duration::from_millis(100))
-        .open()
-        .map_err(|e| {
-            error!("打开串口失败: {}", e);
-            match e.kind() {
-                serialport::ErrorKind::NoDevice => format!("设备未找到: {}", port),
-                serialport::ErrorKind::InvalidInput => format!("无效的参数"),
-                serialport::ErrorKind::Unknown => format!("未知错误: {}", e),
-                _ => format!("串口打开失败: {}", e),
-            }
-        })?;
-    
-    // 保存连接
-    let mut connections = SERIAL_CONNECTIONS.lock().await;
-    connections.insert(port.clone(), Arc::new(Mutex::new(serial_port)));
-    
     info!("串口 {} 连接成功", port);
     Ok(())
 }
 
 #[command]
-pub async fn disconnect_serial(port: String) -> Result<(), String> {
+pub async fn disconnect_serial(port: String, serial: State<'_, SerialManagerState>) -> Result<(), String> {
     info!("断开串口: {}", port);
     
-    let mut connections = SERIAL_CONNECTIONS.lock().await;
-    if connections.remove(&port).is_some() {
-        info!("串口 {} 已断开", port);
-        Ok(())
-    } else {
-        Err(format!("串口 {} 未连接", port))
-    }
+    // 等待读取线程退出后再返回
+    let manager = Arc::clone(&serial);
+    let name = port.clone();
+    tokio::task::spawn_blocking(move || manager.close_monitor(&name))
+        .await
+        .map_err(|e| format!("断开串口失败: {}", e))?
+        .map_err(|e| e.to_string())?;
+    info!("串口 {} 已断开", port);
+    Ok(())
 }
 
 #[command]
-pub async fn write_serial_data(port: String, data: String) -> Result<(), String> {
+pub async fn write_serial_data(
+    port: String,
+    data: String,
+    options: Option<WriteOptions>,
+    serial: State<'_, SerialManagerState>
+) -> Result<(), String> {
     debug!("向串口 {} 写入数据: {}", port, data);
     
-    let connections = SERIAL_CONNECTIONS.lock().await;
-    if let Some(serial_arc) = connections.get(&port) {
-        let mut serial = serial_arc.lock().await;
-        serial.write_all(data.as_bytes()).map_err(|e| {
-            error!("写入串口数据失败: {}", e);
-            format!("写入串口数据失败: {}", e)
-        })?;
-        serial.flush().map_err(|e| {
-            error!("刷新串口缓冲区失败: {}", e);
-            format!("刷新串口缓冲区失败: {}", e)
-        })?;
-        Ok(())
-    } else {
-        Err(format!("串口 {} 未连接", port))
-    }
+    let bytes = options.unwrap_or_default().encode(&data).map_err(|e| e.to_string())?;
+    serial.write(&port, &bytes).map_err(|e| {
+        error!("{}", e);
+        e.to_string()
+    })
 }
 
+/// 设置串口监视器的数据显示方式，之后的 `serial-data` 事件按新方式推送
 #[command]
-pub async fn read_serial_data(port: String) -> Result<String, String> {
-    let connections = SERIAL_CONNECTIONS.lock().await;
-    if let Some(serial_arc) = connections.get(&port) {
-        let mut serial = serial_arc.lock().await;
-        
-        let mut buffer = vec![0; 1024];
-        match serial.read(&mut buffer) {
-            Ok(n) => {
-                let data = String::from_utf8_lossy(&buffer[..n]).to_string();
-                if n > 0 {
-                    debug!("从串口 {} 读取 {} 字节", port, n);
-                }
-                Ok(data)
-            }
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::TimedOut {
-                    // 超时是正常的，返回空字符串
-                    Ok(String::new())
-                } else {
-                    error!("读取串口数据失败: {}", e);
-                    Err(format!("读取串口数据失败: {}", e))
-                }
-            }
-        }
+pub async fn set_serial_read_format(
+    port: String,
+    format: ReadFormat,
+    serial: State<'_, SerialManagerState>
+) -> Result<(), String> {
+    serial.set_read_format(&port, format).map_err(|e| {
+        error!("{}", e);
+        e.to_string()
+    })
+}
+
+/// 串口数据改为通过 `serial-data` 事件推送，保留该命令只为兼容旧的前端
+#[command]
+pub async fn read_serial_data(port: String, serial: State<'_, SerialManagerState>) -> Result<String, String> {
+    if serial.is_monitoring(&port) {
+        Ok(String::new())
     } else {
         Err(format!("串口 {} 未连接", port))
     }
 }
 
 #[command]
-pub async fn get_connected_ports() -> Result<Vec<String>, String> {
-    let connections = SERIAL_CONNECTIONS.lock().await;
-    Ok(connections.keys().cloned().collect())
+pub async fn get_connected_ports(serial: State<'_, SerialManagerState>) -> Result<Vec<String>, String> {
+    Ok(serial.monitored_ports())
 }
 
 #[command]
@@ -172,85 +185,366 @@ pub async fn set_serial_params(
     baud_rate: Option<u32>,
     data_bits: Option<u8>,
     stop_bits: Option<u8>,
-    parity: Option<String>
+    parity: Option<String>,
+    serial: State<'_, SerialManagerState>
 ) -> Result<(), String> {
+    use serialport::{DataBits, Parity, StopBits};
     info!("设置串口参数: {}", port);
     
-    let connections = SERIAL_CONNECTIONS.lock().await;
-    if let Some(serial_arc) = connections.get(&port) {
-        let mut serial = serial_arc.lock().await;
-        
+    let data_bits = match data_bits {
+        None => None,
+        Some(5) => Some(DataBits::Five),
+        Some(6) => Some(DataBits::Six),
+        Some(7) => Some(DataBits::Seven),
+        Some(8) => Some(DataBits::Eight),
+        Some(_) => return Err("无效的数据位数".to_string()),
+    };
+    let stop_bits = match stop_bits {
+        None => None,
+        Some(1) => Some(StopBits::One),
+        Some(2) => Some(StopBits::Two),
+        Some(_) => return Err("无效的停止位数".to_string()),
+    };
+    let parity = match parity.map(|p| p.to_lowercase()).as_deref() {
+        None => None,
+        Some("none") => Some(Parity::None),
+        Some("odd") => Some(Parity::Odd),
+        Some("even") => Some(Parity::Even),
+        Some(_) => return Err("无效的校验位设置".to_string()),
+    };
+    
+    serial.with_port(&port, |serial| {
         if let Some(baud) = baud_rate {
-            serial.set_baud_rate(baud).map_err(|e| {
-                error!("设置波特率失败: {}", e);
-                format!("设置波特率失败: {}", e)
-            })?;
+            serial.set_baud_rate(baud)?;
         }
-        
-        if let Some(bits) = data_bits {
-            use serialport::DataBits;
-            let data_bits = match bits {
-                5 => DataBits::Five,
-                6 => DataBits::Six,
-                7 => DataBits::Seven,
-                8 => DataBits::Eight,
-                _ => return Err("无效的数据位数".to_string()),
-            };
-            serial.set_data_bits(data_bits).map_err(|e| {
-                error!("设置数据位失败: {}", e);
-                format!("设置数据位失败: {}", e)
-            })?;
-        }
-        
-        if let Some(bits) = stop_bits {
-            use serialport::StopBits;
-            let stop_bits = match bits {
-                1 => StopBits::One,
-                2 => StopBits::Two,
-                _ => return Err("无效的停止位数".to_string()),
-            };
-            serial.set_stop_bits(stop_bits).map_err(|e| {
-                error!("设置停止位失败: {}", e);
-                format!("设置停止位失败: {}", e)
-            })?;
-        }
-        
-        if let Some(parity_str) = parity {
-            use serialport::Parity;
-            let parity = match parity_str.to_lowercase().as_str() {
-                "none" => Parity::None,
-                "odd" => Parity::Odd,
-                "even" => Parity::Even,
-                _ => return Err("无效的校验位设置".to_string()),
-            };
-            serial.set_parity(parity).map_err(|e| {
-                error!("设置校验位失败: {}", e);
-                format!("设置校验位失败: {}", e)
-            })?;
+        if data_bits.is_some() || stop_bits.is_some() || parity.is_some() {
+            let mut line = serial.line_settings()?;
+            line.data_bits = data_bits.unwrap_or(line.data_bits);
+            line.stop_bits = stop_bits.unwrap_or(line.stop_bits);
+            line.parity = parity.unwrap_or(line.parity);
+            serial.set_line_settings(line)?;
         }
-        
-        info!("串口 {} 参数设置成功", port);
         Ok(())
-    } else {
-        Err(format!("串口 {} 未连接", port))
-    }
+    }).map_err(|e| {
+        error!("{}", e);
+        e.to_string()
+    })?;
+    
+    info!("串口 {} 参数设置成功", port);
+    Ok(())
 }
 
 #[command]
-pub async fn clear_serial_buffers(port: String) -> Result<(), String> {
+pub async fn clear_serial_buffers(port: String, serial: State<'_, SerialManagerState>) -> Result<(), String> {
     info!("清空串口缓冲区: {}", port);
     
-    let connections = SERIAL_CONNECTIONS.lock().await;
-    if let Some(serial_arc) = connections.get(&port) {
-        let mut serial = serial_arc.lock().await;
-        serial.clear(serialport::ClearBuffer::All).map_err(|e| {
-            error!("清空串口缓冲区失败: {}", e);
-            format!("清空串口缓冲区失败: {}", e)
-        })?;
-        Ok(())
-    } else {
-        Err(format!("串口 {} 未连接", port))
+    serial.with_port(&port, |serial| serial.clear_buffers()).map_err(|e| {
+        error!("{}", e);
+        e.to_string()
+    })
+}
+
+#[command]
+pub async fn set_serial_dtr(port: String, level: bool, serial: State<'_, SerialManagerState>) -> Result<(), String> {
+    debug!("设置 {} 的DTR: {}", port, level);
+    
+    serial.with_port(&port, |serial| serial.set_dtr(level)).map_err(|e| {
+        error!("{}", e);
+        e.to_string()
+    })
+}
+
+#[command]
+pub async fn set_serial_rts(port: String, level: bool, serial: State<'_, SerialManagerState>) -> Result<(), String> {
+    debug!("设置 {} 的RTS: {}", port, level);
+    
+    serial.with_port(&port, |serial| serial.set_rts(level)).map_err(|e| {
+        error!("{}", e);
+        e.to_string()
+    })
+}
+
+/// 读取CTS/DSR/RI/CD
+#[command]
+pub async fn get_serial_control_lines(port: String, serial: State<'_, SerialManagerState>) -> Result<ControlLines, String> {
+    serial.with_port(&port, |serial| serial.control_lines()).map_err(|e| {
+        error!("{}", e);
+        e.to_string()
+    })
+}
+
+/// 发送指定时长（毫秒）的BREAK信号
+#[command]
+pub async fn send_serial_break(port: String, duration_ms: u64, serial: State<'_, SerialManagerState>) -> Result<(), String> {
+    info!("向 {} 发送 {}ms 的BREAK信号", port, duration_ms);
+    
+    let serial = Arc::clone(&serial);
+    tokio::task::spawn_blocking(move || {
+        serial.with_port(&port, |serial| send_break(serial, Duration::from_millis(duration_ms)))
+    })
+    .await
+    .map_err(|e| format!("发送BREAK信号任务失败: {}", e))?
+    .map_err(|e| {
+        error!("{}", e);
+        e.to_string()
+    })
+}
+
+/// 读取设备配置文件 `custom_settings` 中保存的列表（触发规则、宏等）
+async fn profile_setting<T: DeserializeOwned>(detector: &DeviceDetector, device_id: &str, key: &str) -> Vec<T> {
+    let Some(profile) = detector.connection_manager().get_device_profile(device_id).await else {
+        return Vec::new();
+    };
+    profile.custom_settings
+        .get(key)
+        .and_then(|value| serde_json::from_value(value.clone())
+            .map_err(|e| error!("配置文件中的 {} 格式错误: {}", key, e))
+            .ok())
+        .unwrap_or_default()
+}
+
+/// 保存到设备配置文件的 `custom_settings`，设备没有配置文件时新建一个
+async fn save_profile_setting<T: Serialize>(detector: &DeviceDetector, device_id: &str, key: &str, value: &T) -> Result<DeviceInfo, String> {
+    let device = detector.get_device(device_id)
+        .cloned()
+        .ok_or_else(|| format!("未找到设备: {}", device_id))?;
+    let profile_id = ensure_device_profile(detector, &device).await
+        .ok_or_else(|| "无法创建设备配置文件".to_string())?;
+    
+    let value = serde_json::to_value(value).map_err(|e| e.to_string())?;
+    let mut updates = HashMap::new();
+    updates.insert(key.to_string(), value);
+    detector.connection_manager().update_profile(&profile_id, updates).await
+        .map_err(|e| format!("保存设备配置失败: {}", e))?;
+    Ok(device)
+}
+
+async fn profile_triggers(detector: &DeviceDetector, device_id: &str) -> Vec<TriggerRule> {
+    profile_setting(detector, device_id, TRIGGERS_SETTING_KEY).await
+}
+
+/// 获取设备保存的触发规则
+#[command]
+pub async fn get_serial_triggers(
+    device_id: String,
+    detector: State<'_, DeviceDetectorState>
+) -> Result<Vec<TriggerRule>, String> {
+    let detector = detector.lock().await;
+    Ok(profile_triggers(&detector, &device_id).await)
+}
+
+/// 保存设备的触发规则（正则匹配或绘图通道阈值），串口监视器已打开时立即生效
+///
+/// 规则命中时按动作发送 `serial-trigger` 事件、向串口回复文本或开始/停止记录会话。
+#[command]
+pub async fn set_serial_triggers(
+    device_id: String,
+    rules: Vec<TriggerRule>,
+    detector: State<'_, DeviceDetectorState>,
+    serial: State<'_, SerialManagerState>
+) -> Result<(), String> {
+    // 先检查规则，无效的正则表达式不保存
+    TriggerSet::compile(rules.clone()).map_err(|e| e.to_string())?;
+    
+    let device = {
+        let detector = detector.lock().await;
+        save_profile_setting(&detector, &device_id, TRIGGERS_SETTING_KEY, &rules).await?
+    };
+    info!("设备 {} 保存了 {} 条触发规则", device.name, rules.len());
+    
+    if serial.is_monitoring(&device.port) {
+        serial.set_triggers(&device.port, rules).map_err(|e| e.to_string())?;
     }
+    Ok(())
+}
+
+/// 获取设备保存的宏
+#[command]
+pub async fn get_serial_macros(
+    device_id: String,
+    detector: State<'_, DeviceDetectorState>
+) -> Result<Vec<SerialMacro>, String> {
+    let detector = detector.lock().await;
+    Ok(profile_setting(&detector, &device_id, MACROS_SETTING_KEY).await)
+}
+
+/// 保存设备的宏（替换原有的全部宏）
+#[command]
+pub async fn save_serial_macros(
+    device_id: String,
+    macros: Vec<SerialMacro>,
+    detector: State<'_, DeviceDetectorState>
+) -> Result<(), String> {
+    let detector = detector.lock().await;
+    let device = save_profile_setting(&detector, &device_id, MACROS_SETTING_KEY, &macros).await?;
+    info!("设备 {} 保存了 {} 个宏", device.name, macros.len());
+    Ok(())
+}
+
+/// 从文本格式导入宏，与已有宏同名的会被替换，返回导入后的全部宏
+#[command]
+pub async fn import_serial_macros(
+    device_id: String,
+    text: String,
+    detector: State<'_, DeviceDetectorState>
+) -> Result<Vec<SerialMacro>, String> {
+    let imported = parse_macros(&text).map_err(|e| format!("宏格式错误: {}", e))?;
+    
+    let detector = detector.lock().await;
+    let mut macros: Vec<SerialMacro> = profile_setting(&detector, &device_id, MACROS_SETTING_KEY).await;
+    for serial_macro in imported {
+        match macros.iter_mut().find(|m| m.name == serial_macro.name) {
+            Some(existing) => *existing = serial_macro,
+            None => macros.push(serial_macro),
+        }
+    }
+    save_profile_setting(&detector, &device_id, MACROS_SETTING_KEY, &macros).await?;
+    Ok(macros)
+}
+
+/// 把设备的宏导出为文本格式
+#[command]
+pub async fn export_serial_macros(
+    device_id: String,
+    detector: State<'_, DeviceDetectorState>
+) -> Result<String, String> {
+    let detector = detector.lock().await;
+    let macros: Vec<SerialMacro> = profile_setting(&detector, &device_id, MACROS_SETTING_KEY).await;
+    Ok(format_macros(&macros))
+}
+
+/// 在设备的串口上运行宏，串口监视器需要已经打开
+///
+/// 执行进度通过 `serial-macro-progress` 事件推送，回复内容仍然通过 `serial-data` 显示。
+#[command]
+pub async fn run_serial_macro(
+    app: AppHandle,
+    device_id: String,
+    name: String,
+    detector: State<'_, DeviceDetectorState>,
+    runner: State<'_, MacroRunnerState>
+) -> Result<(), String> {
+    let (port, serial_macro) = {
+        let detector = detector.lock().await;
+        let port = detector.get_device(&device_id)
+            .map(|device| device.port.clone())
+            .ok_or_else(|| format!("未找到设备: {}", device_id))?;
+        let macros: Vec<SerialMacro> = profile_setting(&detector, &device_id, MACROS_SETTING_KEY).await;
+        let serial_macro = macros.into_iter()
+            .find(|m| m.name == name)
+            .ok_or_else(|| format!("没有名为 {} 的宏", name))?;
+        (port, serial_macro)
+    };
+    
+    let on_progress: MacroProgressSink = Arc::new(move |progress| {
+        let _ = app.emit("serial-macro-progress", &progress);
+    });
+    runner.start(&port, serial_macro, on_progress).map_err(|e| {
+        error!("运行宏失败: {}", e);
+        e.to_string()
+    })
+}
+
+#[command]
+pub async fn stop_serial_macro(port: String, runner: State<'_, MacroRunnerState>) -> Result<(), String> {
+    runner.stop(&port).map_err(|e| e.to_string())
+}
+
+/// 正在运行的宏（串口 -> 宏名称）
+#[command]
+pub async fn get_running_macros(runner: State<'_, MacroRunnerState>) -> Result<HashMap<String, String>, String> {
+    Ok(runner.running())
+}
+
+/// 获取串口最近的绘图数据，用于打开绘图窗口时补齐之前的数据
+#[command]
+pub async fn get_serial_plot_history(
+    port: String,
+    limit: Option<usize>,
+    serial: State<'_, SerialManagerState>
+) -> Result<Vec<PlotSample>, String> {
+    Ok(serial.plot_history(&port, limit))
+}
+
+#[command]
+pub async fn clear_serial_plot_history(port: String, serial: State<'_, SerialManagerState>) -> Result<(), String> {
+    serial.clear_plot_history(&port);
+    Ok(())
+}
+
+/// 开始把串口监视器的收发数据记录到应用数据目录
+#[command]
+pub async fn start_serial_recording(port: String, serial: State<'_, SerialManagerState>) -> Result<SessionInfo, String> {
+    info!("开始记录串口会话: {}", port);
+    
+    let store = SessionStore::open().map_err(|e| format!("打开会话目录失败: {}", e))?;
+    serial.start_recording(&port, &store).map_err(|e| {
+        error!("开始记录串口会话失败: {}", e);
+        e.to_string()
+    })
+}
+
+#[command]
+pub async fn stop_serial_recording(port: String, serial: State<'_, SerialManagerState>) -> Result<SessionInfo, String> {
+    info!("停止记录串口会话: {}", port);
+    
+    serial.stop_recording(&port).map_err(|e| {
+        error!("停止记录串口会话失败: {}", e);
+        e.to_string()
+    })
+}
+
+#[command]
+pub async fn list_serial_sessions() -> Result<Vec<SessionInfo>, String> {
+    let store = SessionStore::open().map_err(|e| format!("打开会话目录失败: {}", e))?;
+    store.list().map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn delete_serial_session(session_id: String) -> Result<(), String> {
+    let store = SessionStore::open().map_err(|e| format!("打开会话目录失败: {}", e))?;
+    store.delete(&session_id).map_err(|e| {
+        error!("删除串口会话失败: {}", e);
+        e.to_string()
+    })
+}
+
+/// 导出串口会话为文本、绘图通道CSV或JSON Lines
+#[command]
+pub async fn export_serial_session(
+    session_id: String,
+    format: ExportFormat,
+    dest_path: String
+) -> Result<(), String> {
+    info!("导出串口会话 {} 为 {:?}: {}", session_id, format, dest_path);
+    
+    let store = SessionStore::open().map_err(|e| format!("打开会话目录失败: {}", e))?;
+    tokio::task::spawn_blocking(move || store.export(&session_id, format, &PathBuf::from(dest_path)))
+        .await
+        .map_err(|e| format!("导出任务失败: {}", e))?
+        .map_err(|e| {
+            error!("导出串口会话失败: {}", e);
+            e.to_string()
+        })
+}
+
+/// 把记录的会话回放到虚拟串口，数据像真实设备一样推送给监视器和绘图器
+///
+/// 返回虚拟串口名称，可用于 `disconnect_serial` 等命令；`speed` 为回放倍速，默认按原速度。
+#[command]
+pub async fn replay_serial_session(
+    app: AppHandle,
+    session_id: String,
+    speed: Option<f64>,
+    serial: State<'_, SerialManagerState>
+) -> Result<String, String> {
+    info!("回放串口会话: {} ({:?})", session_id, speed);
+    
+    let store = SessionStore::open().map_err(|e| format!("打开会话目录失败: {}", e))?;
+    serial.open_replay(&store, &session_id, speed.unwrap_or(1.0), event_sink(app)).map_err(|e| {
+        error!("回放串口会话失败: {}", e);
+        e.to_string()
+    })
 }
 
 // 连接历史记录
@@ -1,8 +1,21 @@
 use anyhow::{Result, anyhow};
-use tauri::command;
+use tauri::{command, AppHandle, Emitter, State};
 use log::{info, error};
 use std::process::Command;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use crate::device::esp_loader::{EspChipInfo, EspLoader, ROM_BAUD_RATE};
+use crate::device::reset::esp_hard_reset;
+use crate::device::serial::PortUser;
+use crate::device::DeviceType;
+use crate::device::boards::{is_sync_failure, BoardConfig};
+use crate::device::firmware::FirmwareFormat;
+use crate::device::toolchain::{Toolchain, ToolchainStatus};
+use crate::device::flasher::{flash_firmware, select_flash_tool, FlashTool};
+use crate::device::uploader::UploadProgress;
+use crate::device::verify::{verify_device, VerificationReport, DEFAULT_HANDSHAKE_TIMEOUT};
+use crate::commands::device::{remember_board_variant, DeviceDetectorState, DeviceUploaderState, SerialManagerState};
 
 #[derive(serde::Serialize)]
 pub struct ToolInfo {
@@ -17,8 +30,15 @@ pub async fn check_system_tools() -> Result<HashMap<String, ToolInfo>, String> {
     
     let mut tools = HashMap::new();
     
-    // 检查 Arduino CLI
-    tools.insert("arduino-cli".to_string(), check_tool("arduino-cli", &["version"]));
+    // 只检查应用管理的工具链中的 Arduino CLI，上传不会使用PATH中的版本
+    let arduino_cli = match Toolchain::open() {
+        Ok(toolchain) => check_tool(&toolchain.managed_cli_path().to_string_lossy(), &["version"]),
+        Err(e) => {
+            error!("打开工具链目录失败: {}", e);
+            ToolInfo { installed: false, version: None, path: None }
+        }
+    };
+    tools.insert("arduino-cli".to_string(), arduino_cli);
     
     // 检查 Python 3
     tools.insert("python3".to_string(), check_tool("python3", &["--version"]));
@@ -26,9 +46,6 @@ pub async fn check_system_tools() -> Result<HashMap<String, ToolInfo>, String> {
     // 检查 mpremote
     tools.insert("mpremote".to_string(), check_tool("mpremote", &["version"]));
     
-    // 检查 esptool
-    tools.insert("esptool".to_string(), check_tool("esptool.py", &["version"]));
-    
     // 检查 PlatformIO
     tools.insert("platformio".to_string(), check_tool("pio", &["--version"]));
     
@@ -45,9 +62,15 @@ pub async fn check_upload_tools() -> Result<HashMap<String, bool>, String> {
     let mut tools = HashMap::new();
     
     // 检查必需的上传工具
-    tools.insert("arduino-cli".to_string(), check_command_exists("arduino-cli"));
+    let arduino_cli = match Toolchain::open() {
+        Ok(toolchain) => toolchain.cli_available().await,
+        Err(e) => {
+            error!("打开工具链目录失败: {}", e);
+            false
+        }
+    };
+    tools.insert("arduino-cli".to_string(), arduino_cli);
     tools.insert("mpremote".to_string(), check_command_exists("mpremote"));
-    tools.insert("esptool".to_string(), check_command_exists("esptool.py"));
     tools.insert("avrdude".to_string(), check_command_exists("avrdude"));
     
     Ok(tools)
@@ -150,52 +173,12 @@ fn check_command_exists(command: &str) -> bool {
 async fn install_arduino_cli() -> Result<String> {
     info!("开始安装 Arduino CLI");
     
-    // 检测操作系统
-    let os = std::env::consts::OS;
+    // 安装到应用管理的工具链目录，并安装固定版本的开发板核心
+    let toolchain = Toolchain::open()?;
+    toolchain.install_arduino_cli().await?;
+    let cores = toolchain.install_pinned_cores().await?;
     
-    let install_cmd = match os {
-        "macos" => {
-            // macOS 使用 Homebrew
-            Command::new("brew")
-                .args(&["install", "arduino-cli"])
-                .output()
-        }
-        "linux" => {
-            // Linux 使用官方安装脚本
-            Command::new("sh")
-                .arg("-c")
-                .arg("curl -fsSL https://raw.githubusercontent.com/arduino/arduino-cli/master/install.sh | sh")
-                .output()
-        }
-        "windows" => {
-            // Windows 使用 scoop 或 chocolatey
-            Command::new("scoop")
-                .args(&["install", "arduino-cli"])
-                .output()
-                .or_else(|_| {
-                    Command::new("choco")
-                        .args(&["install", "arduino-cli", "-y"])
-                        .output()
-                })
-        }
-        _ => return Err(anyhow!("不支持的操作系统: {}", os)),
-    };
-    
-    match install_cmd {
-        Ok(output) => {
-            if output.status.success() {
-                // 初始化 Arduino CLI
-                let _ = Command::new("arduino-cli")
-                    .args(&["core", "update-index"])
-                    .output();
-                
-                Ok("Arduino CLI 安装成功".to_string())
-            } else {
-                Err(anyhow!("安装失败: {}", String::from_utf8_lossy(&output.stderr)))
-            }
-        }
-        Err(e) => Err(anyhow!("执行安装命令失败: {}", e)),
-    }
+    Ok(format!("Arduino CLI 安装成功，已安装开发板核心: {}", cores.join(", ")))
 }
 
 async fn install_mpremote() -> Result<String> {
@@ -258,6 +241,32 @@ async fn install_platformio() -> Result<String> {
     }
 }
 
+/// 应用管理的工具链状态（arduino-cli版本和固定版本的开发板核心）
+#[command]
+pub async fn get_toolchain_status() -> Result<ToolchainStatus, String> {
+    let toolchain = Toolchain::open().map_err(|e| format!("打开工具链目录失败: {}", e))?;
+    toolchain.status().await.map_err(|e| format!("读取工具链状态失败: {}", e))
+}
+
+/// 安装工具链：arduino-cli 和固定版本的开发板核心
+#[command]
+pub async fn install_toolchain() -> Result<String, String> {
+    install_arduino_cli().await.map_err(|e| {
+        error!("安装工具链失败: {}", e);
+        format!("安装工具链失败: {}", e)
+    })
+}
+
+/// 从离线安装包导入工具链（没有网络的机房）
+#[command]
+pub async fn import_toolchain_bundle(archive_path: String) -> Result<usize, String> {
+    let toolchain = Toolchain::open().map_err(|e| format!("打开工具链目录失败: {}", e))?;
+    toolchain.import_bundle(&PathBuf::from(archive_path)).await.map_err(|e| {
+        error!("导入离线工具链失败: {}", e);
+        format!("导入离线工具链失败: {}", e)
+    })
+}
+
 // 编译和上传相关命令
 
 #[derive(serde::Serialize)]
@@ -273,12 +282,27 @@ pub struct CompileResult {
 pub async fn compile_code(
     code: String,
     language: String,
-    device_type: String
+    device_type: String,
+    board_variant: Option<String>,
+    uploader: State<'_, DeviceUploaderState>
 ) -> Result<CompileResult, String> {
     info!("编译代码 - 语言: {}, 设备: {}", language, device_type);
     
     match language.as_str() {
-        "arduino" => compile_arduino_code(code, device_type).await,
+        "arduino" => {
+            let catalog = uploader.board_catalog();
+            let board = match board_variant {
+                Some(variant) => catalog.get(&variant)
+                    .ok_or_else(|| format!("未知的开发板型号: {}", variant))?,
+                None => {
+                    let device_type: DeviceType = serde_json::from_value(serde_json::Value::String(device_type.clone()))
+                        .map_err(|_| format!("未知的设备类型: {}", device_type))?;
+                    catalog.default_for(&device_type)
+                        .ok_or_else(|| format!("没有 {:?} 的开发板配置", device_type))?
+                },
+            };
+            compile_arduino_code(code, board, uploader.toolchain()).await
+        },
         "micropython" => {
             // MicroPython 不需要编译，直接返回成功
             Ok(CompileResult {
@@ -293,29 +317,26 @@ pub async fn compile_code(
     }
 }
 
-async fn compile_arduino_code(code: String, device_type: String) -> Result<CompileResult, String> {
-    // 创建临时文件
+async fn compile_arduino_code(code: String, board: &BoardConfig, toolchain: &Toolchain) -> Result<CompileResult, String> {
+    // 创建临时文件，Arduino草图文件需要放在同名目录中
     let temp_dir = std::env::temp_dir();
-    let sketch_dir = temp_dir.join(format!("rustblock_sketch_{}", uuid::Uuid::new_v4()));
+    let project_dir = temp_dir.join(format!("rustblock_sketch_{}", uuid::Uuid::new_v4()));
+    let sketch_dir = project_dir.join("sketch");
+    let build_dir = project_dir.join("build");
     std::fs::create_dir_all(&sketch_dir).map_err(|e| format!("创建临时目录失败: {}", e))?;
     
     let sketch_file = sketch_dir.join("sketch.ino");
     std::fs::write(&sketch_file, code).map_err(|e| format!("写入代码文件失败: {}", e))?;
     
-    // 确定板卡类型
-    let board = match device_type.as_str() {
-        "Arduino" => "arduino:avr:uno",
-        "ESP32" => "esp32:esp32:esp32",
-        "MicroBit" => "sandeepmistry:nRF5:BBCmicrobit",
-        "RaspberryPiPico" => "rp2040:rp2040:rpipico",
-        _ => "arduino:avr:uno",
-    };
+    info!("编译开发板: {} ({})", board.name, board.fqbn);
     
     // 编译
-    let output = Command::new("arduino-cli")
+    let output = toolchain.command()
+        .map_err(|e| e.to_string())?
         .args(&[
             "compile",
-            "--fqbn", board,
+            "--fqbn", &board.fqbn,
+            "--output-dir", build_dir.to_str().ok_or_else(|| format!("无法转换路径为字符串: {:?}", build_dir))?,
             sketch_dir.to_str().ok_or_else(|| format!("无法转换路径为字符串: {:?}", sketch_dir))?
         ])
         .output()
@@ -323,8 +344,8 @@ async fn compile_arduino_code(code: String, device_type: String) -> Result<Compi
     
     if output.status.success() {
         // 查找生成的二进制文件
-        let hex_file = sketch_dir.join("sketch.ino.hex");
-        let bin_file = sketch_dir.join("sketch.ino.bin");
+        let hex_file = build_dir.join("sketch.ino.hex");
+        let bin_file = build_dir.join("sketch.ino.bin");
         
         let firmware_path = if hex_file.exists() {
             Some(hex_file.to_str().ok_or_else(|| format!("无法转换路径为字符串: {:?}", hex_file))?.to_string())
@@ -356,51 +377,181 @@ async fn compile_arduino_code(code: String, device_type: String) -> Result<Compi
 pub struct UploadResult {
     pub success: bool,
     pub error: Option<String>,
+    pub tool: Option<FlashTool>,
+    pub board: Option<String>,
+    pub bytes_written: Option<usize>,
+    pub duration_ms: u64,
+    pub message: Option<String>,
 }
 
+/// 按设备类型、开发板和固件格式选择烧录工具并上传固件
 #[command]
 pub async fn upload_firmware(
+    app: AppHandle,
     device_id: String,
     firmware_path: String,
-    port: String
+    port: Option<String>,
+    board_variant: Option<String>,
+    detector: State<'_, DeviceDetectorState>,
+    uploader: State<'_, DeviceUploaderState>,
+    serial: State<'_, SerialManagerState>
 ) -> Result<UploadResult, String> {
-    info!("上传固件到设备: {} @ {}", device_id, port);
-    
-    // 这里应该根据设备类型选择合适的上传工具
-    // 暂时使用 Arduino CLI
-    let output = Command::new("arduino-cli")
-        .args(&[
-            "upload",
-            "-p", &port,
-            "--input-file", &firmware_path
-        ])
-        .output()
-        .map_err(|e| format!("执行上传命令失败: {}", e))?;
+    info!("上传固件到设备: {} ({})", device_id, firmware_path);
+    let started = Instant::now();
+    
+    let device = {
+        let detector = detector.lock().await;
+        detector.get_device(&device_id)
+            .cloned()
+            .ok_or_else(|| format!("未找到设备: {}", device_id))?
+    };
+    let port = port.unwrap_or_else(|| device.port.clone());
+    
+    let board = uploader.board_catalog()
+        .resolve(board_variant.as_deref(), &device)
+        .cloned()
+        .map_err(|e| e.to_string())?;
+    let firmware = PathBuf::from(&firmware_path);
+    let format = FirmwareFormat::from_path(&firmware).map_err(|e| e.to_string())?;
+    let picotool_available = check_tool("picotool", &["version"]).installed;
+    let tool = select_flash_tool(&device.device_type, &board, format, picotool_available)
+        .map_err(|e| e.to_string())?;
+    
+    // 烧录期间独占串口，串口监视器自动暂停
+    let mut lease = serial.acquire(&port, PortUser::Flash).await.map_err(|e| e.to_string())?;
+    lease.set_resume_timeout(board.resume_timeout());
+    
+    let fallback = uploader.board_catalog().fallback_for(&board).cloned();
+    let toolchain = uploader.toolchain().clone();
+    let serial_number = device.serial_number.clone();
+    let mut board_name = board.name.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let emit_progress = |progress: f32| {
+            let _ = app.emit("upload-progress", UploadProgress {
+                stage: "flash".to_string(),
+                progress,
+                message: tool.name().to_string(),
+            });
+        };
+        match (flash_firmware(tool, &port, serial_number.as_deref(), &board, &firmware, &toolchain, emit_progress), fallback) {
+            // 旧引导程序的Nano同步失败时换用备选型号重试
+            (Err(e), Some(fallback)) if is_sync_failure(&e) => {
+                info!("引导程序同步失败，改用 {} 重试", fallback.name);
+                flash_firmware(tool, &port, serial_number.as_deref(), &fallback, &firmware, &toolchain, emit_progress)
+                    .map(|outcome| (outcome, Some(fallback)))
+            }
+            (result, _) => result.map(|outcome| (outcome, None)),
+        }
+    })
+    .await
+    .map_err(|e| format!("上传任务失败: {}", e))?;
+    
+    let result = match result {
+        Ok((outcome, Some(fallback))) => {
+            board_name = fallback.name.clone();
+            let detector = detector.lock().await;
+            remember_board_variant(&detector, &device, &fallback.id).await;
+            Ok(outcome)
+        }
+        Ok((outcome, None)) => Ok(outcome),
+        Err(e) => Err(e),
+    };
     
-    if output.status.success() {
-        Ok(UploadResult {
-            success: true,
-            error: None,
-        })
-    } else {
-        Ok(UploadResult {
-            success: false,
-            error: Some(String::from_utf8_lossy(&output.stderr).to_string()),
-        })
+    let duration_ms = started.elapsed().as_millis() as u64;
+    match result {
+        Ok(outcome) => {
+            info!("固件上传成功: {} ({} ms)", outcome.message, duration_ms);
+            Ok(UploadResult {
+                success: true,
+                error: None,
+                tool: Some(outcome.tool),
+                board: Some(board_name),
+                bytes_written: Some(outcome.bytes_written),
+                duration_ms,
+                message: Some(outcome.message),
+            })
+        }
+        Err(e) => {
+            error!("固件上传失败: {}", e);
+            Ok(UploadResult {
+                success: false,
+                error: Some(e.to_string()),
+                tool: Some(tool),
+                board: Some(board_name),
+                bytes_written: None,
+                duration_ms,
+                message: None,
+            })
+        }
     }
 }
 
+/// 上传后验证设备上运行的是否是新程序
+///
+/// 根据上传方式回读Flash或计算文件哈希；`handshake` 为真时再等待生成代码打印的启动标记。
 #[command]
-pub async fn verify_upload(device_id: String) -> Result<serde_json::Value, String> {
+pub async fn verify_upload(
+    device_id: String,
+    handshake: Option<bool>,
+    detector: State<'_, DeviceDetectorState>,
+    uploader: State<'_, DeviceUploaderState>,
+    serial: State<'_, SerialManagerState>
+) -> Result<VerificationReport, String> {
     info!("验证设备上传: {}", device_id);
     
-    // 这里应该实现实际的验证逻辑
-    // 比如检查设备是否响应，串口通信是否正常等
+    let (port, baud_rate) = {
+        let detector = detector.lock().await;
+        let port = detector.get_device(&device_id)
+            .map(|device| device.port.clone())
+            .ok_or_else(|| format!("未找到设备: {}", device_id))?;
+        (port, detector.get_baud_rate(&device_id))
+    };
+    
+    let artifact = uploader.last_upload(&port)
+        .ok_or_else(|| format!("没有找到设备 {} 的上传记录，请先上传代码", device_id))?;
+    
+    // 验证期间独占串口，避免和排队中的上传冲突
+    let _lease = serial.acquire(&port, PortUser::Verify).await.map_err(|e| e.to_string())?;
     
-    Ok(serde_json::json!({
-        "success": true,
-        "message": "设备运行正常"
-    }))
+    let handshake = handshake.unwrap_or(false).then_some(DEFAULT_HANDSHAKE_TIMEOUT);
+    let report = tokio::task::spawn_blocking(move || verify_device(&port, &artifact, baud_rate, handshake))
+        .await
+        .map_err(|e| format!("验证任务失败: {}", e))?;
+    
+    if !report.success {
+        error!("上传验证失败: {:?}", report.error);
+    }
+    Ok(report)
+}
+
+/// 通过ROM引导程序读取ESP芯片型号和MAC地址
+#[command]
+pub async fn read_esp_chip_info(port: String, serial: State<'_, SerialManagerState>) -> Result<EspChipInfo, String> {
+    info!("读取ESP芯片信息: {}", port);
+    
+    let _lease = serial.acquire(&port, PortUser::Probe).await.map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || -> Result<EspChipInfo> {
+        let serial = serialport::new(&port, ROM_BAUD_RATE)
+            .timeout(Duration::from_millis(50))
+            .open()
+            .map_err(|e| anyhow!("打开串口失败 {}: {}", port, e))?;
+        
+        let mut loader = EspLoader::new(serial);
+        loader.connect(3)?;
+        let chip_info = loader.chip_info()?;
+        
+        // 读取完成后复位，让板子继续运行原来的程序
+        let mut serial = loader.into_inner();
+        esp_hard_reset(&mut serial)?;
+        
+        Ok(chip_info)
+    })
+    .await
+    .map_err(|e| format!("读取芯片信息任务失败: {}", e))?
+    .map_err(|e| {
+        error!("读取ESP芯片信息失败: {}", e);
+        format!("读取ESP芯片信息失败: {}", e)
+    })
 }
 
 #[command]
@@ -85,6 +85,7 @@ const BlocklyWorkspace: React.FC<BlocklyWorkspaceProps> = ({
         ? `// Arduino代码示例
 void setup() {
   Serial.begin(9600);
+  Serial.println("[RustBlock] ready");
   pinMode(13, OUTPUT);
 }
 
@@ -98,6 +99,8 @@ void loop() {
 from machine import Pin
 import time
 
+print("[RustBlock] ready")
+
 led = Pin(13, Pin.OUT)
 
 while True:
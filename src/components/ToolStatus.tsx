@@ -56,15 +56,6 @@ const ToolStatus: React.FC<ToolStatusProps> = ({ onToolsReady }) => {
           description: '用于上传 MicroPython 代码',
           installCommand: 'pip3 install mpremote',
         },
-        {
-          name: 'esptool',
-          displayName: 'esptool',
-          installed: toolsStatus['esptool']?.installed || false,
-          version: toolsStatus['esptool']?.version,
-          required: false,
-          description: '用于 ESP32 固件烧录',
-          installCommand: 'pip3 install esptool',
-        },
         {
           name: 'platformio',
           displayName: 'PlatformIO',
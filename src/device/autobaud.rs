@@ -0,0 +1,150 @@
+use super::transport::open_transport;
+use anyhow::{Result, anyhow};
+use log::{debug, info};
+use serde::{Serialize, Deserialize};
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// 自动检测时依次尝试的波特率
+pub const COMMON_BAUD_RATES: [u32; 5] = [9600, 19200, 38400, 57600, 115200];
+/// 每个波特率的监听时间（打开串口会复位Arduino，需要等程序重新开始输出）
+pub const DEFAULT_LISTEN_TIME: Duration = Duration::from_millis(2500);
+/// 每个波特率最多采集的字节数
+const MAX_SAMPLE_BYTES: usize = 512;
+/// 采集到的字节少于这个数时不评分
+const MIN_SAMPLE_BYTES: usize = 8;
+/// 最高分低于这个值时认为没有检测出来
+const MIN_SCORE: f64 = 0.6;
+/// 达到这个分数就不再尝试其他波特率
+const CONFIDENT_SCORE: f64 = 1.2;
+
+/// 一个波特率的评分
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaudScore {
+    pub baud_rate: u32,
+    pub bytes: usize,
+    /// 可打印字符的比例
+    pub printable_ratio: f64,
+    pub lines: usize,
+    pub score: f64,
+}
+
+/// 自动检测的结果，`baud_rate` 为空表示设备没有输出可识别的文本
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoBaudResult {
+    pub port: String,
+    pub baud_rate: Option<u32>,
+    pub scores: Vec<BaudScore>,
+}
+
+/// 给一个波特率下收到的数据评分
+///
+/// 波特率错误时收到的多是 0x00、0xF0 之类的不可打印字节，而且很少出现换行；
+/// 分数为可打印比例的平方，有规律的换行（每行不太长）再加分。
+pub fn score_sample(baud_rate: u32, data: &[u8]) -> BaudScore {
+    let text = String::from_utf8_lossy(data);
+    let total = text.chars().count();
+    let printable = text.chars()
+        .filter(|&c| c != char::REPLACEMENT_CHARACTER && (!c.is_control() || matches!(c, '\r' | '\n' | '\t')))
+        .count();
+    let lines = data.iter().filter(|&&b| b == b'\n').count();
+
+    let printable_ratio = if total == 0 { 0.0 } else { printable as f64 / total as f64 };
+    let score = if data.len() < MIN_SAMPLE_BYTES {
+        0.0
+    } else {
+        let line_bonus = match data.len().checked_div(lines) {
+            Some(average) if average <= 200 => 0.5,
+            Some(_) => 0.2,
+            None => 0.0,
+        };
+        printable_ratio * printable_ratio * (1.0 + line_bonus)
+    };
+
+    BaudScore {
+        baud_rate,
+        bytes: data.len(),
+        printable_ratio,
+        lines,
+        score,
+    }
+}
+
+/// 在每个波特率下监听设备的输出，选出最像正常文本的波特率
+///
+/// 设备必须在主动输出数据（例如 `Serial.println`），调用方需要先占用串口。
+pub fn detect_baud_rate(port_name: &str, rates: &[u32], listen: Duration) -> Result<AutoBaudResult> {
+    if rates.is_empty() {
+        return Err(anyhow!("没有要检测的波特率"));
+    }
+    info!("开始自动检测 {} 的波特率", port_name);
+
+    let mut scores = Vec::new();
+    for &baud_rate in rates {
+        let data = listen_at(port_name, baud_rate, listen)?;
+        let score = score_sample(baud_rate, &data);
+        debug!("{} @ {}: {} 字节, 可打印 {:.2}, {} 行, 得分 {:.2}",
+               port_name, baud_rate, score.bytes, score.printable_ratio, score.lines, score.score);
+        let confident = score.score >= CONFIDENT_SCORE;
+        scores.push(score);
+        if confident {
+            break;
+        }
+    }
+
+    let best = scores.iter()
+        .filter(|s| s.score >= MIN_SCORE)
+        .max_by(|a, b| a.score.total_cmp(&b.score).then(a.lines.cmp(&b.lines)))
+        .map(|s| s.baud_rate);
+    match best {
+        Some(baud_rate) => info!("{} 的波特率为 {}", port_name, baud_rate),
+        None => info!("{} 没有检测到可识别的输出", port_name),
+    }
+
+    Ok(AutoBaudResult {
+        port: port_name.to_string(),
+        baud_rate: best,
+        scores,
+    })
+}
+
+fn listen_at(port_name: &str, baud_rate: u32, listen: Duration) -> Result<Vec<u8>> {
+    let mut port = open_transport(port_name, baud_rate, Duration::from_millis(50))?;
+    // 丢弃切换波特率前残留的数据
+    let _ = port.clear_buffers();
+
+    let start = Instant::now();
+    let mut data = Vec::new();
+    let mut chunk = [0u8; 256];
+    while start.elapsed() < listen && data.len() < MAX_SAMPLE_BYTES {
+        match port.read(&mut chunk) {
+            Ok(n) => data.extend_from_slice(&chunk[..n]),
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(anyhow!("读取串口失败: {}", e)),
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_readable_text() {
+        let text = score_sample(9600, b"temp:21.5,light:300\r\ntemp:21.6,light:298\r\n");
+        assert!(text.score >= CONFIDENT_SCORE);
+        assert_eq!(text.lines, 2);
+    }
+
+    #[test]
+    fn test_score_wrong_baud_garbage() {
+        let garbage = score_sample(115200, &[0x00, 0xF8, 0x80, 0xFE, 0x00, 0x78, 0xE0, 0x80, 0x00, 0xF0]);
+        assert!(garbage.score < MIN_SCORE);
+    }
+
+    #[test]
+    fn test_score_too_short_sample() {
+        assert_eq!(score_sample(9600, b"ok").score, 0.0);
+    }
+}
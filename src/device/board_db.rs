@@ -0,0 +1,182 @@
+use super::DeviceType;
+use crate::utils::get_app_data_dir;
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// 应用数据目录下的用户开发板数据库，其中的条目优先于内置条目
+pub const USER_BOARD_DB_FILE: &str = "board_db.json";
+
+const BUNDLED_BOARD_DB: &str = include_str!("data/board_db.json");
+
+/// USB转串口芯片或开发板需要的驱动
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriverEntry {
+    pub name: String,
+    pub description: String,
+}
+
+/// 一种USB设备
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsbBoardEntry {
+    pub name: String,
+    #[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
+    pub vendor_id: u16,
+    #[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
+    pub product_id: u16,
+    /// USB产品描述中包含其中之一才匹配（不区分大小写），为空时只按VID/PID匹配。
+    /// 同一个转串口芯片用在不同开发板上时据此区分。
+    #[serde(default)]
+    pub product_patterns: Vec<String>,
+    pub device_type: DeviceType,
+    /// 开发板目录中的型号标识
+    #[serde(default)]
+    pub variant: Option<String>,
+    /// `drivers` 中的驱动键名
+    #[serde(default)]
+    pub driver: Option<String>,
+    pub default_baud_rate: u32,
+    /// 支持的编程语言，第一个为推荐语言
+    pub languages: Vec<String>,
+    /// 通用的USB转串口芯片，用在很多种板子上，识别结果需要主动探测确认
+    #[serde(default)]
+    pub ambiguous: bool,
+}
+
+impl UsbBoardEntry {
+    fn matches_product(&self, product: Option<&str>) -> bool {
+        let Some(product) = product.map(|p| p.to_lowercase()) else {
+            return false;
+        };
+        self.product_patterns.iter().any(|p| product.contains(&p.to_lowercase()))
+    }
+}
+
+/// VID/PID开发板数据库：内置的JSON加上用户的覆盖文件
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BoardDatabase {
+    #[serde(default)]
+    drivers: BTreeMap<String, DriverEntry>,
+    #[serde(default)]
+    boards: Vec<UsbBoardEntry>,
+}
+
+impl BoardDatabase {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| anyhow!("开发板数据库格式错误: {}", e))
+    }
+
+    pub fn bundled() -> Self {
+        Self::from_json(BUNDLED_BOARD_DB).expect("内置开发板数据库格式正确")
+    }
+
+    /// 加载内置数据库，再合并用户的覆盖文件（不存在或格式错误时忽略）
+    pub fn load() -> Self {
+        let mut db = Self::bundled();
+        let Ok(path) = get_app_data_dir().map(|dir| dir.join(USER_BOARD_DB_FILE)) else {
+            return db;
+        };
+        if !path.exists() {
+            return db;
+        }
+        match std::fs::read_to_string(&path).map_err(|e| anyhow!("读取失败: {}", e)).and_then(|json| Self::from_json(&json)) {
+            Ok(overrides) => {
+                info!("已加载用户开发板数据库 {}: {} 种设备", path.display(), overrides.boards.len());
+                db.merge(overrides);
+            }
+            Err(e) => warn!("忽略用户开发板数据库 {}: {}", path.display(), e),
+        }
+        db
+    }
+
+    /// 合并覆盖条目：同名驱动被替换，覆盖的设备条目排在前面优先匹配
+    pub fn merge(&mut self, overrides: BoardDatabase) {
+        self.drivers.extend(overrides.drivers);
+        let mut boards = overrides.boards;
+        boards.append(&mut self.boards);
+        self.boards = boards;
+    }
+
+    pub fn drivers(&self) -> &BTreeMap<String, DriverEntry> {
+        &self.drivers
+    }
+
+    pub fn boards(&self) -> &[UsbBoardEntry] {
+        &self.boards
+    }
+
+    /// 按VID/PID和USB产品描述查找设备
+    ///
+    /// 产品描述匹配的条目优先，其次是不限产品描述的条目。
+    pub fn lookup(&self, vendor_id: Option<u16>, product_id: Option<u16>, product: Option<&str>) -> Option<&UsbBoardEntry> {
+        let (vid, pid) = (vendor_id?, product_id?);
+        let candidates = || self.boards.iter().filter(move |b| b.vendor_id == vid && b.product_id == pid);
+        candidates().find(|b| b.matches_product(product))
+            .or_else(|| candidates().find(|b| b.product_patterns.is_empty()))
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref BOARD_DB: BoardDatabase = BoardDatabase::load();
+}
+
+/// 全局开发板数据库，第一次使用时加载
+pub fn board_db() -> &'static BoardDatabase {
+    &BOARD_DB
+}
+
+fn serialize_hex<S: Serializer>(value: &u16, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("0x{:04x}", value))
+}
+
+/// 接受 "0x2341" 形式的字符串或数字
+fn deserialize_hex<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<u16, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum HexId {
+        Number(u16),
+        Text(String),
+    }
+    match HexId::deserialize(deserializer)? {
+        HexId::Number(value) => Ok(value),
+        HexId::Text(text) => {
+            let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+            u16::from_str_radix(digits, 16).map_err(|_| serde::de::Error::custom(format!("无效的USB ID: {}", text)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_by_vid_pid() {
+        let db = BoardDatabase::bundled();
+        let nano = db.lookup(Some(0x1a86), Some(0x7523), Some("USB2.0-Serial")).unwrap();
+        assert_eq!(nano.device_type, DeviceType::Arduino);
+        assert_eq!(nano.driver.as_deref(), Some("ch340"));
+        assert!(db.lookup(Some(0x1234), Some(0x5678), None).is_none());
+    }
+
+    #[test]
+    fn test_bundled_drivers_exist() {
+        let db = BoardDatabase::bundled();
+        assert!(db.boards().iter().filter_map(|b| b.driver.as_ref()).all(|d| db.drivers().contains_key(d)));
+    }
+
+    #[test]
+    fn test_override_matches_product_pattern() {
+        // 用户条目按产品描述区分同一芯片的ESP32板子
+        let mut db = BoardDatabase::bundled();
+        let overrides = BoardDatabase::from_json(r#"{"boards": [{
+            "name": "ESP32 (CH340)", "vendor_id": "0x1a86", "product_id": 29987,
+            "product_patterns": ["esp32"], "device_type": "ESP32", "driver": "ch340",
+            "default_baud_rate": 115200, "languages": ["micropython"]
+        }]}"#).unwrap();
+        db.merge(overrides);
+        assert_eq!(db.lookup(Some(0x1a86), Some(0x7523), Some("ESP32-DevKit")).unwrap().device_type, DeviceType::ESP32);
+        assert_eq!(db.lookup(Some(0x1a86), Some(0x7523), None).unwrap().device_type, DeviceType::Arduino);
+    }
+}
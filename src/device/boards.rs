@@ -0,0 +1,277 @@
+use super::board_db::{board_db, BoardDatabase};
+use super::stk500::Stk500Error;
+use super::{DeviceInfo, DeviceType};
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+
+/// 上传时自动识别出的型号保存在配置文件 `custom_settings` 中的键
+pub const DETECTED_VARIANT_KEY: &str = "detected_board_variant";
+
+/// 原生USB的板子复位后要重新枚举，Leonardo的引导程序会先等待8秒
+const NATIVE_USB_RESUME_TIMEOUT: Duration = Duration::from_secs(12);
+/// 使用USB转串口芯片的板子复位时串口不会消失
+const SERIAL_CHIP_RESUME_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// USB的VID/PID
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UsbId {
+    pub vid: u16,
+    pub pid: u16,
+}
+
+/// 开发板型号配置，编译、上传和PlatformIO项目生成都使用这份配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoardConfig {
+    /// 型号标识，例如 "uno"、"nano"
+    pub id: String,
+    pub name: String,
+    pub device_type: DeviceType,
+    pub fqbn: String,  // Fully Qualified Board Name for Arduino
+    pub upload_protocol: String,
+    pub upload_speed: u32,
+    pub extra_flags: Vec<String>,
+    pub platformio_platform: String,
+    pub platformio_board: String,
+    /// 可以据此直接认出型号的USB ID，来自开发板数据库中 `variant` 为该型号的条目
+    #[serde(default)]
+    pub usb_ids: Vec<UsbId>,
+    /// 引导程序同步失败时改用的型号（新旧引导程序的Nano互为备选）
+    #[serde(default)]
+    pub fallback: Option<String>,
+}
+
+impl BoardConfig {
+    fn new(id: &str, name: &str, device_type: DeviceType, fqbn: &str, upload_protocol: &str, upload_speed: u32) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            device_type,
+            fqbn: fqbn.to_string(),
+            upload_protocol: upload_protocol.to_string(),
+            upload_speed,
+            extra_flags: Vec::new(),
+            platformio_platform: String::new(),
+            platformio_board: String::new(),
+            usb_ids: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    fn platformio(mut self, platform: &str, board: &str) -> Self {
+        self.platformio_platform = platform.to_string();
+        self.platformio_board = board.to_string();
+        self
+    }
+
+    fn fallback(mut self, id: &str) -> Self {
+        self.fallback = Some(id.to_string());
+        self
+    }
+
+    fn flags(mut self, flags: &[&str]) -> Self {
+        self.extra_flags = flags.iter().map(|f| f.to_string()).collect();
+        self
+    }
+
+    /// 上传或复位后等待串口重新出现的时间
+    pub fn resume_timeout(&self) -> Duration {
+        match self.upload_protocol.as_str() {
+            "avr109" | "picotool" => NATIVE_USB_RESUME_TIMEOUT,
+            _ => SERIAL_CHIP_RESUME_TIMEOUT,
+        }
+    }
+
+    /// 按型号标识或FQBN匹配
+    pub fn matches(&self, key: &str) -> bool {
+        self.id.eq_ignore_ascii_case(key) || self.fqbn == key
+    }
+}
+
+/// 开发板目录
+#[derive(Debug, Clone)]
+pub struct BoardCatalog {
+    boards: Vec<BoardConfig>,
+}
+
+impl BoardCatalog {
+    /// 内置的开发板目录，每种设备类型的第一个型号为默认型号
+    pub fn builtin() -> Self {
+        let esp_flags = ["--before=default_reset", "--after=hard_reset"];
+        let boards = vec![
+            // Arduino 开发板配置
+            BoardConfig::new("uno", "Arduino Uno", DeviceType::Arduino, "arduino:avr:uno", "arduino", 115200)
+                .platformio("atmelavr", "uno"),
+            BoardConfig::new("nano", "Arduino Nano", DeviceType::Arduino, "arduino:avr:nano", "arduino", 115200)
+                .platformio("atmelavr", "nanoatmega328new")
+                .fallback("nano_old"),
+            // 很多Nano兼容板还是57600波特率的旧引导程序
+            BoardConfig::new("nano_old", "Arduino Nano (旧引导程序)", DeviceType::Arduino, "arduino:avr:nano:cpu=atmega328old", "arduino", 57600)
+                .platformio("atmelavr", "nanoatmega328")
+                .fallback("nano"),
+            BoardConfig::new("leonardo", "Arduino Leonardo", DeviceType::Arduino, "arduino:avr:leonardo", "avr109", 57600)
+                .platformio("atmelavr", "leonardo"),
+            // ESP32 开发板配置
+            BoardConfig::new("esp32", "ESP32 Dev Module", DeviceType::ESP32, "esp32:esp32:esp32", "esptool", 921600)
+                .platformio("espressif32", "esp32dev")
+                .flags(&esp_flags),
+            BoardConfig::new("esp32s2", "ESP32-S2", DeviceType::ESP32, "esp32:esp32:esp32s2", "esptool", 460800)
+                .platformio("espressif32", "esp32-s2-saola-1")
+                .flags(&esp_flags),
+            BoardConfig::new("esp32s3", "ESP32-S3", DeviceType::ESP32, "esp32:esp32:esp32s3", "esptool", 921600)
+                .platformio("espressif32", "esp32-s3-devkitc-1")
+                .flags(&esp_flags),
+            // micro:bit 配置
+            BoardConfig::new("microbit", "BBC micro:bit", DeviceType::MicroBit, "sandeepmistry:nRF5:BBCmicrobit", "copy", 115200)
+                .platformio("nordicnrf51", "bbcmicrobit"),
+            // Raspberry Pi Pico 配置
+            BoardConfig::new("pico", "Raspberry Pi Pico", DeviceType::RaspberryPiPico, "rp2040:rp2040:rpipico", "picotool", 115200)
+                .platformio("raspberrypi", "pico"),
+        ];
+        Self::with_usb_ids(boards, board_db())
+    }
+
+    /// 按开发板数据库填写各型号的USB ID
+    fn with_usb_ids(mut boards: Vec<BoardConfig>, db: &BoardDatabase) -> Self {
+        for board in &mut boards {
+            board.usb_ids = db.boards().iter()
+                .filter(|entry| entry.variant.as_deref() == Some(board.id.as_str()))
+                .map(|entry| UsbId { vid: entry.vendor_id, pid: entry.product_id })
+                .collect();
+        }
+        Self { boards }
+    }
+
+    pub fn all(&self) -> &[BoardConfig] {
+        &self.boards
+    }
+
+    /// 某种设备类型的所有型号
+    pub fn for_device_type(&self, device_type: &DeviceType) -> Vec<BoardConfig> {
+        self.boards.iter().filter(|b| &b.device_type == device_type).cloned().collect()
+    }
+
+    /// 按型号标识或FQBN查找
+    pub fn get(&self, key: &str) -> Option<&BoardConfig> {
+        self.boards.iter().find(|b| b.matches(key))
+    }
+
+    /// 设备类型的默认型号
+    pub fn default_for(&self, device_type: &DeviceType) -> Option<&BoardConfig> {
+        self.boards.iter().find(|b| &b.device_type == device_type)
+    }
+
+    /// 根据VID/PID认出型号
+    pub fn detect(&self, vendor_id: Option<u16>, product_id: Option<u16>) -> Option<&BoardConfig> {
+        let (vid, pid) = (vendor_id?, product_id?);
+        self.boards.iter().find(|b| b.usb_ids.iter().any(|id| id.vid == vid && id.pid == pid))
+    }
+
+    /// 引导程序同步失败时的备选型号
+    pub fn fallback_for(&self, board: &BoardConfig) -> Option<&BoardConfig> {
+        board.fallback.as_deref().and_then(|id| self.get(id))
+    }
+
+    /// 确定设备使用的型号
+    ///
+    /// 依次使用：用户指定的型号、VID/PID对应的型号、设备类型的默认型号。
+    /// 指定的型号必须属于该设备类型。
+    pub fn resolve(&self, variant: Option<&str>, device: &DeviceInfo) -> Result<&BoardConfig> {
+        if let Some(key) = variant.filter(|v| !v.is_empty()) {
+            let board = self.get(key).ok_or_else(|| anyhow!("未知的开发板型号: {}", key))?;
+            if board.device_type != device.device_type && device.device_type != DeviceType::Unknown {
+                return Err(anyhow!("开发板型号 {} 与设备类型 {:?} 不匹配", board.name, device.device_type));
+            }
+            return Ok(board);
+        }
+
+        self.detect(device.vendor_id, device.product_id)
+            .filter(|b| b.device_type == device.device_type)
+            .or_else(|| self.default_for(&device.device_type))
+            .ok_or_else(|| anyhow!("未找到设备类型 {:?} 的配置", device.device_type))
+    }
+}
+
+/// 判断上传失败是否是引导程序同步失败（通常是引导程序波特率不对）
+///
+/// 内置STK500客户端的错误按类型判断，外部上传工具只能按avrdude的输出判断。
+pub fn is_sync_failure(error: &anyhow::Error) -> bool {
+    if error.downcast_ref::<Stk500Error>().is_some() {
+        return true;
+    }
+    is_avrdude_sync_failure(&error.to_string())
+}
+
+/// avrdude的输出是否表示同步失败
+pub fn is_avrdude_sync_failure(output: &str) -> bool {
+    const PATTERNS: [&str; 4] = [
+        "stk500_getsync",
+        "not in sync",
+        "stk500_recv",
+        "programmer is not responding",
+    ];
+    let output = output.to_lowercase();
+    PATTERNS.iter().any(|p| output.contains(p))
+}
+
+impl Default for BoardCatalog {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_board_variant() {
+        let catalog = BoardCatalog::builtin();
+
+        // CH340的板子默认按Nano处理
+        let nano = DeviceInfo::new("COM3".to_string(), Some(0x1a86), Some(0x7523));
+        assert_eq!(catalog.resolve(None, &nano).unwrap().id, "nano");
+
+        // 用户选择的型号优先，FQBN也可以
+        assert_eq!(catalog.resolve(Some("arduino:avr:uno"), &nano).unwrap().id, "uno");
+        assert!(catalog.resolve(Some("esp32"), &nano).is_err());
+
+        let old = catalog.fallback_for(catalog.get("nano").unwrap()).unwrap();
+        assert_eq!(old.upload_speed, 57600);
+        assert!(is_avrdude_sync_failure("avrdude: stk500_getsync() attempt 10 of 10: not in sync: resp=0x00"));
+        assert!(!is_avrdude_sync_failure("avrdude: verification error, first mismatch at byte 0x0000"));
+
+        let esp = DeviceInfo::new("COM4".to_string(), Some(0x10c4), Some(0xea60));
+        assert_eq!(catalog.resolve(None, &esp).unwrap().fqbn, "esp32:esp32:esp32");
+    }
+
+    #[test]
+    fn test_usb_ids_follow_board_database() {
+        let db = BoardDatabase::from_json(r#"{"boards": [
+            {"name": "Leonardo", "vendor_id": "0x2341", "product_id": "0x8036", "device_type": "Arduino",
+             "variant": "leonardo", "default_baud_rate": 9600, "languages": ["arduino"]},
+            {"name": "CH340", "vendor_id": "0x1a86", "product_id": "0x7523", "device_type": "Arduino",
+             "default_baud_rate": 9600, "languages": ["arduino"]}
+        ]}"#).unwrap();
+        let catalog = BoardCatalog::with_usb_ids(BoardCatalog::builtin().boards, &db);
+
+        assert_eq!(catalog.detect(Some(0x2341), Some(0x8036)).unwrap().id, "leonardo");
+        assert!(catalog.detect(Some(0x1a86), Some(0x7523)).is_none());
+        assert!(catalog.get("uno").unwrap().usb_ids.is_empty());
+    }
+
+    #[test]
+    fn test_native_usb_boards_wait_longer_to_resume() {
+        let catalog = BoardCatalog::builtin();
+        assert!(catalog.get("leonardo").unwrap().resume_timeout() > Duration::from_secs(8));
+        assert_eq!(catalog.get("uno").unwrap().resume_timeout(), SERIAL_CHIP_RESUME_TIMEOUT);
+    }
+
+    #[test]
+    fn test_stk500_sync_failure_is_typed() {
+        assert!(is_sync_failure(&Stk500Error::NotInSync.into()));
+        assert!(is_sync_failure(&anyhow::Error::from(Stk500Error::LostSync(0x00)).context("上传失败")));
+        assert!(is_sync_failure(&anyhow!("Arduino代码上传失败: avrdude: stk500_recv(): programmer is not responding")));
+        assert!(!is_sync_failure(&anyhow!("Arduino代码编译失败: 'foo' was not declared")));
+    }
+}
@@ -13,6 +13,9 @@ pub struct DeviceProfile {
     pub name: String,
     pub device_type: DeviceType,
     pub preferred_language: String,
+    /// 开发板型号（例如 "nano"），为空时按VID/PID推断
+    #[serde(default)]
+    pub board_variant: Option<String>,
     pub baud_rate: u32,
     pub auto_reconnect: bool,
     pub reconnect_interval_ms: u64,
@@ -40,6 +43,7 @@ impl DeviceProfile {
             name,
             device_type,
             preferred_language,
+            board_variant: None,
             baud_rate,
             auto_reconnect: true,
             reconnect_interval_ms: 5000,
@@ -94,6 +98,7 @@ impl ConnectionManager {
             match key.as_str() {
                 "name" => profile.name = value.as_str().unwrap_or(&profile.name).to_string(),
                 "preferred_language" => profile.preferred_language = value.as_str().unwrap_or(&profile.preferred_language).to_string(),
+                "board_variant" => profile.board_variant = value.as_str().map(|v| v.to_string()),
                 "baud_rate" => profile.baud_rate = value.as_u64().unwrap_or(profile.baud_rate as u64) as u32,
                 "auto_reconnect" => profile.auto_reconnect = value.as_bool().unwrap_or(profile.auto_reconnect),
                 "reconnect_interval_ms" => profile.reconnect_interval_ms = value.as_u64().unwrap_or(profile.reconnect_interval_ms),
@@ -1,4 +1,8 @@
 use super::{DeviceInfo, DeviceType, driver::DriverManager, connection_manager::ConnectionManager};
+use super::board_db::{board_db, UsbBoardEntry};
+use super::hotplug::{diff_devices, DeviceChange};
+use super::probe::{ProbeCache, ProbeResult};
+use super::transport::{VIRTUAL_ARDUINO_PORT, VIRTUAL_ARDUINO_USB_ID, VIRTUAL_PORT_PREFIX};
 use anyhow::{Result, anyhow};
 use serialport::SerialPortType;
 use std::collections::{HashMap, HashSet};
@@ -9,6 +13,12 @@ pub struct DeviceDetector {
     devices: HashMap<String, DeviceInfo>,
     driver_manager: DriverManager,
     connection_manager: Arc<ConnectionManager>,
+    /// 是否在设备列表中显示模拟设备（用于测试和演示）
+    virtual_devices: bool,
+    /// 自动检测出的波特率，按设备ID
+    detected_baud_rates: HashMap<String, u32>,
+    /// 主动探测的结果，重新扫描后仍然生效
+    probe_cache: ProbeCache,
 }
 
 impl DeviceDetector {
@@ -17,9 +27,20 @@ impl DeviceDetector {
             devices: HashMap::new(),
             driver_manager: DriverManager::new(),
             connection_manager: Arc::new(ConnectionManager::new()),
+            virtual_devices: std::env::var("RUSTBLOCK_VIRTUAL_DEVICES")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            detected_baud_rates: HashMap::new(),
+            probe_cache: ProbeCache::load(),
         }
     }
 
+    /// 开启或关闭模拟设备
+    pub fn set_virtual_devices(&mut self, enabled: bool) {
+        info!("模拟设备: {}", if enabled { "开启" } else { "关闭" });
+        self.virtual_devices = enabled;
+    }
+
     /// 获取连接管理器的引用
     pub fn connection_manager(&self) -> Arc<ConnectionManager> {
         Arc::clone(&self.connection_manager)
@@ -69,17 +90,40 @@ impl DeviceDetector {
             filtered_ports.push(port);
         }
 
-        for port in filtered_ports {
-            let (vendor_id, product_id) = match &port.port_type {
+        let located: Vec<_> = filtered_ports.into_iter()
+            .map(|port| {
+                let location = usb_location(&port.port_name);
+                (port, location)
+            })
+            .collect();
+        // 先找出所有序列号冲突，再生成ID，避免哪块板子拿到序列号ID取决于枚举顺序
+        let shared_serials = shared_serial_numbers(located.iter().filter_map(|(port, location)| match &port.port_type {
+            SerialPortType::UsbPort(usb_info) => Some((
+                usb_info.vid,
+                usb_info.pid,
+                usb_info.serial_number.as_deref()?,
+                location.as_deref().unwrap_or(&port.port_name),
+            )),
+            _ => None,
+        }));
+
+        for (port, location) in located {
+            let (vendor_id, product_id, serial_number) = match &port.port_type {
                 SerialPortType::UsbPort(usb_info) => {
-                    (Some(usb_info.vid), Some(usb_info.pid))
+                    (Some(usb_info.vid), Some(usb_info.pid), usb_info.serial_number.clone())
                 },
-                _ => (None, None),
+                _ => (None, None, None),
             };
 
-            // 创建硬件标识符用于去重检查
+            // 创建硬件标识符用于去重检查：同一设备的多个接口序列号和USB位置都相同，
+            // 两块相同型号的板子至少插在不同的USB口上，不会被当成重复设备
             let hardware_id = match (vendor_id, product_id) {
-                (Some(vid), Some(pid)) => format!("{:04x}:{:04x}", vid, pid),
+                (Some(vid), Some(pid)) => format!(
+                    "{:04x}:{:04x}:{}:{}",
+                    vid, pid,
+                    serial_number.as_deref().unwrap_or(""),
+                    location.as_deref().unwrap_or(&port.port_name)
+                ),
                 _ => {
                     // 对于没有VID/PID的设备，使用端口基本名称（去掉cu/tty前缀）
                     let base_name = if port.port_name.starts_with("/dev/cu.") {
@@ -109,8 +153,24 @@ impl DeviceDetector {
 
             // 获取额外的设备信息
             if let SerialPortType::UsbPort(usb_info) = &port.port_type {
-                device_info.manufacturer = usb_info.manufacturer.clone();
-                device_info.description = usb_info.product.clone();
+                device_info.set_usb_identity(serial_number, location);
+                device_info.set_usb_strings(usb_info.manufacturer.clone(), usb_info.product.clone());
+            }
+            // 有的廉价芯片所有板子的序列号都一样，这些板子都改用USB位置区分
+            let shared_serial = match (vendor_id, product_id, device_info.serial_number.as_deref()) {
+                (Some(vid), Some(pid), Some(serial)) => shared_serials.contains(&serial_key(vid, pid, serial)),
+                _ => false,
+            };
+            if shared_serial {
+                debug!("序列号与其他设备相同，按USB位置生成ID: {}", port.port_name);
+                device_info.disambiguate_id();
+            }
+            if self.devices.contains_key(&device_info.id) {
+                warn!("设备ID重复: {}，改用USB位置区分", device_info.id);
+                device_info.disambiguate_id();
+            }
+            if let Some(result) = self.probe_cache.get(&device_info) {
+                device_info.apply_probe(result.clone());
             }
 
             // 记录设备详细信息用于调试
@@ -131,17 +191,86 @@ impl DeviceDetector {
             }
         }
 
+        if self.virtual_devices {
+            let (vid, pid) = VIRTUAL_ARDUINO_USB_ID;
+            let mut device_info = DeviceInfo::new(VIRTUAL_ARDUINO_PORT.to_string(), Some(vid), Some(pid));
+            // 测试用的USB ID不在开发板数据库中，直接按Arduino处理
+            device_info.device_type = DeviceType::Arduino;
+            device_info.name = DeviceInfo::generate_device_name(&DeviceType::Arduino, VIRTUAL_ARDUINO_PORT);
+            device_info.manufacturer = Some("RustBlock 模拟设备".to_string());
+            device_info.description = Some("RustBlock 模拟设备".to_string());
+            self.devices.insert(device_info.id.clone(), device_info.clone());
+            detected_devices.push(device_info);
+        }
+
         info!("扫描完成，共发现 {} 个唯一设备", detected_devices.len());
         Ok(detected_devices)
     }
 
+    /// 重新扫描设备，返回与上次扫描结果相比的变化
+    pub fn rescan(&mut self) -> Result<Vec<DeviceChange>> {
+        let previous = std::mem::take(&mut self.devices);
+        match self.scan_devices() {
+            Ok(devices) => Ok(diff_devices(&previous, &devices)),
+            Err(e) => {
+                self.devices = previous;
+                Err(e)
+            }
+        }
+    }
+
+    /// 设备在开发板数据库中的条目，探测出的设备类型与条目不同时忽略条目
+    fn board_entry(&self, device_id: &str) -> Option<&'static UsbBoardEntry> {
+        let device = self.get_device(device_id)?;
+        board_db()
+            .lookup(device.vendor_id, device.product_id, device.description.as_deref())
+            .filter(|entry| entry.device_type == device.device_type)
+    }
+
+    /// 需要主动探测的设备：USB转串口芯片用在多种板子上或类型未知，且还没有探测过
+    pub fn probe_candidates(&self) -> Vec<DeviceInfo> {
+        self.devices.values()
+            .filter(|device| device.probe.is_none() && !device.port.starts_with(VIRTUAL_PORT_PREFIX))
+            .filter(|device| {
+                device.device_type == DeviceType::Unknown || board_db()
+                    .lookup(device.vendor_id, device.product_id, device.description.as_deref())
+                    .is_some_and(|entry| entry.ambiguous)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// 保存探测结果并修正设备信息
+    pub fn record_probe(&mut self, device_id: &str, result: ProbeResult) -> Option<DeviceInfo> {
+        let device = self.devices.get_mut(device_id)?;
+        self.probe_cache.insert(device, result.clone());
+        device.apply_probe(result);
+        Some(device.clone())
+    }
+
+    /// 重新探测没有识别出设备时，清除之前的探测结果
+    pub fn clear_probe(&mut self, device_id: &str) {
+        if let Some(device) = self.devices.get_mut(device_id) {
+            self.probe_cache.remove(device);
+            device.probe = None;
+        }
+    }
+
     /// 获取指定设备的详细信息
     pub fn get_device(&self, device_id: &str) -> Option<&DeviceInfo> {
         self.devices.get(device_id)
     }
 
+    /// 获取当前已检测到的所有设备
+    pub fn list_devices(&self) -> Vec<DeviceInfo> {
+        self.devices.values().cloned().collect()
+    }
+
     /// 检查设备是否支持指定的编程语言
     pub fn supports_language(&self, device_id: &str, language: &str) -> bool {
+        if let Some(entry) = self.board_entry(device_id) {
+            return entry.languages.iter().any(|l| l == language);
+        }
         if let Some(device) = self.get_device(device_id) {
             match (&device.device_type, language) {
                 (DeviceType::Arduino, "arduino") => true,
@@ -159,6 +288,18 @@ impl DeviceDetector {
 
     /// 根据设备类型获取推荐的编程语言
     pub fn get_recommended_language(&self, device_id: &str) -> Option<&'static str> {
+        // 探测到板子上正在运行的固件时按固件推荐
+        let probed = self.get_device(device_id)
+            .and_then(|device| device.probe.as_ref())
+            .and_then(|probe| probe.language.as_deref());
+        match probed {
+            Some("micropython") => return Some("micropython"),
+            Some("arduino") => return Some("arduino"),
+            _ => {}
+        }
+        if let Some(language) = self.board_entry(device_id).and_then(|entry| entry.languages.first()) {
+            return Some(language.as_str());
+        }
         self.get_device(device_id).map(|device| {
             match device.device_type {
                 DeviceType::Arduino => "arduino",
@@ -170,8 +311,19 @@ impl DeviceDetector {
         })
     }
 
-    /// 获取设备的波特率配置
+    /// 记住自动检测出的波特率
+    pub fn set_detected_baud_rate(&mut self, device_id: &str, baud_rate: u32) {
+        self.detected_baud_rates.insert(device_id.to_string(), baud_rate);
+    }
+
+    /// 获取设备的波特率配置，自动检测过的优先，否则按设备类型猜测
     pub fn get_baud_rate(&self, device_id: &str) -> u32 {
+        if let Some(&baud_rate) = self.detected_baud_rates.get(device_id) {
+            return baud_rate;
+        }
+        if let Some(entry) = self.board_entry(device_id) {
+            return entry.default_baud_rate;
+        }
         if let Some(device) = self.get_device(device_id) {
             match device.device_type {
                 DeviceType::Arduino => 9600,
@@ -227,19 +379,12 @@ impl DeviceDetector {
                     return Ok("设备驱动已安装！".to_string());
                 }
                 
-                // 尝试根据VID/PID获取驱动
-                if let Some(driver) = self.driver_manager.get_required_driver(vid, pid) {
-                    let driver_name = driver.name.clone();
-                    // 获取驱动键名（简化版，实际应该有更好的映射）
-                    let driver_key = match vid {
-                        0x1a86 => "ch340",
-                        0x2341 => "arduino_usb",
-                        0x10c4 => "cp210x",
-                        0x0d28 => "microbit_usb",
-                        0x2e8a => "pico_usb",
-                        _ => return Err(anyhow!("不支持的设备类型：VID:0x{:04x}, PID:0x{:04x}", vid, pid)),
-                    };
-                    return self.driver_manager.install_driver(driver_key).await;
+                // 根据开发板数据库获取驱动
+                let driver_key = board_db()
+                    .lookup(Some(vid), Some(pid), device.description.as_deref())
+                    .and_then(|entry| entry.driver.clone());
+                if let Some(driver_key) = driver_key {
+                    return self.driver_manager.install_driver(&driver_key).await;
                 }
             }
             
@@ -327,6 +472,9 @@ impl DeviceDetector {
 
     /// 获取设备支持的编程语言列表
     pub fn get_supported_languages(&self, device_id: &str) -> Vec<String> {
+        if let Some(entry) = self.board_entry(device_id) {
+            return entry.languages.clone();
+        }
         if let Some(device) = self.get_device(device_id) {
             match device.device_type {
                 DeviceType::Arduino => vec!["arduino".to_string()],
@@ -354,4 +502,111 @@ pub struct DeviceStatus {
 pub struct DriverStatus {
     pub installed: bool,
     pub driver_info: Option<super::driver::DriverInfo>,
-} 
\ No newline at end of file
+} 
+
+/// 按VID/PID和序列号区分设备的键
+fn serial_key(vendor_id: u16, product_id: u16, serial_number: &str) -> String {
+    format!("{:04x}:{:04x}:{}", vendor_id, product_id, serial_number)
+}
+
+/// 找出插在不同USB位置上却有相同序列号的设备
+///
+/// 输入为 (VID, PID, 序列号, USB位置或串口名)；同一设备的多个接口位置相同，不算冲突。
+fn shared_serial_numbers<'a>(ports: impl IntoIterator<Item = (u16, u16, &'a str, &'a str)>) -> HashSet<String> {
+    let mut locations: HashMap<String, HashSet<&str>> = HashMap::new();
+    for (vid, pid, serial, location) in ports {
+        if !serial.is_empty() {
+            locations.entry(serial_key(vid, pid, serial)).or_default().insert(location);
+        }
+    }
+    locations.into_iter()
+        .filter(|(_, locations)| locations.len() > 1)
+        .map(|(key, _)| key)
+        .collect()
+}
+
+/// 串口所在的物理USB位置（例如Linux的 `1-2.3`），换串口名后不变
+fn usb_location(port_name: &str) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        let name = std::path::Path::new(port_name).file_name()?.to_str()?;
+        let device = std::fs::canonicalize(format!("/sys/class/tty/{}/device", name)).ok()?;
+        usb_path_from_sysfs(&device)
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        // macOS的 usbmodem14201、usbserial-1420 后缀是USB位置ID
+        let name = port_name.strip_prefix("/dev/cu.").or_else(|| port_name.strip_prefix("/dev/tty."))?;
+        let suffix = name.strip_prefix("usbmodem")
+            .or_else(|| name.strip_prefix("usbserial-"))
+            .or_else(|| name.strip_prefix("wchusbserial"))?;
+        (!suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_digit())).then(|| suffix.to_string())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = port_name;
+        None
+    }
+}
+
+/// 从sysfs设备路径中取出最后一级USB端口路径，
+/// 例如 `/sys/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2.3/1-2.3:1.0/ttyUSB0` 得到 `1-2.3`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn usb_path_from_sysfs(path: &std::path::Path) -> Option<String> {
+    path.components()
+        .filter_map(|c| c.as_os_str().to_str())
+        .filter(|c| {
+            c.split_once('-').is_some_and(|(bus, port)| {
+                !bus.is_empty() && bus.chars().all(|ch| ch.is_ascii_digit())
+                    && !port.is_empty() && port.chars().all(|ch| ch.is_ascii_digit() || ch == '.')
+            })
+        })
+        .last()
+        .map(|c| c.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_usb_path_from_sysfs() {
+        let path = Path::new("/sys/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2.3/1-2.3:1.0/ttyUSB0");
+        assert_eq!(usb_path_from_sysfs(path).as_deref(), Some("1-2.3"));
+        assert_eq!(usb_path_from_sysfs(Path::new("/sys/devices/platform/serial8250/tty/ttyS0")), None);
+    }
+
+    #[test]
+    fn test_shared_serial_numbers() {
+        let shared = shared_serial_numbers([
+            (0x1a86, 0x55d4, "0001", "1-2"),
+            (0x1a86, 0x55d4, "0001", "1-2"),
+            (0x1a86, 0x55d4, "0001", "1-3"),
+            (0x10c4, 0xea60, "0002", "1-4"),
+        ]);
+        assert_eq!(shared.len(), 1);
+        assert!(shared.contains("1a86:55d4:0001"));
+    }
+
+    #[test]
+    fn test_usb_identity() {
+        // 同一块板子换了串口名，ID不变
+        let mut first = DeviceInfo::new("/dev/ttyUSB0".to_string(), Some(0x10c4), Some(0xea60));
+        first.set_usb_identity(Some("0001".to_string()), Some("1-2".to_string()));
+        let mut moved = DeviceInfo::new("/dev/ttyUSB1".to_string(), Some(0x10c4), Some(0xea60));
+        moved.set_usb_identity(Some("0001".to_string()), Some("1-3".to_string()));
+        assert_eq!(first.id, moved.id);
+
+        // 序列号相同的两块板子都按USB位置区分
+        first.disambiguate_id();
+        moved.disambiguate_id();
+        assert_eq!(first.id, "usb_10c4_ea60_at_1-2");
+        assert_eq!(moved.id, "usb_10c4_ea60_at_1-3");
+        let mut no_serial = DeviceInfo::new("/dev/ttyUSB2".to_string(), Some(0x1a86), Some(0x7523));
+        no_serial.set_usb_identity(None, Some("1-4".to_string()));
+        assert!(no_serial.id.contains("1-4"));
+    }
+}
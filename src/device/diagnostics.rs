@@ -0,0 +1,355 @@
+use super::board_db::BoardDatabase;
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeSet, HashMap};
+use std::process::Stdio;
+
+/// 安装udev规则的位置
+pub const UDEV_RULES_PATH: &str = "/etc/udev/rules.d/99-rustblock.rules";
+/// 常见发行版中拥有串口设备的用户组
+const SERIAL_GROUPS: [&str; 3] = ["dialout", "uucp", "tty"];
+/// brltty的udev规则会把CH340当成盲文显示器占用
+const BRLTTY_RULES: [&str; 2] = ["/usr/lib/udev/rules.d/85-brltty.rules", "/lib/udev/rules.d/85-brltty.rules"];
+/// 上传时直接访问的原始USB设备：引导程序和调试接口
+const BOOTLOADER_USB_IDS: [(u16, u16, &str); 3] = [
+    (0x2e8a, 0x0003, "Raspberry Pi Pico BOOTSEL"),
+    (0x2e8a, 0x000f, "Raspberry Pi Pico 2 BOOTSEL"),
+    (0x0d28, 0x0204, "micro:bit DAPLink"),
+];
+
+/// 诊断出的问题
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum IssueKind {
+    /// 当前用户没有串口设备的读写权限
+    NoAccess,
+    /// 用户不在串口用户组中
+    NotInGroup,
+    /// 已加入用户组但还没有重新登录
+    ReloginRequired,
+    /// ModemManager会在ACM串口出现时发送AT命令探测，干扰上传
+    ModemManager,
+    /// brltty会抢占CH340串口
+    Brltty,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticIssue {
+    pub kind: IssueKind,
+    pub port: Option<String>,
+    pub message: String,
+    /// 建议的修复方法
+    pub fix: String,
+}
+
+/// 一个串口设备节点的权限
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortAccess {
+    pub port: String,
+    pub owner_group: Option<String>,
+    /// 八进制权限，例如 "660"
+    pub mode: String,
+    pub accessible: bool,
+}
+
+/// 串口权限诊断结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SerialDiagnostics {
+    /// 只有Linux需要这些检查
+    pub supported: bool,
+    pub user: Option<String>,
+    /// 当前会话生效的用户组
+    pub groups: Vec<String>,
+    pub ports: Vec<PortAccess>,
+    pub issues: Vec<DiagnosticIssue>,
+    pub udev_rules_installed: bool,
+}
+
+/// 按权限位判断能否读写（不考虑ACL）
+pub fn has_access(mode: u32, owner_uid: u32, owner_gid: u32, uid: u32, gids: &[u32]) -> bool {
+    if uid == 0 {
+        return true;
+    }
+    if owner_uid == uid {
+        return mode & 0o600 == 0o600;
+    }
+    if gids.contains(&owner_gid) {
+        return mode & 0o060 == 0o060;
+    }
+    mode & 0o006 == 0o006
+}
+
+/// 解析 /etc/group，返回组ID到组名和成员的映射
+fn parse_groups(content: &str) -> HashMap<u32, (String, Vec<String>)> {
+    content.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?.to_string();
+            let gid = fields.nth(1)?.parse().ok()?;
+            let members = fields.next()
+                .map(|m| m.split(',').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect())
+                .unwrap_or_default();
+            Some((gid, (name, members)))
+        })
+        .collect()
+}
+
+/// 为开发板数据库中的所有设备生成udev规则
+///
+/// 串口保持串口用户组的 0660 权限，另外通过 `uaccess` 让本机登录的用户可以读写，
+/// 并让ModemManager忽略这些设备；引导程序和调试接口只放开确切的VID/PID。
+pub fn generate_udev_rules(db: &BoardDatabase) -> String {
+    let mut rules = String::from("# RustBlock 开发板串口权限，由 RustBlock 自动生成\n");
+    let mut seen = BTreeSet::new();
+    for board in db.boards() {
+        if !seen.insert((board.vendor_id, board.product_id)) {
+            continue;
+        }
+        // 名称可能来自用户的开发板数据库，去掉换行等控制字符，避免注释变成规则
+        let name: String = board.name.chars().filter(|c| !c.is_control()).collect();
+        rules.push_str(&format!("\n# {}\n", name));
+        rules.push_str(&format!(
+            "SUBSYSTEM==\"tty\", ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\", MODE=\"0660\", TAG+=\"uaccess\", ENV{{ID_MM_DEVICE_IGNORE}}=\"1\"\n",
+            board.vendor_id, board.product_id
+        ));
+    }
+
+    rules.push_str("\n# 引导程序和调试接口的原始USB设备\n");
+    for (vid, pid, name) in BOOTLOADER_USB_IDS {
+        rules.push_str(&format!(
+            "# {}\nSUBSYSTEM==\"usb\", ATTR{{idVendor}}==\"{:04x}\", ATTR{{idProduct}}==\"{:04x}\", MODE=\"0660\", TAG+=\"uaccess\"\n",
+            name, vid, pid
+        ));
+    }
+    rules
+}
+
+/// 检查USB串口的权限和常见的占用问题
+pub fn diagnose() -> SerialDiagnostics {
+    #[cfg(target_os = "linux")]
+    {
+        diagnose_linux()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        SerialDiagnostics::default()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn diagnose_linux() -> SerialDiagnostics {
+    use serialport::SerialPortType;
+    use std::os::unix::fs::MetadataExt;
+
+    let (uid, gids) = process_ids().unwrap_or((u32::MAX, Vec::new()));
+    let groups = std::fs::read_to_string("/etc/group").map(|c| parse_groups(&c)).unwrap_or_default();
+    let user = std::env::var("USER").ok();
+    let group_name = |gid: u32| groups.get(&gid).map(|(name, _)| name.clone());
+
+    let mut report = SerialDiagnostics {
+        supported: true,
+        user: user.clone(),
+        groups: gids.iter().filter_map(|&gid| group_name(gid)).collect(),
+        udev_rules_installed: std::path::Path::new(UDEV_RULES_PATH).exists(),
+        ..Default::default()
+    };
+
+    let usb_ports: Vec<_> = serialport::available_ports().unwrap_or_default()
+        .into_iter()
+        .filter_map(|p| match p.port_type {
+            SerialPortType::UsbPort(info) => Some((p.port_name, info.vid, info.pid)),
+            _ => None,
+        })
+        .collect();
+
+    for (port, vid, pid) in &usb_ports {
+        let Ok(metadata) = std::fs::metadata(port) else {
+            continue;
+        };
+        let owner_group = group_name(metadata.gid());
+        // 规则覆盖的设备通过uaccess的ACL授权，权限位上看不出来
+        let covered = report.udev_rules_installed
+            && super::board_db::board_db().boards().iter().any(|b| b.vendor_id == *vid && b.product_id == *pid);
+        let accessible = covered || has_access(metadata.mode(), metadata.uid(), metadata.gid(), uid, &gids);
+        report.ports.push(PortAccess {
+            port: port.clone(),
+            owner_group: owner_group.clone(),
+            mode: format!("{:o}", metadata.mode() & 0o777),
+            accessible,
+        });
+        if accessible {
+            continue;
+        }
+
+        let group = owner_group.unwrap_or_else(|| "dialout".to_string());
+        let member = user.as_ref().is_some_and(|user| {
+            groups.get(&metadata.gid()).is_some_and(|(_, members)| members.contains(user))
+        });
+        report.issues.push(DiagnosticIssue {
+            kind: IssueKind::NoAccess,
+            port: Some(port.clone()),
+            message: format!("没有 {} 的读写权限（属于 {} 组，权限 {:o}）", port, group, metadata.mode() & 0o777),
+            fix: "安装udev规则，或把当前用户加入串口用户组".to_string(),
+        });
+        if member {
+            report.issues.push(DiagnosticIssue {
+                kind: IssueKind::ReloginRequired,
+                port: Some(port.clone()),
+                message: format!("已加入 {} 组，但当前会话还没有生效", group),
+                fix: "注销后重新登录（或重启电脑）".to_string(),
+            });
+        } else if SERIAL_GROUPS.contains(&group.as_str()) {
+            report.issues.push(DiagnosticIssue {
+                kind: IssueKind::NotInGroup,
+                port: Some(port.clone()),
+                message: format!("当前用户不在 {} 组中", group),
+                fix: format!("sudo usermod -aG {} {}，然后重新登录", group, user.as_deref().unwrap_or("$USER")),
+            });
+        }
+    }
+
+    let running = running_processes();
+    if running.contains("ModemManager") {
+        if let Some((port, _, _)) = usb_ports.iter().find(|(port, _, _)| port.contains("ttyACM")) {
+            report.issues.push(DiagnosticIssue {
+                kind: IssueKind::ModemManager,
+                port: Some(port.clone()),
+                message: "ModemManager正在运行，会在ACM串口出现时占用它发送探测命令".to_string(),
+                fix: "安装udev规则让ModemManager忽略开发板，或 sudo systemctl disable --now ModemManager".to_string(),
+            });
+        }
+    }
+
+    let brltty_claims_ch340 = BRLTTY_RULES.iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .any(|rules| rules.contains("1a86") && rules.contains("7523"));
+    let has_ch340 = usb_ports.iter().any(|&(_, vid, pid)| vid == 0x1a86 && pid == 0x7523);
+    if brltty_claims_ch340 && (has_ch340 || running.contains("brltty")) {
+        report.issues.push(DiagnosticIssue {
+            kind: IssueKind::Brltty,
+            port: None,
+            message: "brltty会把CH340芯片当成盲文显示器，插入后串口会立即消失".to_string(),
+            fix: "不使用盲文显示器时执行 sudo apt remove brltty（或 sudo systemctl mask brltty-udev.service）".to_string(),
+        });
+    }
+
+    report
+}
+
+/// 从 /proc/self/status 读取当前进程的UID和附加组
+#[cfg(target_os = "linux")]
+fn process_ids() -> Option<(u32, Vec<u32>)> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let field = |name: &str| status.lines().find_map(|line| line.strip_prefix(name)).map(|v| v.trim().to_string());
+    let uid = field("Uid:")?.split_whitespace().nth(1)?.parse().ok()?;
+    let mut gids: Vec<u32> = field("Groups:")?.split_whitespace().filter_map(|g| g.parse().ok()).collect();
+    if let Some(gid) = field("Gid:").and_then(|g| g.split_whitespace().nth(1)?.parse().ok()) {
+        gids.push(gid);
+    }
+    Some((uid, gids))
+}
+
+#[cfg(target_os = "linux")]
+fn running_processes() -> BTreeSet<String> {
+    std::fs::read_dir("/proc")
+        .map(|entries| entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| std::fs::read_to_string(e.path().join("comm")).ok())
+            .map(|comm| comm.trim().to_string())
+            .collect())
+        .unwrap_or_default()
+}
+
+/// 通过pkexec安装udev规则并重新加载，返回安装位置
+///
+/// 规则内容从标准输入交给root的shell，不经过共享的临时目录。
+pub async fn install_udev_rules(rules: &str) -> Result<String> {
+    use tokio::io::AsyncWriteExt;
+
+    if !cfg!(target_os = "linux") {
+        return Err(anyhow!("只有Linux需要安装udev规则"));
+    }
+
+    let script = "umask 022 && cat > \"$0.new\" && mv \"$0.new\" \"$0\" && udevadm control --reload-rules && udevadm trigger";
+    let mut child = tokio::process::Command::new("pkexec")
+        .args(["sh", "-c", script, UDEV_RULES_PATH])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("无法运行pkexec: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // 用户取消授权时shell不会读取输入，写入失败由退出码报告
+        let _ = stdin.write_all(rules.as_bytes()).await;
+    }
+    let output = child.wait_with_output()
+        .await
+        .map_err(|e| anyhow!("等待pkexec失败: {}", e))?;
+
+    match output.status.code() {
+        Some(0) => Ok(UDEV_RULES_PATH.to_string()),
+        // pkexec: 126 用户取消授权，127 未授权
+        Some(126) | Some(127) => Err(anyhow!("没有获得管理员授权")),
+        _ => Err(anyhow!("安装udev规则失败: {}", String::from_utf8_lossy(&output.stderr).trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIALOUT: u32 = 20;
+
+    #[test]
+    fn test_access_through_group() {
+        assert!(!has_access(0o20660, 0, DIALOUT, 1000, &[1000]));
+        assert!(has_access(0o20660, 0, DIALOUT, 1000, &[1000, DIALOUT]));
+    }
+
+    #[test]
+    fn test_access_world_writable_and_root() {
+        assert!(has_access(0o20666, 0, DIALOUT, 1000, &[1000]));
+        assert!(has_access(0o20600, 0, DIALOUT, 0, &[0]));
+    }
+
+    #[test]
+    fn test_parse_group_file() {
+        let groups = parse_groups("root:x:0:\ndialout:x:20:alice,bob\nuucp:x:14:\n");
+        assert_eq!(groups[&20].0, "dialout");
+        assert_eq!(groups[&20].1, vec!["alice", "bob"]);
+        assert!(groups[&14].1.is_empty());
+    }
+
+    #[test]
+    fn test_udev_rules_cover_known_boards() {
+        let rules = generate_udev_rules(&BoardDatabase::bundled());
+        assert!(rules.contains(r#"ATTRS{idVendor}=="1a86", ATTRS{idProduct}=="7523""#));
+        assert!(rules.contains(r#"ENV{ID_MM_DEVICE_IGNORE}="1""#));
+    }
+
+    #[test]
+    fn test_udev_rules_usb_only_for_bootloaders() {
+        let rules = generate_udev_rules(&BoardDatabase::bundled());
+        assert!(rules.contains(r#"SUBSYSTEM=="usb", ATTR{idVendor}=="2e8a", ATTR{idProduct}=="0003""#));
+        assert!(rules.contains(r#"SUBSYSTEM=="usb", ATTR{idVendor}=="0d28", ATTR{idProduct}=="0204""#));
+        assert!(!rules.contains(r#"SUBSYSTEM=="usb", ATTR{idVendor}=="2341""#));
+    }
+
+    #[test]
+    fn test_udev_rules_not_world_writable() {
+        let rules = generate_udev_rules(&BoardDatabase::bundled());
+        assert!(!rules.contains("0666"));
+        assert!(rules.lines().filter(|line| line.starts_with("SUBSYSTEM")).all(|line| line.contains(r#"TAG+="uaccess""#)));
+    }
+
+    #[test]
+    fn test_udev_rules_strip_control_characters_from_names() {
+        let db = BoardDatabase::from_json(r#"{"boards": [{
+            "name": "Evil\nSUBSYSTEM==\"tty\", RUN+=\"/bin/sh\"", "vendor_id": "0x1234", "product_id": "0x5678",
+            "device_type": "Arduino", "default_baud_rate": 9600, "languages": ["arduino"]
+        }]}"#).unwrap();
+        let rules = generate_udev_rules(&db);
+        assert!(rules.lines().filter(|line| line.contains("RUN+=")).all(|line| line.starts_with('#')));
+    }
+}
@@ -3,6 +3,7 @@ use std::process::Command;
 use log::{info, error, debug, warn};
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
+use super::board_db::board_db;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DriverInfo {
@@ -28,62 +29,29 @@ impl DriverManager {
         manager
     }
 
-    /// 初始化驱动程序数据库
+    /// 从开发板数据库初始化驱动程序列表
     fn initialize_driver_database(&mut self) {
-        // Arduino Uno/Nano CH340 驱动
-        self.drivers.insert("ch340".to_string(), DriverInfo {
-            name: "CH340/CH341 USB Serial Driver".to_string(),
-            version: None,
-            vendor_id: 0x1a86,
-            product_id: 0x7523,
-            description: "CH340/CH341 USB to Serial converter driver".to_string(),
-            installed: false,
-            required_for_devices: vec![super::DeviceType::Arduino, super::DeviceType::ESP32],
-        });
-
-        // Arduino 官方驱动
-        self.drivers.insert("arduino_usb".to_string(), DriverInfo {
-            name: "Arduino USB Driver".to_string(),
-            version: None,
-            vendor_id: 0x2341,
-            product_id: 0x0043,
-            description: "Official Arduino USB driver".to_string(),
-            installed: false,
-            required_for_devices: vec![super::DeviceType::Arduino],
-        });
-
-        // CP210x 驱动 (ESP32 常用)
-        self.drivers.insert("cp210x".to_string(), DriverInfo {
-            name: "Silicon Labs CP210x USB to UART Bridge".to_string(),
-            version: None,
-            vendor_id: 0x10c4,
-            product_id: 0xea60,
-            description: "CP210x USB to Serial converter driver".to_string(),
-            installed: false,
-            required_for_devices: vec![super::DeviceType::ESP32],
-        });
-
-        // micro:bit 驱动
-        self.drivers.insert("microbit_usb".to_string(), DriverInfo {
-            name: "micro:bit USB Driver".to_string(),
-            version: None,
-            vendor_id: 0x0d28,
-            product_id: 0x0204,
-            description: "BBC micro:bit USB interface driver".to_string(),
-            installed: false,
-            required_for_devices: vec![super::DeviceType::MicroBit],
-        });
-
-        // 树莓派 Pico 驱动
-        self.drivers.insert("pico_usb".to_string(), DriverInfo {
-            name: "Raspberry Pi Pico USB Driver".to_string(),
-            version: None,
-            vendor_id: 0x2e8a,
-            product_id: 0x0005,
-            description: "Raspberry Pi Pico USB interface driver".to_string(),
-            installed: false,
-            required_for_devices: vec![super::DeviceType::RaspberryPiPico],
-        });
+        let db = board_db();
+        for (key, driver) in db.drivers() {
+            let boards: Vec<_> = db.boards().iter()
+                .filter(|b| b.driver.as_deref() == Some(key.as_str()))
+                .collect();
+            let mut required_for_devices = Vec::new();
+            for board in &boards {
+                if !required_for_devices.contains(&board.device_type) {
+                    required_for_devices.push(board.device_type.clone());
+                }
+            }
+            self.drivers.insert(key.clone(), DriverInfo {
+                name: driver.name.clone(),
+                version: None,
+                vendor_id: boards.first().map_or(0, |b| b.vendor_id),
+                product_id: boards.first().map_or(0, |b| b.product_id),
+                description: driver.description.clone(),
+                installed: false,
+                required_for_devices,
+            });
+        }
     }
 
     /// 检查系统中已安装的驱动程序
@@ -206,6 +174,11 @@ impl DriverManager {
             self.parse_lsmod_output(&output_str);
         }
 
+        // 驱动正常时最常见的问题是没有串口权限
+        for issue in super::diagnostics::diagnose().issues {
+            warn!("串口问题: {} ({})", issue.message, issue.fix);
+        }
+
         Ok(self.get_installed_drivers())
     }
 
@@ -213,9 +186,10 @@ impl DriverManager {
     pub fn get_required_driver(&self, vendor_id: u16, product_id: u16) -> Option<&DriverInfo> {
         debug!("查找驱动: VID:0x{:04x}, PID:0x{:04x}", vendor_id, product_id);
         
-        let result = self.drivers.values().find(|driver| {
-            driver.vendor_id == vendor_id && driver.product_id == product_id
-        });
+        let result = board_db()
+            .lookup(Some(vendor_id), Some(product_id), None)
+            .and_then(|entry| entry.driver.as_deref())
+            .and_then(|key| self.drivers.get(key));
         
         match result {
             Some(driver) => {
@@ -0,0 +1,998 @@
+use super::reset::{esp_bootloader_reset, esp_hard_reset};
+use super::serial::PortControl;
+use anyhow::{Result, anyhow};
+use flate2::{write::ZlibEncoder, Compression};
+use log::{debug, info, warn};
+use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+// SLIP帧定界与转义字节
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+// ROM引导程序命令
+const CMD_SPI_SET_PARAMS: u8 = 0x0B;
+const CMD_SYNC: u8 = 0x08;
+const CMD_WRITE_REG: u8 = 0x09;
+const CMD_READ_REG: u8 = 0x0A;
+const CMD_SPI_ATTACH: u8 = 0x0D;
+const CMD_CHANGE_BAUDRATE: u8 = 0x0F;
+const CMD_FLASH_DEFL_BEGIN: u8 = 0x10;
+const CMD_FLASH_DEFL_DATA: u8 = 0x11;
+const CMD_FLASH_DEFL_END: u8 = 0x12;
+const CMD_SPI_FLASH_MD5: u8 = 0x13;
+
+/// 数据包校验和的初始值
+const CHECKSUM_MAGIC: u8 = 0xEF;
+/// 芯片识别寄存器地址，不同芯片读出的魔数不同
+const CHIP_DETECT_MAGIC_REG: u32 = 0x4000_1000;
+/// ROM引导程序每个数据块的大小
+const FLASH_WRITE_SIZE: usize = 0x400;
+const FLASH_SECTOR_SIZE: u32 = 0x1000;
+/// 读不到Flash ID时假定的容量
+const DEFAULT_FLASH_SIZE: u32 = 4 * 1024 * 1024;
+
+// SPI用户命令相关的寄存器位
+const SPI_CMD_USR: u32 = 1 << 18;
+const SPI_USR_COMMAND: u32 = 1 << 31;
+const SPI_USR_MISO: u32 = 1 << 28;
+const SPI_USR2_COMMAND_LEN_SHIFT: u32 = 28;
+/// SPI Flash的读JEDEC ID指令
+const SPIFLASH_RDID: u32 = 0x9F;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+const SYNC_TIMEOUT: Duration = Duration::from_millis(100);
+/// 擦除和写入按数据量放宽超时（每MB）
+const ERASE_TIMEOUT_PER_MB: Duration = Duration::from_secs(30);
+const MD5_TIMEOUT_PER_MB: Duration = Duration::from_secs(8);
+
+/// ROM引导程序的初始波特率
+pub const ROM_BAUD_RATE: u32 = 115200;
+/// Arduino/ESP-IDF默认分区表中应用程序的地址
+pub const ESP32_APP_OFFSET: u32 = 0x10000;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EspChip {
+    ESP8266,
+    ESP32,
+    ESP32S2,
+    ESP32S3,
+    ESP32C3,
+}
+
+impl EspChip {
+    fn from_magic(magic: u32) -> Option<Self> {
+        match magic {
+            0xFFF0_C101 => Some(EspChip::ESP8266),
+            0x00F0_1D83 => Some(EspChip::ESP32),
+            0x0000_07C6 => Some(EspChip::ESP32S2),
+            0x0000_0009 => Some(EspChip::ESP32S3),
+            0x6921_506F | 0x1B31_506F => Some(EspChip::ESP32C3),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EspChip::ESP8266 => "ESP8266",
+            EspChip::ESP32 => "ESP32",
+            EspChip::ESP32S2 => "ESP32-S2",
+            EspChip::ESP32S3 => "ESP32-S3",
+            EspChip::ESP32C3 => "ESP32-C3",
+        }
+    }
+
+    /// eFuse中MAC地址所在的寄存器（低32位、高16位）
+    fn mac_registers(&self) -> Option<(u32, u32)> {
+        match self {
+            // ESP8266的MAC分散在多个eFuse字中，暂不支持
+            EspChip::ESP8266 => None,
+            EspChip::ESP32 => Some((0x3FF5_A004, 0x3FF5_A008)),
+            EspChip::ESP32S2 => Some((0x3F41_A044, 0x3F41_A048)),
+            EspChip::ESP32S3 => Some((0x6000_7044, 0x6000_7048)),
+            EspChip::ESP32C3 => Some((0x6000_8844, 0x6000_8848)),
+        }
+    }
+
+    /// ROM响应末尾状态字节的长度
+    fn status_len(&self) -> usize {
+        match self {
+            EspChip::ESP8266 => 2,
+            _ => 4,
+        }
+    }
+
+    /// 固件默认烧录地址（二级引导程序所在位置）
+    pub fn bootloader_offset(&self) -> u32 {
+        match self {
+            EspChip::ESP32 | EspChip::ESP32S2 => 0x1000,
+            _ => 0x0,
+        }
+    }
+
+    /// SPI0控制器的寄存器布局，用于直接向Flash发送指令
+    fn spi_registers(&self) -> SpiRegisters {
+        match self {
+            EspChip::ESP8266 => SpiRegisters {
+                base: 0x6000_0200,
+                usr: 0x1C,
+                usr1: 0x20,
+                usr2: 0x24,
+                miso_dlen: None,
+                w0: 0x40,
+            },
+            EspChip::ESP32 => SpiRegisters {
+                base: 0x3FF4_2000,
+                usr: 0x1C,
+                usr1: 0x20,
+                usr2: 0x24,
+                miso_dlen: Some(0x2C),
+                w0: 0x80,
+            },
+            EspChip::ESP32S2 => SpiRegisters {
+                base: 0x3F40_2000,
+                usr: 0x18,
+                usr1: 0x1C,
+                usr2: 0x20,
+                miso_dlen: Some(0x28),
+                w0: 0x58,
+            },
+            EspChip::ESP32S3 | EspChip::ESP32C3 => SpiRegisters {
+                base: 0x6000_2000,
+                usr: 0x18,
+                usr1: 0x1C,
+                usr2: 0x20,
+                miso_dlen: Some(0x28),
+                w0: 0x58,
+            },
+        }
+    }
+
+    /// ESP32-S2及之后的芯片在FLASH_BEGIN中多一个加密标志字段
+    fn supports_encrypted_flag(&self) -> bool {
+        matches!(self, EspChip::ESP32S2 | EspChip::ESP32S3 | EspChip::ESP32C3)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EspChipInfo {
+    pub chip: EspChip,
+    pub chip_name: String,
+    pub magic: u32,
+    pub mac_address: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlashProgress {
+    pub written: usize,
+    pub total: usize,
+}
+
+/// SPI0控制器寄存器的基地址和偏移
+#[derive(Debug, Clone, Copy)]
+struct SpiRegisters {
+    base: u32,
+    usr: u32,
+    usr1: u32,
+    usr2: u32,
+    /// ESP8266没有单独的长度寄存器，读取位数写在USR1中
+    miso_dlen: Option<u32>,
+    w0: u32,
+}
+
+/// 引导程序的一条响应
+#[derive(Debug, Clone)]
+struct Response {
+    value: u32,
+    data: Vec<u8>,
+}
+
+/// 将数据包编码为SLIP帧
+pub fn slip_encode(packet: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(packet.len() + 2);
+    frame.push(SLIP_END);
+    for &byte in packet {
+        match byte {
+            SLIP_END => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            _ => frame.push(byte),
+        }
+    }
+    frame.push(SLIP_END);
+    frame
+}
+
+/// 增量式SLIP解码器，可以跨多次读取拼出完整帧
+#[derive(Debug, Default)]
+pub struct SlipDecoder {
+    buffer: Vec<u8>,
+    in_frame: bool,
+    escaped: bool,
+}
+
+impl SlipDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 输入一个字节，帧结束时返回完整数据包
+    pub fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        if byte == SLIP_END {
+            if self.in_frame && !self.buffer.is_empty() {
+                self.in_frame = false;
+                self.escaped = false;
+                return Some(std::mem::take(&mut self.buffer));
+            }
+            // 帧开始（或连续的定界符）
+            self.in_frame = true;
+            self.buffer.clear();
+            return None;
+        }
+
+        if !self.in_frame {
+            // 帧外的字节（例如启动日志）直接丢弃
+            return None;
+        }
+
+        if self.escaped {
+            self.escaped = false;
+            match byte {
+                SLIP_ESC_END => self.buffer.push(SLIP_END),
+                SLIP_ESC_ESC => self.buffer.push(SLIP_ESC),
+                other => {
+                    // 非法转义，按原样保留以便上层报错
+                    self.buffer.push(SLIP_ESC);
+                    self.buffer.push(other);
+                }
+            }
+        } else if byte == SLIP_ESC {
+            self.escaped = true;
+        } else {
+            self.buffer.push(byte);
+        }
+        None
+    }
+}
+
+/// 计算数据块校验和
+pub fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(CHECKSUM_MAGIC, |acc, b| acc ^ b) as u32
+}
+
+/// ESP ROM引导程序客户端
+pub struct EspLoader<P: Read + Write + PortControl> {
+    port: P,
+    chip: Option<EspChip>,
+    baud_rate: u32,
+    /// 从Flash ID识别出的容量，首次连接Flash时读取
+    flash_size: Option<u32>,
+    decoder: SlipDecoder,
+    /// 同一次读取中多收到的完整帧，下次读取时先返回
+    pending: VecDeque<Vec<u8>>,
+}
+
+impl<P: Read + Write + PortControl> EspLoader<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            chip: None,
+            baud_rate: ROM_BAUD_RATE,
+            flash_size: None,
+            decoder: SlipDecoder::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// 取回底层串口
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// 当前识别到的芯片
+    pub fn chip(&self) -> Option<EspChip> {
+        self.chip
+    }
+
+    /// 复位进入下载模式并同步，失败时重试几次
+    pub fn connect(&mut self, attempts: u32) -> Result<()> {
+        let mut last_error = anyhow!("未尝试连接");
+        for attempt in 1..=attempts.max(1) {
+            debug!("ESP同步尝试 {}/{}", attempt, attempts);
+            esp_bootloader_reset(&mut self.port)?;
+            self.drain_input();
+
+            match self.sync() {
+                Ok(()) => {
+                    info!("ESP引导程序同步成功");
+                    let chip = self.detect_chip()?;
+                    info!("识别到芯片: {}", chip.name());
+                    return Ok(());
+                }
+                Err(e) => {
+                    debug!("同步失败: {}", e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(anyhow!("无法与ESP引导程序同步，请按住BOOT键后重试: {}", last_error))
+    }
+
+    /// 发送SYNC命令，等待引导程序响应
+    pub fn sync(&mut self) -> Result<()> {
+        let mut payload = vec![0x07, 0x07, 0x12, 0x20];
+        payload.extend(std::iter::repeat_n(0x55, 32));
+
+        for _ in 0..7 {
+            self.send_command(CMD_SYNC, &payload, 0)?;
+            if self.read_response(CMD_SYNC, SYNC_TIMEOUT).is_ok() {
+                // ROM会对一次SYNC回复多次，把多余的响应读掉
+                while self.read_response(CMD_SYNC, SYNC_TIMEOUT).is_ok() {}
+                return Ok(());
+            }
+        }
+        Err(anyhow!("SYNC无响应"))
+    }
+
+    /// 读取寄存器
+    pub fn read_reg(&mut self, address: u32) -> Result<u32> {
+        let response = self.command(CMD_READ_REG, &address.to_le_bytes(), 0, DEFAULT_TIMEOUT)?;
+        Ok(response.value)
+    }
+
+    /// 写入寄存器
+    pub fn write_reg(&mut self, address: u32, value: u32) -> Result<()> {
+        let mut data = Vec::with_capacity(16);
+        data.extend_from_slice(&address.to_le_bytes());
+        data.extend_from_slice(&value.to_le_bytes());
+        data.extend_from_slice(&0xFFFF_FFFFu32.to_le_bytes()); // mask
+        data.extend_from_slice(&0u32.to_le_bytes()); // delay
+        self.command(CMD_WRITE_REG, &data, 0, DEFAULT_TIMEOUT)?;
+        Ok(())
+    }
+
+    /// 通过魔数寄存器识别芯片型号
+    pub fn detect_chip(&mut self) -> Result<EspChip> {
+        let magic = self.read_reg(CHIP_DETECT_MAGIC_REG)?;
+        let chip = EspChip::from_magic(magic)
+            .ok_or_else(|| anyhow!("未知的芯片魔数: 0x{:08x}", magic))?;
+        self.chip = Some(chip);
+        Ok(chip)
+    }
+
+    /// 读取芯片型号和MAC地址
+    pub fn chip_info(&mut self) -> Result<EspChipInfo> {
+        let magic = self.read_reg(CHIP_DETECT_MAGIC_REG)?;
+        let chip = EspChip::from_magic(magic)
+            .ok_or_else(|| anyhow!("未知的芯片魔数: 0x{:08x}", magic))?;
+        self.chip = Some(chip);
+
+        let mac = self.read_mac()?;
+        Ok(EspChipInfo {
+            chip,
+            chip_name: chip.name().to_string(),
+            magic,
+            mac_address: mac
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(":"),
+        })
+    }
+
+    /// 从eFuse读取出厂MAC地址
+    pub fn read_mac(&mut self) -> Result<[u8; 6]> {
+        let chip = self.require_chip()?;
+        let (low_reg, high_reg) = chip.mac_registers()
+            .ok_or_else(|| anyhow!("{} 暂不支持读取MAC地址", chip.name()))?;
+        let low = self.read_reg(low_reg)?;
+        let high = self.read_reg(high_reg)?;
+
+        Ok([
+            (high >> 8) as u8,
+            high as u8,
+            (low >> 24) as u8,
+            (low >> 16) as u8,
+            (low >> 8) as u8,
+            low as u8,
+        ])
+    }
+
+    /// 切换引导程序波特率，提高烧录速度
+    pub fn change_baud(&mut self, baud_rate: u32) -> Result<()> {
+        info!("切换引导程序波特率: {} -> {}", self.baud_rate, baud_rate);
+        let mut data = baud_rate.to_le_bytes().to_vec();
+        // ROM引导程序要求第二个参数为0
+        data.extend_from_slice(&0u32.to_le_bytes());
+        self.command(CMD_CHANGE_BAUDRATE, &data, 0, DEFAULT_TIMEOUT)?;
+
+        self.port.set_baud_rate(baud_rate)?;
+        self.baud_rate = baud_rate;
+        std::thread::sleep(Duration::from_millis(50));
+        self.drain_input();
+        Ok(())
+    }
+
+    /// 连接SPI Flash并按读出的容量设置参数，ESP32的ROM在烧录前需要这一步
+    fn attach_flash(&mut self) -> Result<()> {
+        let chip = self.require_chip()?;
+        if chip == EspChip::ESP8266 {
+            return Ok(());
+        }
+
+        self.command(CMD_SPI_ATTACH, &[0u8; 8], 0, DEFAULT_TIMEOUT)?;
+        let flash_size = self.detect_flash_size()?;
+
+        let mut params = Vec::with_capacity(24);
+        params.extend_from_slice(&0u32.to_le_bytes()); // flash id
+        params.extend_from_slice(&flash_size.to_le_bytes());
+        params.extend_from_slice(&(64 * 1024u32).to_le_bytes()); // block size
+        params.extend_from_slice(&FLASH_SECTOR_SIZE.to_le_bytes());
+        params.extend_from_slice(&256u32.to_le_bytes()); // page size
+        params.extend_from_slice(&0xFFFFu32.to_le_bytes()); // status mask
+        self.command(CMD_SPI_SET_PARAMS, &params, 0, DEFAULT_TIMEOUT)?;
+        Ok(())
+    }
+
+    /// 读取Flash的JEDEC ID（厂商、类型、容量各一字节）
+    pub fn flash_id(&mut self) -> Result<u32> {
+        self.run_spiflash_command(SPIFLASH_RDID, 24)
+    }
+
+    /// 根据Flash ID确定容量，识别不出时按4MB处理
+    pub fn detect_flash_size(&mut self) -> Result<u32> {
+        if let Some(size) = self.flash_size {
+            return Ok(size);
+        }
+
+        let id = self.flash_id()?;
+        let size = match flash_size_from_id(id) {
+            Some(size) => {
+                info!("Flash ID 0x{:06x}, 容量 {}KB", id, size / 1024);
+                size
+            }
+            None => {
+                warn!("无法从Flash ID 0x{:06x} 识别容量，按4MB处理", id);
+                DEFAULT_FLASH_SIZE
+            }
+        };
+        self.flash_size = Some(size);
+        Ok(size)
+    }
+
+    /// 借用SPI0控制器向Flash发送一条只读指令，返回读到的数据（最多32位）
+    fn run_spiflash_command(&mut self, command: u32, read_bits: u32) -> Result<u32> {
+        let regs = self.require_chip()?.spi_registers();
+        let cmd_reg = regs.base;
+        let usr_reg = regs.base + regs.usr;
+        let usr1_reg = regs.base + regs.usr1;
+        let usr2_reg = regs.base + regs.usr2;
+        let w0_reg = regs.base + regs.w0;
+
+        let old_usr = self.read_reg(usr_reg)?;
+        let old_usr2 = self.read_reg(usr2_reg)?;
+
+        match regs.miso_dlen {
+            Some(offset) => self.write_reg(regs.base + offset, read_bits - 1)?,
+            // ESP8266: MISO位数在USR1的第8位开始
+            None => self.write_reg(usr1_reg, (read_bits - 1) << 8)?,
+        }
+        self.write_reg(usr_reg, SPI_USR_COMMAND | SPI_USR_MISO)?;
+        self.write_reg(usr2_reg, (7 << SPI_USR2_COMMAND_LEN_SHIFT) | command)?;
+        self.write_reg(w0_reg, 0)?;
+        self.write_reg(cmd_reg, SPI_CMD_USR)?;
+
+        let deadline = Instant::now() + DEFAULT_TIMEOUT;
+        while self.read_reg(cmd_reg)? & SPI_CMD_USR != 0 {
+            if Instant::now() > deadline {
+                return Err(anyhow!("SPI Flash指令 0x{:02x} 超时", command));
+            }
+        }
+
+        let value = self.read_reg(w0_reg)?;
+        self.write_reg(usr_reg, old_usr)?;
+        self.write_reg(usr2_reg, old_usr2)?;
+        Ok(value & ((1u64 << read_bits) - 1) as u32)
+    }
+
+    /// 压缩后写入Flash，写完后用MD5校验
+    pub fn flash_image<F>(&mut self, offset: u32, image: &[u8], mut on_progress: F) -> Result<()>
+    where
+        F: FnMut(FlashProgress),
+    {
+        let chip = self.require_chip()?;
+        if chip == EspChip::ESP8266 {
+            return Err(anyhow!("ESP8266的ROM不支持压缩烧录"));
+        }
+        if image.is_empty() {
+            return Err(anyhow!("固件内容为空"));
+        }
+
+        self.attach_flash()?;
+
+        let compressed = compress(image)?;
+        let block_count = compressed.len().div_ceil(FLASH_WRITE_SIZE);
+        info!(
+            "开始烧录: 地址 0x{:x}, 原始 {} 字节, 压缩后 {} 字节, {} 块",
+            offset, image.len(), compressed.len(), block_count
+        );
+
+        // FLASH_DEFL_BEGIN: 擦除大小、块数、块大小、起始地址
+        let erase_size = rom_erase_size(image.len());
+        let mut begin = Vec::with_capacity(20);
+        begin.extend_from_slice(&erase_size.to_le_bytes());
+        begin.extend_from_slice(&(block_count as u32).to_le_bytes());
+        begin.extend_from_slice(&(FLASH_WRITE_SIZE as u32).to_le_bytes());
+        begin.extend_from_slice(&offset.to_le_bytes());
+        if chip.supports_encrypted_flag() {
+            begin.extend_from_slice(&0u32.to_le_bytes());
+        }
+        let erase_timeout = scaled_timeout(ERASE_TIMEOUT_PER_MB, image.len());
+        self.command(CMD_FLASH_DEFL_BEGIN, &begin, 0, erase_timeout)?;
+
+        for (seq, block) in compressed.chunks(FLASH_WRITE_SIZE).enumerate() {
+            let mut data = Vec::with_capacity(16 + block.len());
+            data.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(seq as u32).to_le_bytes());
+            data.extend_from_slice(&[0u8; 8]);
+            data.extend_from_slice(block);
+
+            // 解压后的数据可能触发擦除，超时按未压缩大小估算
+            let block_timeout = scaled_timeout(ERASE_TIMEOUT_PER_MB, FLASH_WRITE_SIZE * 4)
+                .max(DEFAULT_TIMEOUT);
+            self.command(CMD_FLASH_DEFL_DATA, &data, checksum(block), block_timeout)
+                .map_err(|e| anyhow!("写入第 {} 块失败: {}", seq, e))?;
+
+            let written = ((seq + 1) * FLASH_WRITE_SIZE).min(compressed.len());
+            on_progress(FlashProgress {
+                written: written * image.len() / compressed.len(),
+                total: image.len(),
+            });
+        }
+
+        self.verify_md5(offset, image)?;
+        info!("烧录完成并通过MD5校验");
+        Ok(())
+    }
+
+    /// 结束烧录；reboot为true时让芯片运行新固件
+    pub fn finish(&mut self, reboot: bool) -> Result<()> {
+        // 参数为0表示重启，1表示留在引导程序
+        let flag: u32 = if reboot { 0 } else { 1 };
+        self.command(CMD_FLASH_DEFL_END, &flag.to_le_bytes(), 0, DEFAULT_TIMEOUT)?;
+        if reboot {
+            esp_hard_reset(&mut self.port)?;
+        }
+        Ok(())
+    }
+
+    /// 计算Flash指定区域的MD5
+    pub fn flash_md5(&mut self, offset: u32, size: u32) -> Result<[u8; 16]> {
+        let mut data = Vec::with_capacity(16);
+        data.extend_from_slice(&offset.to_le_bytes());
+        data.extend_from_slice(&size.to_le_bytes());
+        data.extend_from_slice(&[0u8; 8]);
+
+        let timeout = scaled_timeout(MD5_TIMEOUT_PER_MB, size as usize);
+        let response = self.command(CMD_SPI_FLASH_MD5, &data, 0, timeout)?;
+
+        match response.data.len() {
+            // ROM引导程序返回32个十六进制字符
+            32 => {
+                let text = std::str::from_utf8(&response.data)
+                    .map_err(|_| anyhow!("MD5响应格式错误"))?;
+                let mut digest = [0u8; 16];
+                for (i, byte) in digest.iter_mut().enumerate() {
+                    *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16)
+                        .map_err(|_| anyhow!("MD5响应格式错误"))?;
+                }
+                Ok(digest)
+            }
+            // 烧录桩程序直接返回16字节
+            16 => {
+                let mut digest = [0u8; 16];
+                digest.copy_from_slice(&response.data);
+                Ok(digest)
+            }
+            len => Err(anyhow!("MD5响应长度异常: {}", len)),
+        }
+    }
+
+    /// 比较Flash内容和镜像的MD5
+    pub fn verify_md5(&mut self, offset: u32, image: &[u8]) -> Result<()> {
+        let expected = md5::compute(image).0;
+        let actual = self.flash_md5(offset, image.len() as u32)?;
+        if expected != actual {
+            return Err(anyhow!(
+                "MD5校验失败: 期望 {}, 实际 {}",
+                hex_string(&expected),
+                hex_string(&actual)
+            ));
+        }
+        Ok(())
+    }
+
+    /// 单独校验已烧录的镜像（不写入），用于上传后回读比对
+    pub fn verify_image(&mut self, offset: u32, image: &[u8]) -> Result<()> {
+        self.require_chip()?;
+        self.attach_flash()?;
+        self.verify_md5(offset, image)
+    }
+
+    fn require_chip(&self) -> Result<EspChip> {
+        self.chip.ok_or_else(|| anyhow!("尚未识别芯片，请先连接引导程序"))
+    }
+
+    /// 发送命令并等待对应的响应，检查状态字节
+    fn command(&mut self, command: u8, data: &[u8], checksum: u32, timeout: Duration) -> Result<Response> {
+        self.send_command(command, data, checksum)?;
+        let response = self.read_response(command, timeout)?;
+
+        let status_len = self.chip.map(|c| c.status_len()).unwrap_or(4);
+        if response.data.len() < status_len {
+            // SYNC之类的命令在芯片识别前可能只返回2字节状态
+            if response.data.len() >= 2 && response.data[0] != 0 {
+                return Err(anyhow!("命令 0x{:02x} 失败，错误码 0x{:02x}", command, response.data[1]));
+            }
+            return Ok(response);
+        }
+
+        let status_at = response.data.len() - status_len;
+        if response.data[status_at] != 0 {
+            return Err(anyhow!(
+                "命令 0x{:02x} 失败，错误码 0x{:02x}",
+                command, response.data[status_at + 1]
+            ));
+        }
+
+        let mut response = response;
+        response.data.truncate(status_at);
+        Ok(response)
+    }
+
+    fn send_command(&mut self, command: u8, data: &[u8], checksum: u32) -> Result<()> {
+        let mut packet = Vec::with_capacity(8 + data.len());
+        packet.push(0x00); // 方向：请求
+        packet.push(command);
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        packet.extend_from_slice(data);
+
+        self.port.write_all(&slip_encode(&packet))
+            .map_err(|e| anyhow!("发送命令失败: {}", e))?;
+        self.port.flush()
+            .map_err(|e| anyhow!("发送命令失败: {}", e))?;
+        Ok(())
+    }
+
+    fn read_response(&mut self, command: u8, timeout: Duration) -> Result<Response> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let packet = self.read_packet(deadline)?;
+            if packet.len() < 8 || packet[0] != 0x01 {
+                debug!("忽略无效响应包: {} 字节", packet.len());
+                continue;
+            }
+            if packet[1] != command {
+                debug!("忽略其他命令的响应: 0x{:02x}", packet[1]);
+                continue;
+            }
+
+            let size = u16::from_le_bytes([packet[2], packet[3]]) as usize;
+            let value = u32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]);
+            let data = packet[8..].to_vec();
+            if data.len() != size {
+                warn!("响应长度不一致: 声明 {}, 实际 {}", size, data.len());
+            }
+            return Ok(Response { value, data });
+        }
+    }
+
+    fn read_packet(&mut self, deadline: Instant) -> Result<Vec<u8>> {
+        if let Some(packet) = self.pending.pop_front() {
+            return Ok(packet);
+        }
+        let mut buffer = [0u8; 256];
+        loop {
+            if Instant::now() >= deadline {
+                return Err(anyhow!("等待引导程序响应超时"));
+            }
+
+            match self.port.read(&mut buffer) {
+                Ok(0) => std::thread::sleep(Duration::from_millis(1)),
+                Ok(n) => {
+                    // 一次读取可能包含多个响应帧，多出的留给下次读取
+                    self.pending.extend(buffer[..n].iter().filter_map(|&byte| self.decoder.push(byte)));
+                    if let Some(packet) = self.pending.pop_front() {
+                        return Ok(packet);
+                    }
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(anyhow!("读取引导程序响应失败: {}", e)),
+            }
+        }
+    }
+
+    /// 丢弃输入缓冲区中的残留数据（例如复位时的启动日志）
+    fn drain_input(&mut self) {
+        let mut buffer = [0u8; 256];
+        let deadline = Instant::now() + Duration::from_millis(50);
+        while Instant::now() < deadline {
+            match self.port.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+        self.decoder = SlipDecoder::new();
+        self.pending.clear();
+    }
+}
+
+/// ROM引导程序（非stub）模式下的擦除大小：向上取整到整扇区，否则最后一个扇区可能擦除不完整
+fn rom_erase_size(image_len: usize) -> u32 {
+    (image_len as u32).div_ceil(FLASH_SECTOR_SIZE) * FLASH_SECTOR_SIZE
+}
+
+/// JEDEC ID第三个字节是容量代码，多数厂商为2的幂次
+fn flash_size_from_id(id: u32) -> Option<u32> {
+    let code = (id >> 16) & 0xFF;
+    match code {
+        0x12..=0x1C => Some(1 << code),
+        // 部分厂商（如GD、XMC）的大容量型号使用0x20起的编码
+        0x20..=0x22 => Some(1 << (code - 6)),
+        0x32..=0x3A => Some(1 << (code - 0x20)),
+        _ => None,
+    }
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn scaled_timeout(per_mb: Duration, size: usize) -> Duration {
+    let scaled = per_mb.mul_f64(size as f64 / (1024.0 * 1024.0));
+    scaled.max(DEFAULT_TIMEOUT)
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::collections::VecDeque;
+
+    /// 模拟ESP32 ROM的SLIP桩，按命令回复并记录写入的Flash内容
+    struct StubPort {
+        rx: VecDeque<u8>,
+        decoder: SlipDecoder,
+        flash: Vec<u8>,
+        inflate: Vec<u8>,
+        flash_offset: usize,
+        erase_size: u32,
+        /// WRITE_REG写入的寄存器值
+        registers: std::collections::HashMap<u32, u32>,
+        /// 模拟Flash的JEDEC ID
+        flash_id: u32,
+        /// SPI_SET_PARAMS收到的Flash容量
+        flash_size: u32,
+        baud_rate: u32,
+        dtr_changes: usize,
+    }
+
+    impl StubPort {
+        fn new() -> Self {
+            Self {
+                rx: VecDeque::new(),
+                decoder: SlipDecoder::new(),
+                flash: vec![0xff; 0x20000],
+                inflate: Vec::new(),
+                flash_offset: 0,
+                erase_size: 0,
+                registers: std::collections::HashMap::new(),
+                // 华邦W25Q32，4MB
+                flash_id: 0x0016_40EF,
+                flash_size: 0,
+                baud_rate: ROM_BAUD_RATE,
+                dtr_changes: 0,
+            }
+        }
+
+        fn reply(&mut self, command: u8, value: u32, mut data: Vec<u8>) {
+            data.extend_from_slice(&[0, 0, 0, 0]);
+            let mut packet = vec![0x01, command];
+            packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+            packet.extend_from_slice(&value.to_le_bytes());
+            packet.extend_from_slice(&data);
+            self.rx.extend(slip_encode(&packet));
+        }
+
+        fn handle(&mut self, packet: Vec<u8>) {
+            let command = packet[1];
+            let data = &packet[8..];
+            let word = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+            match command {
+                CMD_SYNC => {
+                    for _ in 0..3 {
+                        self.reply(CMD_SYNC, 0, Vec::new());
+                    }
+                }
+                CMD_READ_REG => {
+                    let value = match word(0) {
+                        CHIP_DETECT_MAGIC_REG => 0x00F0_1D83,
+                        0x3FF5_A004 => 0xAABB_CCDD,
+                        0x3FF5_A008 => 0x0000_2411,
+                        address => self.registers.get(&address).copied().unwrap_or(0),
+                    };
+                    self.reply(CMD_READ_REG, value, Vec::new());
+                }
+                CMD_WRITE_REG => {
+                    let spi = EspChip::ESP32.spi_registers();
+                    if word(0) == spi.base && word(4) & SPI_CMD_USR != 0 {
+                        // 指令立即完成，结果放在W0
+                        self.registers.insert(spi.base + spi.w0, self.flash_id);
+                    } else {
+                        self.registers.insert(word(0), word(4));
+                    }
+                    self.reply(command, 0, Vec::new());
+                }
+                CMD_CHANGE_BAUDRATE => self.reply(command, 0, Vec::new()),
+                CMD_SPI_ATTACH => self.reply(command, 0, Vec::new()),
+                CMD_SPI_SET_PARAMS => {
+                    self.flash_size = word(4);
+                    self.reply(command, 0, Vec::new());
+                }
+                CMD_FLASH_DEFL_BEGIN => {
+                    self.erase_size = word(0);
+                    self.flash_offset = word(12) as usize;
+                    self.inflate.clear();
+                    self.reply(command, 0, Vec::new());
+                }
+                CMD_FLASH_DEFL_DATA => {
+                    let size = word(0) as usize;
+                    let block = &data[16..16 + size];
+                    assert_eq!(checksum(block), u32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]));
+                    self.inflate.extend_from_slice(block);
+                    let mut decoded = Vec::new();
+                    if ZlibDecoder::new(&self.inflate[..]).read_to_end(&mut decoded).is_ok() {
+                        let start = self.flash_offset;
+                        self.flash[start..start + decoded.len()].copy_from_slice(&decoded);
+                    }
+                    self.reply(command, 0, Vec::new());
+                }
+                CMD_SPI_FLASH_MD5 => {
+                    let (offset, size) = (word(0) as usize, word(4) as usize);
+                    let digest = md5::compute(&self.flash[offset..offset + size]);
+                    self.reply(command, 0, hex_string(&digest.0).into_bytes());
+                }
+                CMD_FLASH_DEFL_END => self.reply(command, 0, Vec::new()),
+                _ => panic!("未预期的命令 0x{:02x}", command),
+            }
+        }
+    }
+
+    impl Read for StubPort {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.rx.len());
+            for slot in buf.iter_mut().take(n) {
+                *slot = self.rx.pop_front().unwrap();
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for StubPort {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            for &byte in buf {
+                if let Some(packet) = self.decoder.push(byte) {
+                    self.handle(packet);
+                }
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl PortControl for StubPort {
+        fn set_dtr(&mut self, _level: bool) -> Result<()> {
+            self.dtr_changes += 1;
+            Ok(())
+        }
+
+        fn set_rts(&mut self, _level: bool) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+            self.baud_rate = baud_rate;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_slip_roundtrip() {
+        let packet = vec![0x01, SLIP_END, 0x02, SLIP_ESC, 0x03];
+        let frame = slip_encode(&packet);
+        assert_eq!(frame, vec![SLIP_END, 0x01, SLIP_ESC, SLIP_ESC_END, 0x02, SLIP_ESC, SLIP_ESC_ESC, 0x03, SLIP_END]);
+
+        let mut decoder = SlipDecoder::new();
+        let decoded: Vec<_> = frame.iter().filter_map(|&b| decoder.push(b)).collect();
+        assert_eq!(decoded, vec![packet]);
+    }
+
+    #[test]
+    fn test_connect_and_chip_info() {
+        let mut loader = EspLoader::new(StubPort::new());
+        loader.connect(3).unwrap();
+        assert_eq!(loader.chip(), Some(EspChip::ESP32));
+
+        let info = loader.chip_info().unwrap();
+        assert_eq!(info.chip_name, "ESP32");
+        assert_eq!(info.mac_address, "24:11:aa:bb:cc:dd");
+        assert!(loader.into_inner().dtr_changes > 0);
+    }
+
+    #[test]
+    fn test_flash_image_with_md5_verify() {
+        let mut loader = EspLoader::new(StubPort::new());
+        loader.connect(1).unwrap();
+        loader.change_baud(460800).unwrap();
+
+        let image: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut last = None;
+        loader.flash_image(0x1000, &image, |p| last = Some(p)).unwrap();
+        loader.finish(false).unwrap();
+
+        let progress = last.unwrap();
+        assert_eq!(progress.total, image.len());
+        let port = loader.into_inner();
+        assert_eq!(port.baud_rate, 460800);
+        assert_eq!(&port.flash[0x1000..0x1000 + image.len()], &image[..]);
+    }
+
+    #[test]
+    fn test_erase_size_rounded_to_sector() {
+        assert_eq!(rom_erase_size(1), 0x1000);
+        assert_eq!(rom_erase_size(0x1000), 0x1000);
+        assert_eq!(rom_erase_size(5000), 0x2000);
+
+        let mut loader = EspLoader::new(StubPort::new());
+        loader.connect(1).unwrap();
+        loader.flash_image(0x1000, &[0xaa; 5000], |_| {}).unwrap();
+        assert_eq!(loader.into_inner().erase_size, 0x2000);
+    }
+
+    #[test]
+    fn test_flash_size_from_jedec_id() {
+        assert_eq!(flash_size_from_id(0x0016_40EF), Some(4 * 1024 * 1024));
+        assert_eq!(flash_size_from_id(0x0018_40EF), Some(16 * 1024 * 1024));
+        assert_eq!(flash_size_from_id(0x0000_0000), None);
+
+        let mut port = StubPort::new();
+        port.flash_id = 0x0017_4068;
+        let mut loader = EspLoader::new(port);
+        loader.connect(1).unwrap();
+        loader.flash_image(0x1000, &[0xaa; 100], |_| {}).unwrap();
+        assert_eq!(loader.into_inner().flash_size, 8 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_multiple_frames_in_one_read() {
+        let mut loader = EspLoader::new(StubPort::new());
+        loader.port.reply(CMD_READ_REG, 1, Vec::new());
+        loader.port.reply(CMD_READ_REG, 2, Vec::new());
+
+        assert_eq!(loader.read_response(CMD_READ_REG, DEFAULT_TIMEOUT).unwrap().value, 1);
+        assert_eq!(loader.read_response(CMD_READ_REG, DEFAULT_TIMEOUT).unwrap().value, 2);
+    }
+}
@@ -0,0 +1,613 @@
+use super::transport::SerialTransport;
+use anyhow::{Result, anyhow};
+use log::{debug, info, warn};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// StandardFirmata 默认的波特率
+pub const FIRMATA_BAUD_RATE: u32 = 57600;
+/// 打开串口后等待固件应答的时间（打开串口会复位开发板，引导程序需要约2秒）
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(4);
+/// 握手期间重发固件查询的间隔
+const HANDSHAKE_RETRY: Duration = Duration::from_millis(500);
+
+// 消息类型
+const DIGITAL_MESSAGE: u8 = 0x90;
+const ANALOG_MESSAGE: u8 = 0xE0;
+const REPORT_ANALOG: u8 = 0xC0;
+const REPORT_DIGITAL: u8 = 0xD0;
+const SET_PIN_MODE: u8 = 0xF4;
+const SET_DIGITAL_PIN_VALUE: u8 = 0xF5;
+const REPORT_VERSION: u8 = 0xF9;
+const SYSTEM_RESET: u8 = 0xFF;
+const START_SYSEX: u8 = 0xF0;
+const END_SYSEX: u8 = 0xF7;
+
+// Sysex命令
+const ANALOG_MAPPING_QUERY: u8 = 0x69;
+const ANALOG_MAPPING_RESPONSE: u8 = 0x6A;
+const EXTENDED_ANALOG: u8 = 0x6F;
+const SERVO_CONFIG: u8 = 0x70;
+const STRING_DATA: u8 = 0x71;
+const I2C_REQUEST: u8 = 0x76;
+const I2C_REPLY: u8 = 0x77;
+const I2C_CONFIG: u8 = 0x78;
+const REPORT_FIRMWARE: u8 = 0x79;
+const SAMPLING_INTERVAL: u8 = 0x7A;
+
+/// sysex消息的最大长度，超过后丢弃
+const MAX_SYSEX_LENGTH: usize = 1024;
+
+/// 引脚模式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PinMode {
+    Input,
+    Output,
+    Analog,
+    Pwm,
+    Servo,
+    I2c,
+    Pullup,
+}
+
+impl PinMode {
+    fn code(&self) -> u8 {
+        match self {
+            PinMode::Input => 0x00,
+            PinMode::Output => 0x01,
+            PinMode::Analog => 0x02,
+            PinMode::Pwm => 0x03,
+            PinMode::Servo => 0x04,
+            PinMode::I2c => 0x06,
+            PinMode::Pullup => 0x0B,
+        }
+    }
+}
+
+/// I2C读取方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum I2cMode {
+    Write,
+    ReadOnce,
+    ReadContinuously,
+    StopReading,
+}
+
+impl I2cMode {
+    fn bits(&self) -> u8 {
+        match self {
+            I2cMode::Write => 0b00,
+            I2cMode::ReadOnce => 0b01,
+            I2cMode::ReadContinuously => 0b10,
+            I2cMode::StopReading => 0b11,
+        }
+    }
+}
+
+/// 开发板发来的消息
+#[derive(Debug, Clone, PartialEq)]
+pub enum FirmataMessage {
+    /// 一组8个数字引脚的电平
+    Digital { port: u8, mask: u8 },
+    Analog { channel: u8, value: u16 },
+    ProtocolVersion { major: u8, minor: u8 },
+    Firmware { major: u8, minor: u8, name: String },
+    /// 每个引脚对应的模拟通道，127表示不支持模拟输入
+    AnalogMapping(Vec<u8>),
+    I2cReply { address: u16, register: u16, data: Vec<u16> },
+    Text(String),
+    /// 其他sysex消息
+    Sysex { command: u8, data: Vec<u8> },
+}
+
+/// 固件信息
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FirmwareInfo {
+    pub name: String,
+    pub major: u8,
+    pub minor: u8,
+}
+
+/// 推送给前端的输入上报（`firmata-report` 事件）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum FirmataEvent {
+    Digital { port: String, pin: u8, value: bool },
+    Analog { port: String, channel: u8, value: u16 },
+    I2cReply { port: String, address: u16, register: u16, data: Vec<u16> },
+    Text { port: String, text: String },
+    Disconnected { port: String, reason: String },
+}
+
+/// Firmata事件的接收者
+pub type FirmataEventSink = Arc<dyn Fn(FirmataEvent) + Send + Sync>;
+
+/// 把字节流解析为Firmata消息
+#[derive(Debug, Default)]
+pub struct FirmataParser {
+    buffer: Vec<u8>,
+    in_sysex: bool,
+}
+
+impl FirmataParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Vec<FirmataMessage> {
+        let mut messages = Vec::new();
+        for &byte in data {
+            if self.in_sysex {
+                match byte {
+                    END_SYSEX => {
+                        self.in_sysex = false;
+                        let body = std::mem::take(&mut self.buffer);
+                        if let Some(message) = parse_sysex(&body) {
+                            messages.push(message);
+                        }
+                    }
+                    _ if self.buffer.len() >= MAX_SYSEX_LENGTH => {
+                        warn!("Firmata sysex消息过长，已丢弃");
+                        self.in_sysex = false;
+                        self.buffer.clear();
+                    }
+                    _ => self.buffer.push(byte),
+                }
+                continue;
+            }
+
+            if byte == START_SYSEX {
+                self.in_sysex = true;
+                self.buffer.clear();
+                continue;
+            }
+            if byte & 0x80 != 0 {
+                // 新的命令字节，丢弃未完成的消息
+                self.buffer.clear();
+            } else if self.buffer.is_empty() {
+                // 没有命令字节的数据无法解析
+                continue;
+            }
+            self.buffer.push(byte);
+
+            if self.buffer.len() == 3 {
+                let (command, lsb, msb) = (self.buffer[0], self.buffer[1], self.buffer[2]);
+                self.buffer.clear();
+                let value = lsb as u16 | (msb as u16) << 7;
+                match command & 0xF0 {
+                    DIGITAL_MESSAGE => messages.push(FirmataMessage::Digital {
+                        port: command & 0x0F,
+                        mask: (value & 0xFF) as u8,
+                    }),
+                    ANALOG_MESSAGE => messages.push(FirmataMessage::Analog {
+                        channel: command & 0x0F,
+                        value,
+                    }),
+                    _ if command == REPORT_VERSION => messages.push(FirmataMessage::ProtocolVersion {
+                        major: lsb,
+                        minor: msb,
+                    }),
+                    _ => debug!("忽略Firmata消息: {:02X}", command),
+                }
+            }
+        }
+        messages
+    }
+}
+
+fn parse_sysex(body: &[u8]) -> Option<FirmataMessage> {
+    let (&command, data) = body.split_first()?;
+    let message = match command {
+        REPORT_FIRMWARE if data.len() >= 2 => FirmataMessage::Firmware {
+            major: data[0],
+            minor: data[1],
+            name: decode_string(&data[2..]),
+        },
+        ANALOG_MAPPING_RESPONSE => FirmataMessage::AnalogMapping(data.to_vec()),
+        I2C_REPLY if data.len() >= 4 => {
+            let values = decode_u14(data);
+            FirmataMessage::I2cReply {
+                address: values[0],
+                register: values[1],
+                data: values[2..].to_vec(),
+            }
+        }
+        STRING_DATA => FirmataMessage::Text(decode_string(data)),
+        _ => FirmataMessage::Sysex { command, data: data.to_vec() },
+    };
+    Some(message)
+}
+
+/// 两个7位字节组成一个值（低位在前）
+fn decode_u14(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|pair| pair[0] as u16 | (pair[1] as u16) << 7)
+        .collect()
+}
+
+fn decode_string(data: &[u8]) -> String {
+    let bytes: Vec<u8> = decode_u14(data).into_iter().map(|v| v as u8).collect();
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+fn push_u14(buf: &mut Vec<u8>, value: u16) {
+    buf.push((value & 0x7F) as u8);
+    buf.push(((value >> 7) & 0x7F) as u8);
+}
+
+fn sysex(command: u8, data: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(data.len() + 3);
+    message.push(START_SYSEX);
+    message.push(command);
+    message.extend_from_slice(data);
+    message.push(END_SYSEX);
+    message
+}
+
+pub fn encode_pin_mode(pin: u8, mode: PinMode) -> Vec<u8> {
+    vec![SET_PIN_MODE, pin & 0x7F, mode.code()]
+}
+
+pub fn encode_digital_write(pin: u8, value: bool) -> Vec<u8> {
+    vec![SET_DIGITAL_PIN_VALUE, pin & 0x7F, value as u8]
+}
+
+/// PWM或舵机输出，引脚大于15或数值超过14位时使用扩展消息
+pub fn encode_analog_write(pin: u8, value: u32) -> Vec<u8> {
+    if pin < 16 && value < 0x4000 {
+        let mut message = vec![ANALOG_MESSAGE | pin];
+        push_u14(&mut message, value as u16);
+        return message;
+    }
+    let mut data = vec![pin & 0x7F];
+    let mut rest = value;
+    loop {
+        data.push((rest & 0x7F) as u8);
+        rest >>= 7;
+        if rest == 0 {
+            break;
+        }
+    }
+    sysex(EXTENDED_ANALOG, &data)
+}
+
+pub fn encode_report_analog(channel: u8, enable: bool) -> Vec<u8> {
+    vec![REPORT_ANALOG | (channel & 0x0F), enable as u8]
+}
+
+pub fn encode_report_digital(port: u8, enable: bool) -> Vec<u8> {
+    vec![REPORT_DIGITAL | (port & 0x0F), enable as u8]
+}
+
+pub fn encode_servo_config(pin: u8, min_pulse: u16, max_pulse: u16) -> Vec<u8> {
+    let mut data = vec![pin & 0x7F];
+    push_u14(&mut data, min_pulse);
+    push_u14(&mut data, max_pulse);
+    sysex(SERVO_CONFIG, &data)
+}
+
+pub fn encode_i2c_config(delay_us: u16) -> Vec<u8> {
+    let mut data = Vec::new();
+    push_u14(&mut data, delay_us);
+    sysex(I2C_CONFIG, &data)
+}
+
+/// I2C请求：写入时 `data` 为要写的字节；读取时 `register` 可选，`data` 为空，`read_length` 为字节数
+pub fn encode_i2c_request(address: u8, mode: I2cMode, register: Option<u16>, data: &[u8], read_length: u16) -> Vec<u8> {
+    let mut body = vec![address & 0x7F, mode.bits() << 3];
+    match mode {
+        I2cMode::Write => data.iter().for_each(|&b| push_u14(&mut body, b as u16)),
+        I2cMode::ReadOnce | I2cMode::ReadContinuously => {
+            if let Some(register) = register {
+                push_u14(&mut body, register);
+            }
+            push_u14(&mut body, read_length);
+        }
+        I2cMode::StopReading => {}
+    }
+    sysex(I2C_REQUEST, &body)
+}
+
+pub fn encode_sampling_interval(interval_ms: u16) -> Vec<u8> {
+    let mut data = Vec::new();
+    push_u14(&mut data, interval_ms);
+    sysex(SAMPLING_INTERVAL, &data)
+}
+
+/// 开发板上报的引脚状态
+#[derive(Debug, Default)]
+struct FirmataState {
+    firmware: Option<FirmwareInfo>,
+    analog_mapping: Vec<u8>,
+    digital_ports: HashMap<u8, u8>,
+    analog_values: HashMap<u8, u16>,
+    /// 读取线程退出的原因
+    closed: Option<String>,
+}
+
+/// 运行StandardFirmata的开发板
+///
+/// 写入在调用线程中完成，后台线程解析开发板的上报并更新引脚状态，
+/// 数值变化时通过 `on_event` 推送。
+pub struct FirmataClient {
+    port_name: String,
+    writer: Mutex<Box<dyn SerialTransport>>,
+    state: Arc<Mutex<FirmataState>>,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl FirmataClient {
+    /// 在已打开的串口上启动客户端，等待固件应答
+    pub fn connect(port_name: &str, mut transport: Box<dyn SerialTransport>, on_event: FirmataEventSink) -> Result<Self> {
+        transport.set_timeout(Duration::from_millis(20))?;
+        let reader = transport.try_clone_transport()?;
+        let state = Arc::new(Mutex::new(FirmataState::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let state = Arc::clone(&state);
+            let stop = Arc::clone(&stop);
+            let port_name = port_name.to_string();
+            std::thread::spawn(move || read_loop(&port_name, reader, &state, &stop, on_event))
+        };
+
+        let client = Self {
+            port_name: port_name.to_string(),
+            writer: Mutex::new(transport),
+            state,
+            stop,
+            reader: Some(handle),
+        };
+        client.handshake()?;
+        Ok(client)
+    }
+
+    fn handshake(&self) -> Result<()> {
+        let start = Instant::now();
+        let mut last_query: Option<Instant> = None;
+        while start.elapsed() < HANDSHAKE_TIMEOUT {
+            if let Some(firmware) = self.firmware() {
+                info!("{} 运行 {} {}.{}", self.port_name, firmware.name, firmware.major, firmware.minor);
+                self.send(&sysex(ANALOG_MAPPING_QUERY, &[]))?;
+                return Ok(());
+            }
+            if let Some(reason) = self.lock().closed.clone() {
+                return Err(anyhow!("串口已断开: {}", reason));
+            }
+            if last_query.is_none_or(|t| t.elapsed() >= HANDSHAKE_RETRY) {
+                self.send(&sysex(REPORT_FIRMWARE, &[]))?;
+                last_query = Some(Instant::now());
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        Err(anyhow!("{} 没有响应Firmata查询，开发板可能没有运行StandardFirmata", self.port_name))
+    }
+
+    pub fn port_name(&self) -> &str {
+        &self.port_name
+    }
+
+    pub fn firmware(&self) -> Option<FirmwareInfo> {
+        self.lock().firmware.clone()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed.is_some()
+    }
+
+    pub fn set_pin_mode(&self, pin: u8, mode: PinMode) -> Result<()> {
+        self.send(&encode_pin_mode(pin, mode))
+    }
+
+    pub fn digital_write(&self, pin: u8, value: bool) -> Result<()> {
+        self.send(&encode_digital_write(pin, value))
+    }
+
+    pub fn analog_write(&self, pin: u8, value: u32) -> Result<()> {
+        self.send(&encode_analog_write(pin, value))
+    }
+
+    /// 开启或关闭某个数字引脚所在端口（8个引脚一组）的上报
+    pub fn report_digital(&self, pin: u8, enable: bool) -> Result<()> {
+        self.send(&encode_report_digital(pin / 8, enable))
+    }
+
+    pub fn report_analog(&self, channel: u8, enable: bool) -> Result<()> {
+        self.send(&encode_report_analog(channel, enable))
+    }
+
+    pub fn servo_config(&self, pin: u8, min_pulse: u16, max_pulse: u16) -> Result<()> {
+        self.send(&encode_servo_config(pin, min_pulse, max_pulse))
+    }
+
+    /// 舵机转到指定角度（0-180）
+    pub fn servo_write(&self, pin: u8, angle: u16) -> Result<()> {
+        self.analog_write(pin, angle.min(180) as u32)
+    }
+
+    pub fn i2c_config(&self, delay_us: u16) -> Result<()> {
+        self.send(&encode_i2c_config(delay_us))
+    }
+
+    pub fn i2c_write(&self, address: u8, data: &[u8]) -> Result<()> {
+        self.send(&encode_i2c_request(address, I2cMode::Write, None, data, 0))
+    }
+
+    /// 读取I2C设备，结果通过 `I2cReply` 事件返回
+    pub fn i2c_read(&self, address: u8, register: Option<u16>, length: u16, continuous: bool) -> Result<()> {
+        let mode = if continuous { I2cMode::ReadContinuously } else { I2cMode::ReadOnce };
+        self.send(&encode_i2c_request(address, mode, register, &[], length))
+    }
+
+    pub fn i2c_stop_reading(&self, address: u8) -> Result<()> {
+        self.send(&encode_i2c_request(address, I2cMode::StopReading, None, &[], 0))
+    }
+
+    pub fn set_sampling_interval(&self, interval_ms: u16) -> Result<()> {
+        self.send(&encode_sampling_interval(interval_ms))
+    }
+
+    /// 最近一次上报的数字引脚电平，需要先开启上报
+    pub fn digital_value(&self, pin: u8) -> Option<bool> {
+        self.lock().digital_ports.get(&(pin / 8)).map(|mask| mask & (1 << (pin % 8)) != 0)
+    }
+
+    /// 最近一次上报的模拟通道数值，需要先开启上报
+    pub fn analog_value(&self, channel: u8) -> Option<u16> {
+        self.lock().analog_values.get(&channel).copied()
+    }
+
+    /// 模拟通道对应的引脚号（例如Uno的A0是14），用于设置引脚模式
+    pub fn analog_pin(&self, channel: u8) -> Option<u8> {
+        self.lock().analog_mapping.iter().position(|&c| c == channel).map(|pin| pin as u8)
+    }
+
+    /// 复位开发板上的所有引脚
+    pub fn reset(&self) -> Result<()> {
+        self.send(&[SYSTEM_RESET])
+    }
+
+    /// 停止读取线程并释放串口
+    pub fn close(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.reader.take() {
+            let _ = handle.join();
+        }
+    }
+
+    fn send(&self, message: &[u8]) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| anyhow!("Firmata串口锁已损坏"))?;
+        writer.write_all(message).map_err(|e| anyhow!("发送Firmata消息失败: {}", e))?;
+        writer.flush().map_err(|e| anyhow!("发送Firmata消息失败: {}", e))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FirmataState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for FirmataClient {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn read_loop(
+    port_name: &str,
+    mut reader: Box<dyn SerialTransport>,
+    state: &Mutex<FirmataState>,
+    stop: &AtomicBool,
+    on_event: FirmataEventSink,
+) {
+    let mut parser = FirmataParser::new();
+    let mut chunk = [0u8; 256];
+
+    let reason = loop {
+        if stop.load(Ordering::SeqCst) {
+            break None;
+        }
+        let n = match reader.read(&mut chunk) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut
+                || e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => break Some(format!("串口读取失败: {}", e)),
+        };
+
+        for message in parser.feed(&chunk[..n]) {
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            match message {
+                FirmataMessage::Digital { port, mask } => {
+                    let previous = state.digital_ports.insert(port, mask);
+                    let changed = previous.map_or(0xFF, |p| p ^ mask);
+                    drop(state);
+                    for bit in (0..8).filter(|bit| changed & (1 << bit) != 0) {
+                        on_event(FirmataEvent::Digital {
+                            port: port_name.to_string(),
+                            pin: port * 8 + bit,
+                            value: mask & (1 << bit) != 0,
+                        });
+                    }
+                }
+                FirmataMessage::Analog { channel, value } => {
+                    if state.analog_values.insert(channel, value) != Some(value) {
+                        drop(state);
+                        on_event(FirmataEvent::Analog { port: port_name.to_string(), channel, value });
+                    }
+                }
+                FirmataMessage::Firmware { major, minor, name } => {
+                    state.firmware = Some(FirmwareInfo { name, major, minor });
+                }
+                FirmataMessage::AnalogMapping(mapping) => state.analog_mapping = mapping,
+                FirmataMessage::I2cReply { address, register, data } => {
+                    drop(state);
+                    on_event(FirmataEvent::I2cReply { port: port_name.to_string(), address, register, data });
+                }
+                FirmataMessage::Text(text) => {
+                    drop(state);
+                    on_event(FirmataEvent::Text { port: port_name.to_string(), text });
+                }
+                FirmataMessage::ProtocolVersion { major, minor } => {
+                    debug!("{} Firmata协议版本 {}.{}", port_name, major, minor);
+                }
+                FirmataMessage::Sysex { command, .. } => debug!("忽略Firmata sysex: {:02X}", command),
+            }
+        }
+    };
+
+    debug!("{} Firmata读取线程退出", port_name);
+    if let Some(reason) = &reason {
+        warn!("{} Firmata连接断开: {}", port_name, reason);
+        on_event(FirmataEvent::Disconnected { port: port_name.to_string(), reason: reason.clone() });
+    }
+    state.lock().unwrap_or_else(|e| e.into_inner()).closed = Some(reason.unwrap_or_default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::transport::{ScriptPeer, ScriptedTransport};
+
+    #[test]
+    fn test_firmata_messages_and_handshake() {
+        assert_eq!(encode_analog_write(3, 200), vec![0xE3, 0x48, 0x01]);
+        assert_eq!(encode_analog_write(20, 200), vec![0xF0, 0x6F, 20, 0x48, 0x01, 0xF7]);
+        assert_eq!(encode_i2c_request(0x48, I2cMode::ReadOnce, Some(0), &[], 2), vec![0xF0, 0x76, 0x48, 0x08, 0, 0, 2, 0, 0xF7]);
+
+        let mut parser = FirmataParser::new();
+        let messages = parser.feed(&[0x91, 0x05, 0x00, 0xE2, 0x7F]);
+        assert_eq!(messages, vec![FirmataMessage::Digital { port: 1, mask: 0x05 }]);
+        let messages = parser.feed(&[0x07, 0xF0, 0x71, b'h', 0, b'i', 0, 0xF7]);
+        assert_eq!(messages, vec![
+            FirmataMessage::Analog { channel: 2, value: 0x3FF },
+            FirmataMessage::Text("hi".to_string()),
+        ]);
+
+        let peer = ScriptPeer::new()
+            .expect(&[0xF0, 0x79, 0xF7], &[0xF0, 0x79, 2, 5, b'S', 0, b'F', 0, 0xF7])
+            .expect(&[0xF0, 0x69, 0xF7], &[0xF0, 0x6A, 127, 127, 0, 1, 0xF7, 0xE0, 0x10, 0x02]);
+        let transport = ScriptedTransport::new("script", Box::new(peer), Duration::from_millis(20));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink_events = Arc::clone(&events);
+        let sink: FirmataEventSink = Arc::new(move |event| sink_events.lock().unwrap().push(event));
+
+        let client = FirmataClient::connect("script", Box::new(transport), sink).unwrap();
+        assert_eq!(client.firmware(), Some(FirmwareInfo { name: "SF".to_string(), major: 2, minor: 5 }));
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(client.analog_pin(1), Some(3));
+        assert_eq!(client.analog_value(0), Some(0x110));
+        assert!(matches!(events.lock().unwrap()[..], [FirmataEvent::Analog { channel: 0, value: 0x110, .. }]));
+        client.close();
+    }
+}
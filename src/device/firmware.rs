@@ -0,0 +1,541 @@
+use super::esp_loader::{EspLoader, FlashProgress, ROM_BAUD_RATE};
+use super::transport::open_transport;
+use super::repl::{probe_micropython, ReplBanner};
+use super::reset::{port_snapshot, wait_for_new_port};
+use super::DeviceType;
+use crate::utils::get_firmware_dir;
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// 固件清单文件名，固件目录和U盘导入目录都使用这个名字
+pub const MANIFEST_FILE: &str = "firmware_manifest.json";
+
+/// 烧录后等待板子重启并出现串口的时间
+const REBOOT_WAIT: Duration = Duration::from_secs(15);
+const ESP_FLASH_BAUD_RATE: u32 = 460800;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FirmwareFormat {
+    /// 复制到UF2引导程序的U盘（Pico）
+    Uf2,
+    /// 复制到DAPLink的U盘（micro:bit）
+    Hex,
+    /// 通过ESP ROM引导程序烧录
+    Bin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirmwareEntry {
+    pub board: String,
+    pub version: String,
+    pub file: String,
+    pub sha256: String,
+    #[serde(default)]
+    pub format: Option<FirmwareFormat>,
+    /// ESP固件的烧录地址，不填时按芯片默认值
+    #[serde(default)]
+    pub offset: Option<u32>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl FirmwareFormat {
+    /// 按文件扩展名推断固件格式
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()) {
+            Some(ext) if ext == "uf2" => Ok(FirmwareFormat::Uf2),
+            Some(ext) if ext == "hex" => Ok(FirmwareFormat::Hex),
+            Some(ext) if ext == "bin" => Ok(FirmwareFormat::Bin),
+            _ => Err(anyhow!("无法识别固件格式: {}", path.display())),
+        }
+    }
+}
+
+impl FirmwareEntry {
+    /// 固件格式，未声明时按文件扩展名推断
+    pub fn format(&self) -> Result<FirmwareFormat> {
+        match self.format {
+            Some(format) => Ok(format),
+            None => FirmwareFormat::from_path(Path::new(&self.file)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FirmwareCatalog {
+    pub firmwares: Vec<FirmwareEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirmwareInstallResult {
+    pub board: String,
+    pub version: String,
+    pub port: Option<String>,
+    pub banner: Option<ReplBanner>,
+    pub duration_ms: u64,
+    pub message: String,
+}
+
+/// 设备类型对应的默认固件板卡名
+pub fn default_board_for(device_type: &DeviceType) -> Option<&'static str> {
+    match device_type {
+        DeviceType::ESP32 => Some("esp32"),
+        DeviceType::RaspberryPiPico => Some("rpi-pico"),
+        DeviceType::MicroBit => Some("microbit"),
+        DeviceType::Arduino | DeviceType::Unknown => None,
+    }
+}
+
+/// 计算文件的SHA256
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let data = fs::read(path).map_err(|e| anyhow!("读取文件失败 {:?}: {}", path, e))?;
+    Ok(format!("{:x}", Sha256::digest(&data)))
+}
+
+/// 本地固件库，固件文件和清单保存在应用数据目录下
+pub struct FirmwareStore {
+    root: PathBuf,
+}
+
+impl FirmwareStore {
+    pub fn open() -> Result<Self> {
+        Ok(Self::with_root(get_firmware_dir()?))
+    }
+
+    pub fn with_root(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// 读取固件清单，没有清单时返回空列表
+    pub fn load_catalog(&self) -> Result<FirmwareCatalog> {
+        read_manifest(&self.root.join(MANIFEST_FILE)).or_else(|e| {
+            if self.root.join(MANIFEST_FILE).exists() {
+                Err(e)
+            } else {
+                Ok(FirmwareCatalog::default())
+            }
+        })
+    }
+
+    fn save_catalog(&self, catalog: &FirmwareCatalog) -> Result<()> {
+        let json = serde_json::to_string_pretty(catalog)?;
+        fs::write(self.root.join(MANIFEST_FILE), json)?;
+        Ok(())
+    }
+
+    /// 查找固件，未指定版本时取版本号最新的一个
+    pub fn find(&self, board: &str, version: Option<&str>) -> Result<FirmwareEntry> {
+        let catalog = self.load_catalog()?;
+        let mut candidates: Vec<FirmwareEntry> = catalog.firmwares
+            .into_iter()
+            .filter(|f| f.board.eq_ignore_ascii_case(board))
+            .filter(|f| version.is_none_or(|v| f.version == v))
+            .collect();
+        candidates.sort_by(|a, b| compare_versions(&a.version, &b.version));
+
+        candidates.pop().ok_or_else(|| match version {
+            Some(v) => anyhow!("固件库中没有 {} 的 {} 版本固件，请先导入", board, v),
+            None => anyhow!("固件库中没有 {} 的固件，请先导入", board),
+        })
+    }
+
+    /// 固件文件路径
+    pub fn file_path(&self, entry: &FirmwareEntry) -> PathBuf {
+        self.root.join(&entry.file)
+    }
+
+    /// 校验固件文件的SHA256
+    pub fn verify(&self, entry: &FirmwareEntry) -> Result<Vec<u8>> {
+        let path = self.file_path(entry);
+        let data = fs::read(&path).map_err(|e| anyhow!("读取固件失败 {:?}: {}", path, e))?;
+        let actual = format!("{:x}", Sha256::digest(&data));
+        if !actual.eq_ignore_ascii_case(&entry.sha256) {
+            return Err(anyhow!(
+                "固件 {} 校验失败，文件可能已损坏: 期望 {}, 实际 {}",
+                entry.file, entry.sha256, actual
+            ));
+        }
+        Ok(data)
+    }
+
+    /// 从目录（例如U盘）导入固件清单和固件文件，返回导入的数量
+    pub fn import_from_dir(&self, source_dir: &Path) -> Result<usize> {
+        info!("从 {:?} 导入固件", source_dir);
+        let source = read_manifest(&source_dir.join(MANIFEST_FILE))?;
+        let mut catalog = self.load_catalog()?;
+        let mut imported = 0;
+
+        for entry in source.firmwares {
+            let source_file = source_dir.join(&entry.file);
+            let actual = match sha256_file(&source_file) {
+                Ok(hash) => hash,
+                Err(e) => {
+                    warn!("跳过固件 {}: {}", entry.file, e);
+                    continue;
+                }
+            };
+            if !actual.eq_ignore_ascii_case(&entry.sha256) {
+                warn!("跳过固件 {}: SHA256不匹配", entry.file);
+                continue;
+            }
+
+            let file_name = Path::new(&entry.file)
+                .file_name()
+                .ok_or_else(|| anyhow!("无效的固件文件名: {}", entry.file))?;
+            fs::copy(&source_file, self.root.join(file_name))?;
+
+            let entry = FirmwareEntry {
+                file: file_name.to_string_lossy().to_string(),
+                ..entry
+            };
+            catalog.firmwares.retain(|f| !(f.board == entry.board && f.version == entry.version));
+            catalog.firmwares.push(entry);
+            imported += 1;
+        }
+
+        self.save_catalog(&catalog)?;
+        info!("导入了 {} 个固件", imported);
+        Ok(imported)
+    }
+}
+
+fn read_manifest(path: &Path) -> Result<FirmwareCatalog> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("读取固件清单失败 {:?}: {}", path, e))?;
+    serde_json::from_str(&content)
+        .map_err(|e| anyhow!("固件清单格式错误 {:?}: {}", path, e))
+}
+
+/// 按数字段比较版本号，例如 1.9.4 < 1.22.0
+fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let parse = |v: &str| -> Vec<u64> {
+        v.trim_start_matches('v')
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse().unwrap_or(0))
+            .collect()
+    };
+    parse(a).cmp(&parse(b))
+}
+
+/// 查找引导程序模拟出的所有U盘（UF2的INFO_UF2.TXT或DAPLink的DETAILS.TXT）
+pub fn find_bootloader_drives(marker_file: &str) -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = Vec::new();
+
+    #[cfg(target_os = "windows")]
+    {
+        for letter in b'D'..=b'Z' {
+            roots.push(PathBuf::from(format!("{}:\\", letter as char)));
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        let mut parents = vec![PathBuf::from("/Volumes"), PathBuf::from("/mnt")];
+        if let Ok(user) = std::env::var("USER") {
+            parents.push(PathBuf::from("/media").join(&user));
+            parents.push(PathBuf::from("/run/media").join(&user));
+        }
+        parents.push(PathBuf::from("/media"));
+
+        for parent in parents {
+            if let Ok(entries) = fs::read_dir(&parent) {
+                roots.extend(entries.flatten().map(|e| e.path()));
+            }
+        }
+    }
+
+    roots.into_iter().filter(|root| root.join(marker_file).exists()).collect()
+}
+
+/// 找到序列号为 `serial_number` 的板子的引导程序U盘
+///
+/// 教室里常常同时插着好几块同型号的板子，只有一个U盘时才允许不核对序列号。
+pub fn find_device_drive(marker_file: &str, serial_number: Option<&str>) -> Result<PathBuf> {
+    pick_drive(find_bootloader_drives(marker_file), serial_number, drive_serial_matches)
+}
+
+fn pick_drive<F>(drives: Vec<PathBuf>, serial_number: Option<&str>, matches: F) -> Result<PathBuf>
+where
+    F: Fn(&Path, &str) -> Option<bool>,
+{
+    if drives.is_empty() {
+        return Err(anyhow!("没有找到板子的U盘，请按住BOOTSEL键插入USB线后重试"));
+    }
+    let Some(serial) = serial_number else {
+        return match <[PathBuf; 1]>::try_from(drives) {
+            Ok([drive]) => Ok(drive),
+            Err(drives) => Err(anyhow!("找到 {} 个板子的U盘，无法确定要写入哪一个，请只连接一块板子", drives.len())),
+        };
+    };
+
+    let results: Vec<(PathBuf, Option<bool>)> = drives.into_iter()
+        .map(|drive| {
+            let matched = matches(&drive, serial);
+            (drive, matched)
+        })
+        .collect();
+    let mut matched = results.iter().filter(|(_, m)| *m == Some(true));
+    match (matched.next(), matched.next()) {
+        (Some((drive, _)), None) => Ok(drive.clone()),
+        (Some(_), Some(_)) => Err(anyhow!("有多个U盘属于序列号为 {} 的板子", serial)),
+        // 只有一个U盘且无法读出它的序列号时直接使用
+        (None, _) if results.len() == 1 && results[0].1.is_none() => {
+            warn!("无法确认U盘 {:?} 的序列号，直接写入", results[0].0);
+            Ok(results[0].0.clone())
+        }
+        (None, _) => Err(anyhow!("没有找到序列号为 {} 的板子的U盘", serial)),
+    }
+}
+
+/// U盘是否属于序列号为 `serial` 的板子，无法判断时返回空
+fn drive_serial_matches(drive: &Path, serial: &str) -> Option<bool> {
+    // DAPLink的DETAILS.TXT中的Unique ID就是串口的USB序列号
+    if let Ok(details) = fs::read_to_string(drive.join("DETAILS.TXT")) {
+        return details_unique_id(&details).map(|id| id.eq_ignore_ascii_case(serial));
+    }
+    // RP2040的BOOTSEL U盘和运行程序时的串口使用相同的序列号（Flash的唯一ID）
+    #[cfg(target_os = "linux")]
+    {
+        linux_drive_serial_matches(drive, serial)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+fn details_unique_id(details: &str) -> Option<&str> {
+    details.lines()
+        .find_map(|line| line.trim().strip_prefix("Unique ID:"))
+        .map(|id| id.trim())
+}
+
+/// 通过 /dev/disk/by-id 和 /proc/mounts 找出挂载在 `drive` 的U盘的USB序列号
+#[cfg(target_os = "linux")]
+fn linux_drive_serial_matches(drive: &Path, serial: &str) -> Option<bool> {
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    let device = mounted_device(&mounts, drive)?;
+    let entries = fs::read_dir("/dev/disk/by-id").ok()?;
+    let serial = serial.to_lowercase();
+    Some(entries.flatten().any(|entry| {
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        name.starts_with("usb-")
+            && name.contains(&serial)
+            && fs::canonicalize(entry.path()).is_ok_and(|target| target == Path::new(&device))
+    }))
+}
+
+/// /proc/mounts 中挂载到 `mount_point` 的设备
+#[cfg(target_os = "linux")]
+fn mounted_device(mounts: &str, mount_point: &Path) -> Option<String> {
+    mounts.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let device = fields.next()?;
+        // 挂载点中的空格被转义为 \040
+        let point = fields.next()?.replace("\\040", " ");
+        (Path::new(&point) == mount_point).then(|| device.to_string())
+    })
+}
+
+/// 复制固件到引导程序U盘，`serial_number` 为板子的USB序列号
+pub fn copy_to_drive(data: &[u8], file_name: &str, marker_file: &str, serial_number: Option<&str>) -> Result<()> {
+    let drive = find_device_drive(marker_file, serial_number)?;
+    info!("复制固件到 {:?}", drive);
+    fs::write(drive.join(file_name), data)
+        .map_err(|e| anyhow!("复制固件到U盘失败: {}", e))
+}
+
+/// 烧录MicroPython固件并通过REPL确认版本
+///
+/// `port` 是ESP32的串口；UF2/HEX格式通过U盘烧录，不需要串口。
+pub fn install_firmware<F>(
+    store: &FirmwareStore,
+    entry: &FirmwareEntry,
+    port: Option<&str>,
+    mut on_progress: F,
+) -> Result<FirmwareInstallResult>
+where
+    F: FnMut(&str, f32),
+{
+    let started = Instant::now();
+    on_progress("verify", 0.0);
+    let data = store.verify(entry)?;
+
+    let format = entry.format()?;
+    on_progress("flash", 0.05);
+    let probe_port = match format {
+        FirmwareFormat::Uf2 => {
+            let known = port_snapshot();
+            copy_to_drive(&data, &entry.file, "INFO_UF2.TXT", None)?;
+            // Pico写入固件后自动重启，MicroPython的CDC串口为 2e8a:0005
+            wait_for_new_port(0x2e8a, Some(0x0005), &known, REBOOT_WAIT)
+        }
+        FirmwareFormat::Hex => {
+            copy_to_drive(&data, &entry.file, "DETAILS.TXT", None)?;
+            // DAPLink的串口在烧录前后保持不变，不需要和之前的列表对比
+            std::thread::sleep(Duration::from_secs(3));
+            port.map(|p| p.to_string()).or_else(|| wait_for_new_port(0x0d28, Some(0x0204), &[], REBOOT_WAIT))
+        }
+        FirmwareFormat::Bin => {
+            let port = port.ok_or_else(|| anyhow!("烧录ESP32固件需要指定串口"))?;
+            flash_esp_firmware(port, &data, entry.offset, &mut on_progress)?;
+            Some(port.to_string())
+        }
+    };
+
+    on_progress("probe", 0.95);
+    let port_name = probe_port.ok_or_else(|| anyhow!("固件已写入，但没有找到重启后的串口"))?;
+    // 给固件留出启动时间
+    std::thread::sleep(Duration::from_secs(2));
+    let banner = probe_micropython(&port_name, Duration::from_secs(3))
+        .map_err(|e| anyhow!("固件已写入，但无法确认MicroPython运行: {}", e))?;
+
+    if !version_matches(&entry.version, &banner.version) {
+        return Err(anyhow!(
+            "固件版本不一致: 期望 {}, 实际 {}，新固件可能没有写入成功",
+            entry.version, banner.version
+        ));
+    }
+    on_progress("done", 1.0);
+
+    Ok(FirmwareInstallResult {
+        board: entry.board.clone(),
+        version: banner.version.clone(),
+        port: Some(port_name),
+        message: format!("MicroPython {} 安装成功", banner.version),
+        banner: Some(banner),
+        duration_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+/// 目录中的版本号（可带 "v" 前缀）是否与REPL欢迎信息中的版本一致
+///
+/// 逐段比较数字，缺少的段按0处理：1.22 与 1.22.0 一致，1.2 与 1.22.0 不一致。
+fn version_matches(expected: &str, actual: &str) -> bool {
+    let mut expected = version_components(expected);
+    let mut actual = version_components(actual);
+    let len = expected.len().max(actual.len());
+    expected.resize(len, 0);
+    actual.resize(len, 0);
+    !expected.is_empty() && expected == actual
+}
+
+/// 版本号开头的数字段，忽略 "-preview" 之类的后缀
+fn version_components(version: &str) -> Vec<u64> {
+    version.trim().trim_start_matches('v')
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()
+        .unwrap_or("")
+        .split('.')
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+/// 通过ROM引导程序烧录ESP固件，`offset` 为空时按芯片默认的引导程序地址
+pub fn flash_esp_firmware<F>(port_name: &str, data: &[u8], offset: Option<u32>, on_progress: &mut F) -> Result<()>
+where
+    F: FnMut(&str, f32),
+{
+    let port = open_transport(port_name, ROM_BAUD_RATE, Duration::from_millis(50))?;
+
+    let mut loader = EspLoader::new(port);
+    loader.connect(5)?;
+    let chip = loader.chip().ok_or_else(|| anyhow!("无法识别芯片"))?;
+    if let Err(e) = loader.change_baud(ESP_FLASH_BAUD_RATE) {
+        warn!("切换高速波特率失败，继续使用 {}: {}", ROM_BAUD_RATE, e);
+    }
+
+    let offset = offset.unwrap_or_else(|| chip.bootloader_offset());
+    loader.flash_image(offset, data, |p: FlashProgress| {
+        on_progress("flash", 0.05 + 0.9 * p.written as f32 / p.total as f32);
+    })?;
+    loader.finish(true)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_import_and_find() {
+        let base = std::env::temp_dir().join(format!("rustblock_fw_test_{}", uuid::Uuid::new_v4()));
+        let usb = base.join("usb");
+        let store_dir = base.join("store");
+        fs::create_dir_all(&usb).unwrap();
+        fs::create_dir_all(&store_dir).unwrap();
+
+        fs::write(usb.join("pico-1.21.uf2"), b"old").unwrap();
+        fs::write(usb.join("pico-1.22.uf2"), b"new").unwrap();
+        let manifest = serde_json::json!({
+            "firmwares": [
+                { "board": "rpi-pico", "version": "1.21.0", "file": "pico-1.21.uf2",
+                  "sha256": format!("{:x}", Sha256::digest(b"old")) },
+                { "board": "rpi-pico", "version": "1.22.0", "file": "pico-1.22.uf2",
+                  "sha256": format!("{:x}", Sha256::digest(b"new")) },
+                { "board": "esp32", "version": "1.22.0", "file": "missing.bin", "sha256": "00" }
+            ]
+        });
+        fs::write(usb.join(MANIFEST_FILE), manifest.to_string()).unwrap();
+
+        let store = FirmwareStore::with_root(store_dir);
+        assert_eq!(store.import_from_dir(&usb).unwrap(), 2);
+
+        let latest = store.find("rpi-pico", None).unwrap();
+        assert_eq!(latest.version, "1.22.0");
+        assert_eq!(latest.format().unwrap(), FirmwareFormat::Uf2);
+        assert_eq!(store.verify(&latest).unwrap(), b"new");
+        assert!(store.find("esp32", None).is_err());
+
+        fs::write(store.file_path(&latest), b"tampered").unwrap();
+        assert!(store.verify(&latest).is_err());
+
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_firmware_version_mismatch() {
+        assert!(version_matches("v1.22.0", "1.22.0"));
+        assert!(version_matches("1.22.0", "v1.22.0"));
+        assert!(!version_matches("1.23.0", "1.22.0"));
+    }
+
+    #[test]
+    fn test_firmware_version_compares_components() {
+        assert!(version_matches("1.22", "1.22.0"));
+        assert!(version_matches("1.22.0", "1.22.0-preview.45"));
+        assert!(!version_matches("1.2", "1.22.0"));
+        assert!(!version_matches("1.22.1", "1.22.10"));
+    }
+
+    #[test]
+    fn test_pick_drive_by_serial() {
+        let drives = || vec![PathBuf::from("/media/a"), PathBuf::from("/media/b")];
+        let serial_of = |drive: &Path, serial: &str| Some(drive.ends_with("b") == (serial == "E661"));
+
+        assert_eq!(pick_drive(drives(), Some("E661"), serial_of).unwrap(), PathBuf::from("/media/b"));
+        assert!(pick_drive(drives(), Some("FFFF"), |_, _| Some(false)).is_err());
+        // 多个U盘又没有序列号时不能随便选一个
+        assert!(pick_drive(drives(), None, serial_of).is_err());
+        assert!(pick_drive(drives(), Some("E661"), |_, _| None).is_err());
+        assert_eq!(pick_drive(vec![PathBuf::from("/media/a")], Some("E661"), |_, _| None).unwrap(), PathBuf::from("/media/a"));
+    }
+
+    #[test]
+    fn test_details_unique_id() {
+        let details = "# DAPLink Firmware - see https://daplink.io\r\nUnique ID: 9904360258994e45002b\r\nHIC ID: 9b8f9f8d\r\n";
+        assert_eq!(details_unique_id(details), Some("9904360258994e45002b"));
+        assert_eq!(details_unique_id("Version: 0255"), None);
+    }
+}
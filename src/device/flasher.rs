@@ -0,0 +1,264 @@
+use super::esp_loader::ESP32_APP_OFFSET;
+use super::firmware::{copy_to_drive, find_device_drive, flash_esp_firmware, FirmwareFormat};
+use super::reset::touch_1200bps;
+use super::toolchain::Toolchain;
+use super::transport::open_transport;
+use super::stk500::{parse_intel_hex, program_avr_flash, ATMEGA328P_PAGE_SIZE};
+use super::uploader::BoardConfig;
+use super::DeviceType;
+use anyhow::{Result, anyhow};
+use log::info;
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// 等待Pico进入BOOTSEL模式并出现U盘的时间
+const BOOTSEL_WAIT: Duration = Duration::from_secs(10);
+/// ESP镜像头的魔数
+const ESP_IMAGE_MAGIC: u8 = 0xE9;
+/// ESP-IDF应用程序描述结构（esp_app_desc_t）的魔数，位于第一个段的开头
+const ESP_APP_DESC_MAGIC: u32 = 0xABCD_5432;
+/// 镜像头（24字节）加第一个段头（8字节）之后就是应用程序描述
+const ESP_APP_DESC_OFFSET: usize = 24 + 8;
+/// ESP32的二级引导程序地址，从0开始的合并镜像在这里才是镜像头
+const ESP32_BOOTLOADER_OFFSET: usize = 0x1000;
+
+/// 烧录使用的工具
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlashTool {
+    /// 调用 arduino-cli upload
+    ArduinoCli,
+    /// 内置的STK500v1客户端（Uno/Nano的optiboot）
+    Stk500,
+    /// 内置的ESP ROM引导程序客户端
+    EspBootloader,
+    /// 复制UF2文件到Pico的BOOTSEL U盘
+    Uf2Copy,
+    /// 复制HEX文件到micro:bit的DAPLink U盘
+    DaplinkCopy,
+    /// 调用 picotool load
+    Picotool,
+}
+
+impl FlashTool {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FlashTool::ArduinoCli => "arduino-cli",
+            FlashTool::Stk500 => "stk500",
+            FlashTool::EspBootloader => "esp-bootloader",
+            FlashTool::Uf2Copy => "uf2-copy",
+            FlashTool::DaplinkCopy => "daplink-copy",
+            FlashTool::Picotool => "picotool",
+        }
+    }
+}
+
+/// 一次烧录的结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlashOutcome {
+    pub tool: FlashTool,
+    pub bytes_written: usize,
+    pub message: String,
+}
+
+/// 根据设备类型、开发板和固件格式选择烧录工具
+pub fn select_flash_tool(
+    device_type: &DeviceType,
+    board: &BoardConfig,
+    format: FirmwareFormat,
+    picotool_available: bool,
+) -> Result<FlashTool> {
+    match (device_type, format) {
+        (DeviceType::Arduino, FirmwareFormat::Hex) if board.upload_protocol == "arduino" => Ok(FlashTool::Stk500),
+        (DeviceType::Arduino, FirmwareFormat::Hex) => Ok(FlashTool::ArduinoCli),
+        (DeviceType::MicroBit, FirmwareFormat::Hex) => Ok(FlashTool::DaplinkCopy),
+        (DeviceType::ESP32, FirmwareFormat::Bin) => Ok(FlashTool::EspBootloader),
+        (DeviceType::RaspberryPiPico, FirmwareFormat::Uf2) if picotool_available => Ok(FlashTool::Picotool),
+        (DeviceType::RaspberryPiPico, FirmwareFormat::Uf2) => Ok(FlashTool::Uf2Copy),
+        (DeviceType::RaspberryPiPico, FirmwareFormat::Bin) | (DeviceType::RaspberryPiPico, FirmwareFormat::Hex) => {
+            Ok(FlashTool::ArduinoCli)
+        }
+        _ => Err(anyhow!("设备类型 {:?} 不支持 {:?} 格式的固件", device_type, format)),
+    }
+}
+
+/// 根据镜像头确定ESP固件的烧录地址，`None` 表示使用芯片默认的引导程序地址
+///
+/// 应用程序镜像的第一个段以esp_app_desc_t开头；带引导程序的合并镜像没有它，
+/// 从0开始的合并镜像在前0x1000字节是空白（ESP32的引导程序地址在0x1000）。
+pub fn esp_image_offset(data: &[u8]) -> Result<Option<u32>> {
+    match data.first() {
+        Some(&ESP_IMAGE_MAGIC) => {
+            let desc_magic = data.get(ESP_APP_DESC_OFFSET..ESP_APP_DESC_OFFSET + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+            if desc_magic == Some(ESP_APP_DESC_MAGIC) {
+                Ok(Some(ESP32_APP_OFFSET))
+            } else {
+                Ok(None)
+            }
+        }
+        Some(0xFF) if data.get(ESP32_BOOTLOADER_OFFSET) == Some(&ESP_IMAGE_MAGIC)
+            && data[..ESP32_BOOTLOADER_OFFSET].iter().all(|&b| b == 0xFF) => Ok(Some(0)),
+        _ => Err(anyhow!("无法识别的ESP固件镜像，文件开头不是镜像头")),
+    }
+}
+
+/// 用选定的工具烧录固件，`on_progress` 的参数为0到1之间的进度
+///
+/// `serial_number` 是板子的USB序列号，用来在同时连接的多块板子中找到它的U盘或BOOTSEL设备。
+pub fn flash_firmware<F>(
+    tool: FlashTool,
+    port: &str,
+    serial_number: Option<&str>,
+    board: &BoardConfig,
+    firmware_path: &Path,
+    toolchain: &Toolchain,
+    mut on_progress: F,
+) -> Result<FlashOutcome>
+where
+    F: FnMut(f32),
+{
+    info!("使用 {} 烧录 {:?} 到 {}", tool.name(), firmware_path, port);
+    let file_name = firmware_path.file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("无效的固件路径: {:?}", firmware_path))?;
+    let data = fs::read(firmware_path)
+        .map_err(|e| anyhow!("读取固件失败 {:?}: {}", firmware_path, e))?;
+
+    let bytes_written = match tool {
+        FlashTool::Stk500 => {
+            let image = parse_intel_hex(&String::from_utf8_lossy(&data))?;
+            let serial = open_transport(port, board.upload_speed, Duration::from_millis(50))?;
+            program_avr_flash(serial, &image, ATMEGA328P_PAGE_SIZE, |written, total| {
+                on_progress(written as f32 / total as f32);
+            })?
+        }
+        FlashTool::EspBootloader => {
+            let offset = esp_image_offset(&data)?;
+            flash_esp_firmware(port, &data, offset, &mut |_: &str, progress: f32| on_progress(progress))?;
+            data.len()
+        }
+        FlashTool::Uf2Copy => {
+            if find_device_drive("INFO_UF2.TXT", serial_number).is_err() {
+                reboot_to_bootsel(port, serial_number)?;
+            }
+            copy_to_drive(&data, file_name, "INFO_UF2.TXT", serial_number)?;
+            data.len()
+        }
+        FlashTool::DaplinkCopy => {
+            copy_to_drive(&data, file_name, "DETAILS.TXT", serial_number)?;
+            data.len()
+        }
+        FlashTool::Picotool => {
+            let mut command = Command::new("picotool");
+            command.args(picotool_args(path_str(firmware_path)?, serial_number)?);
+            run_command("picotool", command)?;
+            data.len()
+        }
+        FlashTool::ArduinoCli => {
+            let mut command = toolchain.command()?;
+            command.args(["upload", "--fqbn", &board.fqbn, "--port", port, "--input-file", path_str(firmware_path)?]);
+            run_command("arduino-cli", command)?;
+            data.len()
+        }
+    };
+
+    on_progress(1.0);
+    Ok(FlashOutcome {
+        tool,
+        bytes_written,
+        message: format!("已通过 {} 写入 {} 字节", tool.name(), bytes_written),
+    })
+}
+
+/// picotool的参数，用 `--ser` 只选中序列号匹配的那块Pico
+///
+/// 不指定时picotool会烧录它找到的第一块板子，多块板子同时连接时可能写错。
+fn picotool_args<'a>(firmware_path: &'a str, serial_number: Option<&'a str>) -> Result<Vec<&'a str>> {
+    let serial = serial_number
+        .ok_or_else(|| anyhow!("设备没有USB序列号，无法确定picotool要烧录哪块Pico"))?;
+    Ok(vec!["load", "-x", firmware_path, "-f", "--ser", serial])
+}
+
+/// 以1200波特率打开再关闭串口，让运行Arduino程序的Pico重启进入BOOTSEL模式
+fn reboot_to_bootsel(port: &str, serial_number: Option<&str>) -> Result<()> {
+    info!("通过1200波特率复位让 {} 进入BOOTSEL模式", port);
+    let _ = touch_1200bps(port);
+
+    let deadline = Instant::now() + BOOTSEL_WAIT;
+    while Instant::now() < deadline {
+        if find_device_drive("INFO_UF2.TXT", serial_number).is_ok() {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+    Err(anyhow!("Pico没有进入BOOTSEL模式，请按住BOOTSEL键重新插入USB线"))
+}
+
+fn run_command(program: &str, mut command: Command) -> Result<()> {
+    let output = command
+        .output()
+        .map_err(|e| anyhow!("执行 {} 失败: {}", program, e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} 执行失败: {}", program, String::from_utf8_lossy(&output.stderr)))
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| anyhow!("无法转换路径为字符串"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::boards::BoardCatalog;
+
+    #[test]
+    fn test_select_flash_tool() {
+        let catalog = BoardCatalog::builtin();
+        let board = |id: &str| catalog.get(id).unwrap();
+        let select = |device_type, id, format, picotool| select_flash_tool(&device_type, board(id), format, picotool).unwrap();
+
+        assert_eq!(select(DeviceType::Arduino, "uno", FirmwareFormat::Hex, false), FlashTool::Stk500);
+        assert_eq!(select(DeviceType::Arduino, "leonardo", FirmwareFormat::Hex, false), FlashTool::ArduinoCli);
+        assert_eq!(select(DeviceType::MicroBit, "microbit", FirmwareFormat::Hex, false), FlashTool::DaplinkCopy);
+        assert_eq!(select(DeviceType::ESP32, "esp32", FirmwareFormat::Bin, false), FlashTool::EspBootloader);
+        assert_eq!(select(DeviceType::RaspberryPiPico, "pico", FirmwareFormat::Uf2, true), FlashTool::Picotool);
+        assert_eq!(select(DeviceType::RaspberryPiPico, "pico", FirmwareFormat::Uf2, false), FlashTool::Uf2Copy);
+        assert!(select_flash_tool(&DeviceType::ESP32, board("esp32"), FirmwareFormat::Uf2, false).is_err());
+    }
+
+    #[test]
+    fn test_esp_image_offset_from_header() {
+        let mut app = vec![0u8; 64];
+        app[0] = ESP_IMAGE_MAGIC;
+        app[ESP_APP_DESC_OFFSET..ESP_APP_DESC_OFFSET + 4].copy_from_slice(&ESP_APP_DESC_MAGIC.to_le_bytes());
+        assert_eq!(esp_image_offset(&app).unwrap(), Some(ESP32_APP_OFFSET));
+
+        // 从引导程序地址开始的合并镜像
+        let mut merged = vec![0u8; 64];
+        merged[0] = ESP_IMAGE_MAGIC;
+        assert_eq!(esp_image_offset(&merged).unwrap(), None);
+
+        // 从0开始、前0x1000字节空白的合并镜像
+        let mut from_zero = vec![0xFF; ESP32_BOOTLOADER_OFFSET + 64];
+        from_zero[ESP32_BOOTLOADER_OFFSET] = ESP_IMAGE_MAGIC;
+        assert_eq!(esp_image_offset(&from_zero).unwrap(), Some(0));
+
+        assert!(esp_image_offset(b"not an image").is_err());
+    }
+
+    #[test]
+    fn test_picotool_targets_serial() {
+        assert_eq!(
+            picotool_args("/tmp/blink.uf2", Some("E6616407E3646A2B")).unwrap(),
+            vec!["load", "-x", "/tmp/blink.uf2", "-f", "--ser", "E6616407E3646A2B"]
+        );
+        assert!(picotool_args("/tmp/blink.uf2", None).is_err());
+    }
+}
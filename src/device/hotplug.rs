@@ -0,0 +1,190 @@
+use super::connection_manager::ConnectionManager;
+use super::DeviceInfo;
+use log::{debug, info};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 检查串口列表的间隔
+pub const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+/// 串口列表保持不变这么久才重新扫描（插拔时会短暂出现又消失的端口）
+pub const DEBOUNCE_TIME: Duration = Duration::from_millis(500);
+
+/// 设备列表的变化（`device-added`、`device-removed`、`device-changed` 事件）
+#[derive(Debug, Clone)]
+pub enum DeviceChange {
+    Added(DeviceInfo),
+    Removed(DeviceInfo),
+    Changed(DeviceInfo),
+}
+
+impl DeviceChange {
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            DeviceChange::Added(_) => "device-added",
+            DeviceChange::Removed(_) => "device-removed",
+            DeviceChange::Changed(_) => "device-changed",
+        }
+    }
+
+    pub fn device(&self) -> &DeviceInfo {
+        match self {
+            DeviceChange::Added(device) | DeviceChange::Removed(device) | DeviceChange::Changed(device) => device,
+        }
+    }
+
+    /// 更新连接管理器中的连接状态，自动重连据此得知设备拔出和重新插入
+    pub async fn update_connection(&self, connections: &ConnectionManager) {
+        match self {
+            DeviceChange::Removed(device) => {
+                connections.update_connection_status(&device.id, false, Some("设备已拔出".to_string())).await
+            }
+            DeviceChange::Added(device) | DeviceChange::Changed(device) => {
+                connections.update_connection_status(&device.id, true, None).await
+            }
+        }
+    }
+}
+
+/// 比较两次扫描的结果
+pub fn diff_devices(old: &HashMap<String, DeviceInfo>, new: &[DeviceInfo]) -> Vec<DeviceChange> {
+    let mut changes: Vec<DeviceChange> = new.iter()
+        .filter_map(|device| match old.get(&device.id) {
+            None => Some(DeviceChange::Added(device.clone())),
+            Some(previous) if previous != device => Some(DeviceChange::Changed(device.clone())),
+            Some(_) => None,
+        })
+        .collect();
+    changes.extend(old.values()
+        .filter(|device| !new.iter().any(|d| d.id == device.id))
+        .map(|device| DeviceChange::Removed(device.clone())));
+    changes
+}
+
+/// 串口列表去抖：列表变化后要保持一段时间不变才算一次插拔
+struct Debouncer {
+    current: BTreeSet<String>,
+    pending: Option<(BTreeSet<String>, Instant)>,
+}
+
+impl Debouncer {
+    fn new(current: BTreeSet<String>) -> Self {
+        Self { current, pending: None }
+    }
+
+    /// 记录一次观察结果，返回是否应该重新扫描
+    fn observe(&mut self, ports: BTreeSet<String>, now: Instant) -> bool {
+        if ports == self.current {
+            self.pending = None;
+            return false;
+        }
+        match &self.pending {
+            Some((pending, since)) if *pending == ports => {
+                if now.duration_since(*since) < DEBOUNCE_TIME {
+                    return false;
+                }
+                self.current = ports;
+                self.pending = None;
+                true
+            }
+            _ => {
+                self.pending = Some((ports, now));
+                false
+            }
+        }
+    }
+}
+
+fn port_names() -> BTreeSet<String> {
+    serialport::available_ports()
+        .map(|ports| ports.into_iter().map(|p| p.port_name).collect())
+        .unwrap_or_default()
+}
+
+/// 后台监视USB串口的插拔
+///
+/// 定时比较 `serialport::available_ports()` 的结果，列表变化并稳定后调用 `on_change`，
+/// 由调用方重新扫描设备。丢弃时停止。
+pub struct HotplugWatcher {
+    stop: Arc<AtomicBool>,
+}
+
+impl HotplugWatcher {
+    pub fn spawn<F>(on_change: F) -> Self
+    where
+        F: Fn() + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        std::thread::spawn(move || {
+            info!("开始监视USB设备插拔");
+            let mut debouncer = Debouncer::new(port_names());
+            while !stopped.load(Ordering::Relaxed) {
+                let interval = if debouncer.pending.is_some() { DEBOUNCE_TIME } else { POLL_INTERVAL };
+                std::thread::sleep(interval);
+                if debouncer.observe(port_names(), Instant::now()) {
+                    debug!("串口列表发生变化: {:?}", debouncer.current);
+                    on_change();
+                }
+            }
+            info!("停止监视USB设备插拔");
+        });
+        Self { stop }
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for HotplugWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hotplug_diff() {
+        let uno = DeviceInfo::new("/dev/ttyACM0".to_string(), Some(0x2341), Some(0x0043));
+        let esp = DeviceInfo::new("/dev/ttyUSB0".to_string(), Some(0x10c4), Some(0xea60));
+        let old: HashMap<_, _> = [(uno.id.clone(), uno.clone())].into_iter().collect();
+        let mut renamed = uno.clone();
+        renamed.name = "我的Uno".to_string();
+
+        let changes = diff_devices(&old, &[renamed, esp]);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].event_name(), "device-changed");
+        assert_eq!(changes[1].event_name(), "device-added");
+        assert_eq!(diff_devices(&old, &[])[0].event_name(), "device-removed");
+
+        // 插入后短暂消失的端口不触发扫描
+        let start = Instant::now();
+        let ports = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<BTreeSet<_>>();
+        let mut debouncer = Debouncer::new(ports(&["/dev/ttyACM0"]));
+        assert!(!debouncer.observe(ports(&["/dev/ttyACM0", "/dev/ttyUSB0"]), start));
+        assert!(!debouncer.observe(ports(&["/dev/ttyACM0"]), start + DEBOUNCE_TIME));
+        assert!(!debouncer.observe(ports(&[]), start + DEBOUNCE_TIME * 2));
+        assert!(debouncer.observe(ports(&[]), start + DEBOUNCE_TIME * 3));
+        assert!(!debouncer.observe(ports(&[]), start + DEBOUNCE_TIME * 4));
+    }
+
+    #[tokio::test]
+    async fn test_hotplug_updates_connection_status() {
+        let connections = ConnectionManager::new();
+        let uno = DeviceInfo::new("/dev/ttyACM0".to_string(), Some(0x2341), Some(0x0043));
+        let connected = || async { connections.get_connection_status(&uno.id).await.unwrap().connected };
+
+        DeviceChange::Added(uno.clone()).update_connection(&connections).await;
+        assert!(connected().await);
+        DeviceChange::Removed(uno.clone()).update_connection(&connections).await;
+        assert!(!connected().await);
+        // 重新插入后自动重连能看到设备回来了
+        DeviceChange::Added(uno.clone()).update_connection(&connections).await;
+        assert!(connected().await);
+    }
+}
@@ -0,0 +1,124 @@
+use super::firmata::{FirmataClient, FirmataEventSink, FirmwareInfo, FIRMATA_BAUD_RATE};
+use super::serial::{PortLease, PortUser, SerialManager};
+use super::transport::open_transport;
+use super::upload_queue::UploadQueue;
+use super::uploader::DeviceUploader;
+use super::{DeviceInfo, DeviceType, UploadOptions};
+use anyhow::{Result, anyhow};
+use log::info;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// 交互模式下的一块开发板，会话期间一直占用串口
+struct InteractiveSession {
+    client: Arc<FirmataClient>,
+    _lease: PortLease,
+}
+
+/// 交互模式：开发板运行StandardFirmata，积木的动作通过串口立即执行，不需要编译上传
+pub struct InteractiveManager {
+    serial: Arc<SerialManager>,
+    sessions: Mutex<HashMap<String, InteractiveSession>>,
+}
+
+impl InteractiveManager {
+    pub fn new(serial: Arc<SerialManager>) -> Self {
+        Self {
+            serial,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 进入交互模式
+    ///
+    /// 开发板已经运行Firmata时直接连接；否则（或 `force_upload` 时）先上传StandardFirmata，
+    /// 同一块板子之后再进入交互模式就不需要重新上传。
+    pub async fn start(
+        &self,
+        device: &DeviceInfo,
+        board_variant: Option<String>,
+        uploader: Arc<DeviceUploader>,
+        queue: &UploadQueue,
+        force_upload: bool,
+        on_event: FirmataEventSink,
+    ) -> Result<FirmwareInfo> {
+        if device.device_type != DeviceType::Arduino {
+            return Err(anyhow!("交互模式目前只支持Arduino开发板"));
+        }
+        if let Some(firmware) = self.firmware(&device.port).await {
+            return Ok(firmware);
+        }
+
+        if !force_upload {
+            match self.connect(&device.port, Arc::clone(&on_event)).await {
+                Ok(firmware) => return Ok(firmware),
+                Err(e) => info!("{} 未运行Firmata，准备上传StandardFirmata: {}", device.port, e),
+            }
+        }
+
+        let options = UploadOptions {
+            device_id: device.id.clone(),
+            code: uploader.standard_firmata_sketch().await?,
+            language: "arduino".to_string(),
+            board_type: "arduino".to_string(),
+            board_variant,
+        };
+        queue.run_upload(uploader, device.clone(), options).await
+            .map_err(|e| anyhow!("上传StandardFirmata失败: {}", e))?;
+        self.connect(&device.port, on_event).await
+    }
+
+    async fn connect(&self, port_name: &str, on_event: FirmataEventSink) -> Result<FirmwareInfo> {
+        let lease = self.serial.acquire(port_name, PortUser::Interactive).await?;
+        let name = port_name.to_string();
+        let client = tokio::task::spawn_blocking(move || {
+            let transport = open_transport(&name, FIRMATA_BAUD_RATE, Duration::from_millis(20))?;
+            FirmataClient::connect(&name, transport, on_event)
+        }).await.map_err(|e| anyhow!("连接Firmata任务失败: {}", e))??;
+
+        let firmware = client.firmware().ok_or_else(|| anyhow!("没有读取到Firmata固件信息"))?;
+        self.sessions.lock().await.insert(port_name.to_string(), InteractiveSession {
+            client: Arc::new(client),
+            _lease: lease,
+        });
+        info!("{} 已进入交互模式", port_name);
+        Ok(firmware)
+    }
+
+    /// 退出交互模式，复位引脚并释放串口
+    pub async fn stop(&self, port_name: &str) -> Result<()> {
+        let session = self.sessions.lock().await.remove(port_name)
+            .ok_or_else(|| anyhow!("{} 不在交互模式", port_name))?;
+        tokio::task::spawn_blocking(move || {
+            let _ = session.client.reset();
+            // 先停止读取线程关闭串口，再释放租约恢复监视器
+            drop(session.client);
+            drop(session._lease);
+        }).await.map_err(|e| anyhow!("退出交互模式失败: {}", e))?;
+        info!("{} 已退出交互模式", port_name);
+        Ok(())
+    }
+
+    /// 交互模式下的Firmata客户端，连接已断开时结束会话
+    pub async fn client(&self, port_name: &str) -> Result<Arc<FirmataClient>> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get(port_name).ok_or_else(|| anyhow!("{} 不在交互模式", port_name))?;
+        if session.client.is_closed() {
+            sessions.remove(port_name);
+            return Err(anyhow!("{} 的交互模式连接已断开", port_name));
+        }
+        Ok(Arc::clone(&session.client))
+    }
+
+    pub async fn firmware(&self, port_name: &str) -> Option<FirmwareInfo> {
+        let client = self.client(port_name).await.ok()?;
+        client.firmware()
+    }
+
+    /// 处于交互模式的串口
+    pub async fn active_ports(&self) -> Vec<String> {
+        self.sessions.lock().await.keys().cloned().collect()
+    }
+}
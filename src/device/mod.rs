@@ -1,9 +1,34 @@
 pub mod detector;
+pub mod hotplug;
 pub mod serial;
+pub mod serial_reader;
+pub mod serial_codec;
+pub mod autobaud;
+pub mod serial_plot;
+pub mod serial_trigger;
+pub mod serial_macro;
+pub mod session;
+pub mod transport;
+pub mod probe;
+pub mod reset;
 pub mod uploader;
 pub mod driver;
+pub mod diagnostics;
 pub mod connection_manager;
+pub mod esp_loader;
+pub mod repl;
+pub mod firmata;
+pub mod interactive;
+pub mod firmware;
+pub mod upload_queue;
+pub mod stk500;
+pub mod verify;
+pub mod flasher;
+pub mod boards;
+pub mod board_db;
+pub mod toolchain;
 
+use log::info;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -16,7 +41,7 @@ pub enum DeviceType {
     Unknown,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DeviceInfo {
     pub id: String,
     pub name: String,
@@ -27,6 +52,15 @@ pub struct DeviceInfo {
     pub manufacturer: Option<String>,
     pub description: Option<String>,
     pub connected: bool,
+    /// USB序列号
+    #[serde(default)]
+    pub serial_number: Option<String>,
+    /// 物理USB位置（例如 `1-2.3`），没有序列号时用于生成ID
+    #[serde(default)]
+    pub usb_location: Option<String>,
+    /// 主动探测的结果
+    #[serde(default)]
+    pub probe: Option<probe::ProbeResult>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,15 +69,18 @@ pub struct UploadOptions {
     pub code: String,
     pub language: String, // "arduino" 或 "micropython"
     pub board_type: String,
+    /// 开发板型号（例如 "nano"），为空时按设备的VID/PID推断
+    #[serde(default)]
+    pub board_variant: Option<String>,
 }
 
 impl DeviceInfo {
     pub fn new(port: String, vendor_id: Option<u16>, product_id: Option<u16>) -> Self {
-        let device_type = Self::detect_device_type(vendor_id, product_id);
+        let device_type = Self::detect_device_type(vendor_id, product_id, None);
         let name = Self::generate_device_name(&device_type, &port);
         
         Self {
-            id: Self::generate_device_id(&port, vendor_id, product_id),
+            id: Self::generate_device_id(&port, vendor_id, product_id, None, None),
             name,
             device_type,
             port,
@@ -52,33 +89,77 @@ impl DeviceInfo {
             manufacturer: None,
             description: None,
             connected: false,
+            serial_number: None,
+            usb_location: None,
+            probe: None,
+        }
+    }
+    
+    /// 记录USB制造商和产品描述，产品描述可能改变识别出的设备类型
+    pub fn set_usb_strings(&mut self, manufacturer: Option<String>, product: Option<String>) {
+        self.manufacturer = manufacturer;
+        self.description = product;
+        let device_type = Self::detect_device_type(self.vendor_id, self.product_id, self.description.as_deref());
+        if device_type != self.device_type {
+            self.name = Self::generate_device_name(&device_type, &self.port);
+            self.device_type = device_type;
         }
     }
     
-    fn detect_device_type(vendor_id: Option<u16>, product_id: Option<u16>) -> DeviceType {
-        match (vendor_id, product_id) {
-            // Arduino Uno (官方)
-            (Some(0x2341), Some(0x0043)) => DeviceType::Arduino,
-            (Some(0x2341), Some(0x0001)) => DeviceType::Arduino,
-            // Arduino Nano (CH340芯片)
-            (Some(0x1a86), Some(0x7523)) => DeviceType::Arduino,
-            // micro:bit
-            (Some(0x0d28), Some(0x0204)) => DeviceType::MicroBit,
-            // ESP32 (CP210x芯片)
-            (Some(0x10c4), Some(0xea60)) => DeviceType::ESP32,
-            // ESP32 (其他芯片)
-            (Some(0x303a), Some(0x1001)) => DeviceType::ESP32,
-            // Raspberry Pi Pico
-            (Some(0x2e8a), Some(0x0005)) => DeviceType::RaspberryPiPico,
-            (Some(0x2e8a), Some(0x000a)) => DeviceType::RaspberryPiPico,
-            _ => DeviceType::Unknown,
+    /// 按探测结果修正设备类型
+    pub fn apply_probe(&mut self, result: probe::ProbeResult) {
+        if let Some(device_type) = result.device_type.clone().filter(|t| *t != self.device_type) {
+            info!("{} 探测为 {:?}（原识别为 {:?}）", self.port, device_type, self.device_type);
+            self.name = Self::generate_device_name(&device_type, &self.port);
+            self.device_type = device_type;
         }
+        self.probe = Some(result);
+    }
+    
+    fn detect_device_type(vendor_id: Option<u16>, product_id: Option<u16>, product: Option<&str>) -> DeviceType {
+        board_db::board_db()
+            .lookup(vendor_id, product_id, product)
+            .map(|entry| entry.device_type.clone())
+            .unwrap_or(DeviceType::Unknown)
     }
     
-    fn generate_device_id(port: &str, vendor_id: Option<u16>, product_id: Option<u16>) -> String {
-        // 生成基于端口和硬件ID的稳定唯一标识符
-        match (vendor_id, product_id) {
-            (Some(vid), Some(pid)) => {
+    /// 记录USB序列号和物理位置，重新生成不随串口名变化的设备ID
+    pub fn set_usb_identity(&mut self, serial_number: Option<String>, usb_location: Option<String>) {
+        self.serial_number = serial_number;
+        self.usb_location = usb_location;
+        self.id = Self::generate_device_id(
+            &self.port, self.vendor_id, self.product_id,
+            self.serial_number.as_deref(), self.usb_location.as_deref(),
+        );
+    }
+    
+    /// 序列号与其他设备重复时，改用USB位置生成ID，没有位置时使用串口名
+    pub fn disambiguate_id(&mut self) {
+        let by_location = Self::generate_device_id(&self.port, self.vendor_id, self.product_id, None, self.usb_location.as_deref());
+        self.id = if by_location != self.id {
+            by_location
+        } else {
+            Self::generate_device_id(&self.port, self.vendor_id, self.product_id, None, None)
+        };
+    }
+    
+    fn generate_device_id(
+        port: &str,
+        vendor_id: Option<u16>,
+        product_id: Option<u16>,
+        serial_number: Option<&str>,
+        usb_location: Option<&str>,
+    ) -> String {
+        let sanitize = |s: &str| s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect::<String>();
+        // 优先用USB序列号，其次是物理USB位置，最后才是串口名
+        match (vendor_id, product_id, serial_number, usb_location) {
+            (Some(vid), Some(pid), Some(serial), _) if !serial.is_empty() => {
+                format!("usb_{:04x}_{:04x}_sn_{}", vid, pid, sanitize(serial))
+            },
+            (Some(vid), Some(pid), _, Some(location)) => {
+                format!("usb_{:04x}_{:04x}_at_{}", vid, pid, sanitize(location))
+            },
+            (Some(vid), Some(pid), _, _) => {
                 format!("{}_{:04x}_{:04x}", port.replace("/dev/", "").replace(".", "_"), vid, pid)
             },
             _ => {
@@ -0,0 +1,277 @@
+use super::esp_loader::{EspChip, EspLoader, ROM_BAUD_RATE};
+use super::repl::{MicroPythonRepl, ReplBanner, REPL_BAUD_RATE};
+use super::reset::esp_hard_reset;
+use super::stk500::Stk500;
+use super::transport::open_transport;
+use super::{DeviceInfo, DeviceType};
+use crate::utils::get_app_data_dir;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// 应用数据目录下保存探测结果的文件
+pub const PROBE_CACHE_FILE: &str = "probe_cache.json";
+/// 新旧两种optiboot引导程序的波特率
+const STK500_BAUD_RATES: [u32; 2] = [115200, 57600];
+const REPL_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// 识别出设备的方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ProbeMethod {
+    /// MicroPython REPL的欢迎信息
+    MicroPython,
+    /// 与AVR的STK500引导程序同步
+    Stk500,
+    /// 与ESP的ROM引导程序同步
+    EspRom,
+}
+
+/// 主动探测的结果
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProbeResult {
+    pub method: ProbeMethod,
+    /// 识别出的设备类型，无法判断时为空
+    pub device_type: Option<DeviceType>,
+    /// 板子上程序使用的语言，只探测到引导程序时为空
+    pub language: Option<String>,
+    pub firmware_version: Option<String>,
+    /// 板卡或芯片描述
+    pub board: Option<String>,
+    /// 开发板目录中的型号标识
+    pub variant: Option<String>,
+    pub probed_at: DateTime<Utc>,
+}
+
+/// 依次尝试MicroPython REPL、STK500同步和ESP ROM同步识别设备
+///
+/// 每一步都会复位或中断板子上正在运行的程序，结束后尽量让它恢复运行；调用方需要先占用串口。
+pub fn probe_port(port_name: &str) -> Option<ProbeResult> {
+    info!("主动探测设备: {}", port_name);
+    let result = probe_micropython_repl(port_name)
+        .or_else(|| probe_stk500(port_name))
+        .or_else(|| probe_esp_rom(port_name));
+    match &result {
+        Some(result) => info!("{} 探测结果: {:?} {:?}", port_name, result.method, result.device_type),
+        None => info!("{} 没有探测到可识别的固件或引导程序", port_name),
+    }
+    result
+}
+
+fn probe_micropython_repl(port_name: &str) -> Option<ProbeResult> {
+    let port = open_transport(port_name, REPL_BAUD_RATE, Duration::from_millis(20)).ok()?;
+    let mut repl = MicroPythonRepl::new(port);
+    let banner = repl.probe_banner(REPL_TIMEOUT);
+    // 软复位让 main.py 重新运行
+    let _ = repl.soft_reset();
+    let banner = banner.map_err(|e| debug!("{} 不是MicroPython: {}", port_name, e)).ok()?;
+
+    Some(ProbeResult {
+        method: ProbeMethod::MicroPython,
+        device_type: banner_device_type(&banner),
+        language: Some("micropython".to_string()),
+        firmware_version: Some(banner.version),
+        board: banner.board,
+        variant: None,
+        probed_at: Utc::now(),
+    })
+}
+
+/// 根据欢迎信息中的板卡名称判断设备类型
+fn banner_device_type(banner: &ReplBanner) -> Option<DeviceType> {
+    let board = banner.board.as_deref()?.to_lowercase();
+    if board.contains("rp2040") || board.contains("pico") {
+        Some(DeviceType::RaspberryPiPico)
+    } else if board.contains("esp32") {
+        Some(DeviceType::ESP32)
+    } else if board.contains("micro:bit") || board.contains("microbit") {
+        Some(DeviceType::MicroBit)
+    } else {
+        None
+    }
+}
+
+fn probe_stk500(port_name: &str) -> Option<ProbeResult> {
+    for baud_rate in STK500_BAUD_RATES {
+        let Ok(port) = open_transport(port_name, baud_rate, Duration::from_millis(100)) else {
+            return None;
+        };
+        let mut stk = Stk500::new(port);
+        if stk.connect(2).is_err() {
+            debug!("{} 在 {} 波特率下没有STK500引导程序", port_name, baud_rate);
+            continue;
+        }
+        // 离开编程模式后引导程序会运行原来的程序
+        let _ = stk.leave_progmode();
+        return Some(ProbeResult {
+            method: ProbeMethod::Stk500,
+            device_type: Some(DeviceType::Arduino),
+            language: Some("arduino".to_string()),
+            firmware_version: None,
+            board: Some(format!("ATmega328P (optiboot {} 波特率)", baud_rate)),
+            // 115200的新引导程序无法区分Uno和Nano，交给VID/PID判断
+            variant: (baud_rate == 57600).then(|| "nano_old".to_string()),
+            probed_at: Utc::now(),
+        });
+    }
+    None
+}
+
+fn probe_esp_rom(port_name: &str) -> Option<ProbeResult> {
+    let chip = probe_esp_chip(port_name)?;
+    Some(ProbeResult {
+        method: ProbeMethod::EspRom,
+        device_type: (chip != EspChip::ESP8266).then_some(DeviceType::ESP32),
+        language: None,
+        firmware_version: None,
+        board: Some(chip.name().to_string()),
+        variant: esp_chip_variant(chip).map(|v| v.to_string()),
+        probed_at: Utc::now(),
+    })
+}
+
+/// 与ESP的ROM引导程序同步并识别芯片型号，结束后硬复位让原来的程序继续运行
+pub fn probe_esp_chip(port_name: &str) -> Option<EspChip> {
+    let port = open_transport(port_name, ROM_BAUD_RATE, Duration::from_millis(50)).ok()?;
+    let mut loader = EspLoader::new(port);
+    let chip = loader.connect(3).ok().and_then(|_| loader.chip());
+    let _ = esp_hard_reset(&mut loader.into_inner());
+    chip
+}
+
+/// ESP芯片对应的开发板型号
+pub fn esp_chip_variant(chip: EspChip) -> Option<&'static str> {
+    match chip {
+        EspChip::ESP32 => Some("esp32"),
+        EspChip::ESP32S2 => Some("esp32s2"),
+        EspChip::ESP32S3 => Some("esp32s3"),
+        _ => None,
+    }
+}
+
+/// 探测结果缓存，按USB序列号保存
+///
+/// 没有序列号的设备（例如CH340）不缓存：同一个USB口换插别的板子时，缓存的结果会张冠李戴。
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProbeCache {
+    entries: HashMap<String, ProbeResult>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl ProbeCache {
+    /// 从应用数据目录加载，文件不存在或损坏时为空
+    pub fn load() -> Self {
+        let Ok(path) = get_app_data_dir().map(|dir| dir.join(PROBE_CACHE_FILE)) else {
+            return Self::default();
+        };
+        let mut cache = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<ProbeCache>(&json)
+                .map_err(|e| warn!("探测缓存格式错误，已忽略: {}", e))
+                .ok())
+            .unwrap_or_default();
+        cache.path = Some(path);
+        cache
+    }
+
+    pub fn key(device: &DeviceInfo) -> Option<String> {
+        match (&device.serial_number, device.vendor_id, device.product_id) {
+            (Some(serial), Some(vid), Some(pid)) => Some(format!("{:04x}:{:04x}:{}", vid, pid, serial)),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, device: &DeviceInfo) -> Option<&ProbeResult> {
+        self.entries.get(&Self::key(device)?)
+    }
+
+    pub fn insert(&mut self, device: &DeviceInfo, result: ProbeResult) {
+        if let Some(key) = Self::key(device) {
+            self.entries.insert(key, result);
+            self.save();
+        }
+    }
+
+    /// 重新探测没有结果时删除旧的缓存
+    pub fn remove(&mut self, device: &DeviceInfo) {
+        if Self::key(device).and_then(|key| self.entries.remove(&key)).is_some() {
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let saved = serde_json::to_string_pretty(self)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(path, json).map_err(|e| e.to_string()));
+        if let Err(e) = saved {
+            warn!("保存探测缓存失败: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::repl::parse_banner;
+
+    fn probe_result() -> ProbeResult {
+        ProbeResult {
+            method: ProbeMethod::MicroPython,
+            device_type: Some(DeviceType::ESP32),
+            language: Some("micropython".to_string()),
+            firmware_version: Some("1.23.0".to_string()),
+            board: None,
+            variant: None,
+            probed_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_banner_device_type() {
+        let banner = parse_banner("MicroPython v1.23.0 on 2024-06-02; Generic ESP32 module with ESP32\r\n>>> ").unwrap();
+        assert_eq!(banner_device_type(&banner), Some(DeviceType::ESP32));
+        let banner = parse_banner("MicroPython v1.22.2 on 2024-02-22; Raspberry Pi Pico with RP2040").unwrap();
+        assert_eq!(banner_device_type(&banner), Some(DeviceType::RaspberryPiPico));
+    }
+
+    #[test]
+    fn test_cache_follows_serial_number_across_ports() {
+        let mut device = DeviceInfo::new("/dev/ttyUSB0".to_string(), Some(0x1a86), Some(0x55d4));
+        device.serial_number = Some("5&2c1f".to_string());
+        assert_eq!(ProbeCache::key(&device).as_deref(), Some("1a86:55d4:5&2c1f"));
+
+        let mut cache = ProbeCache::default();
+        cache.insert(&device, probe_result());
+        device.port = "/dev/ttyUSB1".to_string();
+        assert_eq!(cache.get(&device).unwrap().firmware_version.as_deref(), Some("1.23.0"));
+    }
+
+    #[test]
+    fn test_cache_skips_devices_without_serial_number() {
+        // CH340没有序列号，同一个USB口换插别的板子时不能沿用旧结果
+        let device = DeviceInfo::new("/dev/ttyUSB0".to_string(), Some(0x1a86), Some(0x7523));
+        assert_eq!(ProbeCache::key(&device), None);
+
+        let mut cache = ProbeCache::default();
+        cache.insert(&device, probe_result());
+        assert!(cache.get(&device).is_none());
+    }
+
+    #[test]
+    fn test_cache_remove_after_failed_probe() {
+        let mut device = DeviceInfo::new("/dev/ttyUSB0".to_string(), Some(0x10c4), Some(0xea60));
+        device.serial_number = Some("0001".to_string());
+
+        let mut cache = ProbeCache::default();
+        cache.insert(&device, probe_result());
+        cache.remove(&device);
+        assert!(cache.get(&device).is_none());
+    }
+}
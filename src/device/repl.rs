@@ -0,0 +1,209 @@
+use anyhow::{Result, anyhow};
+use log::{debug, info};
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+// REPL控制字符
+const CTRL_A: u8 = 0x01; // 进入raw REPL
+const CTRL_B: u8 = 0x02; // 退出raw REPL，打印欢迎信息
+const CTRL_C: u8 = 0x03; // 中断正在运行的程序
+const CTRL_D: u8 = 0x04; // 软复位 / raw REPL中执行代码
+
+/// MicroPython默认的REPL波特率
+pub const REPL_BAUD_RATE: u32 = 115200;
+
+/// 从欢迎信息中解析出的固件信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplBanner {
+    pub version: String,
+    pub build_date: Option<String>,
+    pub board: Option<String>,
+    pub raw: String,
+}
+
+/// 解析形如 `MicroPython v1.22.2 on 2024-02-22; Raspberry Pi Pico with RP2040` 的欢迎信息
+pub fn parse_banner(text: &str) -> Option<ReplBanner> {
+    let re = Regex::new(r"MicroPython v?([0-9][0-9A-Za-z.\-]*)(?: on ([0-9-]+))?(?:; ([^\r\n]+))?")
+        .expect("欢迎信息正则表达式无效");
+    let caps = re.captures(text)?;
+    Some(ReplBanner {
+        version: caps.get(1)?.as_str().trim_end_matches(|c| c == ',' || c == '.').to_string(),
+        build_date: caps.get(2).map(|m| m.as_str().to_string()),
+        board: caps.get(3).map(|m| m.as_str().trim().to_string()),
+        raw: caps.get(0)?.as_str().trim().to_string(),
+    })
+}
+
+/// MicroPython REPL会话
+pub struct MicroPythonRepl<P: Read + Write> {
+    port: P,
+}
+
+impl<P: Read + Write> MicroPythonRepl<P> {
+    pub fn new(port: P) -> Self {
+        Self { port }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// 中断正在运行的程序，回到交互提示符
+    pub fn interrupt(&mut self) -> Result<()> {
+        self.write(&[b'\r', CTRL_C, CTRL_C])?;
+        std::thread::sleep(Duration::from_millis(100));
+        self.drain();
+        Ok(())
+    }
+
+    /// 软复位，重新运行 boot.py 和 main.py
+    pub fn soft_reset(&mut self) -> Result<()> {
+        self.interrupt()?;
+        self.write(&[CTRL_D])
+    }
+
+    /// 读取欢迎信息，识别固件版本
+    pub fn probe_banner(&mut self, timeout: Duration) -> Result<ReplBanner> {
+        self.interrupt()?;
+        // Ctrl-B 在普通REPL和raw REPL下都会重新打印欢迎信息
+        self.write(&[CTRL_B])?;
+        let output = self.read_until(b">>> ", timeout)?;
+        let text = String::from_utf8_lossy(&output);
+        debug!("REPL输出: {}", text.trim());
+
+        parse_banner(&text).ok_or_else(|| anyhow!("没有检测到MicroPython欢迎信息"))
+    }
+
+    /// 进入raw REPL模式
+    pub fn enter_raw(&mut self) -> Result<()> {
+        self.interrupt()?;
+        self.write(&[CTRL_A])?;
+        self.read_until(b"raw REPL; CTRL-B to exit\r\n>", Duration::from_secs(2))
+            .map_err(|_| anyhow!("无法进入raw REPL，设备上可能没有MicroPython"))?;
+        Ok(())
+    }
+
+    /// 退出raw REPL模式
+    pub fn exit_raw(&mut self) -> Result<()> {
+        self.write(&[CTRL_B])?;
+        let _ = self.read_until(b">>> ", Duration::from_millis(500));
+        Ok(())
+    }
+
+    /// 在raw REPL中执行代码，返回标准输出；出现异常时返回错误
+    pub fn exec_raw(&mut self, code: &str, timeout: Duration) -> Result<String> {
+        self.write(code.as_bytes())?;
+        self.write(&[CTRL_D])?;
+
+        let ack = self.read_exact_bytes(2, Duration::from_secs(1))?;
+        if ack != b"OK" {
+            return Err(anyhow!("raw REPL未接受代码: {}", String::from_utf8_lossy(&ack)));
+        }
+
+        let stdout = self.read_until(&[CTRL_D], timeout)?;
+        let stderr = self.read_until(&[CTRL_D], Duration::from_secs(1))?;
+        let _ = self.read_until(b">", Duration::from_millis(200));
+
+        let stdout = String::from_utf8_lossy(&stdout[..stdout.len() - 1]).to_string();
+        let stderr = String::from_utf8_lossy(&stderr[..stderr.len() - 1]).trim().to_string();
+        if !stderr.is_empty() {
+            return Err(anyhow!("设备执行代码出错: {}", stderr));
+        }
+        Ok(stdout)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.port.write_all(data)
+            .map_err(|e| anyhow!("写入REPL失败: {}", e))?;
+        self.port.flush()
+            .map_err(|e| anyhow!("写入REPL失败: {}", e))
+    }
+
+    /// 一直读取直到出现指定的结尾，返回读到的全部内容（包含结尾）
+    fn read_until(&mut self, ending: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        let mut output = Vec::new();
+        let mut byte = [0u8; 1];
+
+        while !output.ends_with(ending) {
+            if Instant::now() >= deadline {
+                return Err(anyhow!(
+                    "等待REPL响应超时，已收到: {}",
+                    String::from_utf8_lossy(&output).trim()
+                ));
+            }
+            match self.port.read(&mut byte) {
+                Ok(1) => output.push(byte[0]),
+                Ok(_) => std::thread::sleep(Duration::from_millis(1)),
+                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(anyhow!("读取REPL失败: {}", e)),
+            }
+        }
+        Ok(output)
+    }
+
+    fn read_exact_bytes(&mut self, count: usize, timeout: Duration) -> Result<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        let mut output = Vec::with_capacity(count);
+        let mut byte = [0u8; 1];
+
+        while output.len() < count {
+            if Instant::now() >= deadline {
+                return Err(anyhow!("等待REPL响应超时"));
+            }
+            match self.port.read(&mut byte) {
+                Ok(1) => output.push(byte[0]),
+                Ok(_) => std::thread::sleep(Duration::from_millis(1)),
+                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(anyhow!("读取REPL失败: {}", e)),
+            }
+        }
+        Ok(output)
+    }
+
+    fn drain(&mut self) {
+        let mut buffer = [0u8; 256];
+        let deadline = Instant::now() + Duration::from_millis(100);
+        while Instant::now() < deadline {
+            match self.port.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+/// 打开串口并读取MicroPython版本
+pub fn probe_micropython(port_name: &str, timeout: Duration) -> Result<ReplBanner> {
+    info!("探测MicroPython固件: {}", port_name);
+    let port = serialport::new(port_name, REPL_BAUD_RATE)
+        .timeout(Duration::from_millis(20))
+        .open()
+        .map_err(|e| anyhow!("打开串口失败 {}: {}", port_name, e))?;
+
+    let mut repl = MicroPythonRepl::new(port);
+    let banner = repl.probe_banner(timeout)?;
+    info!("检测到MicroPython {} ({})", banner.version, banner.board.as_deref().unwrap_or("未知板卡"));
+    Ok(banner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_banner() {
+        let text = "\r\nMPY: soft reboot\r\nMicroPython v1.22.2 on 2024-02-22; Raspberry Pi Pico with RP2040\r\nType \"help()\" for more information.\r\n>>> ";
+        let banner = parse_banner(text).unwrap();
+        assert_eq!(banner.version, "1.22.2");
+        assert_eq!(banner.build_date.as_deref(), Some("2024-02-22"));
+        assert_eq!(banner.board.as_deref(), Some("Raspberry Pi Pico with RP2040"));
+
+        let microbit = "MicroPython v2.1.1-0-g6fa6cc1 on 2023-02-16; micro:bit v2.0.0 with nRF52833";
+        assert_eq!(parse_banner(microbit).unwrap().version, "2.1.1-0-g6fa6cc1");
+
+        assert!(parse_banner("garbage output").is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use super::boards::BoardConfig;
+use super::serial::PortControl;
+use super::transport::{open_transport, SerialTransport};
+use super::DeviceType;
+use anyhow::{Result, anyhow};
+use log::info;
+use serde::{Serialize, Deserialize};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// BREAK信号允许的最长持续时间
+pub const MAX_BREAK_DURATION: Duration = Duration::from_secs(5);
+
+/// 开发板的复位方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ResetMethod {
+    /// 拉低再拉高DTR/RTS，适用于Uno、Nano等带自动复位电路的板子
+    Pulse,
+    /// 以1200波特率打开再关闭串口：Leonardo/Micro进入引导程序，运行Arduino程序的Pico进入BOOTSEL模式
+    Touch1200,
+    /// ESP32通过EN/IO0进入ROM下载模式
+    EspBootloader,
+    /// ESP32通过EN复位，运行已烧录的程序
+    EspHardReset,
+    /// 发送Ctrl-C、Ctrl-D让MicroPython软复位
+    MicroPythonSoft,
+}
+
+impl ResetMethod {
+    pub fn description(&self) -> &'static str {
+        match self {
+            ResetMethod::Pulse => "DTR/RTS复位",
+            ResetMethod::Touch1200 => "1200波特率复位",
+            ResetMethod::EspBootloader => "进入ESP下载模式",
+            ResetMethod::EspHardReset => "ESP硬复位",
+            ResetMethod::MicroPythonSoft => "MicroPython软复位",
+        }
+    }
+
+    /// 重启开发板运行程序的默认方式
+    pub fn default_for(device_type: &DeviceType, board: Option<&BoardConfig>) -> Self {
+        match device_type {
+            DeviceType::ESP32 => ResetMethod::EspHardReset,
+            DeviceType::RaspberryPiPico | DeviceType::MicroBit => ResetMethod::MicroPythonSoft,
+            // 原生USB的AVR板子（avr109引导程序）没有自动复位电路
+            _ if board.is_some_and(|b| b.upload_protocol == "avr109") => ResetMethod::Touch1200,
+            _ => ResetMethod::Pulse,
+        }
+    }
+}
+
+/// 普通复位：拉低再拉高DTR/RTS
+pub fn pulse_reset<P: PortControl + ?Sized>(port: &mut P) -> Result<()> {
+    // 拉低DTR和RTS
+    port.set_dtr(false)?;
+    port.set_rts(false)?;
+    std::thread::sleep(Duration::from_millis(100));
+
+    // 拉高DTR和RTS
+    port.set_dtr(true)?;
+    port.set_rts(true)?;
+    std::thread::sleep(Duration::from_millis(100));
+
+    Ok(())
+}
+
+/// ESP32复位进入ROM下载模式
+///
+/// 典型的自动下载电路中 DTR 接 IO0、RTS 接 EN（均为反相），
+/// 先拉低EN复位，再在IO0为低时释放EN，芯片就会进入串口下载模式。
+pub fn esp_bootloader_reset<P: PortControl + ?Sized>(port: &mut P) -> Result<()> {
+    port.set_dtr(false)?; // IO0=HIGH
+    port.set_rts(true)?;  // EN=LOW，芯片复位
+    std::thread::sleep(Duration::from_millis(100));
+
+    port.set_dtr(true)?;  // IO0=LOW
+    port.set_rts(false)?; // EN=HIGH，芯片启动
+    std::thread::sleep(Duration::from_millis(50));
+
+    port.set_dtr(false)?; // IO0=HIGH，结束下载模式选择
+    Ok(())
+}
+
+/// ESP32硬复位，运行已烧录的程序
+pub fn esp_hard_reset<P: PortControl + ?Sized>(port: &mut P) -> Result<()> {
+    port.set_dtr(false)?;
+    port.set_rts(true)?;
+    std::thread::sleep(Duration::from_millis(100));
+    port.set_rts(false)?;
+    Ok(())
+}
+
+/// 中断正在运行的MicroPython程序并软复位
+pub fn micropython_soft_reset<W: Write + ?Sized>(port: &mut W) -> Result<()> {
+    port.write_all(b"\r\x03\x03")
+        .and_then(|_| port.flush())
+        .map_err(|e| anyhow!("发送中断信号失败: {}", e))?;
+    std::thread::sleep(Duration::from_millis(100));
+    port.write_all(b"\x04")
+        .and_then(|_| port.flush())
+        .map_err(|e| anyhow!("发送软复位信号失败: {}", e))
+}
+
+/// 发送指定时长的BREAK信号
+pub fn send_break<T: SerialTransport + ?Sized>(port: &mut T, duration: Duration) -> Result<()> {
+    if duration > MAX_BREAK_DURATION {
+        return Err(anyhow!("BREAK信号最长 {} 毫秒", MAX_BREAK_DURATION.as_millis()));
+    }
+    port.set_break(true)?;
+    std::thread::sleep(duration);
+    port.set_break(false)
+}
+
+/// 以1200波特率打开再关闭串口
+///
+/// Arduino的USB CDC固件看到这个波特率时会重启进入引导程序，
+/// 串口会消失并可能以新的名称重新出现，调用方需要先占用串口。
+pub fn touch_1200bps(port_name: &str) -> Result<()> {
+    info!("通过1200波特率复位 {}", port_name);
+    let mut port = open_transport(port_name, 1200, Duration::from_millis(100))?;
+    // 部分系统只在DTR拉低时触发复位
+    let _ = port.set_dtr(false);
+    drop(port);
+    std::thread::sleep(Duration::from_millis(200));
+    Ok(())
+}
+
+/// 当前所有串口的名称，复位或烧录前记录下来，用于识别重启后新出现的串口
+pub fn port_snapshot() -> Vec<String> {
+    serialport::available_ports()
+        .map(|ports| ports.into_iter().map(|p| p.port_name).collect())
+        .unwrap_or_default()
+}
+
+/// 等待出现一个匹配VID/PID且不在 `known` 中的串口
+///
+/// Leonardo等板子进入引导程序后会以新的串口名称重新枚举；同型号的板子可能同时插着好几块，
+/// 只有和复位前的列表对比才能找到刚重启的那一块。
+pub fn wait_for_new_port(vendor_id: u16, product_id: Option<u16>, known: &[String], timeout: Duration) -> Option<String> {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if let Ok(ports) = serialport::available_ports() {
+            if let Some(port) = find_new_port(ports, known, vendor_id, product_id) {
+                return Some(port);
+            }
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+    None
+}
+
+fn find_new_port(
+    ports: Vec<serialport::SerialPortInfo>,
+    known: &[String],
+    vendor_id: u16,
+    product_id: Option<u16>,
+) -> Option<String> {
+    ports.into_iter()
+        .filter(|p| !known.contains(&p.port_name))
+        .find(|p| match &p.port_type {
+            serialport::SerialPortType::UsbPort(usb) => {
+                usb.vid == vendor_id && product_id.is_none_or(|pid| usb.pid == pid)
+            }
+            _ => false,
+        })
+        .map(|p| p.port_name)
+}
+
+/// 按指定方式复位开发板，调用方需要先占用串口
+pub fn reset_board(port_name: &str, baud_rate: u32, method: ResetMethod) -> Result<()> {
+    info!("复位开发板 {}: {}", port_name, method.description());
+    let open = || open_transport(port_name, baud_rate, Duration::from_millis(100));
+    match method {
+        ResetMethod::Pulse => pulse_reset(&mut open()?),
+        ResetMethod::Touch1200 => touch_1200bps(port_name),
+        ResetMethod::EspBootloader => esp_bootloader_reset(&mut open()?),
+        ResetMethod::EspHardReset => esp_hard_reset(&mut open()?),
+        ResetMethod::MicroPythonSoft => micropython_soft_reset(&mut open()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::boards::BoardCatalog;
+
+    #[test]
+    fn test_auto_reset_boards_pulse() {
+        let catalog = BoardCatalog::builtin();
+        assert_eq!(ResetMethod::default_for(&DeviceType::Arduino, catalog.get("uno")), ResetMethod::Pulse);
+        assert_eq!(ResetMethod::default_for(&DeviceType::Arduino, None), ResetMethod::Pulse);
+    }
+
+    #[test]
+    fn test_native_usb_avr_uses_touch_1200() {
+        let catalog = BoardCatalog::builtin();
+        assert_eq!(ResetMethod::default_for(&DeviceType::Arduino, catalog.get("leonardo")), ResetMethod::Touch1200);
+    }
+
+    #[test]
+    fn test_esp32_hard_resets() {
+        assert_eq!(ResetMethod::default_for(&DeviceType::ESP32, None), ResetMethod::EspHardReset);
+    }
+
+    #[test]
+    fn test_micropython_boards_soft_reset() {
+        assert_eq!(ResetMethod::default_for(&DeviceType::RaspberryPiPico, None), ResetMethod::MicroPythonSoft);
+        assert_eq!(ResetMethod::default_for(&DeviceType::MicroBit, None), ResetMethod::MicroPythonSoft);
+    }
+
+    #[test]
+    fn test_new_port_ignores_known_ports() {
+        let usb = |name: &str, pid: u16| serialport::SerialPortInfo {
+            port_name: name.to_string(),
+            port_type: serialport::SerialPortType::UsbPort(serialport::UsbPortInfo {
+                vid: 0x2e8a,
+                pid,
+                serial_number: None,
+                manufacturer: None,
+                product: None,
+            }),
+        };
+        let ports = vec![usb("/dev/ttyACM0", 0x0005), usb("/dev/ttyACM1", 0x000a), usb("/dev/ttyACM2", 0x0005)];
+        let known = vec!["/dev/ttyACM0".to_string()];
+
+        assert_eq!(find_new_port(ports.clone(), &known, 0x2e8a, Some(0x0005)), Some("/dev/ttyACM2".to_string()));
+        assert_eq!(find_new_port(ports, &known, 0x2e8a, None), Some("/dev/ttyACM1".to_string()));
+    }
+}
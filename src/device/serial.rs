@@ -1,11 +1,51 @@
+use super::serial_plot::{extract_samples, new_plot_history, PlotSample, SerialPlotBatch};
+use super::session::{SessionDirection, SessionInfo, SessionRecorder, SessionReplay, SessionStore, REPLAY_PORT_PREFIX};
+use super::serial_reader::{RawTap, RingBuffer, SerialDisconnected, SerialLine, SerialHandoff, SerialReader, SerialReaderEvent, DEFAULT_RING_CAPACITY};
 use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+use super::serial_codec::ReadFormat;
+use super::serial_trigger::{TriggerAction, TriggerRule, TriggerSet, SerialTriggerEvent};
+use super::reset::{esp_bootloader_reset, pulse_reset};
+use super::transport::{open_transport, LineSettings, SerialTransport, VIRTUAL_PORT_PREFIX};
 use serialport::SerialPort;
+use std::collections::HashMap;
 use std::io::{Read, Write};
-use std::time::Duration;
-use log::{info, debug};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use log::{info, debug, warn};
+
+/// 租约结束后等待串口重新出现、恢复监视器的默认时间
+pub const DEFAULT_RESUME_TIMEOUT: Duration = Duration::from_secs(3);
+/// 恢复监视器时两次打开串口之间的间隔
+const RESUME_RETRY_INTERVAL: Duration = Duration::from_millis(300);
+
+/// 串口控制线和参数调整，复位时序与引导程序协议依赖这些操作
+pub trait PortControl {
+    fn set_dtr(&mut self, level: bool) -> Result<()>;
+    fn set_rts(&mut self, level: bool) -> Result<()>;
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()>;
+}
+
+impl PortControl for Box<dyn SerialPort> {
+    fn set_dtr(&mut self, level: bool) -> Result<()> {
+        self.write_data_terminal_ready(level)
+            .map_err(|e| anyhow!("设置DTR失败: {}", e))
+    }
+
+    fn set_rts(&mut self, level: bool) -> Result<()> {
+        self.write_request_to_send(level)
+            .map_err(|e| anyhow!("设置RTS失败: {}", e))
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        SerialPort::set_baud_rate(&mut **self, baud_rate)
+            .map_err(|e| anyhow!("设置波特率失败: {}", e))
+    }
+}
 
 pub struct SerialConnection {
-    port: Box<dyn SerialPort>,
+    port: Box<dyn SerialTransport>,
     port_name: String,
     baud_rate: u32,
 }
@@ -15,10 +55,7 @@ impl SerialConnection {
     pub fn open(port_name: &str, baud_rate: u32) -> Result<Self> {
         info!("尝试打开串口: {} (波特率: {})", port_name, baud_rate);
         
-        let port = serialport::new(port_name, baud_rate)
-            .timeout(Duration::from_millis(1000))
-            .open()
-            .map_err(|e| anyhow!("打开串口失败 {}: {}", port_name, e))?;
+        let port = open_transport(port_name, baud_rate, Duration::from_millis(1000))?;
         
         info!("串口连接成功: {}", port_name);
         Ok(Self {
@@ -87,7 +124,7 @@ impl SerialConnection {
     /// 清空输入缓冲区
     pub fn flush_input(&mut self) -> Result<()> {
         let mut buffer = [0u8; 1024];
-        while let Ok(_) = self.port.read(&mut buffer) {
+        while self.port.read(&mut buffer).is_ok() {
             // 继续读取直到没有数据
         }
         Ok(())
@@ -103,20 +140,18 @@ impl SerialConnection {
     pub fn reset_device(&mut self) -> Result<()> {
         info!("重置设备: {}", self.port_name);
         
-        // 拉低DTR和RTS
-        self.port.write_data_terminal_ready(false)?;
-        self.port.write_request_to_send(false)?;
-        std::thread::sleep(Duration::from_millis(100));
-        
-        // 拉高DTR和RTS
-        self.port.write_data_terminal_ready(true)?;
-        self.port.write_request_to_send(true)?;
-        std::thread::sleep(Duration::from_millis(100));
+        pulse_reset(&mut self.port)?;
         
         info!("设备重置完成: {}", self.port_name);
         Ok(())
     }
     
+    /// 复位ESP32并进入ROM下载模式
+    pub fn enter_esp_bootloader(&mut self) -> Result<()> {
+        info!("复位设备进入下载模式: {}", self.port_name);
+        esp_bootloader_reset(&mut self.port)
+    }
+    
     /// 获取串口名称
     pub fn port_name(&self) -> &str {
         &self.port_name
@@ -129,6 +164,9 @@ impl SerialConnection {
     
     /// 检查串口是否仍然可用
     pub fn is_available(&self) -> bool {
+        if self.port_name.starts_with(VIRTUAL_PORT_PREFIX) {
+            return true;
+        }
         // 尝试获取串口状态来检查连接
         serialport::available_ports()
             .map(|ports| {
@@ -144,53 +182,812 @@ impl Drop for SerialConnection {
     }
 }
 
-/// 串口管理器
+/// 占用串口的操作
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum PortUser {
+    Monitor,
+    Upload,
+    Flash,
+    Verify,
+    Repl,
+    Probe,
+    Interactive,
+    Reset,
+}
+
+impl PortUser {
+    pub fn description(&self) -> &'static str {
+        match self {
+            PortUser::Monitor => "串口监视器",
+            PortUser::Upload => "上传程序",
+            PortUser::Flash => "烧录固件",
+            PortUser::Verify => "验证上传",
+            PortUser::Repl => "REPL会话",
+            PortUser::Probe => "设备探测",
+            PortUser::Interactive => "交互模式",
+            PortUser::Reset => "复位开发板",
+        }
+    }
+}
+
+/// 串口监视器事件的接收者
+pub type SerialEventSink = Arc<dyn Fn(SerialReaderEvent) + Send + Sync>;
+
+/// 监视器使用的串口参数，暂停后按原参数重新打开
+#[derive(Debug, Clone, Copy)]
+struct MonitorConfig {
+    baud_rate: u32,
+    line: LineSettings,
+    read_format: ReadFormat,
+}
+
+impl MonitorConfig {
+    fn new(baud_rate: u32) -> Self {
+        Self {
+            baud_rate,
+            line: LineSettings::default(),
+            read_format: ReadFormat::default(),
+        }
+    }
+
+    /// 读取串口当前的参数（可能被 `set_serial_params` 修改过）
+    fn from_port(port: &dyn SerialTransport, fallback: MonitorConfig) -> Self {
+        Self {
+            baud_rate: port.baud_rate().unwrap_or(fallback.baud_rate),
+            line: port.line_settings().unwrap_or(fallback.line),
+            read_format: fallback.read_format,
+        }
+    }
+
+    fn open(&self, port_name: &str) -> Result<Box<dyn SerialTransport>> {
+        // 读取超时较短，以便及时推送数据和停止读取线程
+        let mut port = open_transport(port_name, self.baud_rate, Duration::from_millis(20))?;
+        if self.line != LineSettings::default() {
+            port.set_line_settings(self.line)?;
+        }
+        Ok(port)
+    }
+}
+
+struct MonitorPort {
+    /// 写入和修改参数用的句柄，回放等虚拟串口没有
+    handle: Option<Arc<Mutex<Box<dyn SerialTransport>>>>,
+    reader: SerialReader,
+    config: MonitorConfig,
+    sink: SerialEventSink,
+}
+
+/// 因其他操作占用而暂停的监视器
+#[derive(Clone)]
+struct PausedMonitor {
+    config: MonitorConfig,
+    sink: SerialEventSink,
+}
+
+#[derive(Default)]
+struct PortTable {
+    monitors: HashMap<String, MonitorPort>,
+    paused: HashMap<String, PausedMonitor>,
+    leases: HashMap<String, PortUser>,
+    /// 每个串口解析出的绘图数据，关闭监视器后仍然保留
+    plots: HashMap<String, RingBuffer<PlotSample>>,
+    /// 正在记录的会话
+    recorders: HashMap<String, Arc<SessionRecorder>>,
+    /// 每个串口的触发规则，监视器暂停后仍然保留
+    triggers: HashMap<String, TriggerSet>,
+    /// 订阅收到的行的接收者（例如运行中的宏）
+    line_listeners: HashMap<String, Vec<mpsc::Sender<SerialLine>>>,
+}
+
+/// 串口服务，所有串口的打开都经过这里
+///
+/// 监视器收到的每一行都会尝试解析成绘图数据，通过 `SerialReaderEvent::Plot` 推送。
+/// 串口监视器长期占用串口；上传、烧录、REPL等操作通过 `acquire` 获得独占租约，
+/// 期间监视器暂停并关闭串口，租约结束后按原参数自动重新打开。
 pub struct SerialManager {
-    connections: std::collections::HashMap<String, SerialConnection>,
+    table: Arc<Mutex<PortTable>>,
+    port_locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
 }
 
 impl SerialManager {
     pub fn new() -> Self {
         Self {
-            connections: std::collections::HashMap::new(),
+            table: Arc::new(Mutex::new(PortTable::default())),
+            port_locks: Mutex::new(HashMap::new()),
         }
     }
-    
-    /// 打开串口连接
-    pub fn connect(&mut self, port_name: &str, baud_rate: u32) -> Result<()> {
-        if self.connections.contains_key(port_name) {
-            return Err(anyhow!("串口 {} 已经连接", port_name));
+
+    fn table(&self) -> MutexGuard<'_, PortTable> {
+        lock_table(&self.table)
+    }
+
+    /// 打开串口监视器，数据通过 `sink` 推送
+    ///
+    /// 串口正被其他操作占用时，监视器会在占用结束后自动打开。
+    pub fn open_monitor(&self, port_name: &str, baud_rate: u32, sink: SerialEventSink) -> Result<()> {
+        if let Some(session_id) = port_name.strip_prefix(REPLAY_PORT_PREFIX) {
+            return self.open_replay(&SessionStore::open()?, session_id, 1.0, sink).map(|_| ());
         }
-        
-        let connection = SerialConnection::open(port_name, baud_rate)?;
-        self.connections.insert(port_name.to_string(), connection);
-        
+
+        let config = MonitorConfig::new(baud_rate);
+        // 打开串口期间持有串口锁，其他操作不能取得租约；不持有表锁，打开慢的串口不会阻塞其他串口
+        let Ok(_guard) = self.port_lock(port_name).try_lock_owned() else {
+            return self.defer_monitor(port_name, config, sink);
+        };
+        if self.is_monitoring(port_name) {
+            return Err(anyhow!("串口 {} 已连接", port_name));
+        }
+
+        let monitor = start_monitor(&self.table, port_name, config, sink)?;
+        let mut table = self.table();
+        table.monitors.insert(port_name.to_string(), monitor);
+        table.plots.insert(port_name.to_string(), new_plot_history());
+        info!("串口 {} 监视器已打开 ({} baud)", port_name, baud_rate);
         Ok(())
     }
-    
-    /// 断开串口连接
-    pub fn disconnect(&mut self, port_name: &str) -> Result<()> {
-        match self.connections.remove(port_name) {
-            Some(_) => {
-                info!("断开串口连接: {}", port_name);
+
+    /// 串口正被占用时登记暂停的监视器，占用结束后自动打开
+    fn defer_monitor(&self, port_name: &str, config: MonitorConfig, sink: SerialEventSink) -> Result<()> {
+        let mut table = self.table();
+        if table.monitors.contains_key(port_name) || table.paused.contains_key(port_name) {
+            return Err(anyhow!("串口 {} 已连接", port_name));
+        }
+        let Some(user) = table.leases.get(port_name).copied() else {
+            return Err(anyhow!("串口 {} 正忙，请稍后再试", port_name));
+        };
+
+        info!("串口 {} 正在{}，结束后打开监视器", port_name, user.description());
+        sink(SerialReaderEvent::Paused(SerialHandoff { port: port_name.to_string(), user }));
+        table.paused.insert(port_name.to_string(), PausedMonitor { config, sink });
+        table.plots.insert(port_name.to_string(), new_plot_history());
+        Ok(())
+    }
+
+    fn port_lock(&self, port_name: &str) -> Arc<AsyncMutex<()>> {
+        let mut locks = self.port_locks.lock().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(locks.entry(port_name.to_string()).or_insert_with(|| Arc::new(AsyncMutex::new(()))))
+    }
+
+    /// 把记录的会话回放到虚拟串口，返回虚拟串口名称
+    ///
+    /// 回放的数据和真实串口一样经过监视器、绘图和记录，`speed` 为回放倍速。
+    pub fn open_replay(&self, store: &SessionStore, session_id: &str, speed: f64, sink: SerialEventSink) -> Result<String> {
+        let port_name = format!("{}{}", REPLAY_PORT_PREFIX, session_id);
+        let info = store.info(session_id)?;
+        let replay = SessionReplay::load(store, session_id, speed)?;
+        let speed = replay.speed();
+
+        let mut table = self.table();
+        if table.monitors.contains_key(&port_name) {
+            return Err(anyhow!("会话 {} 正在回放", session_id));
+        }
+        let monitor = spawn_monitor(&self.table, &port_name, MonitorConfig::new(info.baud_rate), replay, None, sink);
+        table.monitors.insert(port_name.clone(), monitor);
+        table.plots.insert(port_name.clone(), new_plot_history());
+        info!("开始回放串口会话 {} ({}倍速)", session_id, speed);
+        Ok(port_name)
+    }
+
+    /// 关闭串口监视器并等待读取线程退出
+    pub fn close_monitor(&self, port_name: &str) -> Result<()> {
+        let (monitor, paused, recorder) = {
+            let mut table = self.table();
+            let recorder = detach_port(&mut table, port_name);
+            (table.monitors.remove(port_name), table.paused.remove(port_name), recorder)
+        };
+        finish_recording(recorder);
+        match (monitor, paused) {
+            (Some(monitor), _) => {
+                monitor.reader.stop();
+                info!("串口 {} 监视器已关闭", port_name);
                 Ok(())
-            },
-            None => Err(anyhow!("串口 {} 未连接", port_name)),
+            }
+            (None, Some(_)) => Ok(()),
+            (None, None) => Err(anyhow!("串口 {} 未连接", port_name)),
         }
     }
-    
-    /// 获取串口连接
-    pub fn get_connection(&mut self, port_name: &str) -> Option<&mut SerialConnection> {
-        self.connections.get_mut(port_name)
+
+    /// 向监视器打开的串口写入数据
+    pub fn write(&self, port_name: &str, data: &[u8]) -> Result<()> {
+        write_port(&self.table, port_name, data)
     }
-    
-    /// 检查串口是否已连接
-    pub fn is_connected(&self, port_name: &str) -> bool {
-        self.connections.contains_key(port_name)
+
+    /// 开始记录串口会话，监视器必须已经打开
+    pub fn start_recording(&self, port_name: &str, store: &SessionStore) -> Result<SessionInfo> {
+        start_recording(&mut self.table(), port_name, store)
     }
-    
-    /// 获取所有已连接的串口
-    pub fn connected_ports(&self) -> Vec<&str> {
-        self.connections.keys().map(|s| s.as_str()).collect()
+
+    /// 停止记录串口会话
+    pub fn stop_recording(&self, port_name: &str) -> Result<SessionInfo> {
+        let recorder = self.table().recorders.remove(port_name)
+            .ok_or_else(|| anyhow!("串口 {} 没有在记录", port_name))?;
+        recorder.finish()
+    }
+
+    /// 正在记录的会话
+    pub fn recording(&self, port_name: &str) -> Option<SessionInfo> {
+        self.table().recorders.get(port_name).map(|recorder| recorder.info())
+    }
+
+    /// 对监视器打开的串口执行操作（修改参数、清空缓冲区等）
+    pub fn with_port<T, F>(&self, port_name: &str, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut dyn SerialTransport) -> Result<T>,
+    {
+        with_monitor_port(&self.table, port_name, f)
+    }
+
+    /// 设置串口的触发规则，替换原有规则；监视器必须已经打开
+    pub fn set_triggers(&self, port_name: &str, rules: Vec<TriggerRule>) -> Result<()> {
+        let triggers = TriggerSet::compile(rules)?;
+        let mut table = self.table();
+        if !table.monitors.contains_key(port_name) && !table.paused.contains_key(port_name) {
+            return Err(anyhow!("串口 {} 未连接", port_name));
+        }
+        if triggers.is_empty() {
+            table.triggers.remove(port_name);
+        } else {
+            info!("串口 {} 设置了 {} 条触发规则", port_name, triggers.rules().len());
+            table.triggers.insert(port_name.to_string(), triggers);
+        }
+        Ok(())
+    }
+
+    /// 订阅监视器收到的行，接收端丢弃后自动取消订阅；关闭监视器时接收端收到断开
+    pub fn subscribe_lines(&self, port_name: &str) -> Result<mpsc::Receiver<SerialLine>> {
+        let mut table = self.table();
+        if !table.monitors.contains_key(port_name) && !table.paused.contains_key(port_name) {
+            return Err(anyhow!("串口 {} 未连接，请先打开串口监视器", port_name));
+        }
+        let (sender, receiver) = mpsc::channel();
+        table.line_listeners.entry(port_name.to_string()).or_default().push(sender);
+        Ok(receiver)
+    }
+
+    /// 串口当前的触发规则
+    pub fn triggers(&self, port_name: &str) -> Vec<TriggerRule> {
+        self.table().triggers.get(port_name).map(|t| t.rules()).unwrap_or_default()
+    }
+
+    /// 切换监视器显示收到数据的方式（文本编码、十六进制或Base64），暂停恢复后保持不变
+    pub fn set_read_format(&self, port_name: &str, format: ReadFormat) -> Result<()> {
+        let mut table = self.table();
+        if let Some(monitor) = table.monitors.get_mut(port_name) {
+            monitor.reader.set_format(format);
+            monitor.config.read_format = format;
+        } else if let Some(paused) = table.paused.get_mut(port_name) {
+            paused.config.read_format = format;
+        } else {
+            return Err(anyhow!("串口 {} 未连接", port_name));
+        }
+        info!("串口 {} 数据显示方式: {:?}", port_name, format);
+        Ok(())
+    }
+
+    /// 监视器打开的串口（包括暂停中的）
+    pub fn monitored_ports(&self) -> Vec<String> {
+        let table = self.table();
+        table.monitors.keys().chain(table.paused.keys()).cloned().collect()
+    }
+
+    /// 检查串口监视器是否已打开
+    pub fn is_monitoring(&self, port_name: &str) -> bool {
+        let table = self.table();
+        table.monitors.contains_key(port_name) || table.paused.contains_key(port_name)
+    }
+
+    /// 串口最近的绘图数据，`limit` 为空时返回全部
+    pub fn plot_history(&self, port_name: &str, limit: Option<usize>) -> Vec<PlotSample> {
+        let table = self.table();
+        let Some(history) = table.plots.get(port_name) else {
+            return Vec::new();
+        };
+        let skip = limit.map_or(0, |limit| history.len().saturating_sub(limit));
+        history.iter().skip(skip).cloned().collect()
+    }
+
+    /// 清空串口的绘图数据
+    pub fn clear_plot_history(&self, port_name: &str) {
+        if let Some(history) = self.table().plots.get_mut(port_name) {
+            history.clear();
+        }
+    }
+
+    /// 当前占用串口的操作
+    pub fn current_user(&self, port_name: &str) -> Option<PortUser> {
+        let table = self.table();
+        table.leases.get(port_name).copied()
+            .or_else(|| table.monitors.contains_key(port_name).then_some(PortUser::Monitor))
+    }
+
+    /// 独占串口，同一串口的租约依次发放
+    ///
+    /// 监视器打开着时先暂停并关闭串口，租约释放后自动恢复。交互模式会一直占用串口，
+    /// 这时不排队等待，直接返回错误。
+    pub async fn acquire(&self, port_name: &str, user: PortUser) -> Result<PortLease> {
+        if self.table().leases.get(port_name) == Some(&PortUser::Interactive) {
+            return Err(anyhow!("串口 {} 处于交互模式，请先退出交互模式", port_name));
+        }
+        let guard = self.port_lock(port_name).lock_owned().await;
+
+        // 先记录租约和暂停状态再等待读取线程退出：等待期间调用方被取消时，
+        // 租约的Drop仍会清理占用记录并恢复监视器
+        let monitor = {
+            let mut table = self.table();
+            table.leases.insert(port_name.to_string(), user);
+            table.monitors.remove(port_name).map(|monitor| {
+                let MonitorPort { handle, reader, config, sink } = monitor;
+                let config = handle.as_ref()
+                    .and_then(|handle| handle.lock().ok().map(|port| MonitorConfig::from_port(port.as_ref(), config)))
+                    .unwrap_or(config);
+                table.paused.insert(port_name.to_string(), PausedMonitor { config, sink: Arc::clone(&sink) });
+                (handle, reader, sink)
+            })
+        };
+        let lease = PortLease {
+            port_name: port_name.to_string(),
+            user,
+            table: Arc::clone(&self.table),
+            guard: Some(guard),
+            resume_timeout: DEFAULT_RESUME_TIMEOUT,
+        };
+
+        if let Some((handle, reader, sink)) = monitor {
+            info!("{}需要串口 {}，暂停串口监视器", user.description(), port_name);
+            // 读取线程退出并关闭串口后才能交给其他操作
+            let _ = tokio::task::spawn_blocking(move || {
+                reader.stop();
+                drop(handle);
+            }).await;
+            sink(SerialReaderEvent::Paused(SerialHandoff { port: port_name.to_string(), user }));
+        }
+
+        Ok(lease)
     }
-} 
\ No newline at end of file
+}
+
+impl Default for SerialManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 串口的独占租约，释放时恢复暂停的监视器
+pub struct PortLease {
+    port_name: String,
+    user: PortUser,
+    table: Arc<Mutex<PortTable>>,
+    guard: Option<OwnedMutexGuard<()>>,
+    resume_timeout: Duration,
+}
+
+impl PortLease {
+    pub fn port_name(&self) -> &str {
+        &self.port_name
+    }
+
+    pub fn user(&self) -> PortUser {
+        self.user
+    }
+
+    /// 设置释放后等待串口重新出现的时间，原生USB的板子复位后重新枚举较慢
+    pub fn set_resume_timeout(&mut self, timeout: Duration) {
+        self.resume_timeout = timeout;
+    }
+}
+
+impl Drop for PortLease {
+    fn drop(&mut self) {
+        // 暂停的监视器在恢复完成前保留在表中，期间关闭监视器会取消恢复
+        let paused = {
+            let mut table = lock_table(&self.table);
+            table.leases.remove(&self.port_name);
+            table.paused.contains_key(&self.port_name)
+        };
+        if !paused {
+            return;
+        }
+
+        // 上传后板子会复位，有的板子（如Leonardo）还会重新枚举USB，需要等待串口重新出现。
+        // 恢复完成前继续持有串口锁，避免下一个租约与之冲突。
+        let guard = self.guard.take();
+        let table = Arc::clone(&self.table);
+        let port_name = self.port_name.clone();
+        let user = self.user;
+        let timeout = self.resume_timeout;
+        std::thread::spawn(move || {
+            let _guard = guard;
+            resume_monitor(&table, &port_name, user, timeout);
+        });
+    }
+}
+
+fn lock_table(table: &Mutex<PortTable>) -> MutexGuard<'_, PortTable> {
+    table.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn with_monitor_port<T, F>(table: &Mutex<PortTable>, port_name: &str, f: F) -> Result<T>
+where
+    F: FnOnce(&mut dyn SerialTransport) -> Result<T>,
+{
+    let handle = {
+        let table = lock_table(table);
+        match (table.monitors.get(port_name), table.leases.get(port_name)) {
+            (Some(MonitorPort { handle: Some(handle), .. }), _) => Arc::clone(handle),
+            (Some(_), _) => return Err(anyhow!("串口 {} 是回放的会话，不能写入或修改参数", port_name)),
+            (None, Some(user)) => return Err(anyhow!("串口 {} 正在{}，请稍后再试", port_name, user.description())),
+            (None, None) => return Err(anyhow!("串口 {} 未连接", port_name)),
+        }
+    };
+    let mut port = handle.lock().map_err(|_| anyhow!("串口 {} 状态异常", port_name))?;
+    f(port.as_mut())
+}
+
+fn write_port(table: &Mutex<PortTable>, port_name: &str, data: &[u8]) -> Result<()> {
+    with_monitor_port(table, port_name, |port| {
+        port.write_all(data).map_err(|e| anyhow!("写入串口数据失败: {}", e))?;
+        port.flush().map_err(|e| anyhow!("刷新串口缓冲区失败: {}", e))
+    })?;
+    if let Some(recorder) = lock_table(table).recorders.get(port_name) {
+        recorder.record(SessionDirection::Tx, data);
+    }
+    Ok(())
+}
+
+/// 监视器关闭或断开后清理串口的触发规则和订阅，返回需要保存的会话记录
+fn detach_port(table: &mut PortTable, port_name: &str) -> Option<Arc<SessionRecorder>> {
+    table.triggers.remove(port_name);
+    table.line_listeners.remove(port_name);
+    table.recorders.remove(port_name)
+}
+
+fn finish_recording(recorder: Option<Arc<SessionRecorder>>) {
+    if let Some(recorder) = recorder {
+        if let Err(e) = recorder.finish() {
+            warn!("保存串口会话失败: {}", e);
+        }
+    }
+}
+
+fn start_recording(table: &mut PortTable, port_name: &str, store: &SessionStore) -> Result<SessionInfo> {
+    if table.recorders.contains_key(port_name) {
+        return Err(anyhow!("串口 {} 正在记录", port_name));
+    }
+    let baud_rate = match (table.monitors.get(port_name), table.paused.get(port_name)) {
+        (Some(monitor), _) => monitor.config.baud_rate,
+        (None, Some(paused)) => paused.config.baud_rate,
+        (None, None) => return Err(anyhow!("串口 {} 未连接", port_name)),
+    };
+
+    let recorder = store.start(port_name, baud_rate)?;
+    let info = recorder.info();
+    table.recorders.insert(port_name.to_string(), Arc::new(recorder));
+    Ok(info)
+}
+
+/// 执行触发规则的动作，动作失败只记录日志
+fn run_trigger_actions(table: &Mutex<PortTable>, sink: &SerialEventSink, fired: Vec<(SerialTriggerEvent, Vec<TriggerAction>)>) {
+    for (event, actions) in fired {
+        info!("串口 {} 触发规则: {}", event.port, event.rule_name);
+        for action in actions {
+            let result = match action {
+                TriggerAction::Emit => {
+                    sink(SerialReaderEvent::Trigger(event.clone()));
+                    Ok(())
+                }
+                TriggerAction::Reply { text, line_ending } => {
+                    let mut data = text.into_bytes();
+                    data.extend_from_slice(line_ending.as_bytes());
+                    write_port(table, &event.port, &data)
+                }
+                TriggerAction::StartRecording => {
+                    let already = lock_table(table).recorders.contains_key(&event.port);
+                    if already {
+                        Ok(())
+                    } else {
+                        SessionStore::open()
+                            .and_then(|store| start_recording(&mut lock_table(table), &event.port, &store))
+                            .map(|info| info!("触发规则开始记录会话 {}", info.id))
+                    }
+                }
+                TriggerAction::StopRecording => {
+                    let recorder = lock_table(table).recorders.remove(&event.port);
+                    match recorder {
+                        Some(recorder) => recorder.finish().map(|_| ()),
+                        None => Ok(()),
+                    }
+                }
+            };
+            if let Err(e) = result {
+                warn!("执行触发规则 {} 的动作失败: {}", event.rule_name, e);
+            }
+        }
+    }
+}
+
+fn resume_monitor(table: &Arc<Mutex<PortTable>>, port_name: &str, user: PortUser, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    let mut attempt = 0;
+    let last_error = loop {
+        attempt += 1;
+        std::thread::sleep(RESUME_RETRY_INTERVAL);
+        // 每次按最新的参数打开，暂停期间显示方式可能被修改
+        let Some(paused) = lock_table(table).paused.get(port_name).cloned() else {
+            debug!("串口 {} 监视器已关闭，不再恢复", port_name);
+            return;
+        };
+        match start_monitor(table, port_name, paused.config, Arc::clone(&paused.sink)) {
+            Ok(monitor) => {
+                let closed = {
+                    let mut table = lock_table(table);
+                    if table.paused.remove(port_name).is_some() {
+                        table.monitors.insert(port_name.to_string(), monitor);
+                        None
+                    } else {
+                        Some(monitor)
+                    }
+                };
+                // 打开串口期间监视器被关闭
+                if let Some(monitor) = closed {
+                    monitor.reader.stop();
+                    return;
+                }
+                info!("{}结束，串口 {} 监视器已恢复", user.description(), port_name);
+                (paused.sink)(SerialReaderEvent::Resumed(SerialHandoff { port: port_name.to_string(), user }));
+                return;
+            }
+            Err(e) if Instant::now() >= deadline => break e,
+            Err(e) => debug!("恢复串口 {} 监视器失败 (第{}次): {}", port_name, attempt, e),
+        }
+    };
+
+    let failed = {
+        let mut table = lock_table(table);
+        table.paused.remove(port_name).map(|paused| (paused, detach_port(&mut table, port_name)))
+    };
+    let Some((paused, recorder)) = failed else {
+        return;
+    };
+    finish_recording(recorder);
+    warn!("无法恢复串口 {} 监视器: {}", port_name, last_error);
+    (paused.sink)(SerialReaderEvent::Disconnected(SerialDisconnected {
+        port: port_name.to_string(),
+        reason: format!("{}后无法重新打开串口: {}", user.description(), last_error),
+    }));
+}
+
+/// 打开串口并启动读取任务
+fn start_monitor(
+    table: &Arc<Mutex<PortTable>>,
+    port_name: &str,
+    config: MonitorConfig,
+    sink: SerialEventSink,
+) -> Result<MonitorPort> {
+    let port = config.open(port_name)?;
+    let read_handle = port.try_clone_transport()?;
+    Ok(spawn_monitor(table, port_name, config, read_handle, Some(port), sink))
+}
+
+/// 为数据源启动读取任务，读取任务检测到断开时从表中移除监视器
+fn spawn_monitor<R: Read + Send + 'static>(
+    table: &Arc<Mutex<PortTable>>,
+    port_name: &str,
+    config: MonitorConfig,
+    source: R,
+    handle: Option<Box<dyn SerialTransport>>,
+    sink: SerialEventSink,
+) -> MonitorPort {
+    let weak_table: Weak<Mutex<PortTable>> = Arc::downgrade(table);
+    let tap_table = Weak::clone(&weak_table);
+    let tap_port = port_name.to_string();
+    let raw_tap: RawTap = Box::new(move |data| {
+        let recorder = tap_table.upgrade()
+            .and_then(|table| lock_table(&table).recorders.get(&tap_port).cloned());
+        if let Some(recorder) = recorder {
+            recorder.record(SessionDirection::Rx, data);
+        }
+    });
+    let reader_sink = Arc::clone(&sink);
+    let reader = SerialReader::spawn(port_name, source, DEFAULT_RING_CAPACITY, config.read_format, Some(raw_tap), move |event| {
+        let (plot, fired) = match &event {
+            SerialReaderEvent::Data(batch) => {
+                // 十六进制等二进制显示不解析绘图数据
+                let samples = if batch.format.is_text() { extract_samples(&batch.lines) } else { Vec::new() };
+                let mut fired = Vec::new();
+                if let Some(table) = weak_table.upgrade() {
+                    let mut table = lock_table(&table);
+                    if !samples.is_empty() {
+                        let history = table.plots.entry(batch.port.clone()).or_insert_with(new_plot_history);
+                        for sample in &samples {
+                            history.push(sample.clone());
+                        }
+                    }
+                    if let Some(triggers) = table.triggers.get_mut(&batch.port) {
+                        fired = triggers.evaluate(&batch.port, &batch.lines, &samples);
+                    }
+                    if let Some(listeners) = table.line_listeners.get_mut(&batch.port) {
+                        listeners.retain(|listener| batch.lines.iter().all(|line| listener.send(line.clone()).is_ok()));
+                    }
+                }
+                ((!samples.is_empty()).then(|| SerialPlotBatch { port: batch.port.clone(), samples }), fired)
+            }
+            SerialReaderEvent::Disconnected(disconnected) => {
+                if let Some(table) = weak_table.upgrade() {
+                    let recorder = {
+                        let mut table = lock_table(&table);
+                        table.monitors.remove(&disconnected.port);
+                        detach_port(&mut table, &disconnected.port)
+                    };
+                    finish_recording(recorder);
+                }
+                (None, Vec::new())
+            }
+            _ => (None, Vec::new()),
+        };
+        reader_sink(event);
+        if let Some(plot) = plot {
+            reader_sink(SerialReaderEvent::Plot(plot));
+        }
+        if !fired.is_empty() {
+            if let Some(table) = weak_table.upgrade() {
+                run_trigger_actions(&table, &reader_sink, fired);
+            }
+        }
+    });
+
+    MonitorPort {
+        handle: handle.map(|port| Arc::new(Mutex::new(port))),
+        reader,
+        config,
+        sink,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::serial_trigger::TriggerCondition;
+    use crate::device::transport::{VIRTUAL_ARDUINO_PORT, VIRTUAL_ARDUINO_TEST_LOCK};
+    use tempfile::TempDir;
+
+    fn channel_sink() -> (SerialEventSink, mpsc::Receiver<SerialReaderEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let sink: SerialEventSink = Arc::new(move |event| {
+            let _ = sender.lock().unwrap().send(event);
+        });
+        (sink, receiver)
+    }
+
+    /// 跳过数据事件，等待下一个占用状态相关的事件
+    fn next_handoff(events: &mpsc::Receiver<SerialReaderEvent>, timeout: Duration) -> Option<SerialReaderEvent> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            match events.recv_timeout(remaining).ok()? {
+                SerialReaderEvent::Data(_) | SerialReaderEvent::Plot(_) => {}
+                event => return Some(event),
+            }
+        }
+    }
+
+    fn temp_store() -> (SessionStore, TempDir) {
+        let dir = TempDir::new().unwrap();
+        (SessionStore::with_root(dir.path().join("sessions")), dir)
+    }
+
+    #[tokio::test]
+    async fn test_lease_pauses_and_resumes_monitor() {
+        let _arduino = VIRTUAL_ARDUINO_TEST_LOCK.lock().await;
+        let manager = SerialManager::new();
+        let (sink, events) = channel_sink();
+        manager.open_monitor(VIRTUAL_ARDUINO_PORT, 115200, sink).unwrap();
+
+        let lease = manager.acquire(VIRTUAL_ARDUINO_PORT, PortUser::Upload).await.unwrap();
+        assert!(matches!(next_handoff(&events, Duration::from_secs(1)), Some(SerialReaderEvent::Paused(h)) if h.user == PortUser::Upload));
+        assert_eq!(manager.current_user(VIRTUAL_ARDUINO_PORT), Some(PortUser::Upload));
+        assert!(manager.is_monitoring(VIRTUAL_ARDUINO_PORT));
+        // 暂停期间不能再打开一个监视器
+        assert!(manager.open_monitor(VIRTUAL_ARDUINO_PORT, 115200, channel_sink().0).is_err());
+
+        drop(lease);
+        assert!(matches!(next_handoff(&events, Duration::from_secs(5)), Some(SerialReaderEvent::Resumed(h)) if h.user == PortUser::Upload));
+        assert_eq!(manager.current_user(VIRTUAL_ARDUINO_PORT), Some(PortUser::Monitor));
+        manager.close_monitor(VIRTUAL_ARDUINO_PORT).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_close_monitor_during_lease_skips_resume() {
+        let _arduino = VIRTUAL_ARDUINO_TEST_LOCK.lock().await;
+        let manager = SerialManager::new();
+        let (sink, events) = channel_sink();
+        manager.open_monitor(VIRTUAL_ARDUINO_PORT, 115200, sink).unwrap();
+
+        let lease = manager.acquire(VIRTUAL_ARDUINO_PORT, PortUser::Flash).await.unwrap();
+        assert!(matches!(next_handoff(&events, Duration::from_secs(1)), Some(SerialReaderEvent::Paused(_))));
+        manager.close_monitor(VIRTUAL_ARDUINO_PORT).unwrap();
+        drop(lease);
+
+        assert!(next_handoff(&events, Duration::from_secs(1)).is_none());
+        assert!(!manager.is_monitoring(VIRTUAL_ARDUINO_PORT));
+        assert_eq!(manager.current_user(VIRTUAL_ARDUINO_PORT), None);
+    }
+
+    #[tokio::test]
+    async fn test_interactive_lease_fails_fast() {
+        let manager = SerialManager::new();
+        let interactive = manager.acquire("COM7", PortUser::Interactive).await.unwrap();
+        let error = tokio::time::timeout(Duration::from_secs(1), manager.acquire("COM7", PortUser::Upload))
+            .await
+            .expect("交互模式下获取串口不应等待")
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("交互模式"));
+
+        drop(interactive);
+        assert!(manager.acquire("COM7", PortUser::Upload).await.is_ok());
+    }
+
+    #[test]
+    fn test_replay_feeds_monitor_and_plot() {
+        let (store, _dir) = temp_store();
+        let recorder = store.start("COM3", 9600).unwrap();
+        recorder.record(SessionDirection::Rx, b"temp:21\r\n");
+        recorder.record(SessionDirection::Tx, b"reset\n");
+        recorder.record(SessionDirection::Rx, b"temp:22\r\nhello\r\n");
+        let session = recorder.finish().unwrap();
+
+        let manager = SerialManager::new();
+        let (sink, events) = channel_sink();
+        let port = manager.open_replay(&store, &session.id, 100.0, sink).unwrap();
+        assert!(port.starts_with(REPLAY_PORT_PREFIX));
+        assert!(manager.is_monitoring(&port));
+        // 回放的串口不能写入
+        assert!(manager.write(&port, b"x").is_err());
+
+        let mut lines = Vec::new();
+        loop {
+            match events.recv_timeout(Duration::from_secs(5)).expect("回放没有结束") {
+                SerialReaderEvent::Data(batch) => lines.extend(batch.lines.into_iter().map(|line| line.text)),
+                SerialReaderEvent::Disconnected(_) => break,
+                _ => {}
+            }
+        }
+        assert_eq!(lines, vec!["temp:21", "temp:22", "hello"]);
+        let temps: Vec<f64> = manager.plot_history(&port, None).iter()
+            .flat_map(|sample| sample.values.iter().filter(|v| v.channel == "temp").map(|v| v.value))
+            .collect();
+        assert_eq!(temps, vec![21.0, 22.0]);
+        assert!(!manager.is_monitoring(&port));
+    }
+
+    #[test]
+    fn test_disconnect_clears_triggers_and_recording() {
+        let (store, _dir) = temp_store();
+        let recorder = store.start("COM3", 9600).unwrap();
+        recorder.record(SessionDirection::Rx, b"temp:21\n");
+        std::thread::sleep(Duration::from_millis(300));
+        recorder.record(SessionDirection::Rx, b"temp:22\n");
+        let session = recorder.finish().unwrap();
+
+        let manager = SerialManager::new();
+        let (sink, events) = channel_sink();
+        let port = manager.open_replay(&store, &session.id, 1.0, sink).unwrap();
+        manager.set_triggers(&port, vec![TriggerRule {
+            id: "hot".to_string(),
+            name: "太热了".to_string(),
+            enabled: true,
+            condition: TriggerCondition::Pattern { regex: "temp:99".to_string() },
+            actions: vec![TriggerAction::Emit],
+            cooldown_ms: 0,
+        }]).unwrap();
+        let recording = manager.start_recording(&port, &store).unwrap();
+
+        assert!(matches!(next_handoff(&events, Duration::from_secs(5)), Some(SerialReaderEvent::Disconnected(_))));
+        assert!(!manager.is_monitoring(&port));
+        assert!(manager.triggers(&port).is_empty());
+        assert!(manager.recording(&port).is_none());
+        assert!(store.info(&recording.id).unwrap().ended_at.is_some());
+    }
+}
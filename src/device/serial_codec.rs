@@ -0,0 +1,214 @@
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use serde::{Serialize, Deserialize};
+
+/// 发送数据后追加的行尾
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    #[default]
+    None,
+    Lf,
+    Cr,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            LineEnding::None => b"",
+            LineEnding::Lf => b"\n",
+            LineEnding::Cr => b"\r",
+            LineEnding::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// 文本编码，国产模块常用GBK输出中文
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    Gbk,
+    Latin1,
+}
+
+impl TextEncoding {
+    /// 解码收到的字节，无效字节替换为 U+FFFD
+    pub fn decode(&self, data: &[u8]) -> String {
+        match self {
+            TextEncoding::Utf8 => String::from_utf8_lossy(data).to_string(),
+            TextEncoding::Gbk => encoding_rs::GBK.decode_without_bom_handling(data).0.to_string(),
+            TextEncoding::Latin1 => data.iter().map(|&b| b as char).collect(),
+        }
+    }
+
+    /// 编码要发送的文本，无法表示的字符替换为 `?`
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            TextEncoding::Utf8 => text.as_bytes().to_vec(),
+            TextEncoding::Gbk => {
+                let (bytes, _, unmappable) = encoding_rs::GBK.encode(text);
+                if unmappable {
+                    // encoding_rs 会把无法表示的字符写成 HTML 实体，这里逐字符处理
+                    text.chars().flat_map(|c| {
+                        let mut buf = [0u8; 4];
+                        let (bytes, _, unmappable) = encoding_rs::GBK.encode(c.encode_utf8(&mut buf));
+                        if unmappable { vec![b'?'] } else { bytes.into_owned() }
+                    }).collect()
+                } else {
+                    bytes.into_owned()
+                }
+            }
+            TextEncoding::Latin1 => text.chars()
+                .map(|c| if (c as u32) <= 0xFF { c as u8 } else { b'?' })
+                .collect(),
+        }
+    }
+}
+
+/// 串口监视器显示收到数据的方式（前端传入 `{"mode": "text", "encoding": "gbk"}`）
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum ReadFormat {
+    /// 按换行切分的文本
+    Text {
+        #[serde(default)]
+        encoding: TextEncoding,
+    },
+    /// 十六进制，例如 `0A FF 01`
+    Hex,
+    /// Base64编码的原始字节
+    Base64,
+}
+
+impl Default for ReadFormat {
+    fn default() -> Self {
+        ReadFormat::Text { encoding: TextEncoding::Utf8 }
+    }
+}
+
+impl ReadFormat {
+    pub fn is_text(&self) -> bool {
+        matches!(self, ReadFormat::Text { .. })
+    }
+
+    /// 把一行（文本模式）或一帧（二进制模式）数据转换为显示的字符串
+    pub fn render(&self, data: &[u8]) -> String {
+        match self {
+            ReadFormat::Text { encoding } => encoding.decode(data),
+            ReadFormat::Hex => hex_string(data),
+            ReadFormat::Base64 => base64::engine::general_purpose::STANDARD.encode(data),
+        }
+    }
+}
+
+/// 发送数据的选项，全部省略时与原来一样按UTF-8文本原样发送
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct WriteOptions {
+    pub line_ending: LineEnding,
+    /// 把输入当作十六进制字符串，例如 `0A FF 01`
+    pub hex: bool,
+    pub encoding: TextEncoding,
+}
+
+impl WriteOptions {
+    /// 把前端输入转换为要发送的字节
+    pub fn encode(&self, data: &str) -> Result<Vec<u8>> {
+        let mut bytes = if self.hex {
+            parse_hex(data)?
+        } else {
+            self.encoding.encode(data)
+        };
+        bytes.extend_from_slice(self.line_ending.as_bytes());
+        Ok(bytes)
+    }
+}
+
+/// 解析十六进制字符串
+///
+/// 支持空格、逗号分隔和 `0x` 前缀，例如 `0A FF 01`、`0x0a,0xff`、`0AFF01`。
+pub fn parse_hex(input: &str) -> Result<Vec<u8>> {
+    let mut digits = String::new();
+    for token in input.split(|c: char| c.is_whitespace() || c == ',') {
+        let token = token.strip_prefix("0x")
+            .or_else(|| token.strip_prefix("0X"))
+            .unwrap_or(token);
+        // 分隔开的单个数字当作一个字节，例如 `A` 表示 `0A`
+        if token.len() == 1 {
+            digits.push('0');
+        }
+        digits.push_str(token);
+    }
+
+    if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(anyhow!("无效的十六进制字符: {}", c));
+    }
+    if digits.len() % 2 != 0 {
+        return Err(anyhow!("十六进制数据长度不是偶数: {}", input.trim()));
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|e| anyhow!("无效的十六进制数据: {}", e)))
+        .collect()
+}
+
+/// 字节转换为空格分隔的大写十六进制
+pub fn hex_string(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex_separators() {
+        assert_eq!(parse_hex("0A FF 01").unwrap(), vec![0x0a, 0xff, 0x01]);
+        assert_eq!(parse_hex("0x0a,0xFF, 1").unwrap(), vec![0x0a, 0xff, 0x01]);
+        assert_eq!(parse_hex("0AFF01").unwrap(), vec![0x0a, 0xff, 0x01]);
+    }
+
+    #[test]
+    fn test_parse_hex_rejects_invalid() {
+        assert!(parse_hex("0G").is_err());
+        assert!(parse_hex("0AF").is_err());
+    }
+
+    #[test]
+    fn test_write_options_line_ending() {
+        let options = WriteOptions { line_ending: LineEnding::CrLf, ..Default::default() };
+        assert_eq!(options.encode("AT").unwrap(), b"AT\r\n");
+        let options = WriteOptions { hex: true, line_ending: LineEnding::Lf, ..Default::default() };
+        assert_eq!(options.encode("41 42").unwrap(), b"AB\n");
+    }
+
+    #[test]
+    fn test_text_encodings() {
+        let gbk = TextEncoding::Gbk.encode("温度");
+        assert_eq!(gbk, vec![0xCE, 0xC2, 0xB6, 0xC8]);
+        assert_eq!(TextEncoding::Gbk.decode(&gbk), "温度");
+        assert_eq!(TextEncoding::Latin1.decode(&[0x41, 0xE9]), "Aé");
+        assert_eq!(TextEncoding::Latin1.encode("Aé温"), vec![0x41, 0xE9, b'?']);
+    }
+
+    #[test]
+    fn test_read_format_render() {
+        assert_eq!(ReadFormat::Hex.render(&[0x0a, 0xff]), "0A FF");
+        assert_eq!(ReadFormat::Base64.render(b"hi\n"), "aGkK");
+    }
+
+    #[test]
+    fn test_read_format_deserialize() {
+        let format: ReadFormat = serde_json::from_str(r#"{"mode":"text","encoding":"gbk"}"#).unwrap();
+        assert_eq!(format, ReadFormat::Text { encoding: TextEncoding::Gbk });
+        let format: ReadFormat = serde_json::from_str(r#"{"mode":"text"}"#).unwrap();
+        assert_eq!(format, ReadFormat::default());
+    }
+}
@@ -0,0 +1,508 @@
+use super::serial::SerialManager;
+use super::serial_codec::{LineEnding, TextEncoding, WriteOptions};
+use super::serial_reader::SerialLine;
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 设备配置文件 `custom_settings` 中保存宏的键
+pub const MACROS_SETTING_KEY: &str = "serial_macros";
+/// 检查停止请求的间隔
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// 宏的一个步骤
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum MacroStep {
+    Send {
+        data: String,
+        #[serde(default)]
+        options: WriteOptions,
+    },
+    Delay { ms: u64 },
+    /// 等待收到匹配正则表达式的一行，超时则宏失败
+    WaitFor { pattern: String, timeout_ms: u64 },
+    /// 重复执行，`count` 为0时一直重复到手动停止
+    Repeat { count: u32, steps: Vec<MacroStep> },
+}
+
+/// 命名的发送序列
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SerialMacro {
+    pub name: String,
+    pub steps: Vec<MacroStep>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MacroState {
+    Running,
+    Finished,
+    Stopped,
+    Failed,
+}
+
+/// 宏的执行进度（`serial-macro-progress` 事件）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MacroProgress {
+    pub port: String,
+    pub macro_name: String,
+    pub state: MacroState,
+    /// 已执行的步骤数（重复的步骤每次都计数）
+    pub steps_done: usize,
+    /// 正在执行的步骤，或结束时的说明
+    pub message: String,
+}
+
+pub type MacroProgressSink = Arc<dyn Fn(MacroProgress) + Send + Sync>;
+
+/// 解析宏的文本格式
+///
+/// ```text
+/// # 注释
+/// macro 前进测试
+///   send "F100" crlf
+///   send hex "AA 55 01"
+///   send gbk "你好" lf
+///   delay 500
+///   wait "OK" 2000
+///   repeat 5
+///     send "L" lf
+///     delay 200
+///   end
+/// endmacro
+/// ```
+pub fn parse_macros(text: &str) -> Result<Vec<SerialMacro>> {
+    let mut macros = Vec::new();
+    let mut current: Option<String> = None;
+    // 嵌套的 repeat：(次数, 步骤)，最底层是宏本身
+    let mut stack: Vec<(u32, Vec<MacroStep>)> = Vec::new();
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| anyhow!("第 {} 行: {}", index + 1, message);
+        let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+
+        match (keyword, current.is_some()) {
+            ("macro", false) => {
+                if rest.is_empty() {
+                    return Err(error("宏缺少名称"));
+                }
+                current = Some(rest.to_string());
+                stack.push((0, Vec::new()));
+            }
+            ("macro", true) => return Err(error("上一个宏没有 endmacro")),
+            (_, false) => return Err(error("步骤必须写在 macro 和 endmacro 之间")),
+            ("endmacro", true) => {
+                if stack.len() != 1 {
+                    return Err(error("repeat 缺少 end"));
+                }
+                let (_, steps) = stack.pop().expect("宏的步骤");
+                macros.push(SerialMacro { name: current.take().expect("宏名称"), steps });
+            }
+            ("repeat", true) => {
+                let count = rest.parse().map_err(|_| error("repeat 后面需要次数"))?;
+                stack.push((count, Vec::new()));
+            }
+            ("end", true) => {
+                if stack.len() < 2 {
+                    return Err(error("多余的 end"));
+                }
+                let (count, steps) = stack.pop().expect("repeat的步骤");
+                if steps.is_empty() {
+                    return Err(error("repeat 中没有步骤"));
+                }
+                push_step(&mut stack, MacroStep::Repeat { count, steps });
+            }
+            ("send", true) => {
+                // 引号前面是发送选项：hex 和文本编码
+                let mut options = WriteOptions::default();
+                let mut rest = rest;
+                while !rest.is_empty() && !rest.starts_with('"') {
+                    let (word, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    match word {
+                        "hex" => options.hex = true,
+                        "utf8" => options.encoding = TextEncoding::Utf8,
+                        "gbk" => options.encoding = TextEncoding::Gbk,
+                        "latin1" => options.encoding = TextEncoding::Latin1,
+                        other => return Err(error(&format!("未知的发送选项: {}", other))),
+                    }
+                    rest = tail.trim_start();
+                }
+                let (data, rest) = parse_quoted(rest).map_err(|e| error(&e))?;
+                options.line_ending = match rest.trim() {
+                    "" => LineEnding::None,
+                    "lf" => LineEnding::Lf,
+                    "cr" => LineEnding::Cr,
+                    "crlf" => LineEnding::CrLf,
+                    other => return Err(error(&format!("未知的行尾: {}", other))),
+                };
+                push_step(&mut stack, MacroStep::Send { data, options });
+            }
+            ("delay", true) => {
+                let ms = rest.parse().map_err(|_| error("delay 后面需要毫秒数"))?;
+                push_step(&mut stack, MacroStep::Delay { ms });
+            }
+            ("wait", true) => {
+                let (pattern, rest) = parse_quoted(rest).map_err(|e| error(&e))?;
+                Regex::new(&pattern).map_err(|e| error(&format!("正则表达式无效: {}", e)))?;
+                let timeout_ms = rest.trim().parse().map_err(|_| error("wait 后面需要超时毫秒数"))?;
+                push_step(&mut stack, MacroStep::WaitFor { pattern, timeout_ms });
+            }
+            (other, true) => return Err(error(&format!("未知的命令: {}", other))),
+        }
+    }
+
+    if current.is_some() {
+        return Err(anyhow!("最后一个宏缺少 endmacro"));
+    }
+    Ok(macros)
+}
+
+fn push_step(stack: &mut [(u32, Vec<MacroStep>)], step: MacroStep) {
+    if let Some((_, steps)) = stack.last_mut() {
+        steps.push(step);
+    }
+}
+
+/// 读取开头的带引号字符串，返回内容和剩余部分
+fn parse_quoted(text: &str) -> std::result::Result<(String, &str), String> {
+    let body = text.strip_prefix('"').ok_or("需要用双引号括起来的字符串")?;
+    let mut value = String::new();
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &body[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => value.push('\n'),
+                Some('r') => value.push('\r'),
+                Some('t') => value.push('\t'),
+                Some(c) => value.push(c),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err("字符串缺少结尾的双引号".to_string())
+}
+
+/// 把宏导出为文本格式
+pub fn format_macros(macros: &[SerialMacro]) -> String {
+    let mut out = String::new();
+    for serial_macro in macros {
+        out.push_str(&format!("macro {}\n", serial_macro.name));
+        format_steps(&serial_macro.steps, 1, &mut out);
+        out.push_str("endmacro\n\n");
+    }
+    out
+}
+
+fn format_steps(steps: &[MacroStep], depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    for step in steps {
+        match step {
+            MacroStep::Send { data, options } => {
+                let hex = if options.hex { "hex " } else { "" };
+                let encoding = match options.encoding {
+                    TextEncoding::Utf8 => "",
+                    TextEncoding::Gbk => "gbk ",
+                    TextEncoding::Latin1 => "latin1 ",
+                };
+                let ending = match options.line_ending {
+                    LineEnding::None => "",
+                    LineEnding::Lf => " lf",
+                    LineEnding::Cr => " cr",
+                    LineEnding::CrLf => " crlf",
+                };
+                out.push_str(&format!("{}send {}{}{}{}\n", indent, hex, encoding, quote(data), ending));
+            }
+            MacroStep::Delay { ms } => out.push_str(&format!("{}delay {}\n", indent, ms)),
+            MacroStep::WaitFor { pattern, timeout_ms } => {
+                out.push_str(&format!("{}wait {} {}\n", indent, quote(pattern), timeout_ms));
+            }
+            MacroStep::Repeat { count, steps } => {
+                out.push_str(&format!("{}repeat {}\n", indent, count));
+                format_steps(steps, depth + 1, out);
+                out.push_str(&format!("{}end\n", indent));
+            }
+        }
+    }
+}
+
+fn quote(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+/// 执行宏，同一串口同时只能运行一个宏
+pub struct MacroRunner {
+    serial: Arc<SerialManager>,
+    running: Mutex<HashMap<String, (String, Arc<AtomicBool>)>>,
+}
+
+impl MacroRunner {
+    pub fn new(serial: Arc<SerialManager>) -> Self {
+        Self {
+            serial,
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 在后台线程中执行宏，进度通过 `on_progress` 推送；串口监视器必须已经打开
+    pub fn start(self: &Arc<Self>, port_name: &str, serial_macro: SerialMacro, on_progress: MacroProgressSink) -> Result<()> {
+        let stop = Arc::new(AtomicBool::new(false));
+        {
+            let mut running = self.lock();
+            if let Some((name, _)) = running.get(port_name) {
+                return Err(anyhow!("串口 {} 正在运行宏 {}", port_name, name));
+            }
+            running.insert(port_name.to_string(), (serial_macro.name.clone(), Arc::clone(&stop)));
+        }
+        let lines = match self.serial.subscribe_lines(port_name) {
+            Ok(lines) => lines,
+            Err(e) => {
+                self.lock().remove(port_name);
+                return Err(e);
+            }
+        };
+
+        let runner = Arc::clone(self);
+        let port_name = port_name.to_string();
+        std::thread::spawn(move || {
+            info!("串口 {} 开始运行宏 {}", port_name, serial_macro.name);
+            let mut execution = MacroExecution {
+                serial: &runner.serial,
+                port_name: &port_name,
+                macro_name: &serial_macro.name,
+                lines,
+                stop: &stop,
+                on_progress: &on_progress,
+                steps_done: 0,
+            };
+            let (state, message) = match execution.run_steps(&serial_macro.steps) {
+                Ok(()) => (MacroState::Finished, "宏执行完成".to_string()),
+                Err(_) if stop.load(Ordering::SeqCst) => (MacroState::Stopped, "宏已停止".to_string()),
+                Err(e) => {
+                    warn!("串口 {} 的宏 {} 执行失败: {}", port_name, serial_macro.name, e);
+                    (MacroState::Failed, e.to_string())
+                }
+            };
+            execution.report(state, message);
+            runner.lock().remove(&port_name);
+        });
+        Ok(())
+    }
+
+    /// 请求停止串口上正在运行的宏
+    pub fn stop(&self, port_name: &str) -> Result<()> {
+        let running = self.lock();
+        let (_, stop) = running.get(port_name).ok_or_else(|| anyhow!("串口 {} 没有正在运行的宏", port_name))?;
+        stop.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// 正在运行的宏，按串口
+    pub fn running(&self) -> HashMap<String, String> {
+        self.lock().iter().map(|(port, (name, _))| (port.clone(), name.clone())).collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, (String, Arc<AtomicBool>)>> {
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+struct MacroExecution<'a> {
+    serial: &'a SerialManager,
+    port_name: &'a str,
+    macro_name: &'a str,
+    lines: Receiver<SerialLine>,
+    stop: &'a AtomicBool,
+    on_progress: &'a MacroProgressSink,
+    steps_done: usize,
+}
+
+impl MacroExecution<'_> {
+    fn run_steps(&mut self, steps: &[MacroStep]) -> Result<()> {
+        for step in steps {
+            self.check_stop()?;
+            match step {
+                MacroStep::Send { data, options } => {
+                    self.report(MacroState::Running, format!("发送 {}", data));
+                    let bytes = options.encode(data)?;
+                    // 只等待发送之后收到的回复
+                    while self.lines.try_recv().is_ok() {}
+                    self.serial.write(self.port_name, &bytes)?;
+                }
+                MacroStep::Delay { ms } => {
+                    self.report(MacroState::Running, format!("等待 {} 毫秒", ms));
+                    self.sleep(Duration::from_millis(*ms))?;
+                }
+                MacroStep::WaitFor { pattern, timeout_ms } => {
+                    self.report(MacroState::Running, format!("等待回复 {}", pattern));
+                    let regex = Regex::new(pattern).map_err(|e| anyhow!("正则表达式无效: {}", e))?;
+                    self.wait_for(&regex, Duration::from_millis(*timeout_ms))?;
+                }
+                // 配置文件里保存的宏没有经过文本解析，空的循环体直接跳过
+                MacroStep::Repeat { steps, .. } if steps.is_empty() => continue,
+                MacroStep::Repeat { count, steps } => {
+                    let mut iteration = 0;
+                    while *count == 0 || iteration < *count {
+                        // 循环体里没有等待时也要能及时停止
+                        self.check_stop()?;
+                        self.run_steps(steps)?;
+                        iteration += 1;
+                    }
+                    continue;
+                }
+            }
+            self.steps_done += 1;
+        }
+        Ok(())
+    }
+
+    fn wait_for(&self, regex: &Regex, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.check_stop()?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(anyhow!("等待回复 {} 超时", regex.as_str()));
+            }
+            match self.lines.recv_timeout(remaining.min(STOP_CHECK_INTERVAL)) {
+                Ok(line) if regex.is_match(&line.text) => return Ok(()),
+                Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err(anyhow!("串口 {} 已断开", self.port_name)),
+            }
+        }
+    }
+
+    fn sleep(&self, duration: Duration) -> Result<()> {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.check_stop()?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            std::thread::sleep(remaining.min(STOP_CHECK_INTERVAL));
+        }
+    }
+
+    fn check_stop(&self) -> Result<()> {
+        if self.stop.load(Ordering::SeqCst) {
+            Err(anyhow!("宏已停止"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn report(&self, state: MacroState, message: String) {
+        (self.on_progress)(MacroProgress {
+            port: self.port_name.to_string(),
+            macro_name: self.macro_name.to_string(),
+            state,
+            steps_done: self.steps_done,
+            message,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::transport::{VIRTUAL_ARDUINO_PORT, VIRTUAL_ARDUINO_TEST_LOCK};
+    use crate::device::verify::BOOT_MARKER;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_macro_text_format() {
+        let text = r#"
+# 小车测试
+macro 前进测试
+  send "F100" crlf
+  send hex "AA 55 01"
+  wait "OK|\"done\"" 2000
+  repeat 3
+    send "L" lf
+    delay 200
+  end
+endmacro
+"#;
+        let macros = parse_macros(text).unwrap();
+        assert_eq!(macros.len(), 1);
+        assert_eq!(macros[0].name, "前进测试");
+        assert_eq!(macros[0].steps.len(), 4);
+        assert_eq!(macros[0].steps[2], MacroStep::WaitFor { pattern: "OK|\"done\"".to_string(), timeout_ms: 2000 });
+        assert!(matches!(&macros[0].steps[3], MacroStep::Repeat { count: 3, steps } if steps.len() == 2));
+
+        assert_eq!(parse_macros(&format_macros(&macros)).unwrap(), macros);
+        assert!(parse_macros("macro a\n  repeat 2\nendmacro").is_err());
+        assert!(parse_macros("send \"x\"").is_err());
+        assert!(parse_macros("macro a\n  send \"x\" tab\nendmacro").is_err());
+    }
+
+    #[test]
+    fn test_macro_keeps_encoding() {
+        let macros = parse_macros("macro 中文\n  send gbk \"你好\" lf\n  send hex latin1 \"41\"\nendmacro").unwrap();
+        let MacroStep::Send { options, .. } = &macros[0].steps[0] else {
+            panic!("应该是发送步骤");
+        };
+        assert_eq!(options.encoding, TextEncoding::Gbk);
+        assert_eq!(parse_macros(&format_macros(&macros)).unwrap(), macros);
+        assert!(parse_macros("macro a\n  send big5 \"x\"\nendmacro").is_err());
+    }
+
+    #[test]
+    fn test_rejects_empty_repeat() {
+        assert!(parse_macros("macro a\n  repeat 0\n  end\nendmacro").is_err());
+    }
+
+    #[test]
+    fn test_runner_against_virtual_arduino() {
+        let _arduino = VIRTUAL_ARDUINO_TEST_LOCK.blocking_lock();
+        let serial = Arc::new(SerialManager::new());
+        serial.open_monitor(VIRTUAL_ARDUINO_PORT, 115200, Arc::new(|_| {})).unwrap();
+        let runner = Arc::new(MacroRunner::new(Arc::clone(&serial)));
+
+        let text = format!(r#"
+macro 回显
+  wait {} 3000
+  repeat 2
+    send "ping" lf
+    wait "^echo: ping$" 2000
+  end
+endmacro
+"#, quote(&regex::escape(BOOT_MARKER)));
+        let serial_macro = parse_macros(&text).unwrap().remove(0);
+        let (sender, progress) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        runner.start(VIRTUAL_ARDUINO_PORT, serial_macro, Arc::new(move |p| {
+            let _ = sender.lock().unwrap().send(p);
+        })).unwrap();
+        assert!(runner.start(VIRTUAL_ARDUINO_PORT, SerialMacro { name: "b".to_string(), steps: Vec::new() }, Arc::new(|_| {})).is_err());
+
+        let last = loop {
+            let p = progress.recv_timeout(Duration::from_secs(10)).expect("宏没有结束");
+            if p.state != MacroState::Running {
+                break p;
+            }
+        };
+        assert_eq!(last.state, MacroState::Finished, "{}", last.message);
+        assert_eq!(last.steps_done, 5);
+        serial.close_monitor(VIRTUAL_ARDUINO_PORT).unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+use super::serial_reader::{RingBuffer, SerialLine};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+/// 每个串口保留的绘图采样数
+pub const PLOT_HISTORY_CAPACITY: usize = 2000;
+/// 一行最多解析的通道数，超过的当作普通文本
+const MAX_CHANNELS: usize = 16;
+
+/// 一个通道的数值
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PlotValue {
+    pub channel: String,
+    pub value: f64,
+}
+
+/// 从一行串口数据中解析出的采样
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PlotSample {
+    pub timestamp: DateTime<Utc>,
+    pub values: Vec<PlotValue>,
+}
+
+/// 一批绘图数据（`serial-plot` 事件）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerialPlotBatch {
+    pub port: String,
+    pub samples: Vec<PlotSample>,
+}
+
+/// 解析一行串口输出中的数值
+///
+/// 支持的格式：
+/// - 单个数字：`512`
+/// - 逗号、空格或制表符分隔的数字：`12,34,56`
+/// - Arduino绘图器的 `标签:数值`：`temp:21.5,hum:40`
+/// - MicroPython打印的元组：`(12, -3, 1020)`
+///
+/// 没有名字的数值按位置命名为 `value1`、`value2`……，只有一个时命名为 `value`。
+/// 只要有一项不是数字，就当作普通文本返回 `None`。
+pub fn parse_plot_line(line: &str) -> Option<Vec<PlotValue>> {
+    let line = line.trim();
+    let body = line.strip_prefix('(')
+        .and_then(|l| l.strip_suffix(')'))
+        .unwrap_or(line);
+
+    let tokens: Vec<&str> = body
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .collect();
+    if tokens.is_empty() || tokens.len() > MAX_CHANNELS {
+        return None;
+    }
+
+    let single = tokens.len() == 1;
+    tokens.iter().enumerate().map(|(index, token)| {
+        let (channel, number) = match token.split_once(':') {
+            Some((label, number)) if !label.is_empty() => (label.to_string(), number),
+            Some(_) => return None,
+            None if single => ("value".to_string(), *token),
+            None => (format!("value{}", index + 1), *token),
+        };
+        let value: f64 = number.parse().ok().filter(|v: &f64| v.is_finite())?;
+        Some(PlotValue { channel, value })
+    }).collect()
+}
+
+/// 解析一批串口数据，跳过不含数值的行
+pub fn extract_samples(lines: &[SerialLine]) -> Vec<PlotSample> {
+    lines.iter()
+        .filter_map(|line| {
+            parse_plot_line(&line.text).map(|values| PlotSample {
+                timestamp: line.timestamp,
+                values,
+            })
+        })
+        .collect()
+}
+
+/// 每个串口的绘图历史
+pub fn new_plot_history() -> RingBuffer<PlotSample> {
+    RingBuffer::new(PLOT_HISTORY_CAPACITY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channels(line: &str) -> Option<Vec<(String, f64)>> {
+        parse_plot_line(line).map(|values| values.into_iter().map(|v| (v.channel, v.value)).collect())
+    }
+
+    #[test]
+    fn test_plot_single_value() {
+        assert_eq!(channels("512"), Some(vec![("value".to_string(), 512.0)]));
+    }
+
+    #[test]
+    fn test_plot_separated_values() {
+        assert_eq!(channels("1.5,2\t-3"), Some(vec![
+            ("value1".to_string(), 1.5),
+            ("value2".to_string(), 2.0),
+            ("value3".to_string(), -3.0),
+        ]));
+        assert_eq!(channels("(12, -3, 1020)").map(|c| c.len()), Some(3));
+    }
+
+    #[test]
+    fn test_plot_labeled_values() {
+        assert_eq!(channels("temp:21.5, hum:40"), Some(vec![
+            ("temp".to_string(), 21.5),
+            ("hum".to_string(), 40.0),
+        ]));
+    }
+
+    #[test]
+    fn test_plot_ignores_text() {
+        assert_eq!(channels("Hello world"), None);
+        assert_eq!(channels("temp: hot"), None);
+        assert_eq!(channels(""), None);
+    }
+}
@@ -0,0 +1,530 @@
+use super::serial::PortUser;
+use super::serial_codec::ReadFormat;
+use super::serial_plot::SerialPlotBatch;
+use super::serial_trigger::SerialTriggerEvent;
+use super::session::REPLAY_PORT_PREFIX;
+use super::transport::VIRTUAL_PORT_PREFIX;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// 等待发送的行数上限，前端处理不过来时丢弃最旧的行
+pub const DEFAULT_RING_CAPACITY: usize = 5000;
+/// 两次推送之间的间隔
+const BATCH_INTERVAL: Duration = Duration::from_millis(50);
+/// 每次推送的最大行数
+const MAX_BATCH_LINES: usize = 500;
+/// 没有换行的数据（例如 `>>> ` 提示符）超过这个时间后也作为一行推送
+const PARTIAL_LINE_TIMEOUT: Duration = Duration::from_millis(100);
+/// 单行最大长度，防止二进制数据导致一行无限增长
+const MAX_LINE_LENGTH: usize = 4096;
+/// 十六进制和Base64模式下按接收间隔分帧，每帧最多的字节数
+const MAX_BINARY_FRAME: usize = 64;
+/// 读取一直超时时，检查串口是否还在的间隔（部分系统拔线后不会返回错误）
+const PRESENCE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 在切分成行之前接收原始数据，用于会话记录
+pub type RawTap = Box<dyn Fn(&[u8]) + Send>;
+
+/// 串口收到的一行数据
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SerialLine {
+    pub text: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// 一批推送给前端的数据（`serial-data` 事件）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerialDataBatch {
+    pub port: String,
+    pub lines: Vec<SerialLine>,
+    /// 自上次推送以来因缓冲区满而丢弃的行数
+    pub dropped: usize,
+    /// 这批数据的显示方式
+    pub format: ReadFormat,
+}
+
+/// 串口断开（`serial-disconnected` 事件）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerialDisconnected {
+    pub port: String,
+    pub reason: String,
+}
+
+/// 串口被其他操作占用或归还（`serial-paused` / `serial-resumed` 事件）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerialHandoff {
+    pub port: String,
+    pub user: PortUser,
+}
+
+/// 读取任务产生的事件
+#[derive(Debug, Clone)]
+pub enum SerialReaderEvent {
+    Data(SerialDataBatch),
+    Disconnected(SerialDisconnected),
+    /// 监视器暂停，串口交给其他操作
+    Paused(SerialHandoff),
+    /// 监视器恢复
+    Resumed(SerialHandoff),
+    /// 从数据中解析出的绘图采样
+    Plot(SerialPlotBatch),
+    /// 触发规则命中
+    Trigger(SerialTriggerEvent),
+}
+
+/// 把字节流切分成行，`\r\n` 和 `\n` 都作为行结束
+#[derive(Debug, Default)]
+pub struct LineDecoder {
+    pending: Vec<u8>,
+    last_data: Option<Instant>,
+    format: ReadFormat,
+}
+
+impl LineDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_format(format: ReadFormat) -> Self {
+        Self { format, ..Self::default() }
+    }
+
+    pub fn format(&self) -> ReadFormat {
+        self.format
+    }
+
+    /// 切换显示方式，之前未完成的数据按原来的方式输出
+    pub fn set_format(&mut self, format: ReadFormat) -> Option<String> {
+        let pending = (!self.pending.is_empty()).then(|| self.take_pending());
+        self.format = format;
+        pending
+    }
+
+    /// 输入新收到的字节，返回其中完整的行
+    ///
+    /// 二进制模式不按换行切分，满 `MAX_BINARY_FRAME` 字节或等待超时后输出一帧。
+    pub fn feed(&mut self, data: &[u8]) -> Vec<String> {
+        let text = self.format.is_text();
+        let limit = if text { MAX_LINE_LENGTH } else { MAX_BINARY_FRAME };
+        let mut lines = Vec::new();
+        for &byte in data {
+            if text && byte == b'\n' {
+                lines.push(self.take_pending());
+            } else {
+                self.pending.push(byte);
+                if self.pending.len() >= limit {
+                    lines.push(self.take_pending());
+                }
+            }
+        }
+        if !data.is_empty() {
+            self.last_data = Some(Instant::now());
+        }
+        lines
+    }
+
+    /// 没有换行的数据等待超时后作为一行返回
+    pub fn flush_idle(&mut self, idle: Duration) -> Option<String> {
+        let expired = self.last_data.is_some_and(|t| t.elapsed() >= idle);
+        if expired && !self.pending.is_empty() {
+            Some(self.take_pending())
+        } else {
+            None
+        }
+    }
+
+    fn take_pending(&mut self) -> String {
+        let mut line = std::mem::take(&mut self.pending);
+        if self.format.is_text() && line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        self.format.render(&line)
+    }
+}
+
+/// 容量固定的环形缓冲区，满了以后丢弃最旧的数据并计数
+#[derive(Debug)]
+pub struct RingBuffer<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    dropped: usize,
+}
+
+impl<T> RingBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::with_capacity(capacity.min(1024)),
+            capacity: capacity.max(1),
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.items.len() >= self.capacity {
+            self.items.pop_front();
+            self.dropped += 1;
+        }
+        self.items.push_back(item);
+    }
+
+    /// 取出最多 `max` 条数据和丢弃计数，计数随之清零
+    pub fn drain(&mut self, max: usize) -> (Vec<T>, usize) {
+        let count = self.items.len().min(max);
+        let items = self.items.drain(..count).collect();
+        (items, std::mem::take(&mut self.dropped))
+    }
+
+    /// 按从旧到新的顺序遍历
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.dropped = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+struct ReaderShared {
+    buffer: Mutex<RingBuffer<SerialLine>>,
+    format: Mutex<ReadFormat>,
+    stop: AtomicBool,
+    /// 读取线程退出的原因，为空表示仍在运行
+    closed: Mutex<Option<String>>,
+}
+
+impl ReaderShared {
+    fn format(&self) -> ReadFormat {
+        self.format.lock().map(|f| *f).unwrap_or_default()
+    }
+}
+
+/// 每个打开的串口一个后台读取任务
+///
+/// 读取线程只负责把数据切成行放进环形缓冲区，推送线程定时批量取出并回调，
+/// 前端处理慢时不会阻塞串口读取。
+pub struct SerialReader {
+    port_name: String,
+    shared: Arc<ReaderShared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl SerialReader {
+    /// 启动读取任务，`reader` 的读取超时应较短（几十毫秒）
+    pub fn spawn<R, F>(
+        port_name: &str,
+        reader: R,
+        capacity: usize,
+        format: ReadFormat,
+        raw_tap: Option<RawTap>,
+        on_event: F,
+    ) -> Self
+    where
+        R: Read + Send + 'static,
+        F: FnMut(SerialReaderEvent) + Send + 'static,
+    {
+        info!("启动串口读取任务: {}", port_name);
+        let shared = Arc::new(ReaderShared {
+            buffer: Mutex::new(RingBuffer::new(capacity)),
+            format: Mutex::new(format),
+            stop: AtomicBool::new(false),
+            closed: Mutex::new(None),
+        });
+
+        let read_thread = {
+            let shared = Arc::clone(&shared);
+            let port_name = port_name.to_string();
+            std::thread::spawn(move || read_loop(&port_name, reader, raw_tap, &shared))
+        };
+        let emit_thread = {
+            let shared = Arc::clone(&shared);
+            let port_name = port_name.to_string();
+            std::thread::spawn(move || emit_loop(&port_name, &shared, on_event))
+        };
+
+        Self {
+            port_name: port_name.to_string(),
+            shared,
+            threads: vec![read_thread, emit_thread],
+        }
+    }
+
+    pub fn port_name(&self) -> &str {
+        &self.port_name
+    }
+
+    pub fn format(&self) -> ReadFormat {
+        self.shared.format.lock().map(|f| *f).unwrap_or_default()
+    }
+
+    /// 切换收到数据的显示方式，下一次读取时生效
+    pub fn set_format(&self, format: ReadFormat) {
+        if let Ok(mut current) = self.shared.format.lock() {
+            *current = format;
+        }
+    }
+
+    /// 读取线程是否已经因为断开或出错而退出
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.lock().map(|c| c.is_some()).unwrap_or(true)
+    }
+
+    /// 停止读取任务并等待线程退出
+    pub fn stop(mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SerialReader {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn read_loop<R: Read>(port_name: &str, mut reader: R, raw_tap: Option<RawTap>, shared: &ReaderShared) {
+    let mut decoder = LineDecoder::with_format(shared.format());
+    let mut chunk = [0u8; 1024];
+    let mut last_presence_check = Instant::now();
+
+    let push_lines = |lines: Vec<String>| {
+        if lines.is_empty() {
+            return;
+        }
+        let timestamp = Utc::now();
+        if let Ok(mut buffer) = shared.buffer.lock() {
+            for text in lines {
+                buffer.push(SerialLine { text, timestamp });
+            }
+        }
+    };
+
+    let reason = loop {
+        if shared.stop.load(Ordering::SeqCst) {
+            break None;
+        }
+
+        let format = shared.format();
+        if format != decoder.format() {
+            push_lines(decoder.set_format(format).into_iter().collect());
+        }
+
+        match reader.read(&mut chunk) {
+            Ok(0) => {}
+            Ok(n) => {
+                if let Some(tap) = &raw_tap {
+                    tap(&chunk[..n]);
+                }
+                push_lines(decoder.feed(&chunk[..n]));
+                continue;
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut
+                || e.kind() == std::io::ErrorKind::Interrupted => {}
+            // 回放等虚拟数据源结束
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break Some(e.to_string()),
+            Err(e) => {
+                warn!("串口 {} 读取失败: {}", port_name, e);
+                break Some(format!("串口读取失败: {}", e));
+            }
+        }
+
+        push_lines(decoder.flush_idle(PARTIAL_LINE_TIMEOUT).into_iter().collect());
+
+        if !is_virtual_port(port_name) && last_presence_check.elapsed() >= PRESENCE_CHECK_INTERVAL {
+            last_presence_check = Instant::now();
+            if !port_present(port_name) {
+                break Some("设备已拔出".to_string());
+            }
+        }
+    };
+
+    push_lines(decoder.flush_idle(Duration::ZERO).into_iter().collect());
+    debug!("串口 {} 读取线程退出", port_name);
+    if let Ok(mut closed) = shared.closed.lock() {
+        *closed = Some(reason.unwrap_or_default());
+    }
+}
+
+fn emit_loop<F: FnMut(SerialReaderEvent)>(port_name: &str, shared: &ReaderShared, mut on_event: F) {
+    loop {
+        std::thread::sleep(BATCH_INTERVAL);
+        // 先读取关闭状态，保证退出前缓冲区里的数据都已推送
+        let closed = shared.closed.lock().ok().and_then(|c| c.clone());
+
+        loop {
+            let (lines, dropped) = match shared.buffer.lock() {
+                Ok(mut buffer) => buffer.drain(MAX_BATCH_LINES),
+                Err(_) => (Vec::new(), 0),
+            };
+            if lines.is_empty() && dropped == 0 {
+                break;
+            }
+            if dropped > 0 {
+                warn!("串口 {} 数据过多，丢弃了 {} 行", port_name, dropped);
+            }
+            let full = lines.len() == MAX_BATCH_LINES;
+            on_event(SerialReaderEvent::Data(SerialDataBatch {
+                port: port_name.to_string(),
+                lines,
+                dropped,
+                format: shared.format(),
+            }));
+            if !full {
+                break;
+            }
+        }
+
+        match closed {
+            // 主动停止时不发送断开事件
+            Some(reason) if !reason.is_empty() => {
+                info!("串口 {} 已断开: {}", port_name, reason);
+                on_event(SerialReaderEvent::Disconnected(SerialDisconnected {
+                    port: port_name.to_string(),
+                    reason,
+                }));
+                return;
+            }
+            Some(_) => return,
+            None => {}
+        }
+    }
+}
+
+/// 不对应真实设备的串口（会话回放和模拟设备）
+pub fn is_virtual_port(port_name: &str) -> bool {
+    port_name.starts_with(REPLAY_PORT_PREFIX) || port_name.starts_with(VIRTUAL_PORT_PREFIX)
+}
+
+fn port_present(port_name: &str) -> bool {
+    serialport::available_ports()
+        .map(|ports| ports.iter().any(|p| p.port_name == port_name))
+        // 无法枚举串口时不判断为断开
+        .unwrap_or(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::transport::{ScriptedPeer, ScriptedTransport};
+    use std::sync::mpsc;
+
+    /// 第一次读取时一次性发出 `lines` 行
+    struct BurstPeer {
+        lines: usize,
+        sent: bool,
+    }
+
+    impl ScriptedPeer for BurstPeer {
+        fn on_write(&mut self, _data: &[u8], _output: &mut Vec<u8>) {}
+
+        fn on_tick(&mut self, _now: Instant, output: &mut Vec<u8>) {
+            if !self.sent {
+                self.sent = true;
+                for i in 0..self.lines {
+                    output.extend_from_slice(format!("line {}\n", i).as_bytes());
+                }
+            }
+        }
+    }
+
+    /// 数据发完后读取超时就当作拔线
+    struct Unplugged(ScriptedTransport);
+
+    impl Read for Unplugged {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf).map_err(|e| match e.kind() {
+                std::io::ErrorKind::TimedOut => std::io::Error::new(std::io::ErrorKind::BrokenPipe, "设备已拔出"),
+                _ => e,
+            })
+        }
+    }
+
+    #[test]
+    fn test_line_decoder_splits_lines() {
+        let mut decoder = LineDecoder::new();
+        assert_eq!(decoder.feed(b"temp=2"), Vec::<String>::new());
+        assert_eq!(decoder.feed(b"1\r\nhum=40\nok"), vec!["temp=21", "hum=40"]);
+    }
+
+    #[test]
+    fn test_line_decoder_flushes_idle_partial_line() {
+        let mut decoder = LineDecoder::new();
+        decoder.feed(b"ok");
+        assert_eq!(decoder.flush_idle(Duration::ZERO), Some("ok".to_string()));
+        assert_eq!(decoder.flush_idle(Duration::ZERO), None);
+    }
+
+    #[test]
+    fn test_line_decoder_hex_format() {
+        let mut decoder = LineDecoder::with_format(ReadFormat::Hex);
+        assert_eq!(decoder.feed(b"\x0a\xff"), Vec::<String>::new());
+        assert_eq!(decoder.flush_idle(Duration::ZERO), Some("0A FF".to_string()));
+    }
+
+    #[test]
+    fn test_ring_buffer_drops_oldest() {
+        let mut buffer = RingBuffer::new(3);
+        for i in 0..5 {
+            buffer.push(i);
+        }
+        assert_eq!(buffer.drain(2), (vec![2, 3], 2));
+        assert_eq!(buffer.drain(10), (vec![4], 0));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_reader_batches_lines_and_reports_disconnect() {
+        let port_name = format!("{}reader", VIRTUAL_PORT_PREFIX);
+        let transport = ScriptedTransport::new(
+            &port_name,
+            Box::new(BurstPeer { lines: 1200, sent: false }),
+            Duration::from_millis(20),
+        );
+        let (tx, rx) = mpsc::channel();
+        let reader = SerialReader::spawn(
+            &port_name,
+            Unplugged(transport),
+            DEFAULT_RING_CAPACITY,
+            ReadFormat::default(),
+            None,
+            move |event| {
+                let _ = tx.send(event);
+            },
+        );
+
+        let mut batches = Vec::new();
+        let reason = loop {
+            match rx.recv_timeout(Duration::from_secs(5)).expect("读取任务没有报告断开") {
+                SerialReaderEvent::Data(batch) => batches.push(batch),
+                SerialReaderEvent::Disconnected(event) => break event.reason,
+                other => panic!("意外的事件: {:?}", other),
+            }
+        };
+
+        assert!(reason.contains("设备已拔出"));
+        assert!(reader.is_closed());
+        assert!(batches.iter().all(|b| b.lines.len() <= MAX_BATCH_LINES && b.dropped == 0));
+        assert!(batches.iter().any(|b| b.lines.len() == MAX_BATCH_LINES));
+        let lines: Vec<String> = batches.into_iter().flat_map(|b| b.lines).map(|l| l.text).collect();
+        let expected: Vec<String> = (0..1200).map(|i| format!("line {}", i)).collect();
+        assert_eq!(lines, expected);
+        reader.stop();
+    }
+}
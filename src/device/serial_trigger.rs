@@ -0,0 +1,253 @@
+use super::serial_codec::LineEnding;
+use super::serial_plot::PlotSample;
+use super::serial_reader::SerialLine;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use std::time::{Duration, Instant};
+
+/// 设备配置文件 `custom_settings` 中保存触发规则的键
+pub const TRIGGERS_SETTING_KEY: &str = "serial_triggers";
+/// 规则默认的最短触发间隔，避免每一行都触发
+const DEFAULT_COOLDOWN_MS: u64 = 1000;
+
+/// 触发条件
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TriggerCondition {
+    /// 收到的一行匹配正则表达式
+    Pattern { regex: String },
+    /// 绘图通道的数值越过阈值（从不满足变为满足时触发一次）
+    Threshold { channel: String, op: ThresholdOp, value: f64 },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThresholdOp {
+    Above,
+    Below,
+}
+
+/// 触发后执行的动作
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TriggerAction {
+    /// 发送 `serial-trigger` 事件，前端可以提示或发出声音
+    Emit,
+    /// 向串口回复一段文本
+    Reply {
+        text: String,
+        #[serde(default)]
+        line_ending: LineEnding,
+    },
+    StartRecording,
+    StopRecording,
+}
+
+/// 一条触发规则
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TriggerRule {
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub condition: TriggerCondition,
+    pub actions: Vec<TriggerAction>,
+    /// 两次触发之间的最短间隔（毫秒）
+    #[serde(default = "default_cooldown_ms")]
+    pub cooldown_ms: u64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_cooldown_ms() -> u64 {
+    DEFAULT_COOLDOWN_MS
+}
+
+/// 规则触发（`serial-trigger` 事件）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerialTriggerEvent {
+    pub port: String,
+    pub rule_id: String,
+    pub rule_name: String,
+    /// 匹配的一行（正则规则）
+    pub line: Option<String>,
+    /// 越过阈值的通道和数值（阈值规则）
+    pub channel: Option<String>,
+    pub value: Option<f64>,
+    pub timestamp: DateTime<Utc>,
+}
+
+struct CompiledRule {
+    rule: TriggerRule,
+    regex: Option<Regex>,
+    /// 阈值条件上一次是否满足，用于只在越过阈值时触发
+    satisfied: bool,
+    last_fired: Option<Instant>,
+}
+
+impl CompiledRule {
+    fn ready(&self) -> bool {
+        let cooldown = Duration::from_millis(self.rule.cooldown_ms);
+        self.last_fired.is_none_or(|t| t.elapsed() >= cooldown)
+    }
+}
+
+/// 一个串口的全部触发规则及其状态，在监视器的读取任务中求值
+pub struct TriggerSet {
+    rules: Vec<CompiledRule>,
+}
+
+impl TriggerSet {
+    /// 检查并编译规则，正则表达式无效时返回错误
+    pub fn compile(rules: Vec<TriggerRule>) -> Result<Self> {
+        let rules = rules.into_iter()
+            .map(|rule| {
+                let regex = match &rule.condition {
+                    TriggerCondition::Pattern { regex } => Some(Regex::new(regex)
+                        .map_err(|e| anyhow!("规则 {} 的正则表达式无效: {}", rule.name, e))?),
+                    TriggerCondition::Threshold { .. } => None,
+                };
+                Ok(CompiledRule { rule, regex, satisfied: false, last_fired: None })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn rules(&self) -> Vec<TriggerRule> {
+        self.rules.iter().map(|r| r.rule.clone()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// 对一批数据求值，返回触发的规则和要执行的动作
+    pub fn evaluate(&mut self, port: &str, lines: &[SerialLine], samples: &[PlotSample]) -> Vec<(SerialTriggerEvent, Vec<TriggerAction>)> {
+        let mut fired = Vec::new();
+        for compiled in self.rules.iter_mut().filter(|r| r.rule.enabled) {
+            let event = match &compiled.rule.condition {
+                TriggerCondition::Pattern { .. } => {
+                    let regex = compiled.regex.as_ref().expect("正则规则已编译");
+                    let matched = lines.iter().find(|line| regex.is_match(&line.text));
+                    match matched {
+                        Some(line) if compiled.ready() => Some(SerialTriggerEvent {
+                            port: port.to_string(),
+                            rule_id: compiled.rule.id.clone(),
+                            rule_name: compiled.rule.name.clone(),
+                            line: Some(line.text.clone()),
+                            channel: None,
+                            value: None,
+                            timestamp: line.timestamp,
+                        }),
+                        _ => None,
+                    }
+                }
+                TriggerCondition::Threshold { channel, op, value: threshold } => {
+                    let mut crossed = None;
+                    let values = samples.iter()
+                        .flat_map(|sample| sample.values.iter().map(move |v| (sample.timestamp, v)))
+                        .filter(|(_, v)| &v.channel == channel);
+                    for (timestamp, v) in values {
+                        let satisfied = match op {
+                            ThresholdOp::Above => v.value > *threshold,
+                            ThresholdOp::Below => v.value < *threshold,
+                        };
+                        if satisfied && !compiled.satisfied && crossed.is_none() {
+                            crossed = Some((timestamp, v.value));
+                        }
+                        compiled.satisfied = satisfied;
+                    }
+                    match crossed {
+                        Some((timestamp, value)) if compiled.ready() => Some(SerialTriggerEvent {
+                            port: port.to_string(),
+                            rule_id: compiled.rule.id.clone(),
+                            rule_name: compiled.rule.name.clone(),
+                            line: None,
+                            channel: Some(channel.clone()),
+                            value: Some(value),
+                            timestamp,
+                        }),
+                        _ => None,
+                    }
+                }
+            };
+            if let Some(event) = event {
+                compiled.last_fired = Some(Instant::now());
+                fired.push((event, compiled.rule.actions.clone()));
+            }
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::serial_plot::extract_samples;
+
+    fn lines(texts: &[&str]) -> Vec<SerialLine> {
+        texts.iter().map(|t| SerialLine { text: t.to_string(), timestamp: Utc::now() }).collect()
+    }
+
+    fn rule(id: &str, condition: TriggerCondition, cooldown_ms: u64) -> TriggerRule {
+        TriggerRule {
+            id: id.to_string(),
+            name: id.to_string(),
+            enabled: true,
+            condition,
+            actions: vec![TriggerAction::Emit],
+            cooldown_ms,
+        }
+    }
+
+    fn evaluate(triggers: &mut TriggerSet, texts: &[&str]) -> Vec<(SerialTriggerEvent, Vec<TriggerAction>)> {
+        let batch = lines(texts);
+        triggers.evaluate("COM3", &batch, &extract_samples(&batch))
+    }
+
+    #[test]
+    fn test_rules_from_json() {
+        let rules: Vec<TriggerRule> = serde_json::from_str(r#"[
+            {"id": "hot", "name": "太热了",
+             "condition": {"type": "threshold", "channel": "temp", "op": "above", "value": 30},
+             "actions": [{"type": "emit"}, {"type": "reply", "text": "FAN ON", "line_ending": "lf"}]}
+        ]"#).unwrap();
+        assert!(rules[0].enabled);
+        assert_eq!(rules[0].cooldown_ms, DEFAULT_COOLDOWN_MS);
+        assert_eq!(rules[0].actions.len(), 2);
+    }
+
+    #[test]
+    fn test_threshold_fires_on_crossing() {
+        let threshold = TriggerCondition::Threshold { channel: "temp".to_string(), op: ThresholdOp::Above, value: 30.0 };
+        let mut triggers = TriggerSet::compile(vec![rule("hot", threshold, 0)]).unwrap();
+
+        let fired = evaluate(&mut triggers, &["temp:29", "temp:31", "temp:32"]);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].0.value, Some(31.0));
+        // 一直高于阈值时不重复触发，回落后再次越过才触发
+        assert!(evaluate(&mut triggers, &["temp:33"]).is_empty());
+        assert_eq!(evaluate(&mut triggers, &["temp:25", "temp:35"]).len(), 1);
+    }
+
+    #[test]
+    fn test_pattern_respects_cooldown() {
+        let pattern = TriggerCondition::Pattern { regex: "ERR(OR)?".to_string() };
+        let mut triggers = TriggerSet::compile(vec![rule("err", pattern, 60_000)]).unwrap();
+
+        let fired = evaluate(&mut triggers, &["ok", "ERROR 1", "ERR 2"]);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].0.line.as_deref(), Some("ERROR 1"));
+        assert!(evaluate(&mut triggers, &["ERROR 3"]).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_regex_rejected() {
+        let pattern = TriggerCondition::Pattern { regex: "(".to_string() };
+        assert!(TriggerSet::compile(vec![rule("bad", pattern, 0)]).is_err());
+    }
+}
@@ -0,0 +1,585 @@
+use super::serial_plot::parse_plot_line;
+use super::serial_reader::LineDecoder;
+use crate::utils::get_sessions_dir;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use std::fs::{self, File};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 回放会话时虚拟串口名称的前缀，后面是会话ID
+pub const REPLAY_PORT_PREFIX: &str = "replay:";
+/// 回放时每次等待的最长时间，保证读取线程能及时停止
+const REPLAY_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// 回放倍速的范围
+const MIN_REPLAY_SPEED: f64 = 0.1;
+const MAX_REPLAY_SPEED: f64 = 100.0;
+
+/// 单个会话数据文件的最大大小，超过后停止记录
+pub const MAX_SESSION_BYTES: u64 = 20 * 1024 * 1024;
+/// 所有会话的总大小上限，开始新的记录前删除最旧的会话
+pub const MAX_TOTAL_BYTES: u64 = 200 * 1024 * 1024;
+
+/// 数据方向
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionDirection {
+    /// 设备发来的数据
+    Rx,
+    /// 发送给设备的数据
+    Tx,
+}
+
+/// 会话中的一段原始数据
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SessionRecord {
+    pub timestamp: DateTime<Utc>,
+    pub direction: SessionDirection,
+    /// 原始字节的十六进制
+    pub hex: String,
+}
+
+impl SessionRecord {
+    pub fn new(direction: SessionDirection, data: &[u8]) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            direction,
+            hex: data.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+
+    pub fn data(&self) -> Result<Vec<u8>> {
+        (0..self.hex.len())
+            .step_by(2)
+            .map(|i| {
+                self.hex.get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                    .ok_or_else(|| anyhow!("会话数据格式错误"))
+            })
+            .collect()
+    }
+}
+
+/// 会话信息，保存在与数据文件同名的 `.json` 中
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub port: String,
+    pub baud_rate: u32,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// 是否因为超过大小上限而停止了记录
+    pub truncated: bool,
+}
+
+/// 导出格式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// 带时间戳的文本，每行一条
+    Text,
+    /// 绘图通道的CSV表格
+    Csv,
+    /// 每行一个JSON记录
+    Jsonl,
+}
+
+/// 会话数据文件和已经写入的字节数
+struct SessionWriter {
+    file: BufWriter<File>,
+    written: u64,
+}
+
+/// 正在进行的会话记录
+pub struct SessionRecorder {
+    info: Mutex<SessionInfo>,
+    writer: Mutex<Option<SessionWriter>>,
+    store: SessionStore,
+    /// 数据文件的大小上限
+    limit: u64,
+}
+
+impl SessionRecorder {
+    pub fn info(&self) -> SessionInfo {
+        self.info.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// 记录一段数据，数据文件超过大小上限后忽略
+    pub fn record(&self, direction: SessionDirection, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let mut info = self.info.lock().unwrap_or_else(|e| e.into_inner());
+        if info.truncated {
+            return;
+        }
+
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(writer) = writer.as_mut() else {
+            return;
+        };
+        let line = match serde_json::to_string(&SessionRecord::new(direction, data)) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("写入串口会话失败: {}", e);
+                return;
+            }
+        };
+        // 按写入文件的字节数计算，十六进制和JSON格式会让文件比原始数据大一倍以上
+        let size = line.len() as u64 + 1;
+        if writer.written + size > self.limit {
+            warn!("串口会话 {} 超过大小上限，停止记录", info.id);
+            info.truncated = true;
+            return;
+        }
+        match writeln!(writer.file, "{}", line) {
+            Ok(()) => {
+                writer.written += size;
+                match direction {
+                    SessionDirection::Rx => info.rx_bytes += data.len() as u64,
+                    SessionDirection::Tx => info.tx_bytes += data.len() as u64,
+                }
+            }
+            Err(e) => warn!("写入串口会话失败: {}", e),
+        }
+    }
+
+    /// 结束记录，写入会话信息
+    pub fn finish(&self) -> Result<SessionInfo> {
+        if let Some(mut writer) = self.writer.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            writer.file.flush().map_err(|e| anyhow!("写入串口会话失败: {}", e))?;
+        }
+        let mut info = self.info.lock().unwrap_or_else(|e| e.into_inner());
+        if info.ended_at.is_none() {
+            info.ended_at = Some(Utc::now());
+        }
+        self.store.save_info(&info)?;
+        info!("串口会话 {} 记录结束: 接收 {} 字节, 发送 {} 字节", info.id, info.rx_bytes, info.tx_bytes);
+        Ok(info.clone())
+    }
+}
+
+/// 保存在应用数据目录下的串口会话
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    root: PathBuf,
+}
+
+impl SessionStore {
+    pub fn open() -> Result<Self> {
+        Ok(Self::with_root(get_sessions_dir()?))
+    }
+
+    pub fn with_root(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn data_path(&self, id: &str) -> Result<PathBuf> {
+        check_session_id(id)?;
+        Ok(self.root.join(format!("{}.jsonl", id)))
+    }
+
+    fn info_path(&self, id: &str) -> Result<PathBuf> {
+        check_session_id(id)?;
+        Ok(self.root.join(format!("{}.json", id)))
+    }
+
+    fn save_info(&self, info: &SessionInfo) -> Result<()> {
+        let json = serde_json::to_string_pretty(info)
+            .map_err(|e| anyhow!("序列化会话信息失败: {}", e))?;
+        fs::write(self.info_path(&info.id)?, json)
+            .map_err(|e| anyhow!("保存会话信息失败: {}", e))
+    }
+
+    /// 开始记录新的会话
+    pub fn start(&self, port: &str, baud_rate: u32) -> Result<SessionRecorder> {
+        fs::create_dir_all(&self.root).map_err(|e| anyhow!("创建会话目录失败: {}", e))?;
+        self.enforce_total_cap(MAX_TOTAL_BYTES.saturating_sub(MAX_SESSION_BYTES))?;
+
+        let started_at = Utc::now();
+        let id = format!("{}-{}", started_at.format("%Y%m%d-%H%M%S"), &uuid::Uuid::new_v4().to_string()[..8]);
+        let file = File::create(self.data_path(&id)?)
+            .map_err(|e| anyhow!("创建会话文件失败: {}", e))?;
+        let info = SessionInfo {
+            id,
+            port: port.to_string(),
+            baud_rate,
+            started_at,
+            ended_at: None,
+            rx_bytes: 0,
+            tx_bytes: 0,
+            truncated: false,
+        };
+        self.save_info(&info)?;
+        info!("开始记录串口会话 {} ({})", info.id, port);
+
+        Ok(SessionRecorder {
+            info: Mutex::new(info),
+            writer: Mutex::new(Some(SessionWriter { file: BufWriter::new(file), written: 0 })),
+            store: self.clone(),
+            limit: MAX_SESSION_BYTES,
+        })
+    }
+
+    /// 列出所有会话，最新的在前
+    pub fn list(&self) -> Result<Vec<SessionInfo>> {
+        if !self.root.exists() {
+            return Ok(Vec::new());
+        }
+        let mut sessions: Vec<SessionInfo> = fs::read_dir(&self.root)
+            .map_err(|e| anyhow!("读取会话目录失败: {}", e))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|entry| {
+                let content = fs::read_to_string(entry.path()).ok()?;
+                serde_json::from_str(&content).ok()
+            })
+            .collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.started_at));
+        Ok(sessions)
+    }
+
+    pub fn info(&self, id: &str) -> Result<SessionInfo> {
+        let content = fs::read_to_string(self.info_path(id)?)
+            .map_err(|_| anyhow!("未找到串口会话: {}", id))?;
+        serde_json::from_str(&content).map_err(|e| anyhow!("会话信息格式错误: {}", e))
+    }
+
+    pub fn delete(&self, id: &str) -> Result<()> {
+        let info_path = self.info_path(id)?;
+        if !info_path.exists() {
+            return Err(anyhow!("未找到串口会话: {}", id));
+        }
+        let _ = fs::remove_file(self.data_path(id)?);
+        fs::remove_file(info_path).map_err(|e| anyhow!("删除会话失败: {}", e))?;
+        info!("已删除串口会话 {}", id);
+        Ok(())
+    }
+
+    /// 读取会话中的所有记录
+    pub fn records(&self, id: &str) -> Result<Vec<SessionRecord>> {
+        let file = File::open(self.data_path(id)?)
+            .map_err(|_| anyhow!("未找到串口会话: {}", id))?;
+        BufReader::new(file)
+            .lines()
+            .filter(|line| !line.as_ref().is_ok_and(|l| l.trim().is_empty()))
+            .map(|line| {
+                let line = line.map_err(|e| anyhow!("读取会话失败: {}", e))?;
+                serde_json::from_str(&line).map_err(|e| anyhow!("会话记录格式错误: {}", e))
+            })
+            .collect()
+    }
+
+    /// 删除最旧的会话，直到总大小不超过 `limit`
+    fn enforce_total_cap(&self, limit: u64) -> Result<()> {
+        let mut sessions = self.list()?;
+        let size_of = |id: &str| self.data_path(id).and_then(|path| Ok(fs::metadata(path)?.len())).unwrap_or(0);
+        let mut total: u64 = sessions.iter().map(|s| size_of(&s.id)).sum();
+
+        while total > limit {
+            let Some(oldest) = sessions.pop() else {
+                break;
+            };
+            total = total.saturating_sub(size_of(&oldest.id));
+            warn!("串口会话总大小超过上限，删除最旧的会话 {}", oldest.id);
+            self.delete(&oldest.id)?;
+        }
+        Ok(())
+    }
+
+    /// 导出会话到文件
+    pub fn export(&self, id: &str, format: ExportFormat, dest: &Path) -> Result<()> {
+        let records = self.records(id)?;
+        let content = match format {
+            ExportFormat::Text => export_text(&records),
+            ExportFormat::Csv => export_csv(&records),
+            ExportFormat::Jsonl => export_jsonl(&records),
+        };
+        fs::write(dest, content).map_err(|e| anyhow!("导出会话失败: {}", e))?;
+        info!("已导出串口会话 {} 到 {:?}", id, dest);
+        Ok(())
+    }
+}
+
+/// 会话ID由前端传入，只接受 `start` 生成的 `YYYYMMDD-HHMMSS-xxxxxxxx` 格式，避免拼出会话目录以外的路径
+fn check_session_id(id: &str) -> Result<()> {
+    let parts: Vec<&str> = id.split('-').collect();
+    let valid = matches!(parts.as_slice(), [date, time, suffix]
+        if date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit())
+            && time.len() == 6 && time.bytes().all(|b| b.is_ascii_digit())
+            && suffix.len() == 8 && suffix.bytes().all(|b| b.is_ascii_alphanumeric()));
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("无效的会话ID: {}", id))
+    }
+}
+
+/// 把记录的会话按原来的时间间隔回放，作为串口监视器的数据源
+///
+/// 只回放设备发来的数据；`speed` 为回放倍速，限制在0.1到100倍之间。
+/// 数据放完后读取返回 `UnexpectedEof`，监视器随之发送断开事件。
+pub struct SessionReplay {
+    chunks: VecDeque<(Duration, Vec<u8>)>,
+    speed: f64,
+    started: Option<Instant>,
+}
+
+impl SessionReplay {
+    pub fn load(store: &SessionStore, id: &str, speed: f64) -> Result<Self> {
+        Ok(Self::from_records(&store.records(id)?, speed))
+    }
+
+    pub fn from_records(records: &[SessionRecord], speed: f64) -> Self {
+        let first = records.first().map(|r| r.timestamp);
+        let chunks = records.iter()
+            .filter(|record| record.direction == SessionDirection::Rx)
+            .filter_map(|record| {
+                let offset = (record.timestamp - first?).to_std().unwrap_or_default();
+                Some((offset, record.data().ok()?))
+            })
+            .collect();
+        // 非法的倍速按原速度回放
+        let speed = if speed.is_nan() { 1.0 } else { speed.clamp(MIN_REPLAY_SPEED, MAX_REPLAY_SPEED) };
+        Self { chunks, speed, started: None }
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    fn due(&self, offset: Duration) -> Duration {
+        offset.div_f64(self.speed)
+    }
+}
+
+impl Read for SessionReplay {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let Some((offset, _)) = self.chunks.front() else {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "会话回放结束"));
+        };
+
+        let due = self.due(*offset);
+        let elapsed = started.elapsed();
+        if elapsed < due {
+            std::thread::sleep((due - elapsed).min(REPLAY_POLL_INTERVAL));
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "等待回放数据"));
+        }
+
+        let (offset, mut data) = self.chunks.pop_front().unwrap_or_default();
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        if n < data.len() {
+            self.chunks.push_front((offset, data.split_off(n)));
+        }
+        Ok(n)
+    }
+}
+
+/// 把记录还原成带时间戳的文本行，每行使用收到换行符时的时间
+fn session_lines(records: &[SessionRecord]) -> Vec<(DateTime<Utc>, SessionDirection, String)> {
+    let mut rx = LineDecoder::new();
+    let mut tx = LineDecoder::new();
+    let mut lines = Vec::new();
+
+    for record in records {
+        let Ok(data) = record.data() else {
+            continue;
+        };
+        let decoder = match record.direction {
+            SessionDirection::Rx => &mut rx,
+            SessionDirection::Tx => &mut tx,
+        };
+        for line in decoder.feed(&data) {
+            lines.push((record.timestamp, record.direction, line));
+        }
+    }
+    // 最后没有换行的数据也导出
+    if let Some(last) = records.last() {
+        for (direction, decoder) in [(SessionDirection::Rx, &mut rx), (SessionDirection::Tx, &mut tx)] {
+            if let Some(line) = decoder.flush_idle(std::time::Duration::ZERO) {
+                lines.push((last.timestamp, direction, line));
+            }
+        }
+    }
+    lines
+}
+
+fn export_text(records: &[SessionRecord]) -> String {
+    session_lines(records)
+        .into_iter()
+        .map(|(timestamp, direction, text)| {
+            let arrow = match direction {
+                SessionDirection::Rx => "<-",
+                SessionDirection::Tx => "->",
+            };
+            format!("[{}] {} {}\n", timestamp.format("%Y-%m-%d %H:%M:%S%.3f"), arrow, text)
+        })
+        .collect()
+}
+
+fn export_csv(records: &[SessionRecord]) -> String {
+    let mut channels: Vec<String> = Vec::new();
+    let mut rows = Vec::new();
+
+    for (timestamp, direction, text) in session_lines(records) {
+        if direction != SessionDirection::Rx {
+            continue;
+        }
+        let Some(values) = parse_plot_line(&text) else {
+            continue;
+        };
+        for value in &values {
+            if !channels.contains(&value.channel) {
+                channels.push(value.channel.clone());
+            }
+        }
+        rows.push((timestamp, values));
+    }
+
+    let mut csv = format!("timestamp,{}\n", channels.join(","));
+    for (timestamp, values) in rows {
+        let cells: Vec<String> = channels.iter()
+            .map(|channel| {
+                values.iter()
+                    .find(|v| &v.channel == channel)
+                    .map(|v| v.value.to_string())
+                    .unwrap_or_default()
+            })
+            .collect();
+        csv.push_str(&format!("{},{}\n", timestamp.to_rfc3339(), cells.join(",")));
+    }
+    csv
+}
+
+/// 导出为便于其他程序处理的JSON Lines，数据转为文本
+fn export_jsonl(records: &[SessionRecord]) -> String {
+    let mut output = String::new();
+    for (timestamp, direction, text) in session_lines(records) {
+        let line = serde_json::json!({
+            "timestamp": timestamp,
+            "direction": direction,
+            "text": text,
+        });
+        output.push_str(&line.to_string());
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn temp_store() -> (SessionStore, TempDir) {
+        let dir = TempDir::new().unwrap();
+        (SessionStore::with_root(dir.path().join("sessions")), dir)
+    }
+
+    /// 两行温湿度数据中间夹着一次发送
+    fn record_sample(store: &SessionStore) -> SessionInfo {
+        let recorder = store.start("COM3", 9600).unwrap();
+        recorder.record(SessionDirection::Rx, b"temp:21,hum:40\r\ntemp:");
+        recorder.record(SessionDirection::Tx, b"reset\n");
+        recorder.record(SessionDirection::Rx, b"22\r\nhello\r\n");
+        recorder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_record_counts_bytes() {
+        let (store, _dir) = temp_store();
+        let info = record_sample(&store);
+        assert_eq!(info.rx_bytes, 32);
+        assert_eq!(info.tx_bytes, 6);
+        assert_eq!(store.list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_record_stops_at_file_size_cap() {
+        let (store, _dir) = temp_store();
+        let mut recorder = store.start("COM3", 9600).unwrap();
+        recorder.limit = 200;
+        for _ in 0..10 {
+            recorder.record(SessionDirection::Rx, b"temp:21\r\n");
+        }
+        let info = recorder.finish().unwrap();
+        assert!(info.truncated);
+        assert!(info.rx_bytes > 0 && info.rx_bytes < 90);
+        assert!(fs::metadata(store.data_path(&info.id).unwrap()).unwrap().len() <= 200);
+    }
+
+    #[test]
+    fn test_export_csv_columns() {
+        let (store, _dir) = temp_store();
+        let info = record_sample(&store);
+        let csv = export_csv(&store.records(&info.id).unwrap());
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows[0], "timestamp,temp,hum");
+        assert!(rows[1].ends_with(",21,40"));
+        assert!(rows[2].ends_with(",22,"));
+        assert_eq!(rows.len(), 3);
+    }
+
+    #[test]
+    fn test_export_text_directions() {
+        let (store, _dir) = temp_store();
+        let info = record_sample(&store);
+        let text = export_text(&store.records(&info.id).unwrap());
+        assert!(text.contains("-> reset"));
+        assert!(text.contains("<- hello"));
+    }
+
+    #[test]
+    fn test_replay_only_received_data() {
+        let (store, _dir) = temp_store();
+        let info = record_sample(&store);
+        // 数据放完后返回UnexpectedEof，之前读到的数据保留在缓冲区中
+        let mut replay = SessionReplay::load(&store, &info.id, MAX_REPLAY_SPEED).unwrap();
+        let mut replayed = Vec::new();
+        assert!(replay.read_to_end(&mut replayed).is_err());
+        assert_eq!(replayed, b"temp:21,hum:40\r\ntemp:22\r\nhello\r\n");
+    }
+
+    #[test]
+    fn test_replay_speed_is_clamped() {
+        let offset = Duration::from_secs(10);
+        assert_eq!(SessionReplay::from_records(&[], 0.0).due(offset), Duration::from_secs(100));
+        assert_eq!(SessionReplay::from_records(&[], 1e9).due(offset), Duration::from_millis(100));
+        assert_eq!(SessionReplay::from_records(&[], f64::NAN).due(offset), offset);
+        assert_eq!(SessionReplay::from_records(&[], 2.0).due(offset), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_delete_session() {
+        let (store, _dir) = temp_store();
+        let info = record_sample(&store);
+        store.delete(&info.id).unwrap();
+        assert!(store.list().unwrap().is_empty());
+        assert!(store.delete(&info.id).is_err());
+    }
+
+    #[test]
+    fn test_rejects_path_like_session_ids() {
+        let (store, dir) = temp_store();
+        let root = dir.path().join("sessions");
+        fs::create_dir_all(&root).unwrap();
+        let outside = root.with_extension("json");
+        fs::write(&outside, "{}").unwrap();
+
+        let escape = format!("../{}", outside.file_stem().unwrap().to_string_lossy());
+        assert!(store.delete(&escape).is_err());
+        assert!(outside.exists());
+        assert!(store.info("20240101-120000-abc/../x").is_err());
+        assert!(store.export("", ExportFormat::Text, &root.join("out.txt")).is_err());
+        assert!(check_session_id("20240101-120000-1a2b3c4d").is_ok());
+    }
+}
@@ -0,0 +1,304 @@
+use super::reset::pulse_reset;
+use super::serial::PortControl;
+use anyhow::{Result, anyhow};
+use log::{debug, info};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+// STK500v1 协议常量（Arduino Uno/Nano 的 optiboot 引导程序）
+const STK_OK: u8 = 0x10;
+const STK_INSYNC: u8 = 0x14;
+const CRC_EOP: u8 = 0x20;
+const STK_GET_SYNC: u8 = 0x30;
+const STK_ENTER_PROGMODE: u8 = 0x50;
+const STK_LEAVE_PROGMODE: u8 = 0x51;
+const STK_LOAD_ADDRESS: u8 = 0x55;
+const STK_PROG_PAGE: u8 = 0x64;
+const STK_READ_PAGE: u8 = 0x74;
+
+/// 每次读取的字节数，optiboot单次最多支持256字节
+const READ_BLOCK_SIZE: usize = 128;
+/// ATmega328P的Flash页大小
+pub const ATMEGA328P_PAGE_SIZE: usize = 128;
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// 引导程序通信错误
+#[derive(Debug, thiserror::Error)]
+pub enum Stk500Error {
+    /// 复位后无法同步，通常是引导程序的波特率不对
+    #[error("无法与AVR引导程序同步")]
+    NotInSync,
+    /// 命令的响应没有以INSYNC开头
+    #[error("引导程序未同步: {0:02X}")]
+    LostSync(u8),
+}
+
+/// Intel HEX 文件解析出的连续镜像，中间的空洞用0xFF填充
+#[derive(Debug, Clone, PartialEq)]
+pub struct HexImage {
+    pub start: u32,
+    pub data: Vec<u8>,
+}
+
+/// 解析 Intel HEX 文件
+pub fn parse_intel_hex(text: &str) -> Result<HexImage> {
+    let mut base: u32 = 0;
+    let mut records: Vec<(u32, Vec<u8>)> = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let line_no = index + 1;
+        let body = line.strip_prefix(':')
+            .ok_or_else(|| anyhow!("HEX文件第{}行格式错误", line_no))?;
+        if body.len() % 2 != 0 || body.len() < 10 {
+            return Err(anyhow!("HEX文件第{}行长度错误", line_no));
+        }
+
+        let bytes = (0..body.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&body[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| anyhow!("HEX文件第{}行包含非法字符", line_no))?;
+
+        let sum = bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        if sum != 0 {
+            return Err(anyhow!("HEX文件第{}行校验和错误", line_no));
+        }
+
+        let length = bytes[0] as usize;
+        if bytes.len() != length + 5 {
+            return Err(anyhow!("HEX文件第{}行数据长度不符", line_no));
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..4 + length];
+
+        match bytes[3] {
+            0x00 => records.push((base + address, data.to_vec())),
+            0x01 => break,
+            0x02 if length == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            0x04 if length == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            // 起始地址记录与校验无关
+            0x03 | 0x05 => {}
+            other => return Err(anyhow!("HEX文件第{}行记录类型不支持: {:02X}", line_no, other)),
+        }
+    }
+
+    let start = records.iter().map(|(address, _)| *address).min()
+        .ok_or_else(|| anyhow!("HEX文件中没有数据"))?;
+    let end = records.iter().map(|(address, data)| *address + data.len() as u32).max().unwrap_or(start);
+
+    let mut image = vec![0xFF; (end - start) as usize];
+    for (address, data) in records {
+        let offset = (address - start) as usize;
+        image[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    Ok(HexImage { start, data: image })
+}
+
+/// STK500v1 引导程序客户端，用于回读AVR Flash
+pub struct Stk500<P: Read + Write + PortControl> {
+    port: P,
+}
+
+impl<P: Read + Write + PortControl> Stk500<P> {
+    pub fn new(port: P) -> Self {
+        Self { port }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// 复位板子并与引导程序同步
+    ///
+    /// optiboot只在复位后的短时间内等待命令，所以复位后立即同步。
+    pub fn connect(&mut self, attempts: u32) -> Result<()> {
+        pulse_reset(&mut self.port)?;
+        for attempt in 1..=attempts.max(1) {
+            debug!("STK500同步尝试 {}/{}", attempt, attempts);
+            self.drain_input();
+            if self.command(&[STK_GET_SYNC, CRC_EOP], 0).is_ok() {
+                info!("AVR引导程序同步成功");
+                return Ok(());
+            }
+        }
+        Err(Stk500Error::NotInSync.into())
+    }
+
+    pub fn enter_progmode(&mut self) -> Result<()> {
+        self.command(&[STK_ENTER_PROGMODE, CRC_EOP], 0)?;
+        Ok(())
+    }
+
+    /// 离开编程模式，引导程序随后会运行用户程序
+    pub fn leave_progmode(&mut self) -> Result<()> {
+        self.command(&[STK_LEAVE_PROGMODE, CRC_EOP], 0)?;
+        Ok(())
+    }
+
+    /// 读取Flash，地址为字节地址
+    pub fn read_flash(&mut self, address: u32, length: usize) -> Result<Vec<u8>> {
+        if address % 2 != 0 {
+            return Err(anyhow!("Flash读取地址必须是偶数: {:#X}", address));
+        }
+
+        let mut data = Vec::with_capacity(length);
+        while data.len() < length {
+            let current = address + data.len() as u32;
+            let block = (length - data.len()).min(READ_BLOCK_SIZE);
+
+            // 地址以字（2字节）为单位
+            let word = (current / 2) as u16;
+            self.command(&[STK_LOAD_ADDRESS, (word & 0xFF) as u8, (word >> 8) as u8, CRC_EOP], 0)?;
+
+            let size = block as u16;
+            let page = self.command(&[STK_READ_PAGE, (size >> 8) as u8, (size & 0xFF) as u8, b'F', CRC_EOP], block)?;
+            data.extend_from_slice(&page);
+        }
+        Ok(data)
+    }
+
+    /// 按页写入Flash，地址必须页对齐，不足一页的部分用0xFF补齐
+    ///
+    /// optiboot在写入每页之前会自动擦除该页。
+    pub fn write_flash<F>(&mut self, address: u32, data: &[u8], page_size: usize, mut on_progress: F) -> Result<()>
+    where
+        F: FnMut(usize, usize),
+    {
+        if address as usize % page_size != 0 {
+            return Err(anyhow!("Flash写入地址必须按页对齐: {:#X}", address));
+        }
+
+        for (index, chunk) in data.chunks(page_size).enumerate() {
+            let mut page = chunk.to_vec();
+            page.resize(page_size, 0xFF);
+
+            let current = address + (index * page_size) as u32;
+            let word = (current / 2) as u16;
+            self.command(&[STK_LOAD_ADDRESS, (word & 0xFF) as u8, (word >> 8) as u8, CRC_EOP], 0)?;
+
+            let size = page_size as u16;
+            let mut request = Vec::with_capacity(page_size + 5);
+            request.extend_from_slice(&[STK_PROG_PAGE, (size >> 8) as u8, (size & 0xFF) as u8, b'F']);
+            request.extend_from_slice(&page);
+            request.push(CRC_EOP);
+            self.command(&request, 0)
+                .map_err(|e| anyhow!("写入地址 {:#06X} 失败: {}", current, e))?;
+
+            on_progress(((index + 1) * page_size).min(data.len()), data.len());
+        }
+        Ok(())
+    }
+
+    /// 发送命令，检查 INSYNC ... OK 包裹的响应，返回中间的数据
+    fn command(&mut self, request: &[u8], response_len: usize) -> Result<Vec<u8>> {
+        self.port.write_all(request)
+            .map_err(|e| anyhow!("写入引导程序失败: {}", e))?;
+        self.port.flush()
+            .map_err(|e| anyhow!("写入引导程序失败: {}", e))?;
+
+        let response = self.read_bytes(response_len + 2, RESPONSE_TIMEOUT)?;
+        if response[0] != STK_INSYNC {
+            return Err(Stk500Error::LostSync(response[0]).into());
+        }
+        if response[response_len + 1] != STK_OK {
+            return Err(anyhow!("引导程序返回错误: {:02X}", response[response_len + 1]));
+        }
+        Ok(response[1..=response_len].to_vec())
+    }
+
+    fn read_bytes(&mut self, count: usize, timeout: Duration) -> Result<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        let mut output = vec![0u8; count];
+        let mut filled = 0;
+
+        while filled < count {
+            if Instant::now() >= deadline {
+                return Err(anyhow!("等待引导程序响应超时"));
+            }
+            match self.port.read(&mut output[filled..]) {
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(anyhow!("读取引导程序失败: {}", e)),
+            }
+        }
+        Ok(output)
+    }
+
+    fn drain_input(&mut self) {
+        let mut buffer = [0u8; 64];
+        while let Ok(n) = self.port.read(&mut buffer) {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+}
+
+/// 通过引导程序写入HEX镜像并回读校验，返回写入的字节数
+pub fn program_avr_flash<P, F>(port: P, image: &HexImage, page_size: usize, mut on_progress: F) -> Result<usize>
+where
+    P: Read + Write + PortControl,
+    F: FnMut(usize, usize),
+{
+    let mut client = Stk500::new(port);
+    client.connect(5)?;
+    client.enter_progmode()?;
+
+    let result = client.write_flash(image.start, &image.data, page_size, &mut on_progress)
+        .and_then(|_| client.read_flash(image.start, image.data.len()));
+    let _ = client.leave_progmode();
+    let flash = result?;
+
+    if flash != image.data {
+        return Err(anyhow!("写入后回读的内容与HEX文件不一致"));
+    }
+    info!("AVR程序写入完成: {} 字节", image.data.len());
+    Ok(image.data.len())
+}
+
+/// 回读AVR Flash并与HEX镜像比较，返回比较的字节数
+pub fn verify_avr_flash<P: Read + Write + PortControl>(port: P, image: &HexImage) -> Result<usize> {
+    let mut client = Stk500::new(port);
+    client.connect(5)?;
+    client.enter_progmode()?;
+
+    let flash = client.read_flash(image.start, image.data.len());
+    // 无论比较结果如何都要离开编程模式，让板子运行程序
+    let _ = client.leave_progmode();
+    let flash = flash?;
+
+    if let Some(offset) = flash.iter().zip(image.data.iter()).position(|(a, b)| a != b) {
+        return Err(anyhow!(
+            "Flash内容与上传的程序不一致，地址 {:#06X}: 期望 {:02X}, 实际 {:02X}",
+            image.start as usize + offset,
+            image.data[offset],
+            flash[offset]
+        ));
+    }
+    Ok(flash.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_intel_hex() {
+        let hex = ":100000000C9434000C9446000C9446000C9446006A\n\
+                   :020010000C944E\n\
+                   :00000001FF\n";
+        let image = parse_intel_hex(hex).unwrap();
+        assert_eq!(image.start, 0);
+        assert_eq!(image.data.len(), 0x12);
+        assert_eq!(&image.data[..4], &[0x0C, 0x94, 0x34, 0x00]);
+        assert_eq!(&image.data[0x10..], &[0x0C, 0x94]);
+
+        let bad_checksum = ":100000000C9434000C9446000C9446000C9446006B\n";
+        assert!(parse_intel_hex(bad_checksum).is_err());
+    }
+}
@@ -0,0 +1,528 @@
+use crate::utils::get_toolchain_dir;
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 工具链清单文件名，离线安装包的根目录也使用这个名字
+pub const TOOLCHAIN_MANIFEST_FILE: &str = "toolchain_manifest.json";
+const CONFIG_FILE: &str = "arduino-cli.yaml";
+
+/// 固定使用的 arduino-cli 版本
+pub const ARDUINO_CLI_VERSION: &str = "1.0.4";
+const ARDUINO_CLI_DOWNLOAD_URL: &str = "https://downloads.arduino.cc/arduino-cli";
+
+/// 固定版本的开发板核心，保证所有教室电脑编译结果一致
+pub const PINNED_CORES: &[PinnedCore] = &[
+    PinnedCore {
+        core: "arduino:avr",
+        version: "1.8.6",
+        index_url: None,
+    },
+    PinnedCore {
+        core: "esp32:esp32",
+        version: "2.0.17",
+        index_url: Some("https://espressif.github.io/arduino-esp32/package_esp32_index.json"),
+    },
+    PinnedCore {
+        core: "rp2040:rp2040",
+        version: "3.9.3",
+        index_url: Some("https://github.com/earlephilhower/arduino-pico/releases/download/global/package_rp2040_index.json"),
+    },
+];
+
+/// 交互模式上传的StandardFirmata所在的Firmata库版本
+pub const FIRMATA_LIBRARY_VERSION: &str = "2.5.9";
+
+#[derive(Debug, Clone, Copy)]
+pub struct PinnedCore {
+    pub core: &'static str,
+    pub version: &'static str,
+    pub index_url: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentKind {
+    Cli,
+    Core,
+    Library,
+}
+
+/// 已安装的组件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolchainComponent {
+    pub name: String,
+    pub version: String,
+    pub kind: ComponentKind,
+    pub installed_at: chrono::DateTime<chrono::Utc>,
+    /// 来源：在线安装或离线包文件名
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ToolchainManifest {
+    #[serde(default)]
+    pub components: Vec<ToolchainComponent>,
+}
+
+impl ToolchainManifest {
+    pub fn find(&self, name: &str) -> Option<&ToolchainComponent> {
+        self.components.iter().find(|c| c.name == name)
+    }
+
+    /// 记录组件，同名组件会被替换
+    pub fn record(&mut self, component: ToolchainComponent) {
+        self.components.retain(|c| c.name != component.name);
+        self.components.push(component);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoreStatus {
+    pub core: String,
+    pub pinned_version: String,
+    pub installed_version: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolchainStatus {
+    pub root: String,
+    pub cli_installed: bool,
+    pub cli_version: Option<String>,
+    pub cores: Vec<CoreStatus>,
+}
+
+/// 应用自己管理的工具链目录
+///
+/// arduino-cli 的程序、配置、核心和库都放在这个目录中，不使用系统的 `~/.arduino15`，
+/// 这样编译环境与学生电脑上的其他Arduino安装互不影响。
+#[derive(Debug, Clone)]
+pub struct Toolchain {
+    root: PathBuf,
+}
+
+impl Toolchain {
+    /// 打开应用数据目录下的工具链
+    pub fn open() -> Result<Self> {
+        let toolchain = Self::with_root(get_toolchain_dir()?);
+        toolchain.ensure_layout()?;
+        Ok(toolchain)
+    }
+
+    /// 使用指定目录，不创建目录结构
+    pub fn with_root(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn bin_dir(&self) -> PathBuf {
+        self.root.join("bin")
+    }
+
+    pub fn data_dir(&self) -> PathBuf {
+        self.root.join("arduino15")
+    }
+
+    pub fn user_dir(&self) -> PathBuf {
+        self.root.join("sketchbook")
+    }
+
+    pub fn staging_dir(&self) -> PathBuf {
+        self.root.join("staging")
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.root.join(CONFIG_FILE)
+    }
+
+    /// 工具链中 arduino-cli 的位置（不检查是否存在）
+    pub fn managed_cli_path(&self) -> PathBuf {
+        self.bin_dir().join(if cfg!(windows) { "arduino-cli.exe" } else { "arduino-cli" })
+    }
+
+    /// 工具链中的 arduino-cli
+    ///
+    /// 不使用PATH中的 arduino-cli：系统安装的版本不确定，和固定版本的核心一起使用可能不兼容。
+    pub fn arduino_cli_path(&self) -> Result<PathBuf> {
+        let managed = self.managed_cli_path();
+        if managed.exists() {
+            Ok(managed)
+        } else {
+            Err(anyhow!("工具链中没有arduino-cli，请先安装工具链"))
+        }
+    }
+
+    /// 使用工具链配置的 arduino-cli 命令
+    pub fn command(&self) -> Result<std::process::Command> {
+        let mut command = std::process::Command::new(self.arduino_cli_path()?);
+        command.arg("--config-file").arg(self.config_path());
+        Ok(command)
+    }
+
+    /// 异步版本的 `command`
+    pub fn async_command(&self) -> Result<tokio::process::Command> {
+        let mut command = tokio::process::Command::new(self.arduino_cli_path()?);
+        command.arg("--config-file").arg(self.config_path());
+        Ok(command)
+    }
+
+    /// 创建目录并写入 arduino-cli 配置
+    pub fn ensure_layout(&self) -> Result<()> {
+        for dir in [self.bin_dir(), self.data_dir(), self.user_dir(), self.staging_dir()] {
+            fs::create_dir_all(&dir)
+                .map_err(|e| anyhow!("创建工具链目录失败 {:?}: {}", dir, e))?;
+        }
+        fs::write(self.config_path(), self.render_config())
+            .map_err(|e| anyhow!("写入arduino-cli配置失败: {}", e))
+    }
+
+    fn render_config(&self) -> String {
+        let urls: Vec<String> = PINNED_CORES.iter()
+            .filter_map(|c| c.index_url)
+            .map(|url| format!("    - {}", url))
+            .collect();
+        format!(
+            "board_manager:\n  additional_urls:\n{}\ndirectories:\n  data: {}\n  downloads: {}\n  user: {}\nupdater:\n  enable_notification: false\n",
+            urls.join("\n"),
+            yaml_path(&self.data_dir()),
+            yaml_path(&self.staging_dir().join("downloads")),
+            yaml_path(&self.user_dir()),
+        )
+    }
+
+    pub fn load_manifest(&self) -> Result<ToolchainManifest> {
+        read_manifest(&self.root.join(TOOLCHAIN_MANIFEST_FILE))
+    }
+
+    fn save_manifest(&self, manifest: &ToolchainManifest) -> Result<()> {
+        let json = serde_json::to_string_pretty(manifest)?;
+        fs::write(self.root.join(TOOLCHAIN_MANIFEST_FILE), json)
+            .map_err(|e| anyhow!("保存工具链清单失败: {}", e))
+    }
+
+    fn record_component(&self, name: &str, version: &str, kind: ComponentKind, source: Option<String>) -> Result<()> {
+        let mut manifest = self.load_manifest()?;
+        manifest.record(ToolchainComponent {
+            name: name.to_string(),
+            version: version.to_string(),
+            kind,
+            installed_at: chrono::Utc::now(),
+            source,
+        });
+        self.save_manifest(&manifest)
+    }
+
+    /// arduino-cli 是否可用
+    pub async fn cli_available(&self) -> bool {
+        let Ok(mut command) = self.async_command() else {
+            return false;
+        };
+        command
+            .arg("version")
+            .output()
+            .await
+            .map(|output| output.status.success())
+            .unwrap_or(false)
+    }
+
+    /// 工具链状态：arduino-cli版本和各核心的安装情况
+    pub async fn status(&self) -> Result<ToolchainStatus> {
+        let manifest = self.load_manifest()?;
+        let output = match self.async_command() {
+            Ok(mut command) => command.arg("version").output().await.ok(),
+            Err(_) => None,
+        };
+        let cli_version = output
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+
+        Ok(ToolchainStatus {
+            root: self.root.to_string_lossy().to_string(),
+            cli_installed: cli_version.is_some(),
+            cli_version,
+            cores: PINNED_CORES.iter().map(|pinned| CoreStatus {
+                core: pinned.core.to_string(),
+                pinned_version: pinned.version.to_string(),
+                installed_version: manifest.find(pinned.core).map(|c| c.version.clone()),
+            }).collect(),
+        })
+    }
+
+    /// 下载固定版本的 arduino-cli 到工具链目录，按官方发布的SHA256校验后再解压
+    pub async fn install_arduino_cli(&self) -> Result<()> {
+        let asset = cli_asset_name()?;
+        let url = format!("{}/{}", ARDUINO_CLI_DOWNLOAD_URL, asset);
+        info!("下载Arduino CLI: {}", url);
+
+        let checksums = download(&format!("{}/arduino-cli_{}_checksums.txt", ARDUINO_CLI_DOWNLOAD_URL, ARDUINO_CLI_VERSION))
+            .await
+            .map_err(|e| anyhow!("下载Arduino CLI校验文件失败: {}", e))?;
+        let checksums = String::from_utf8_lossy(&checksums);
+        let expected = expected_checksum(&checksums, &asset)
+            .ok_or_else(|| anyhow!("校验文件中没有 {}", asset))?;
+
+        let bytes = download(&url).await
+            .map_err(|e| anyhow!("下载Arduino CLI失败: {}", e))?;
+        let actual = format!("{:x}", Sha256::digest(&bytes));
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(anyhow!("Arduino CLI校验失败，下载的文件可能已损坏: 期望 {}, 实际 {}", expected, actual));
+        }
+
+        let archive = self.staging_dir().join(&asset);
+        fs::write(&archive, &bytes)?;
+        let result = extract_archive(&archive, &self.bin_dir()).await;
+        let _ = fs::remove_file(&archive);
+        result?;
+
+        self.record_component("arduino-cli", ARDUINO_CLI_VERSION, ComponentKind::Cli, Some(url))?;
+        info!("Arduino CLI {} 安装完成", ARDUINO_CLI_VERSION);
+        Ok(())
+    }
+
+    /// 安装固定版本的开发板核心，已安装相同版本的跳过；任何一个安装失败都返回错误
+    pub async fn install_pinned_cores(&self) -> Result<Vec<String>> {
+        let manifest = self.load_manifest()?;
+        let missing: Vec<&PinnedCore> = PINNED_CORES.iter()
+            .filter(|pinned| manifest.find(pinned.core).map(|c| c.version.as_str()) != Some(pinned.version))
+            .collect();
+        if missing.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.run_cli(&["core", "update-index"]).await?;
+
+        let mut installed = Vec::new();
+        for pinned in missing {
+            let spec = format!("{}@{}", pinned.core, pinned.version);
+            info!("安装开发板核心: {}", spec);
+            self.run_cli(&["core", "install", &spec]).await
+                .map_err(|e| anyhow!("安装开发板核心 {} 失败: {}", spec, e))?;
+            self.record_component(pinned.core, pinned.version, ComponentKind::Core, None)?;
+            installed.push(spec);
+        }
+        Ok(installed)
+    }
+
+    /// 安装Arduino库到工具链的库目录
+    ///
+    /// 不指定版本时安装最新版，清单中记录arduino-cli报告的实际版本，方便其他电脑安装相同版本。
+    pub async fn install_library(&self, library_name: &str, version: Option<&str>) -> Result<String> {
+        let spec = match version {
+            Some(version) => format!("{}@{}", library_name, version),
+            None => library_name.to_string(),
+        };
+        self.run_cli(&["lib", "install", &spec]).await?;
+
+        let installed_version = self.library_version(library_name).await?
+            .ok_or_else(|| anyhow!("安装后没有找到库 {}", library_name))?;
+        if let Some(version) = version.filter(|v| *v != installed_version) {
+            warn!("库 {} 要求版本 {}，实际安装了 {}", library_name, version, installed_version);
+        }
+        self.record_component(library_name, &installed_version, ComponentKind::Library, None)?;
+        Ok(installed_version)
+    }
+
+    /// 已安装的库版本，未安装时返回 `None`
+    pub async fn library_version(&self, library_name: &str) -> Result<Option<String>> {
+        let installed = self.run_cli(&["lib", "list", library_name, "--format", "json"]).await?;
+        Ok(installed_library_version(&installed, library_name))
+    }
+
+    /// 从离线安装包导入工具链
+    ///
+    /// 安装包是压缩包，根目录包含 `toolchain_manifest.json` 以及 `bin`、`arduino15`、`sketchbook` 等目录，
+    /// 老师可以在有网络的电脑上准备好，再拷贝到机房电脑上导入。
+    pub async fn import_bundle(&self, archive: &Path) -> Result<usize> {
+        info!("导入离线工具链: {:?}", archive);
+        let staging = self.staging_dir().join(format!("bundle_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&staging)?;
+
+        let result = self.import_from_staging(archive, &staging).await;
+        let _ = fs::remove_dir_all(&staging);
+        result
+    }
+
+    async fn import_from_staging(&self, archive: &Path, staging: &Path) -> Result<usize> {
+        extract_archive(archive, staging).await?;
+
+        let bundle = read_manifest(&staging.join(TOOLCHAIN_MANIFEST_FILE))?;
+        if bundle.components.is_empty() {
+            return Err(anyhow!("离线安装包中没有 {}", TOOLCHAIN_MANIFEST_FILE));
+        }
+
+        for dir in ["bin", "arduino15", "sketchbook"] {
+            let source = staging.join(dir);
+            if source.exists() {
+                copy_dir(&source, &self.root.join(dir))?;
+            }
+        }
+
+        let source = archive.file_name().map(|n| n.to_string_lossy().to_string());
+        let mut manifest = self.load_manifest()?;
+        let count = bundle.components.len();
+        for mut component in bundle.components {
+            component.source = source.clone();
+            manifest.record(component);
+        }
+        self.save_manifest(&manifest)?;
+
+        // 目录路径是绝对路径，导入后重新生成配置
+        self.ensure_layout()?;
+        info!("离线工具链导入完成: {} 个组件", count);
+        Ok(count)
+    }
+
+    async fn run_cli(&self, args: &[&str]) -> Result<String> {
+        let output = self.async_command()?
+            .args(args)
+            .output()
+            .await
+            .map_err(|e| anyhow!("执行arduino-cli失败: {}", e))?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            Err(anyhow!("arduino-cli {} 失败: {}", args.join(" "), String::from_utf8_lossy(&output.stderr)))
+        }
+    }
+}
+
+fn read_manifest(path: &Path) -> Result<ToolchainManifest> {
+    if !path.exists() {
+        return Ok(ToolchainManifest::default());
+    }
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("读取工具链清单失败: {}", e))?;
+    serde_json::from_str(&content)
+        .map_err(|e| anyhow!("工具链清单格式错误: {}", e))
+}
+
+async fn download(url: &str) -> Result<Vec<u8>> {
+    let response = reqwest::get(url).await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("HTTP {}", response.status()));
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// 在 `sha256sum` 格式的校验文件中查找文件的哈希
+fn expected_checksum<'a>(checksums: &'a str, asset: &str) -> Option<&'a str> {
+    checksums.lines().find_map(|line| {
+        let (hash, name) = line.split_once(char::is_whitespace)?;
+        (name.trim().trim_start_matches('*') == asset).then_some(hash)
+    })
+}
+
+/// 从 `arduino-cli lib list --format json` 的输出中读取库的版本
+fn installed_library_version(json: &str, library_name: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(json).ok()?;
+    let libraries = value.get("installed_libraries").unwrap_or(&value).as_array()?;
+    libraries.iter()
+        .filter_map(|entry| entry.get("library"))
+        .find(|library| library.get("name").and_then(|n| n.as_str()) == Some(library_name))
+        .and_then(|library| library.get("version"))
+        .and_then(|version| version.as_str())
+        .map(|version| version.to_string())
+}
+
+/// 当前平台的 arduino-cli 下载文件名
+fn cli_asset_name() -> Result<String> {
+    let platform = match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => "Linux_64bit.tar.gz",
+        ("linux", "aarch64") => "Linux_ARM64.tar.gz",
+        ("linux", "arm") => "Linux_ARMv7.tar.gz",
+        ("macos", "x86_64") => "macOS_64bit.tar.gz",
+        ("macos", "aarch64") => "macOS_ARM64.tar.gz",
+        ("windows", "x86_64") => "Windows_64bit.zip",
+        (os, arch) => return Err(anyhow!("不支持的平台: {} {}", os, arch)),
+    };
+    Ok(format!("arduino-cli_{}_{}", ARDUINO_CLI_VERSION, platform))
+}
+
+/// 用系统的 tar 解压（Windows 10 起自带的 tar 也支持zip）
+async fn extract_archive(archive: &Path, dest: &Path) -> Result<()> {
+    let output = tokio::process::Command::new("tar")
+        .arg("-xf")
+        .arg(archive)
+        .arg("-C")
+        .arg(dest)
+        .output()
+        .await
+        .map_err(|e| anyhow!("执行tar失败: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("解压 {:?} 失败: {}", archive, String::from_utf8_lossy(&output.stderr)))
+    }
+}
+
+fn copy_dir(source: &Path, dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let target = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// YAML中的路径统一用正斜杠并加引号，避免Windows路径中的反斜杠和空格出问题
+fn yaml_path(path: &Path) -> String {
+    format!("\"{}\"", path.to_string_lossy().replace('\\', "/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_and_manifest() {
+        let root = std::env::temp_dir().join(format!("rustblock_toolchain_test_{}", uuid::Uuid::new_v4()));
+        let toolchain = Toolchain::with_root(root.clone());
+        toolchain.ensure_layout().unwrap();
+
+        let config = fs::read_to_string(toolchain.config_path()).unwrap();
+        assert!(config.contains("package_esp32_index.json"));
+        assert!(config.contains(&yaml_path(&toolchain.data_dir())));
+
+        toolchain.record_component("arduino:avr", "1.8.6", ComponentKind::Core, None).unwrap();
+        toolchain.record_component("arduino:avr", "1.8.6", ComponentKind::Core, None).unwrap();
+        let manifest = toolchain.load_manifest().unwrap();
+        assert_eq!(manifest.components.len(), 1);
+        assert_eq!(manifest.find("arduino:avr").unwrap().version, "1.8.6");
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_expected_checksum() {
+        let checksums = "0123abcd  arduino-cli_1.0.4_Linux_64bit.tar.gz\n\
+                         4567ef01  arduino-cli_1.0.4_Windows_64bit.zip\n";
+        assert_eq!(expected_checksum(checksums, "arduino-cli_1.0.4_Windows_64bit.zip"), Some("4567ef01"));
+        assert_eq!(expected_checksum(checksums, "arduino-cli_1.0.4_macOS_64bit.tar.gz"), None);
+    }
+
+    #[test]
+    fn test_installed_library_version() {
+        let json = r#"{"installed_libraries":[{"library":{"name":"Firmata","version":"2.5.9"}},{"library":{"name":"Servo","version":"1.2.1"}}]}"#;
+        assert_eq!(installed_library_version(json, "Firmata").as_deref(), Some("2.5.9"));
+        assert_eq!(installed_library_version(json, "Stepper"), None);
+    }
+
+    #[test]
+    fn test_missing_managed_cli_is_an_error() {
+        let toolchain = Toolchain::with_root(std::env::temp_dir().join(format!("rustblock_toolchain_test_{}", uuid::Uuid::new_v4())));
+        assert!(toolchain.arduino_cli_path().is_err());
+        assert!(toolchain.command().is_err());
+    }
+}
@@ -0,0 +1,707 @@
+use super::serial::PortControl;
+use super::verify::BOOT_MARKER;
+use anyhow::{Result, anyhow};
+use log::{debug, info};
+use serde::{Serialize, Deserialize};
+use serialport::{DataBits, Parity, SerialPort, StopBits};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 模拟设备串口名称的前缀
+pub const VIRTUAL_PORT_PREFIX: &str = "virtual:";
+/// 模拟Arduino的串口名称
+pub const VIRTUAL_ARDUINO_PORT: &str = "virtual:arduino";
+/// 模拟Arduino使用的USB ID（pid.codes 的测试ID）
+pub const VIRTUAL_ARDUINO_USB_ID: (u16, u16) = (0x1209, 0x0001);
+
+/// 数据位、停止位和校验位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineSettings {
+    pub data_bits: DataBits,
+    pub stop_bits: StopBits,
+    pub parity: Parity,
+}
+
+impl Default for LineSettings {
+    fn default() -> Self {
+        Self {
+            data_bits: DataBits::Eight,
+            stop_bits: StopBits::One,
+            parity: Parity::None,
+        }
+    }
+}
+
+/// 对端送来的控制线（调制解调器状态）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ControlLines {
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri: bool,
+    pub cd: bool,
+}
+
+/// 串口传输层
+///
+/// 真实串口、Linux伪终端和内存中的模拟设备都实现这个接口，
+/// 串口服务、引导程序客户端和上传验证都通过它读写，不依赖真实硬件也能测试。
+pub trait SerialTransport: Read + Write + Send {
+    fn name(&self) -> String;
+    /// 复制一个读写同一串口的句柄（读取线程和写入各用一个）
+    fn try_clone_transport(&self) -> Result<Box<dyn SerialTransport>>;
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()>;
+    fn set_dtr(&mut self, level: bool) -> Result<()>;
+    fn set_rts(&mut self, level: bool) -> Result<()>;
+    fn baud_rate(&self) -> Result<u32>;
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()>;
+    fn line_settings(&self) -> Result<LineSettings>;
+    fn set_line_settings(&mut self, settings: LineSettings) -> Result<()>;
+    /// 清空收发缓冲区
+    fn clear_buffers(&mut self) -> Result<()>;
+    /// 开始（`true`）或结束发送BREAK信号
+    fn set_break(&mut self, on: bool) -> Result<()>;
+    /// 读取CTS/DSR/RI/CD
+    fn control_lines(&mut self) -> Result<ControlLines>;
+}
+
+impl PortControl for Box<dyn SerialTransport> {
+    fn set_dtr(&mut self, level: bool) -> Result<()> {
+        SerialTransport::set_dtr(&mut **self, level)
+    }
+
+    fn set_rts(&mut self, level: bool) -> Result<()> {
+        SerialTransport::set_rts(&mut **self, level)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        SerialTransport::set_baud_rate(&mut **self, baud_rate)
+    }
+}
+
+/// 打开串口，模拟设备的名称以 `virtual:` 开头
+pub fn open_transport(port_name: &str, baud_rate: u32, timeout: Duration) -> Result<Box<dyn SerialTransport>> {
+    if port_name == VIRTUAL_ARDUINO_PORT {
+        info!("打开模拟Arduino");
+        return Ok(Box::new(virtual_arduino(timeout)));
+    }
+    if port_name.starts_with(VIRTUAL_PORT_PREFIX) {
+        return Err(anyhow!("未知的模拟设备: {}", port_name));
+    }
+
+    let port = serialport::new(port_name, baud_rate)
+        .timeout(timeout)
+        .open()
+        .map_err(|e| match e.kind() {
+            serialport::ErrorKind::NoDevice => anyhow!("设备未找到: {}", port_name),
+            _ => anyhow!("打开串口失败 {}: {}", port_name, e),
+        })?;
+    Ok(Box::new(SystemTransport::new(port)))
+}
+
+/// `serialport` 打开的真实串口
+pub struct SystemTransport {
+    port: Box<dyn SerialPort>,
+}
+
+impl SystemTransport {
+    pub fn new(port: Box<dyn SerialPort>) -> Self {
+        Self { port }
+    }
+}
+
+impl Read for SystemTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for SystemTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl SerialTransport for SystemTransport {
+    fn name(&self) -> String {
+        self.port.name().unwrap_or_default()
+    }
+
+    fn try_clone_transport(&self) -> Result<Box<dyn SerialTransport>> {
+        let port = self.port.try_clone().map_err(|e| anyhow!("复制串口句柄失败: {}", e))?;
+        Ok(Box::new(SystemTransport::new(port)))
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.port.set_timeout(timeout).map_err(|e| anyhow!("设置超时失败: {}", e))
+    }
+
+    fn set_dtr(&mut self, level: bool) -> Result<()> {
+        PortControl::set_dtr(&mut self.port, level)
+    }
+
+    fn set_rts(&mut self, level: bool) -> Result<()> {
+        PortControl::set_rts(&mut self.port, level)
+    }
+
+    fn baud_rate(&self) -> Result<u32> {
+        self.port.baud_rate().map_err(|e| anyhow!("读取波特率失败: {}", e))
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        PortControl::set_baud_rate(&mut self.port, baud_rate)
+    }
+
+    fn line_settings(&self) -> Result<LineSettings> {
+        let read = |e: serialport::Error| anyhow!("读取串口参数失败: {}", e);
+        Ok(LineSettings {
+            data_bits: self.port.data_bits().map_err(read)?,
+            stop_bits: self.port.stop_bits().map_err(read)?,
+            parity: self.port.parity().map_err(read)?,
+        })
+    }
+
+    fn set_line_settings(&mut self, settings: LineSettings) -> Result<()> {
+        self.port.set_data_bits(settings.data_bits).map_err(|e| anyhow!("设置数据位失败: {}", e))?;
+        self.port.set_stop_bits(settings.stop_bits).map_err(|e| anyhow!("设置停止位失败: {}", e))?;
+        self.port.set_parity(settings.parity).map_err(|e| anyhow!("设置校验位失败: {}", e))
+    }
+
+    fn clear_buffers(&mut self) -> Result<()> {
+        self.port.clear(serialport::ClearBuffer::All)
+            .map_err(|e| anyhow!("清空串口缓冲区失败: {}", e))
+    }
+
+    fn set_break(&mut self, on: bool) -> Result<()> {
+        let result = if on { self.port.set_break() } else { self.port.clear_break() };
+        result.map_err(|e| anyhow!("设置BREAK信号失败: {}", e))
+    }
+
+    fn control_lines(&mut self) -> Result<ControlLines> {
+        let read = |e: serialport::Error| anyhow!("读取控制线状态失败: {}", e);
+        Ok(ControlLines {
+            cts: self.port.read_clear_to_send().map_err(read)?,
+            dsr: self.port.read_data_set_ready().map_err(read)?,
+            ri: self.port.read_ring_indicator().map_err(read)?,
+            cd: self.port.read_carrier_detect().map_err(read)?,
+        })
+    }
+}
+
+/// 创建一对相连的Linux伪终端，写入一端的数据从另一端读出
+///
+/// 第二个的名称（例如 `/dev/pts/3`）可以交给外部程序打开，用于集成测试。
+#[cfg(unix)]
+pub fn pty_pair() -> Result<(Box<dyn SerialTransport>, Box<dyn SerialTransport>)> {
+    let (master, slave) = serialport::TTYPort::pair()
+        .map_err(|e| anyhow!("创建伪终端失败: {}", e))?;
+    Ok((
+        Box::new(SystemTransport::new(Box::new(master))),
+        Box::new(SystemTransport::new(Box::new(slave))),
+    ))
+}
+
+/// 内存中模拟的串口对端
+pub trait ScriptedPeer: Send {
+    /// 收到主机写入的数据，回复写入 `output`
+    fn on_write(&mut self, data: &[u8], output: &mut Vec<u8>);
+    /// DTR从高变低，相当于按下复位键
+    fn on_reset(&mut self, _output: &mut Vec<u8>) {}
+    /// 每次读取前调用，主动发出的数据写入 `output`
+    fn on_tick(&mut self, _now: Instant, _output: &mut Vec<u8>) {}
+}
+
+struct ScriptedState {
+    peer: Box<dyn ScriptedPeer>,
+    /// 对端发出、等待主机读取的数据
+    pending: VecDeque<u8>,
+    baud_rate: u32,
+    line: LineSettings,
+    dtr: bool,
+    rts: bool,
+}
+
+/// 连接内存中对端的串口，复制出的句柄共享同一个对端
+#[derive(Clone)]
+pub struct ScriptedTransport {
+    name: String,
+    state: Arc<Mutex<ScriptedState>>,
+    timeout: Duration,
+}
+
+impl ScriptedTransport {
+    pub fn new(name: &str, peer: Box<dyn ScriptedPeer>, timeout: Duration) -> Self {
+        Self {
+            name: name.to_string(),
+            state: Arc::new(Mutex::new(ScriptedState {
+                peer,
+                pending: VecDeque::new(),
+                baud_rate: 115200,
+                line: LineSettings::default(),
+                dtr: true,
+                rts: true,
+            })),
+            timeout,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ScriptedState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Read for ScriptedTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            {
+                let mut state = self.lock();
+                let mut output = Vec::new();
+                state.peer.on_tick(Instant::now(), &mut output);
+                state.pending.extend(output);
+                if !state.pending.is_empty() {
+                    let n = state.pending.len().min(buf.len());
+                    for (slot, byte) in buf.iter_mut().zip(state.pending.drain(..n)) {
+                        *slot = byte;
+                    }
+                    return Ok(n);
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "读取超时"));
+            }
+            std::thread::sleep((deadline - now).min(Duration::from_millis(2)));
+        }
+    }
+}
+
+impl Write for ScriptedTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = self.lock();
+        let mut output = Vec::new();
+        state.peer.on_write(buf, &mut output);
+        state.pending.extend(output);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialTransport for ScriptedTransport {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn try_clone_transport(&self) -> Result<Box<dyn SerialTransport>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn set_dtr(&mut self, level: bool) -> Result<()> {
+        let mut state = self.lock();
+        if state.dtr && !level {
+            // 复位时丢弃还没读取的输出
+            state.pending.clear();
+            let mut output = Vec::new();
+            state.peer.on_reset(&mut output);
+            state.pending.extend(output);
+        }
+        state.dtr = level;
+        Ok(())
+    }
+
+    fn set_rts(&mut self, level: bool) -> Result<()> {
+        self.lock().rts = level;
+        Ok(())
+    }
+
+    fn baud_rate(&self) -> Result<u32> {
+        Ok(self.lock().baud_rate)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        self.lock().baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn line_settings(&self) -> Result<LineSettings> {
+        Ok(self.lock().line)
+    }
+
+    fn set_line_settings(&mut self, settings: LineSettings) -> Result<()> {
+        self.lock().line = settings;
+        Ok(())
+    }
+
+    fn clear_buffers(&mut self) -> Result<()> {
+        self.lock().pending.clear();
+        Ok(())
+    }
+
+    fn set_break(&mut self, _on: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /// 模拟设备的控制线回环：CTS跟随RTS，DSR跟随DTR
+    fn control_lines(&mut self) -> Result<ControlLines> {
+        let state = self.lock();
+        Ok(ControlLines {
+            cts: state.rts,
+            dsr: state.dtr,
+            ri: false,
+            cd: true,
+        })
+    }
+}
+
+/// 按脚本应答的对端：收到期望的数据后回复对应的内容，用于测试协议客户端
+pub struct ScriptPeer {
+    steps: VecDeque<(Vec<u8>, Vec<u8>)>,
+    received: Vec<u8>,
+}
+
+impl ScriptPeer {
+    pub fn new() -> Self {
+        Self {
+            steps: VecDeque::new(),
+            received: Vec::new(),
+        }
+    }
+
+    /// 收到 `expect` 后回复 `reply`，按添加顺序依次匹配
+    pub fn expect(mut self, expect: &[u8], reply: &[u8]) -> Self {
+        self.steps.push_back((expect.to_vec(), reply.to_vec()));
+        self
+    }
+
+    /// 脚本是否已全部执行
+    pub fn finished(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl Default for ScriptPeer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptedPeer for ScriptPeer {
+    fn on_write(&mut self, data: &[u8], output: &mut Vec<u8>) {
+        self.received.extend_from_slice(data);
+        while let Some((expect, reply)) = self.steps.front() {
+            let Some(end) = find_subsequence(&self.received, expect).map(|start| start + expect.len()) else {
+                break;
+            };
+            output.extend_from_slice(reply);
+            self.received.drain(..end);
+            self.steps.pop_front();
+        }
+    }
+}
+
+fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// 模拟Arduino只有一块，上传后的程序在多次打开之间保留
+lazy_static::lazy_static! {
+    static ref VIRTUAL_ARDUINO: ScriptedTransport = ScriptedTransport::new(
+        VIRTUAL_ARDUINO_PORT,
+        Box::new(VirtualArduino::new()),
+        Duration::from_millis(100),
+    );
+}
+
+/// 打开模拟Arduino的测试共用同一块板子，需要依次执行
+#[cfg(test)]
+pub static VIRTUAL_ARDUINO_TEST_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// 打开模拟Arduino，和真实的板子一样打开串口时会复位
+fn virtual_arduino(timeout: Duration) -> ScriptedTransport {
+    let mut transport = VIRTUAL_ARDUINO.clone();
+    transport.timeout = timeout;
+    let _ = SerialTransport::set_dtr(&mut transport, true);
+    let _ = SerialTransport::set_dtr(&mut transport, false);
+    let _ = SerialTransport::set_dtr(&mut transport, true);
+    transport
+}
+
+// 模拟的optiboot支持的STK500v1命令
+const STK_OK: u8 = 0x10;
+const STK_FAILED: u8 = 0x11;
+const STK_INSYNC: u8 = 0x14;
+const CRC_EOP: u8 = 0x20;
+/// 模拟Arduino的Flash大小（ATmega328P去掉引导程序）
+const VIRTUAL_FLASH_SIZE: usize = 32 * 1024 - 512;
+/// 复位后引导程序等待命令的时间
+const BOOTLOADER_WINDOW: Duration = Duration::from_millis(1000);
+/// 模拟程序打印传感器数据的间隔
+const SENSOR_INTERVAL: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VirtualArduinoState {
+    /// 复位后运行optiboot，在此期间应答STK500v1命令
+    Bootloader { last_activity: Instant },
+    /// 运行用户程序：打印启动标记，定时打印传感器数据，回显收到的行
+    Running { next_report: Instant },
+}
+
+/// 模拟的Arduino Uno，用于老师演示和集成测试
+///
+/// 复位后的引导程序应答STK500v1命令（可以上传和回读程序），随后运行的程序会打印
+/// 启动标记，每秒打印一次 `temp:..,light:..`，并把收到的每一行以 `echo: ` 开头发回。
+pub struct VirtualArduino {
+    state: VirtualArduinoState,
+    flash: Vec<u8>,
+    address: usize,
+    command: Vec<u8>,
+    line: Vec<u8>,
+    reports: u32,
+}
+
+impl VirtualArduino {
+    pub fn new() -> Self {
+        Self {
+            state: VirtualArduinoState::Bootloader { last_activity: Instant::now() },
+            flash: vec![0xFF; VIRTUAL_FLASH_SIZE],
+            address: 0,
+            command: Vec::new(),
+            line: Vec::new(),
+            reports: 0,
+        }
+    }
+
+    fn start_sketch(&mut self, output: &mut Vec<u8>) {
+        debug!("模拟Arduino开始运行程序");
+        self.state = VirtualArduinoState::Running { next_report: Instant::now() + SENSOR_INTERVAL };
+        self.command.clear();
+        output.extend_from_slice(format!("{}\r\n", BOOT_MARKER).as_bytes());
+    }
+
+    /// 解析缓冲区中完整的STK500命令并应答
+    fn handle_stk500(&mut self, output: &mut Vec<u8>) {
+        loop {
+            let Some(&command) = self.command.first() else {
+                return;
+            };
+            let length = match command {
+                0x30 | 0x50 | 0x51 | 0x75 => 2,
+                0x41 => 3,
+                0x55 => 4,
+                0x74 => 5,
+                0x64 if self.command.len() >= 3 => 5 + u16::from_be_bytes([self.command[1], self.command[2]]) as usize,
+                0x64 => return,
+                // 引导程序忽略不认识的字节
+                _ => {
+                    self.command.remove(0);
+                    continue;
+                }
+            };
+            if self.command.len() < length {
+                return;
+            }
+            let request: Vec<u8> = self.command.drain(..length).collect();
+            if request[length - 1] != CRC_EOP {
+                continue;
+            }
+
+            output.push(STK_INSYNC);
+            match command {
+                // 读取参数，返回0即可
+                0x41 => output.push(0x00),
+                // 读取签名：ATmega328P
+                0x75 => output.extend_from_slice(&[0x1E, 0x95, 0x0F]),
+                0x55 => self.address = u16::from_le_bytes([request[1], request[2]]) as usize * 2,
+                0x64 => {
+                    let data = &request[4..length - 1];
+                    // 超出程序区域（写到引导程序上）时拒绝
+                    let Some(page) = self.flash.get_mut(self.address..self.address + data.len()) else {
+                        output.push(STK_FAILED);
+                        continue;
+                    };
+                    page.copy_from_slice(data);
+                }
+                0x74 => {
+                    let size = u16::from_be_bytes([request[1], request[2]]) as usize;
+                    // 超出程序区域的部分读出0xFF
+                    let start = self.address.min(self.flash.len());
+                    let end = (self.address + size).min(self.flash.len());
+                    output.extend_from_slice(&self.flash[start..end]);
+                    output.extend(std::iter::repeat_n(0xFF, size - (end - start)));
+                }
+                _ => {}
+            }
+            output.push(STK_OK);
+
+            if command == 0x51 {
+                self.start_sketch(output);
+                return;
+            }
+        }
+    }
+}
+
+impl Default for VirtualArduino {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptedPeer for VirtualArduino {
+    fn on_write(&mut self, data: &[u8], output: &mut Vec<u8>) {
+        match self.state {
+            VirtualArduinoState::Bootloader { .. } => {
+                self.state = VirtualArduinoState::Bootloader { last_activity: Instant::now() };
+                self.command.extend_from_slice(data);
+                self.handle_stk500(output);
+            }
+            VirtualArduinoState::Running { .. } => {
+                for &byte in data {
+                    match byte {
+                        b'\n' => {
+                            let line = String::from_utf8_lossy(&self.line).trim_end_matches('\r').to_string();
+                            output.extend_from_slice(format!("echo: {}\r\n", line).as_bytes());
+                            self.line.clear();
+                        }
+                        _ => self.line.push(byte),
+                    }
+                }
+            }
+        }
+    }
+
+    fn on_reset(&mut self, _output: &mut Vec<u8>) {
+        self.state = VirtualArduinoState::Bootloader { last_activity: Instant::now() };
+        self.command.clear();
+        self.line.clear();
+    }
+
+    fn on_tick(&mut self, now: Instant, output: &mut Vec<u8>) {
+        match self.state {
+            VirtualArduinoState::Bootloader { last_activity } if now.duration_since(last_activity) >= BOOTLOADER_WINDOW => {
+                self.start_sketch(output);
+            }
+            VirtualArduinoState::Running { next_report } if now >= next_report => {
+                self.reports += 1;
+                let temp = 20 + self.reports % 5;
+                let light = 300 + (self.reports * 37) % 200;
+                output.extend_from_slice(format!("temp:{},light:{}\r\n", temp, light).as_bytes());
+                self.state = VirtualArduinoState::Running { next_report: now + SENSOR_INTERVAL };
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::stk500::{parse_intel_hex, program_avr_flash, verify_avr_flash, ATMEGA328P_PAGE_SIZE};
+
+    #[test]
+    fn test_scripted_peer_and_virtual_arduino() {
+        let mut port = ScriptedTransport::new("test", Box::new(ScriptPeer::new().expect(b"ping\n", b"pong\n")), Duration::from_millis(20));
+        port.write_all(b"pi").unwrap();
+        port.write_all(b"ng\n").unwrap();
+        let mut reply = [0u8; 8];
+        let n = port.read(&mut reply).unwrap();
+        assert_eq!(&reply[..n], b"pong\n");
+
+        // 通过STK500v1把程序写入模拟Arduino并回读
+        let hex = ":100000000C9434000C9446000C9446000C9446006A\n\
+                   :020010000C944E\n\
+                   :00000001FF\n";
+        let image = parse_intel_hex(hex).unwrap();
+        let arduino: Box<dyn SerialTransport> = Box::new(ScriptedTransport::new(
+            VIRTUAL_ARDUINO_PORT,
+            Box::new(VirtualArduino::new()),
+            Duration::from_millis(20),
+        ));
+        let written = program_avr_flash(arduino.try_clone_transport().unwrap(), &image, ATMEGA328P_PAGE_SIZE, |_, _| {}).unwrap();
+        assert_eq!(written, image.data.len());
+        assert_eq!(verify_avr_flash(arduino, &image).unwrap(), image.data.len());
+    }
+
+    /// 向模拟Arduino的引导程序发送一条STK500命令，返回应答
+    fn stk500(arduino: &mut VirtualArduino, request: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        arduino.on_write(request, &mut output);
+        output
+    }
+
+    /// 设置字地址，`address` 为字节地址
+    fn load_address(arduino: &mut VirtualArduino, address: usize) {
+        let word = ((address / 2) as u16).to_le_bytes();
+        assert_eq!(stk500(arduino, &[0x55, word[0], word[1], CRC_EOP]), [STK_INSYNC, STK_OK]);
+    }
+
+    #[test]
+    fn test_virtual_arduino_get_sync() {
+        let mut arduino = VirtualArduino::new();
+        assert_eq!(stk500(&mut arduino, &[0x30, CRC_EOP]), [STK_INSYNC, STK_OK]);
+        // 结束符不对的命令不应答
+        assert!(stk500(&mut arduino, &[0x30, 0x00]).is_empty());
+    }
+
+    #[test]
+    fn test_virtual_arduino_get_parameter() {
+        let mut arduino = VirtualArduino::new();
+        assert_eq!(stk500(&mut arduino, &[0x41, 0x81, CRC_EOP]), [STK_INSYNC, 0x00, STK_OK]);
+    }
+
+    #[test]
+    fn test_virtual_arduino_read_signature() {
+        let mut arduino = VirtualArduino::new();
+        assert_eq!(stk500(&mut arduino, &[0x75, CRC_EOP]), [STK_INSYNC, 0x1E, 0x95, 0x0F, STK_OK]);
+    }
+
+    #[test]
+    fn test_virtual_arduino_prog_page() {
+        let mut arduino = VirtualArduino::new();
+        load_address(&mut arduino, 0x100);
+        assert_eq!(stk500(&mut arduino, &[0x64, 0x00, 0x02, b'F', 0xAA, 0xBB, CRC_EOP]), [STK_INSYNC, STK_OK]);
+        assert_eq!(&arduino.flash[0x100..0x102], [0xAA, 0xBB]);
+
+        // 写到引导程序区域时失败，Flash不变
+        load_address(&mut arduino, VIRTUAL_FLASH_SIZE - 2);
+        assert_eq!(stk500(&mut arduino, &[0x64, 0x00, 0x04, b'F', 1, 2, 3, 4, CRC_EOP]), [STK_INSYNC, STK_FAILED]);
+        assert_eq!(&arduino.flash[VIRTUAL_FLASH_SIZE - 2..], [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_virtual_arduino_read_page() {
+        let mut arduino = VirtualArduino::new();
+        arduino.flash[VIRTUAL_FLASH_SIZE - 1] = 0x42;
+        load_address(&mut arduino, VIRTUAL_FLASH_SIZE - 2);
+        assert_eq!(stk500(&mut arduino, &[0x74, 0x00, 0x04, b'F', CRC_EOP]), [STK_INSYNC, 0xFF, 0x42, 0xFF, 0xFF, STK_OK]);
+
+        // 整页都在程序区域之外
+        load_address(&mut arduino, 0x7F00);
+        assert_eq!(stk500(&mut arduino, &[0x74, 0x00, 0x02, b'F', CRC_EOP]), [STK_INSYNC, 0xFF, 0xFF, STK_OK]);
+    }
+
+    #[test]
+    fn test_virtual_arduino_leave_progmode_starts_sketch() {
+        let mut arduino = VirtualArduino::new();
+        let reply = stk500(&mut arduino, &[0x51, CRC_EOP]);
+        assert_eq!(&reply[..2], [STK_INSYNC, STK_OK]);
+        assert_eq!(&reply[2..], format!("{}\r\n", BOOT_MARKER).as_bytes());
+        assert_eq!(stk500(&mut arduino, b"hi\n"), b"echo: hi\r\n");
+    }
+}
@@ -0,0 +1,294 @@
+use super::{DeviceInfo, UploadOptions, uploader::{DeviceUploader, UploadOutcome}};
+use super::serial::{PortUser, SerialManager};
+use crate::utils::performance::TaskManager;
+use anyhow::Result;
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// 默认同时上传的板子数量，USB集线器带宽有限，不宜过多
+pub const DEFAULT_UPLOAD_CONCURRENCY: usize = 4;
+/// 保留的已完成任务记录数量
+const MAX_FINISHED_JOBS: usize = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum UploadJobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadJobStatus {
+    pub job_id: String,
+    pub device_id: String,
+    pub device_name: String,
+    pub port: String,
+    pub state: UploadJobState,
+    pub message: Option<String>,
+    pub queued_at: chrono::DateTime<chrono::Utc>,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub duration_ms: Option<u64>,
+}
+
+/// 上传任务队列
+///
+/// 上传前从串口服务取得串口租约，同一块板子的上传依次执行，串口监视器会自动暂停；
+/// 不同串口的上传并发执行，总并发数由 `TaskManager` 限制。
+pub struct UploadQueue {
+    serial: Arc<SerialManager>,
+    task_manager: TaskManager,
+    jobs: RwLock<HashMap<String, UploadJobStatus>>,
+}
+
+impl UploadQueue {
+    pub fn new(max_concurrent_uploads: usize, serial: Arc<SerialManager>) -> Self {
+        Self {
+            serial,
+            task_manager: TaskManager::new(max_concurrent_uploads),
+            jobs: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// 修改并发上限，正在执行的任务不受影响
+    pub async fn set_concurrency_limit(&self, max_concurrent_uploads: usize) {
+        info!("设置上传并发数: {}", max_concurrent_uploads);
+        self.task_manager.set_max_concurrent_tasks(max_concurrent_uploads);
+    }
+
+    pub async fn concurrency_limit(&self) -> usize {
+        self.task_manager.max_concurrent_tasks()
+    }
+
+    /// 排队并执行一次上传，等待其完成
+    pub async fn run_upload(
+        &self,
+        uploader: Arc<DeviceUploader>,
+        device: DeviceInfo,
+        options: UploadOptions,
+    ) -> Result<UploadOutcome> {
+        let resume_timeout = uploader.resolve_board(&options, &device).ok().map(|board| board.resume_timeout());
+        self.run_job(&device, resume_timeout, uploader.upload(&device, &options)).await
+    }
+
+    async fn run_job<F>(&self, device: &DeviceInfo, resume_timeout: Option<Duration>, upload: F) -> Result<UploadOutcome>
+    where
+        F: Future<Output = Result<UploadOutcome>>,
+    {
+        let job_id = self.enqueue(device).await;
+        // 先等串口空闲再占用并发名额，避免同一串口的排队任务占满名额
+        let result = match self.serial.acquire(&device.port, PortUser::Upload).await {
+            Ok(mut lease) => {
+                if let Some(timeout) = resume_timeout {
+                    lease.set_resume_timeout(timeout);
+                }
+                self.task_manager.run(async {
+                    self.mark_running(&job_id).await;
+                    info!("开始上传任务 {} -> {}", job_id, device.port);
+                    upload.await
+                }).await
+            }
+            Err(e) => Err(e),
+        };
+
+        self.mark_finished(&job_id, &result).await;
+        result
+    }
+
+    /// 查询任务状态
+    pub async fn job_status(&self, job_id: &str) -> Option<UploadJobStatus> {
+        self.jobs.read().await.get(job_id).cloned()
+    }
+
+    /// 列出所有任务，按排队时间排序
+    pub async fn list_jobs(&self) -> Vec<UploadJobStatus> {
+        let jobs = self.jobs.read().await;
+        let mut list: Vec<UploadJobStatus> = jobs.values().cloned().collect();
+        list.sort_by_key(|job| job.queued_at);
+        list
+    }
+
+    async fn enqueue(&self, device: &DeviceInfo) -> String {
+        let job_id = uuid::Uuid::new_v4().to_string();
+        let status = UploadJobStatus {
+            job_id: job_id.clone(),
+            device_id: device.id.clone(),
+            device_name: device.name.clone(),
+            port: device.port.clone(),
+            state: UploadJobState::Queued,
+            message: None,
+            queued_at: chrono::Utc::now(),
+            started_at: None,
+            finished_at: None,
+            duration_ms: None,
+        };
+
+        let mut jobs = self.jobs.write().await;
+        jobs.insert(job_id.clone(), status);
+        Self::prune_finished(&mut jobs);
+        job_id
+    }
+
+    async fn mark_running(&self, job_id: &str) {
+        let mut jobs = self.jobs.write().await;
+        if let Some(job) = jobs.get_mut(job_id) {
+            job.state = UploadJobState::Running;
+            job.started_at = Some(chrono::Utc::now());
+        }
+    }
+
+    async fn mark_finished(&self, job_id: &str, result: &Result<UploadOutcome>) {
+        let mut jobs = self.jobs.write().await;
+        if let Some(job) = jobs.get_mut(job_id) {
+            let now = chrono::Utc::now();
+            job.finished_at = Some(now);
+            job.duration_ms = job.started_at
+                .map(|started| (now - started).num_milliseconds().max(0) as u64);
+            match result {
+                Ok(outcome) => {
+                    job.state = UploadJobState::Succeeded;
+                    job.message = Some(outcome.message.clone());
+                }
+                Err(e) => {
+                    warn!("上传任务 {} 失败: {}", job_id, e);
+                    job.state = UploadJobState::Failed;
+                    job.message = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    fn prune_finished(jobs: &mut HashMap<String, UploadJobStatus>) {
+        let mut finished: Vec<(String, chrono::DateTime<chrono::Utc>)> = jobs
+            .values()
+            .filter_map(|job| job.finished_at.map(|t| (job.job_id.clone(), t)))
+            .collect();
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort_by_key(|(_, t)| *t);
+        let excess = finished.len() - MAX_FINISHED_JOBS;
+        for (job_id, _) in finished.into_iter().take(excess) {
+            jobs.remove(&job_id);
+        }
+    }
+}
+
+/// 批量上传中单块板子的结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchUploadResult {
+    pub device_id: String,
+    pub device_name: String,
+    pub port: String,
+    pub success: bool,
+    pub message: String,
+    pub duration_ms: u64,
+}
+
+impl BatchUploadResult {
+    pub fn from_result(device: &DeviceInfo, result: &Result<UploadOutcome>, started: Instant) -> Self {
+        Self {
+            device_id: device.id.clone(),
+            device_name: device.name.clone(),
+            port: device.port.clone(),
+            success: result.is_ok(),
+            message: match result {
+                Ok(outcome) => outcome.message.clone(),
+                Err(e) => e.to_string(),
+            },
+            duration_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use futures_util::future::join_all;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn device(port: &str) -> DeviceInfo {
+        DeviceInfo::new(port.to_string(), Some(0x2341), Some(0x0043))
+    }
+
+    /// 记录同时运行的任务数的最大值
+    #[derive(Default)]
+    struct Concurrency {
+        running: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    impl Concurrency {
+        async fn job(&self) -> Result<UploadOutcome> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(UploadOutcome { message: "上传成功".to_string(), fallback_variant: None })
+        }
+
+        fn peak(&self) -> usize {
+            self.peak.load(Ordering::SeqCst)
+        }
+    }
+
+    async fn run_on_ports(queue: &UploadQueue, concurrency: &Concurrency, ports: &[&str]) {
+        let devices: Vec<DeviceInfo> = ports.iter().map(|port| device(port)).collect();
+        let results = join_all(devices.iter().map(|d| queue.run_job(d, None, concurrency.job()))).await;
+        assert!(results.iter().all(|r| r.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit_bounds_running_jobs() {
+        let queue = UploadQueue::new(2, Arc::new(SerialManager::new()));
+        let concurrency = Concurrency::default();
+        run_on_ports(&queue, &concurrency, &["/dev/ttyA", "/dev/ttyB", "/dev/ttyC", "/dev/ttyD"]).await;
+        assert_eq!(concurrency.peak(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_same_port_jobs_run_one_at_a_time() {
+        let queue = UploadQueue::new(4, Arc::new(SerialManager::new()));
+        let concurrency = Concurrency::default();
+        run_on_ports(&queue, &concurrency, &["/dev/ttyA", "/dev/ttyA", "/dev/ttyA"]).await;
+        assert_eq!(concurrency.peak(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_set_concurrency_limit_resizes() {
+        let queue = UploadQueue::new(1, Arc::new(SerialManager::new()));
+        queue.set_concurrency_limit(3).await;
+        assert_eq!(queue.concurrency_limit().await, 3);
+        let raised = Concurrency::default();
+        run_on_ports(&queue, &raised, &["/dev/ttyA", "/dev/ttyB", "/dev/ttyC"]).await;
+        assert_eq!(raised.peak(), 3);
+
+        queue.set_concurrency_limit(1).await;
+        // 让后台任务收回多出的名额
+        tokio::task::yield_now().await;
+        let lowered = Concurrency::default();
+        run_on_ports(&queue, &lowered, &["/dev/ttyA", "/dev/ttyB", "/dev/ttyC"]).await;
+        assert_eq!(lowered.peak(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_job_status() {
+        let queue = UploadQueue::new(1, Arc::new(SerialManager::new()));
+        let result = queue.run_job(&device("/dev/ttyA"), None, async { Err(anyhow!("编译失败")) }).await;
+        assert!(result.is_err());
+
+        let jobs = queue.list_jobs().await;
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].state, UploadJobState::Failed);
+        assert_eq!(jobs[0].message.as_deref(), Some("编译失败"));
+        assert!(jobs[0].finished_at.is_some());
+    }
+}
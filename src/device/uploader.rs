@@ -1,4 +1,9 @@
-use super::{DeviceType, UploadOptions};
+use super::{DeviceInfo, DeviceType, UploadOptions};
+use super::boards::{is_sync_failure, BoardCatalog};
+pub use super::boards::BoardConfig;
+use super::esp_loader::ESP32_APP_OFFSET;
+use super::toolchain::{Toolchain, FIRMATA_LIBRARY_VERSION};
+use super::verify::UploadArtifact;
 use anyhow::{Result, anyhow};
 use log::{info, warn};
 use std::path::PathBuf;
@@ -7,6 +12,7 @@ use std::path::Path;
 use std::fs;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
+use std::sync::Mutex;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UploadProgress {
@@ -15,117 +21,140 @@ pub struct UploadProgress {
     pub message: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct BoardConfig {
-    pub name: String,
-    pub fqbn: String,  // Fully Qualified Board Name for Arduino
-    pub upload_protocol: String,
-    pub upload_speed: u32,
-    pub extra_flags: Vec<String>,
+/// 一次上传的结果
+#[derive(Debug, Clone)]
+pub struct UploadOutcome {
+    pub message: String,
+    /// 引导程序同步失败后自动改用的开发板型号，没有重试时为空
+    pub fallback_variant: Option<String>,
 }
 
 pub struct DeviceUploader {
-    board_configs: HashMap<DeviceType, Vec<BoardConfig>>,
+    boards: BoardCatalog,
+    toolchain: Toolchain,
+    /// 每个串口最近一次成功上传的内容，用于上传后验证
+    last_uploads: Mutex<HashMap<String, UploadArtifact>>,
+    /// 每个设备（按设备ID）自动重试成功的开发板型号
+    working_variants: Mutex<HashMap<String, String>>,
 }
 
 impl DeviceUploader {
     pub fn new() -> Self {
-        let mut uploader = Self {
-            board_configs: HashMap::new(),
-        };
-        uploader.initialize_board_configs();
-        uploader
+        // 应用数据目录不可用时退回到临时目录，仍然与系统的Arduino环境隔离
+        let toolchain = Toolchain::open().unwrap_or_else(|e| {
+            warn!("打开工具链目录失败，改用临时目录: {}", e);
+            let toolchain = Toolchain::with_root(std::env::temp_dir().join("rustblock_toolchain"));
+            let _ = toolchain.ensure_layout();
+            toolchain
+        });
+        Self::with_toolchain(toolchain)
     }
 
-    /// 初始化开发板配置
-    fn initialize_board_configs(&mut self) {
-        // Arduino 开发板配置
-        self.board_configs.insert(DeviceType::Arduino, vec![
-            BoardConfig {
-                name: "Arduino Uno".to_string(),
-                fqbn: "arduino:avr:uno".to_string(),
-                upload_protocol: "arduino".to_string(),
-                upload_speed: 115200,
-                extra_flags: vec![],
-            },
-            BoardConfig {
-                name: "Arduino Nano".to_string(),
-                fqbn: "arduino:avr:nano".to_string(),
-                upload_protocol: "arduino".to_string(),
-                upload_speed: 57600,
-                extra_flags: vec![],
-            },
-            BoardConfig {
-                name: "Arduino Leonardo".to_string(),
-                fqbn: "arduino:avr:leonardo".to_string(),
-                upload_protocol: "avr109".to_string(),
-                upload_speed: 57600,
-                extra_flags: vec![],
-            },
-        ]);
-
-        // ESP32 开发板配置
-        self.board_configs.insert(DeviceType::ESP32, vec![
-            BoardConfig {
-                name: "ESP32 Dev Module".to_string(),
-                fqbn: "esp32:esp32:esp32".to_string(),
-                upload_protocol: "esptool".to_string(),
-                upload_speed: 921600,
-                extra_flags: vec!["--before=default_reset".to_string(), "--after=hard_reset".to_string()],
-            },
-            BoardConfig {
-                name: "ESP32-S2".to_string(),
-                fqbn: "esp32:esp32:esp32s2".to_string(),
-                upload_protocol: "esptool".to_string(),
-                upload_speed: 460800,
-                extra_flags: vec!["--before=default_reset".to_string(), "--after=hard_reset".to_string()],
-            },
-        ]);
-
-        // micro:bit 配置
-        self.board_configs.insert(DeviceType::MicroBit, vec![
-            BoardConfig {
-                name: "BBC micro:bit".to_string(),
-                fqbn: "microbit".to_string(),
-                upload_protocol: "copy".to_string(),
-                upload_speed: 115200,
-                extra_flags: vec![],
-            },
-        ]);
-
-        // Raspberry Pi Pico 配置
-        self.board_configs.insert(DeviceType::RaspberryPiPico, vec![
-            BoardConfig {
-                name: "Raspberry Pi Pico".to_string(),
-                fqbn: "rp2040:rp2040:rpipico".to_string(),
-                upload_protocol: "picotool".to_string(),
-                upload_speed: 115200,
-                extra_flags: vec![],
-            },
-        ]);
+    pub fn with_toolchain(toolchain: Toolchain) -> Self {
+        Self {
+            boards: BoardCatalog::builtin(),
+            toolchain,
+            last_uploads: Mutex::new(HashMap::new()),
+            working_variants: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 开发板目录
+    pub fn board_catalog(&self) -> &BoardCatalog {
+        &self.boards
+    }
+
+    /// 应用管理的工具链
+    pub fn toolchain(&self) -> &Toolchain {
+        &self.toolchain
     }
 
     /// 获取设备类型支持的开发板配置
     pub fn get_board_configs(&self, device_type: &DeviceType) -> Vec<BoardConfig> {
-        self.board_configs.get(device_type).cloned().unwrap_or_default()
+        self.boards.for_device_type(device_type)
+    }
+
+    /// 确定上传使用的开发板型号
+    ///
+    /// 优先使用 `board_variant`，其次是 `board_type` 中的型号或FQBN，最后按VID/PID和设备类型推断。
+    pub fn resolve_board(&self, options: &UploadOptions, device: &DeviceInfo) -> Result<BoardConfig> {
+        let working = self.working_variant(&device.id);
+        let variant = options.board_variant.as_deref()
+            .or(working.as_deref())
+            .or_else(|| Some(options.board_type.as_str()).filter(|t| self.boards.get(t).is_some()));
+        self.boards.resolve(variant, device).cloned()
+    }
+
+    /// 本次运行中自动重试成功的型号（例如旧引导程序的Nano）
+    pub fn working_variant(&self, device_id: &str) -> Option<String> {
+        self.working_variants.lock().ok()?.get(device_id).cloned()
+    }
+
+    /// 根据设备类型和编程语言选择上传方式
+    pub async fn upload(&self, device: &DeviceInfo, options: &UploadOptions) -> Result<UploadOutcome> {
+        match (&device.device_type, options.language.as_str()) {
+            (DeviceType::Arduino, "arduino")
+            | (DeviceType::ESP32, "arduino")
+            | (DeviceType::RaspberryPiPico, "arduino") => {
+                self.upload_arduino_code(options, device).await
+            },
+            (DeviceType::MicroBit, "micropython")
+            | (DeviceType::ESP32, "micropython")
+            | (DeviceType::RaspberryPiPico, "micropython") => {
+                self.upload_micropython_code(options, &device.port).await
+                    .map(|message| UploadOutcome { message, fallback_variant: None })
+            },
+            _ => Err(anyhow!("不支持的设备类型和语言组合")),
+        }
     }
 
     /// 上传Arduino代码
-    pub async fn upload_arduino_code(&self, options: &UploadOptions) -> Result<String> {
-        info!("开始上传Arduino代码...");
+    ///
+    /// 引导程序同步失败时自动换用备选型号重试（新旧引导程序的Nano），
+    /// 成功后记住该设备可用的型号。
+    pub async fn upload_arduino_code(&self, options: &UploadOptions, device: &DeviceInfo) -> Result<UploadOutcome> {
+        let board_config = self.resolve_board(options, device)?;
+        let error = match self.upload_arduino_with_board(options, &device.port, &board_config).await {
+            Err(e) if is_sync_failure(&e) => e,
+            result => return result.map(|message| UploadOutcome { message, fallback_variant: None }),
+        };
         
-        let device_type = self.parse_device_type(&options.board_type)?;
-        let board_config = self.get_default_board_config(&device_type)?;
+        let fallback = match self.boards.fallback_for(&board_config) {
+            Some(fallback) => fallback.clone(),
+            None => return Err(error),
+        };
+        warn!("{} 引导程序同步失败，改用 {} 重试", board_config.name, fallback.name);
+        
+        let message = self.upload_arduino_with_board(options, &device.port, &fallback).await
+            .map_err(|e| anyhow!("{}；改用 {} 重试也失败: {}", error, fallback.name, e))?;
+        if let Ok(mut variants) = self.working_variants.lock() {
+            variants.insert(device.id.clone(), fallback.id.clone());
+        }
+        Ok(UploadOutcome {
+            message: format!("{}（已自动切换为 {}）", message, fallback.name),
+            fallback_variant: Some(fallback.id.clone()),
+        })
+    }
+
+    async fn upload_arduino_with_board(&self, options: &UploadOptions, port: &str, board_config: &BoardConfig) -> Result<String> {
+        info!("开始上传Arduino代码到 {} ({})...", board_config.name, board_config.fqbn);
         
         // 创建临时项目目录
-        let temp_dir = self.create_temp_project(&options.code, "ino").await?;
-        let sketch_file = temp_dir.join("sketch.ino");
+        let temp_dir = self.create_temp_project(&options.code, "sketch.ino").await?;
+        let sketch_file = temp_dir.join("sketch").join("sketch.ino");
         
         let result = match self.check_arduino_cli().await {
-            true => self.upload_with_arduino_cli(&sketch_file, &options.device_id, &board_config).await,
-            false => self.upload_with_platformio(&sketch_file, &options.device_id, &board_config).await,
+            true => self.upload_with_arduino_cli(&sketch_file, port, board_config).await,
+            false => self.upload_with_platformio(&sketch_file, port, board_config).await,
         };
 
+        if result.is_ok() {
+            match self.read_arduino_artifact(&temp_dir, board_config) {
+                Some(artifact) => self.remember_upload(port, artifact),
+                None => warn!("没有找到编译产物，无法进行回读验证"),
+            }
+        }
+
         // 清理临时文件
         let _ = fs::remove_dir_all(&temp_dir);
         
@@ -133,23 +162,30 @@ impl DeviceUploader {
     }
 
     /// 上传MicroPython代码
-    pub async fn upload_micropython_code(&self, options: &UploadOptions) -> Result<String> {
+    pub async fn upload_micropython_code(&self, options: &UploadOptions, port: &str) -> Result<String> {
         info!("开始上传MicroPython代码...");
         
         // 创建临时Python文件
-        let temp_dir = self.create_temp_project(&options.code, "py").await?;
+        let temp_dir = self.create_temp_project(&options.code, "main.py").await?;
         let python_file = temp_dir.join("main.py");
         
         let result = if self.check_command("mpremote").await {
-            self.upload_with_mpremote(&python_file, &options.device_id).await
+            self.upload_with_mpremote(&python_file, port).await
         } else if self.check_command("ampy").await {
-            self.upload_with_ampy(&python_file, &options.device_id).await
+            self.upload_with_ampy(&python_file, port).await
         } else if self.check_command("rshell").await {
-            self.upload_with_rshell(&python_file, &options.device_id).await
+            self.upload_with_rshell(&python_file, port).await
         } else {
             Err(anyhow!("未找到MicroPython上传工具 (mpremote, ampy, rshell)"))
         };
 
+        if result.is_ok() {
+            self.remember_upload(port, UploadArtifact::MicroPythonFile {
+                path: "main.py".to_string(),
+                content: options.code.as_bytes().to_vec(),
+            });
+        }
+
         // 清理临时文件
         let _ = fs::remove_dir_all(&temp_dir);
         
@@ -160,11 +196,17 @@ impl DeviceUploader {
     async fn upload_with_arduino_cli(&self, sketch_file: &Path, port: &str, board_config: &BoardConfig) -> Result<String> {
         info!("使用Arduino CLI上传代码...");
         
+        // 编译产物放在临时目录的build中，上传后用于回读验证
+        let sketch_dir = sketch_file.parent().ok_or_else(|| anyhow!("无法获取父目录"))?;
+        let build_dir = sketch_dir.with_file_name("build");
+        let build_dir = build_dir.to_str().ok_or_else(|| anyhow!("无法转换路径为字符串"))?;
+        
         // 编译代码
-        let compile_output = AsyncCommand::new("arduino-cli")
+        let compile_output = self.toolchain.async_command()?
             .args(&[
                 "compile",
                 "--fqbn", &board_config.fqbn,
+                "--output-dir", build_dir,
                 sketch_file.parent().ok_or_else(|| anyhow!("无法获取父目录"))?.to_str().ok_or_else(|| anyhow!("无法转换路径为字符串"))?,
             ])
             .output()
@@ -178,11 +220,12 @@ impl DeviceUploader {
         info!("Arduino代码编译成功，开始上传...");
 
         // 上传代码
-        let upload_output = AsyncCommand::new("arduino-cli")
+        let upload_output = self.toolchain.async_command()?
             .args(&[
                 "upload",
                 "--fqbn", &board_config.fqbn,
                 "--port", port,
+                "--input-dir", build_dir,
                 sketch_file.parent().ok_or_else(|| anyhow!("无法获取父目录"))?.to_str().ok_or_else(|| anyhow!("无法转换路径为字符串"))?,
             ])
             .output()
@@ -232,12 +275,14 @@ impl DeviceUploader {
     async fn upload_with_mpremote(&self, python_file: &Path, port: &str) -> Result<String> {
         info!("使用mpremote上传MicroPython代码...");
         
+        // 必须指定端口，否则多块板子同时连接时mpremote会选中第一块
         let output = AsyncCommand::new("mpremote")
             .args(&[
-                "mount", 
-                python_file.parent().ok_or_else(|| anyhow!("无法获取父目录"))?.to_str().ok_or_else(|| anyhow!("无法转换路径为字符串"))?,
-                "exec",
-                &format!("exec(open('{}').read())", python_file.file_name().ok_or_else(|| anyhow!("无法获取文件名"))?.to_str().ok_or_else(|| anyhow!("无法转换文件名为字符串"))?),
+                "connect", port,
+                "cp",
+                python_file.to_str().ok_or_else(|| anyhow!("无法转换路径为字符串"))?,
+                ":main.py",
+                "+", "reset",
             ])
             .output()
             .await?;
@@ -282,7 +327,8 @@ impl DeviceUploader {
             python_file.to_str().ok_or_else(|| anyhow!("无法转换路径为字符串"))?
         );
         
-        let temp_script = std::env::temp_dir().join("rshell_upload.txt");
+        // 脚本放在本次上传的临时目录中，避免并发上传互相覆盖
+        let temp_script = python_file.parent().ok_or_else(|| anyhow!("无法获取父目录"))?.join("rshell_upload.txt");
         fs::write(&temp_script, rshell_script)?;
         
         let output = AsyncCommand::new("rshell")
@@ -300,6 +346,44 @@ impl DeviceUploader {
         }
     }
 
+    /// 最近一次上传到该串口的内容
+    pub fn last_upload(&self, port: &str) -> Option<UploadArtifact> {
+        self.last_uploads.lock().ok()?.get(port).cloned()
+    }
+
+    fn remember_upload(&self, port: &str, artifact: UploadArtifact) {
+        if let Ok(mut uploads) = self.last_uploads.lock() {
+            uploads.insert(port.to_string(), artifact);
+        }
+    }
+
+    /// 读取Arduino CLI或PlatformIO的编译产物
+    fn read_arduino_artifact(&self, temp_dir: &Path, board_config: &BoardConfig) -> Option<UploadArtifact> {
+        let is_esp = board_config.fqbn.starts_with("esp32:");
+        let (cli_output, pio_output) = if is_esp {
+            ("sketch.ino.bin", "firmware.bin")
+        } else {
+            ("sketch.ino.hex", "firmware.hex")
+        };
+        let candidates = [
+            temp_dir.join("build").join(cli_output),
+            temp_dir.join("sketch").join(".pio").join("build").join("default").join(pio_output),
+        ];
+        let path = candidates.iter().find(|path| path.exists())?;
+
+        if is_esp {
+            fs::read(path).ok().map(|image| UploadArtifact::EspImage {
+                offset: ESP32_APP_OFFSET,
+                image,
+            })
+        } else {
+            fs::read_to_string(path).ok().map(|hex| UploadArtifact::AvrHex {
+                hex,
+                upload_protocol: board_config.upload_protocol.clone(),
+            })
+        }
+    }
+
     /// 检查所需工具是否已安装
     pub async fn check_tools(&self) -> Result<HashMap<String, bool>> {
         let mut tools = HashMap::new();
@@ -309,14 +393,13 @@ impl DeviceUploader {
         tools.insert("mpremote".to_string(), self.check_command("mpremote").await);
         tools.insert("ampy".to_string(), self.check_command("ampy").await);
         tools.insert("rshell".to_string(), self.check_command("rshell").await);
-        tools.insert("esptool".to_string(), self.check_command("esptool.py").await);
         
         Ok(tools)
     }
 
     /// 检查Arduino CLI是否可用
     async fn check_arduino_cli(&self) -> bool {
-        self.check_command("arduino-cli").await
+        self.toolchain.cli_available().await
     }
 
     /// 检查命令是否可用
@@ -341,6 +424,14 @@ impl DeviceUploader {
             }
         }
         
+        // 安装固定版本的开发板核心
+        if self.check_arduino_cli().await {
+            match self.toolchain.install_pinned_cores().await {
+                Ok(cores) => installed.extend(cores),
+                Err(e) => warn!("安装开发板核心失败: {}", e),
+            }
+        }
+        
         // 安装Python工具
         if !self.check_command("mpremote").await {
             match self.install_python_tool("mpremote").await {
@@ -355,40 +446,7 @@ impl DeviceUploader {
     /// 安装Arduino CLI
     async fn install_arduino_cli(&self) -> Result<()> {
         info!("尝试安装Arduino CLI...");
-        
-        #[cfg(target_os = "windows")]
-        {
-            // Windows: 使用winget或下载安装包
-            let output = AsyncCommand::new("winget")
-                .args(&["install", "ArduinoSA.ArduinoCLI"])
-                .output()
-                .await;
-                
-            match output {
-                Ok(result) if result.status.success() => Ok(()),
-                _ => Err(anyhow!("请手动安装Arduino CLI: https://arduino.github.io/arduino-cli/")),
-            }
-        }
-        
-        #[cfg(target_os = "macos")]
-        {
-            // macOS: 使用Homebrew
-            let output = AsyncCommand::new("brew")
-                .args(&["install", "arduino-cli"])
-                .output()
-                .await;
-                
-            match output {
-                Ok(result) if result.status.success() => Ok(()),
-                _ => Err(anyhow!("请手动安装Arduino CLI: brew install arduino-cli")),
-            }
-        }
-        
-        #[cfg(target_os = "linux")]
-        {
-            // Linux: 下载二进制文件
-            Err(anyhow!("请手动安装Arduino CLI: https://arduino.github.io/arduino-cli/"))
-        }
+        self.toolchain.install_arduino_cli().await
     }
 
     /// 安装Python工具
@@ -418,11 +476,19 @@ impl DeviceUploader {
     }
 
     /// 创建临时项目目录
-    async fn create_temp_project(&self, code: &str, extension: &str) -> Result<PathBuf> {
-        let temp_dir = std::env::temp_dir().join(format!("rustblock_{}", chrono::Utc::now().timestamp()));
-        fs::create_dir_all(&temp_dir)?;
+    ///
+    /// 每次上传使用独立目录，多块板子同时上传时互不干扰。
+    /// Arduino草图文件需要放在同名目录中（sketch/sketch.ino）。
+    async fn create_temp_project(&self, code: &str, file_name: &str) -> Result<PathBuf> {
+        let temp_dir = std::env::temp_dir().join(format!("rustblock_{}", uuid::Uuid::new_v4()));
         
-        let code_file = temp_dir.join(format!("sketch.{}", extension));
+        let code_file = match Path::new(file_name).file_stem().and_then(|s| s.to_str()) {
+            Some(stem) if file_name.ends_with(".ino") => temp_dir.join(stem).join(file_name),
+            _ => temp_dir.join(file_name),
+        };
+        if let Some(parent) = code_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
         fs::write(&code_file, code)?;
         
         Ok(temp_dir)
@@ -438,68 +504,20 @@ upload_port = {}
 upload_speed = {}
 monitor_speed = 9600
 ",
-            self.get_platform_for_board(&board_config.fqbn),
-            self.get_board_for_fqbn(&board_config.fqbn),
+            board_config.platformio_platform,
+            board_config.platformio_board,
             port,
             board_config.upload_speed
         )
     }
 
-    /// 根据FQBN获取PlatformIO平台
-    fn get_platform_for_board(&self, fqbn: &str) -> &str {
-        if fqbn.starts_with("arduino:avr") {
-            "atmelavr"
-        } else if fqbn.starts_with("esp32:") {
-            "espressif32"
-        } else if fqbn.starts_with("rp2040:") {
-            "raspberrypi"
-        } else {
-            "arduino"
-        }
-    }
-
-    /// 根据FQBN获取PlatformIO开发板
-    fn get_board_for_fqbn(&self, fqbn: &str) -> &str {
-        if fqbn.contains("uno") {
-            "uno"
-        } else if fqbn.contains("nano") {
-            "nanoatmega328"
-        } else if fqbn.contains("esp32") {
-            "esp32dev"
-        } else if fqbn.contains("rpipico") {
-            "pico"
-        } else {
-            "uno"
-        }
-    }
-
-    /// 解析设备类型
-    fn parse_device_type(&self, board_type: &str) -> Result<DeviceType> {
-        match board_type.to_lowercase().as_str() {
-            s if s.contains("arduino") => Ok(DeviceType::Arduino),
-            s if s.contains("esp32") => Ok(DeviceType::ESP32),
-            s if s.contains("microbit") || s.contains("micro:bit") => Ok(DeviceType::MicroBit),
-            s if s.contains("pico") => Ok(DeviceType::RaspberryPiPico),
-            _ => Err(anyhow!("不支持的设备类型: {}", board_type)),
-        }
-    }
-
-    /// 获取默认开发板配置
-    fn get_default_board_config(&self, device_type: &DeviceType) -> Result<BoardConfig> {
-        self.board_configs
-            .get(device_type)
-            .and_then(|configs| configs.first())
-            .cloned()
-            .ok_or_else(|| anyhow!("未找到设备类型 {:?} 的配置", device_type))
-    }
-
     /// 获取Arduino库列表
     pub async fn list_arduino_libraries(&self) -> Result<Vec<String>> {
         if !self.check_arduino_cli().await {
             return Err(anyhow!("Arduino CLI未安装"));
         }
         
-        let output = AsyncCommand::new("arduino-cli")
+        let output = self.toolchain.async_command()?
             .args(&["lib", "list"])
             .output()
             .await?;
@@ -523,6 +541,23 @@ monitor_speed = 9600
         }
     }
 
+    /// StandardFirmata草图源码（交互模式使用），Firmata库未安装或版本不对时先安装固定版本
+    pub async fn standard_firmata_sketch(&self) -> Result<String> {
+        let path = self.toolchain.user_dir()
+            .join("libraries")
+            .join("Firmata")
+            .join("examples")
+            .join("StandardFirmata")
+            .join("StandardFirmata.ino");
+        let installed = self.toolchain.library_version("Firmata").await.ok().flatten();
+        if !path.exists() || installed.as_deref() != Some(FIRMATA_LIBRARY_VERSION) {
+            info!("安装Firmata库 {}...", FIRMATA_LIBRARY_VERSION);
+            self.toolchain.install_library("Firmata", Some(FIRMATA_LIBRARY_VERSION)).await
+                .map_err(|e| anyhow!("安装Firmata库失败: {}", e))?;
+        }
+        fs::read_to_string(&path).map_err(|e| anyhow!("读取StandardFirmata草图失败 {:?}: {}", path, e))
+    }
+
     /// 安装Arduino库
     pub async fn install_arduino_library(&self, library_name: &str) -> Result<String> {
         if !self.check_arduino_cli().await {
@@ -531,16 +566,38 @@ monitor_speed = 9600
         
         info!("安装Arduino库: {}", library_name);
         
-        let output = AsyncCommand::new("arduino-cli")
-            .args(&["lib", "install", library_name])
-            .output()
-            .await?;
-        
-        if output.status.success() {
-            Ok(format!("Arduino库 {} 安装成功", library_name))
-        } else {
-            let error_msg = String::from_utf8_lossy(&output.stderr);
-            Err(anyhow!("安装Arduino库失败: {}", error_msg))
-        }
+        // 支持 "库名@版本" 的写法
+        let (name, version) = match library_name.split_once('@') {
+            Some((name, version)) => (name, Some(version)),
+            None => (library_name, None),
+        };
+        self.toolchain.install_library(name, version).await
+            .map(|version| format!("Arduino库 {} {} 安装成功", name, version))
+            .map_err(|e| anyhow!("安装Arduino库失败: {}", e))
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_working_variant_follows_device() {
+        let uploader = DeviceUploader::with_toolchain(Toolchain::with_root(std::env::temp_dir().join("rustblock_uploader_test")));
+        let options = UploadOptions {
+            device_id: String::new(),
+            code: String::new(),
+            language: "arduino".to_string(),
+            board_type: "arduino".to_string(),
+            board_variant: None,
+        };
+        let old_nano = DeviceInfo::new("COM3".to_string(), Some(0x1a86), Some(0x7523));
+        uploader.working_variants.lock().unwrap().insert(old_nano.id.clone(), "nano_old".to_string());
+        assert_eq!(uploader.resolve_board(&options, &old_nano).unwrap().id, "nano_old");
+
+        // 另一块板子插到同一个串口，不沿用旧Nano的型号
+        let mut new_nano = old_nano.clone();
+        new_nano.id = "usb_1a86_7523_at_1-2".to_string();
+        assert_eq!(uploader.resolve_board(&options, &new_nano).unwrap().id, "nano");
+    }
+}
@@ -0,0 +1,349 @@
+use super::esp_loader::{EspLoader, ROM_BAUD_RATE};
+use super::repl::{MicroPythonRepl, REPL_BAUD_RATE};
+use super::reset::{esp_hard_reset, pulse_reset};
+use super::stk500::{parse_intel_hex, verify_avr_flash};
+use super::transport::{open_transport, SerialTransport};
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// 生成的代码在启动时通过串口打印的标记，用于握手验证
+pub const BOOT_MARKER: &str = "[RustBlock] ready";
+/// 默认等待启动标记的时间
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 验证方式
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyStrategy {
+    /// 通过引导程序回读Flash并与镜像比较
+    FlashReadback,
+    /// 计算设备文件系统中文件的哈希
+    FileHash,
+    /// 等待程序启动时打印的标记
+    Handshake,
+}
+
+/// 上传成功后保留的内容，验证时与设备上的数据比较
+#[derive(Debug, Clone)]
+pub enum UploadArtifact {
+    /// AVR程序（Intel HEX）
+    AvrHex {
+        hex: String,
+        upload_protocol: String,
+    },
+    /// ESP32应用程序镜像及其Flash地址
+    EspImage {
+        offset: u32,
+        image: Vec<u8>,
+    },
+    /// 写入MicroPython文件系统的文件
+    MicroPythonFile {
+        path: String,
+        content: Vec<u8>,
+    },
+}
+
+impl UploadArtifact {
+    /// 该上传方式对应的主要验证方式
+    pub fn strategy(&self) -> VerifyStrategy {
+        match self {
+            // 只有STK500v1引导程序（Uno/Nano）支持回读
+            UploadArtifact::AvrHex { upload_protocol, .. } if upload_protocol == "arduino" => VerifyStrategy::FlashReadback,
+            UploadArtifact::AvrHex { .. } => VerifyStrategy::Handshake,
+            UploadArtifact::EspImage { .. } => VerifyStrategy::FlashReadback,
+            UploadArtifact::MicroPythonFile { .. } => VerifyStrategy::FileHash,
+        }
+    }
+
+    /// 等待启动标记时使用的波特率：程序使用设备的波特率，MicroPython使用REPL的波特率
+    fn handshake_baud_rate(&self, device_baud_rate: u32) -> u32 {
+        match self {
+            UploadArtifact::AvrHex { .. } | UploadArtifact::EspImage { .. } => device_baud_rate,
+            UploadArtifact::MicroPythonFile { .. } => REPL_BAUD_RATE,
+        }
+    }
+}
+
+/// 验证结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationReport {
+    pub success: bool,
+    pub strategy: VerifyStrategy,
+    pub message: String,
+    pub error: Option<String>,
+    pub checked_bytes: Option<usize>,
+    /// 是否收到启动标记，没有进行握手时为空
+    pub handshake_received: Option<bool>,
+}
+
+/// 验证设备上运行的是否是刚上传的程序
+///
+/// `handshake` 不为空时，在主要验证通过后以 `baud_rate`（设备程序的串口波特率）等待启动标记。
+/// 不支持回读的板子只进行握手验证。
+pub fn verify_device(port: &str, artifact: &UploadArtifact, baud_rate: u32, handshake: Option<Duration>) -> VerificationReport {
+    info!("验证上传: {} ({:?})", port, artifact.strategy());
+    let open = |baud_rate: u32, timeout: Duration| open_transport(port, baud_rate, timeout);
+    verify_with(&open, artifact, baud_rate, handshake)
+}
+
+/// 按波特率和读取超时打开被验证的串口，每一步验证都重新打开一次
+type OpenPort<'a> = dyn Fn(u32, Duration) -> Result<Box<dyn SerialTransport>> + 'a;
+
+fn verify_with(open: &OpenPort, artifact: &UploadArtifact, baud_rate: u32, handshake: Option<Duration>) -> VerificationReport {
+    let strategy = artifact.strategy();
+    let checked = match strategy {
+        VerifyStrategy::FlashReadback => verify_flash_readback(open, artifact).map(Some),
+        VerifyStrategy::FileHash => verify_file_hash(open, artifact).map(Some),
+        VerifyStrategy::Handshake => Ok(None),
+    };
+
+    let checked_bytes = match checked {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("上传验证失败: {}", e);
+            return VerificationReport {
+                success: false,
+                strategy,
+                message: "上传验证失败".to_string(),
+                error: Some(e.to_string()),
+                checked_bytes: None,
+                handshake_received: None,
+            };
+        }
+    };
+
+    let handshake = match (&strategy, handshake) {
+        (VerifyStrategy::Handshake, timeout) => Some(timeout.unwrap_or(DEFAULT_HANDSHAKE_TIMEOUT)),
+        (_, timeout) => timeout,
+    };
+    let handshake_result = handshake.map(|timeout| wait_for_boot_marker(open, artifact, baud_rate, timeout));
+
+    match handshake_result {
+        Some(Err(e)) => VerificationReport {
+            success: false,
+            strategy,
+            message: "没有收到程序的启动信息".to_string(),
+            error: Some(e.to_string()),
+            checked_bytes,
+            handshake_received: Some(false),
+        },
+        handshake_result => VerificationReport {
+            success: true,
+            strategy,
+            message: match checked_bytes {
+                Some(bytes) => format!("已校验 {} 字节，设备正在运行新程序", bytes),
+                None => "设备正在运行新程序".to_string(),
+            },
+            error: None,
+            checked_bytes,
+            handshake_received: handshake_result.map(|_| true),
+        },
+    }
+}
+
+/// 通过引导程序回读Flash
+fn verify_flash_readback(open: &OpenPort, artifact: &UploadArtifact) -> Result<usize> {
+    match artifact {
+        UploadArtifact::AvrHex { hex, .. } => {
+            let image = parse_intel_hex(hex)?;
+            let mut last_error = anyhow!("未尝试回读");
+            // 老款Nano的引导程序使用57600
+            for baud_rate in [115200, 57600] {
+                let port = open(baud_rate, Duration::from_millis(50))?;
+                match verify_avr_flash(port, &image) {
+                    Ok(bytes) => return Ok(bytes),
+                    Err(e) => last_error = e,
+                }
+            }
+            Err(last_error)
+        }
+        UploadArtifact::EspImage { offset, image } => {
+            let port = open(ROM_BAUD_RATE, Duration::from_millis(50))?;
+            let mut loader = EspLoader::new(port);
+            loader.connect(3)?;
+            let result = loader.verify_image(*offset, image);
+            let mut port = loader.into_inner();
+            esp_hard_reset(&mut port)?;
+            result.map(|_| image.len())
+        }
+        UploadArtifact::MicroPythonFile { .. } => Err(anyhow!("MicroPython程序不支持回读Flash")),
+    }
+}
+
+/// 在设备上计算文件哈希，与上传的内容比较
+fn verify_file_hash(open: &OpenPort, artifact: &UploadArtifact) -> Result<usize> {
+    let (path, content) = match artifact {
+        UploadArtifact::MicroPythonFile { path, content } => (path, content),
+        _ => return Err(anyhow!("只有MicroPython程序支持文件哈希校验")),
+    };
+
+    let port = open(REPL_BAUD_RATE, Duration::from_millis(20))?;
+    let mut repl = MicroPythonRepl::new(port);
+    repl.enter_raw()?;
+    let output = repl.exec_raw(&file_hash_script(path), Duration::from_secs(5));
+    let _ = repl.exit_raw();
+    let output = output?;
+
+    let expected = hex_string(&Sha256::digest(content));
+    let line = output.trim();
+    // 没有hashlib的固件（例如micro:bit）直接返回文件内容
+    let matches = if let Some(actual) = line.strip_prefix("H ") {
+        actual == expected
+    } else if let Some(actual) = line.strip_prefix("D ") {
+        actual == hex_string(content)
+    } else {
+        return Err(anyhow!("无法读取设备上的 {}: {}", path, line));
+    };
+
+    if !matches {
+        return Err(anyhow!("设备上的 {} 与上传的代码不一致", path));
+    }
+    Ok(content.len())
+}
+
+fn file_hash_script(path: &str) -> String {
+    format!(
+        "try:\n import hashlib\nexcept ImportError:\n hashlib=None\n\
+         f=open('{}','rb')\n\
+         if hashlib:\n h=hashlib.sha256()\n while True:\n  d=f.read(256)\n  if not d:\n   break\n  h.update(d)\n print('H',''.join('%02x'%b for b in h.digest()))\n\
+         else:\n print('D',''.join('%02x'%b for b in f.read()))\n\
+         f.close()\n",
+        path.replace('\'', "")
+    )
+}
+
+/// 复位设备并等待程序打印启动标记
+fn wait_for_boot_marker(open: &OpenPort, artifact: &UploadArtifact, baud_rate: u32, timeout: Duration) -> Result<()> {
+    let mut port = open(artifact.handshake_baud_rate(baud_rate), Duration::from_millis(20))?;
+
+    match artifact {
+        UploadArtifact::AvrHex { .. } => pulse_reset(&mut port)?,
+        UploadArtifact::EspImage { .. } => esp_hard_reset(&mut port)?,
+        UploadArtifact::MicroPythonFile { .. } => {
+            let mut repl = MicroPythonRepl::new(port);
+            repl.soft_reset()?;
+            port = repl.into_inner();
+        }
+    }
+
+    read_until_marker(&mut port, timeout)
+}
+
+fn read_until_marker<P: Read + ?Sized>(port: &mut P, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 256];
+
+    while Instant::now() < deadline {
+        match port.read(&mut buffer) {
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(anyhow!("读取串口失败: {}", e)),
+        }
+        if String::from_utf8_lossy(&received).contains(BOOT_MARKER) {
+            info!("收到启动标记");
+            return Ok(());
+        }
+    }
+    Err(anyhow!("{}秒内没有收到启动标记", timeout.as_secs()))
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::stk500::{program_avr_flash, ATMEGA328P_PAGE_SIZE};
+    use crate::device::transport::{ScriptPeer, ScriptedTransport, VirtualArduino};
+
+    const HEX: &str = ":100000000C9434000C9446000C9446000C9446006A\n\
+                       :020010000C944E\n\
+                       :00000001FF\n";
+
+    fn avr_artifact() -> UploadArtifact {
+        UploadArtifact::AvrHex { hex: HEX.to_string(), upload_protocol: "arduino".to_string() }
+    }
+
+    fn virtual_arduino() -> ScriptedTransport {
+        ScriptedTransport::new("virtual:test", Box::new(VirtualArduino::new()), Duration::from_millis(20))
+    }
+
+    /// 每次打开都返回同一个模拟设备的新句柄
+    fn opener(transport: ScriptedTransport) -> impl Fn(u32, Duration) -> Result<Box<dyn SerialTransport>> {
+        move |_baud_rate, timeout| {
+            let mut port = transport.try_clone_transport()?;
+            port.set_timeout(timeout)?;
+            Ok(port)
+        }
+    }
+
+    #[test]
+    fn test_strategy_per_artifact() {
+        assert_eq!(avr_artifact().strategy(), VerifyStrategy::FlashReadback);
+        let leonardo = UploadArtifact::AvrHex { hex: HEX.to_string(), upload_protocol: "avr109".to_string() };
+        assert_eq!(leonardo.strategy(), VerifyStrategy::Handshake);
+        assert_eq!(UploadArtifact::EspImage { offset: 0x10000, image: vec![0] }.strategy(), VerifyStrategy::FlashReadback);
+        let file = UploadArtifact::MicroPythonFile { path: "main.py".to_string(), content: vec![] };
+        assert_eq!(file.strategy(), VerifyStrategy::FileHash);
+    }
+
+    #[test]
+    fn test_file_hash_script_strips_quotes() {
+        let script = file_hash_script("ma'in.py");
+        assert!(script.contains("f=open('main.py','rb')"));
+        assert!(script.contains("print('H',"));
+        assert!(script.contains("print('D',"));
+    }
+
+    #[test]
+    fn test_read_until_marker() {
+        let output = format!("garbage\r\n{}\r\n", BOOT_MARKER).into_bytes();
+        assert!(read_until_marker(&mut &output[..], Duration::from_millis(100)).is_ok());
+
+        let mut silent = ScriptedTransport::new("test", Box::new(ScriptPeer::new()), Duration::from_millis(10));
+        assert!(read_until_marker(&mut silent, Duration::from_millis(50)).is_err());
+    }
+
+    #[test]
+    fn test_verify_avr_readback_and_handshake() {
+        let arduino = virtual_arduino();
+        let image = parse_intel_hex(HEX).unwrap();
+        program_avr_flash(arduino.try_clone_transport().unwrap(), &image, ATMEGA328P_PAGE_SIZE, |_, _| {}).unwrap();
+
+        let report = verify_with(&opener(arduino), &avr_artifact(), 9600, Some(Duration::from_secs(3)));
+        assert!(report.success, "{:?}", report.error);
+        assert_eq!(report.checked_bytes, Some(image.data.len()));
+        assert_eq!(report.handshake_received, Some(true));
+    }
+
+    #[test]
+    fn test_verify_avr_readback_mismatch() {
+        let report = verify_with(&opener(virtual_arduino()), &avr_artifact(), 9600, None);
+        assert!(!report.success);
+        assert_eq!(report.strategy, VerifyStrategy::FlashReadback);
+        assert!(report.error.unwrap().contains("不一致"));
+    }
+
+    #[test]
+    fn test_verify_micropython_file_hash() {
+        let content = b"print('hi')\n".to_vec();
+        let digest = hex_string(&Sha256::digest(&content));
+        let peer = ScriptPeer::new()
+            .expect(b"\x01", b"raw REPL; CTRL-B to exit\r\n>")
+            .expect(b"f.close()\n\x04", format!("OKH {}\r\n\x04\x04>", digest).as_bytes());
+        let device = ScriptedTransport::new("test", Box::new(peer), Duration::from_millis(20));
+        let artifact = UploadArtifact::MicroPythonFile { path: "main.py".to_string(), content };
+
+        let report = verify_with(&opener(device), &artifact, REPL_BAUD_RATE, None);
+        assert!(report.success, "{:?}", report.error);
+        assert_eq!(report.strategy, VerifyStrategy::FileHash);
+        assert_eq!(report.checked_bytes, Some(12));
+        assert_eq!(report.handshake_received, None);
+    }
+}
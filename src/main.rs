@@ -11,8 +11,11 @@ use device::{
     detector::DeviceDetector,
     uploader::DeviceUploader,
     serial::SerialManager,
+    interactive::InteractiveManager,
+    serial_macro::MacroRunner,
+    upload_queue::{UploadQueue, DEFAULT_UPLOAD_CONCURRENCY},
 };
-use commands::device::{DeviceDetectorState, DeviceUploaderState, SerialManagerState};
+use commands::device::{DeviceDetectorState, DeviceUploaderState, InteractiveManagerState, MacroRunnerState, SerialManagerState, UploadQueueState};
 use commands::ai::AIServiceState;
 use commands::enhanced_ai::EnhancedAIServiceState;
 
@@ -31,6 +34,9 @@ async fn main() {
     // 创建性能管理状态
     let (performance_monitor, global_cache, task_manager) = commands::performance::create_performance_states();
     
+    // 串口服务由串口监视器、上传队列、交互模式和宏共用
+    let serial_manager = SerialManagerState::new(SerialManager::new());
+    
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
@@ -38,7 +44,10 @@ async fn main() {
         .plugin(tauri_plugin_http::init())
         .manage(DeviceDetectorState::new(DeviceDetector::new()))
         .manage(DeviceUploaderState::new(DeviceUploader::new()))
-        .manage(SerialManagerState::new(SerialManager::new()))
+        .manage(UploadQueueState::new(UploadQueue::new(DEFAULT_UPLOAD_CONCURRENCY, serial_manager.clone())))
+        .manage(InteractiveManagerState::new(InteractiveManager::new(serial_manager.clone())))
+        .manage(MacroRunnerState::new(MacroRunner::new(serial_manager.clone())))
+        .manage(serial_manager)
         .manage(AIServiceState::new(None))
         .manage(EnhancedAIServiceState::new(None))
         .manage(performance_monitor)
@@ -48,13 +57,25 @@ async fn main() {
             commands::greet,
             // 设备管理命令
             commands::device::scan_devices,
+            commands::device::probe_devices,
             commands::device::connect_device,
             commands::device::disconnect_device,
             commands::device::upload_code,
+            commands::device::flash_project_to_boards,
+            commands::device::get_upload_jobs,
+            commands::device::set_upload_concurrency,
+            commands::device::set_virtual_devices_enabled,
+            commands::device::list_boards,
+            commands::device::detect_board_variant,
+            commands::device::detect_baud_rate,
+            commands::device::reset_board,
             commands::device::get_device_status,
             commands::device::check_device_drivers,
             commands::device::install_device_driver,
             commands::device::get_available_drivers,
+            commands::device::diagnose_serial_permissions,
+            commands::device::get_udev_rules,
+            commands::device::install_udev_rules,
             commands::device::get_installed_drivers,
             commands::device::get_arduino_libraries,
             commands::device::install_arduino_library,
@@ -125,10 +146,32 @@ async fn main() {
             commands::serial::connect_serial,
             commands::serial::disconnect_serial,
             commands::serial::write_serial_data,
+            commands::serial::set_serial_read_format,
+            commands::serial::get_serial_triggers,
+            commands::serial::set_serial_triggers,
+            commands::serial::get_serial_macros,
+            commands::serial::save_serial_macros,
+            commands::serial::import_serial_macros,
+            commands::serial::export_serial_macros,
+            commands::serial::run_serial_macro,
+            commands::serial::stop_serial_macro,
+            commands::serial::get_running_macros,
             commands::serial::read_serial_data,
             commands::serial::get_connected_ports,
             commands::serial::set_serial_params,
             commands::serial::clear_serial_buffers,
+            commands::serial::set_serial_dtr,
+            commands::serial::set_serial_rts,
+            commands::serial::get_serial_control_lines,
+            commands::serial::send_serial_break,
+            commands::serial::get_serial_plot_history,
+            commands::serial::clear_serial_plot_history,
+            commands::serial::start_serial_recording,
+            commands::serial::stop_serial_recording,
+            commands::serial::list_serial_sessions,
+            commands::serial::delete_serial_session,
+            commands::serial::export_serial_session,
+            commands::serial::replay_serial_session,
             commands::serial::record_connection_history,
             commands::serial::get_connection_history,
             // 工具和上传命令
@@ -139,11 +182,40 @@ async fn main() {
             commands::tools::compile_code,
             commands::tools::upload_firmware,
             commands::tools::verify_upload,
-            commands::tools::cancel_upload
+            commands::tools::get_toolchain_status,
+            commands::tools::install_toolchain,
+            commands::tools::import_toolchain_bundle,
+            commands::tools::read_esp_chip_info,
+            commands::tools::cancel_upload,
+            // 固件管理命令
+            commands::firmware::list_firmware_catalog,
+            commands::firmware::import_firmware_catalog,
+            commands::firmware::install_micropython_firmware,
+            commands::firmware::probe_micropython_version,
+            // 交互模式（Firmata）命令
+            commands::firmata::start_interactive_mode,
+            commands::firmata::stop_interactive_mode,
+            commands::firmata::get_interactive_ports,
+            commands::firmata::firmata_set_pin_mode,
+            commands::firmata::firmata_digital_write,
+            commands::firmata::firmata_analog_write,
+            commands::firmata::firmata_report_digital,
+            commands::firmata::firmata_report_analog,
+            commands::firmata::firmata_digital_read,
+            commands::firmata::firmata_analog_read,
+            commands::firmata::firmata_servo_write,
+            commands::firmata::firmata_i2c_config,
+            commands::firmata::firmata_i2c_write,
+            commands::firmata::firmata_i2c_read,
+            commands::firmata::firmata_i2c_stop_reading,
+            commands::firmata::firmata_set_sampling_interval
         ])
         .setup(|app| {
             println!("RustBlock Desktop 正在启动...");
             
+            // 监视USB设备插拔，随应用一起停止
+            app.manage(commands::device::start_hotplug_watcher(app.handle().clone()));
+            
             // 确保窗口可见
             if let Some(window) = app.get_webview_window("main") {
                 println!("找到主窗口，尝试显示...");
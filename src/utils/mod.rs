@@ -27,6 +27,42 @@ pub fn get_projects_dir() -> Result<PathBuf> {
     Ok(path)
 }
 
+/// 获取固件库目录
+pub fn get_firmware_dir() -> Result<PathBuf> {
+    let mut path = get_app_data_dir()?;
+    path.push("firmware");
+    
+    if !path.exists() {
+        std::fs::create_dir_all(&path)?;
+    }
+    
+    Ok(path)
+}
+
+/// 获取工具链目录（arduino-cli及开发板核心）
+pub fn get_toolchain_dir() -> Result<PathBuf> {
+    let mut path = get_app_data_dir()?;
+    path.push("toolchain");
+    
+    if !path.exists() {
+        std::fs::create_dir_all(&path)?;
+    }
+    
+    Ok(path)
+}
+
+/// 获取串口会话记录目录
+pub fn get_sessions_dir() -> Result<PathBuf> {
+    let mut path = get_app_data_dir()?;
+    path.push("sessions");
+    
+    if !path.exists() {
+        std::fs::create_dir_all(&path)?;
+    }
+    
+    Ok(path)
+}
+
 /// 格式化文件大小
 pub fn format_file_size(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
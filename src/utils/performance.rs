@@ -1,7 +1,9 @@
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
+use tokio::sync::Semaphore;
 use serde::{Serialize, Deserialize};
 use anyhow::Result;
 use log::{debug, info, warn};
@@ -138,21 +140,71 @@ where
 
 /// 并发任务管理器
 pub struct TaskManager {
-    max_concurrent_tasks: usize,
-    active_tasks: Arc<Mutex<usize>>,
+    max_concurrent_tasks: AtomicUsize,
+    active_tasks: Arc<AtomicUsize>,
+    permits: Arc<Semaphore>,
 }
 
 impl TaskManager {
     pub fn new(max_concurrent_tasks: usize) -> Self {
+        let max_concurrent_tasks = max_concurrent_tasks.max(1);
         Self {
-            max_concurrent_tasks,
-            active_tasks: Arc::new(Mutex::new(0)),
+            max_concurrent_tasks: AtomicUsize::new(max_concurrent_tasks),
+            active_tasks: Arc::new(AtomicUsize::new(0)),
+            permits: Arc::new(Semaphore::new(max_concurrent_tasks)),
         }
     }
 
     pub async fn active_task_count(&self) -> usize {
-        let count = self.active_tasks.lock().await;
-        *count
+        self.active_tasks.load(Ordering::SeqCst)
+    }
+
+    pub fn max_concurrent_tasks(&self) -> usize {
+        self.max_concurrent_tasks.load(Ordering::SeqCst)
+    }
+
+    /// 调整并发上限，正在执行的任务不受影响
+    ///
+    /// 调小时多出的名额要等正在执行的任务结束后才能收回，所以在后台等待并丢弃它们。
+    pub fn set_max_concurrent_tasks(&self, max_concurrent_tasks: usize) {
+        let new = max_concurrent_tasks.max(1);
+        let old = self.max_concurrent_tasks.swap(new, Ordering::SeqCst);
+        if new > old {
+            self.permits.add_permits(new - old);
+        } else if new < old {
+            let permits = Arc::clone(&self.permits);
+            let excess = (old - new) as u32;
+            tokio::spawn(async move {
+                if let Ok(permit) = permits.acquire_many_owned(excess).await {
+                    permit.forget();
+                }
+            });
+        }
+    }
+
+    /// 在并发限制内执行任务，超过上限时排队等待
+    pub async fn run<F: Future>(&self, task: F) -> F::Output {
+        let _permit = self.permits.acquire().await.expect("任务信号量已关闭");
+        let _active = ActiveTaskGuard::new(Arc::clone(&self.active_tasks));
+        task.await
+    }
+}
+
+/// 活动任务计数守卫，任务结束或被取消时自动减少计数
+struct ActiveTaskGuard {
+    counter: Arc<AtomicUsize>,
+}
+
+impl ActiveTaskGuard {
+    fn new(counter: Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self { counter }
+    }
+}
+
+impl Drop for ActiveTaskGuard {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::SeqCst);
     }
 }
 